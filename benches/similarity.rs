@@ -0,0 +1,52 @@
+//! Brute-force nearest neighbours with `lancor::embeddings::math::top_k`.
+//!
+//! Scores a query against 100,000 random 768-dimensional vectors, the size
+//! where an in-memory index starts to feel slow. Compare
+//! `cargo bench --bench similarity` with
+//! `cargo bench --bench similarity --features simd`, and either with
+//! `RUSTFLAGS="-C target-cpu=native"`.
+
+use lancor::embeddings::math;
+use std::time::Instant;
+
+const VECTORS: usize = 100_000;
+const DIMS: usize = 768;
+const ROUNDS: u32 = 10;
+
+fn vectors(count: usize, seed: u32) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..DIMS)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (state % 2000) as f32 / 1000.0 - 1.0
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn main() {
+    let candidates = vectors(VECTORS, 0x2545_f491);
+    let queries = vectors(ROUNDS as usize, 0x9e37_79b9);
+    println!(
+        "{} vectors of {} dimensions, simd feature {}",
+        VECTORS,
+        DIMS,
+        if cfg!(feature = "simd") { "on" } else { "off" }
+    );
+
+    let start = Instant::now();
+    for query in &queries {
+        std::hint::black_box(math::top_k(query, &candidates, 10));
+    }
+    let per_query = start.elapsed() / ROUNDS;
+    println!(
+        "top_k: {:.2?} per query, {:.0} vectors/s",
+        per_query,
+        VECTORS as f64 / per_query.as_secs_f64()
+    );
+}
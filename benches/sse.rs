@@ -0,0 +1,166 @@
+//! Allocations and time spent decoding a fast token stream.
+//!
+//! Decodes 20,000 llama.cpp-style chunks, read in 1,400-byte pieces as they
+//! come off the network, with [`SseDecoder`] and with the line-by-line
+//! `String` decoder it replaced, once keeping just the event data and once
+//! parsing every chunk. Run with `cargo bench --bench sse`.
+
+use lancor::ChatCompletionChunk;
+use lancor::stream::SseDecoder;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The system allocator, counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const EVENTS: usize = 20_000;
+const READ_SIZE: usize = 1_400;
+
+fn body() -> Vec<u8> {
+    let mut body = String::new();
+    for i in 0..EVENTS {
+        body.push_str(&format!(
+            "data: {{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1760000000,\
+             \"model\":\"qwen\",\"choices\":[{{\"index\":0,\"delta\":{{\"content\":\" tok{}\"}},\
+             \"finish_reason\":null}}]}}\n\n",
+            i % 100
+        ));
+    }
+    body.push_str("data: [DONE]\n\n");
+    body.into_bytes()
+}
+
+/// The decoder before [`SseDecoder`]: text decoded per network read, and a
+/// `String` per line and per event
+fn line_strings(reads: &[&[u8]], mut on_event: impl FnMut(&[u8])) {
+    let mut buffer = String::new();
+    let mut data: Option<String> = None;
+    for read in reads {
+        buffer.push_str(&String::from_utf8_lossy(read));
+        while let Some(pos) = buffer.find('\n') {
+            let line: String = buffer.drain(..=pos).collect();
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if let Some(data) = data.take()
+                    && data != "[DONE]"
+                {
+                    on_event(data.as_bytes());
+                }
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            if field == "data" {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match &mut data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => data = Some(value.to_string()),
+                }
+            }
+        }
+    }
+}
+
+fn decoder(reads: &[&[u8]], mut on_event: impl FnMut(&[u8])) {
+    let mut decoder = SseDecoder::new();
+    for read in reads {
+        decoder.push(read);
+        while let Some(data) = decoder.next_event() {
+            if data != b"[DONE]" {
+                on_event(data);
+            }
+        }
+    }
+}
+
+/// A decoder run over the reads, calling back with each event's data
+type Decode = fn(&[&[u8]], &mut dyn FnMut(&[u8]));
+
+fn measure(name: &str, reads: &[&[u8]], decode: Decode, parse: bool) {
+    let mut events = 0;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    decode(reads, &mut |data| {
+        events += 1;
+        if parse {
+            let chunk: ChatCompletionChunk = serde_json::from_slice(data).unwrap();
+            std::hint::black_box(chunk);
+        } else {
+            std::hint::black_box(data);
+        }
+    });
+    let elapsed: Duration = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    assert_eq!(events, EVENTS);
+    println!(
+        "{:<28} {:>10} {:>14.2} {:>12.2?} {:>14.0}",
+        name,
+        allocations,
+        allocations as f64 / events as f64,
+        elapsed,
+        events as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let body = body();
+    let reads: Vec<&[u8]> = body.chunks(READ_SIZE).collect();
+    println!(
+        "{} events, {} bytes in {} reads\n",
+        EVENTS,
+        body.len(),
+        reads.len()
+    );
+    println!(
+        "{:<28} {:>10} {:>14} {:>12} {:>14}",
+        "decoder", "allocs", "allocs/event", "time", "events/s"
+    );
+    measure(
+        "line strings",
+        &reads,
+        |reads, on_event| line_strings(reads, on_event),
+        false,
+    );
+    measure(
+        "SseDecoder",
+        &reads,
+        |reads, on_event| decoder(reads, on_event),
+        false,
+    );
+    measure(
+        "line strings + parse",
+        &reads,
+        |reads, on_event| line_strings(reads, on_event),
+        true,
+    );
+    measure(
+        "SseDecoder + parse",
+        &reads,
+        |reads, on_event| decoder(reads, on_event),
+        true,
+    );
+}
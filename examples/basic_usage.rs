@@ -32,10 +32,10 @@ async fn main() -> Result<()> {
     let mut stream = client.chat_completion_stream(streaming_request).await?;
     print!("Streaming response: ");
     while let Some(chunk_result) = stream.next().await {
-        if let Ok(chunk) = chunk_result {
-            if let Some(content) = &chunk.choices[0].delta.content {
-                print!("{}", content);
-            }
+        if let Ok(chunk) = chunk_result
+            && let Some(content) = &chunk.choices[0].delta.content
+        {
+            print!("{}", content);
         }
     }
     println!();
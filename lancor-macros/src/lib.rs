@@ -0,0 +1,185 @@
+//! Procedural macros for [lancor](https://docs.rs/lancor). Use them through
+//! the re-exports in `lancor` rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{Attribute, Expr, FnArg, Ident, ItemFn, Lit, Meta, Pat, parse_macro_input};
+
+/// Turn a function into a tool for `lancor::ToolRegistry`
+///
+/// The function's doc comment becomes the tool description and each
+/// parameter's doc comment its argument description. Argument schemas come
+/// from the parameter types via `lancor::agent::ToolParam`. The function may
+/// be `async` and must return a `Result` whose value is serializable.
+///
+/// Alongside the function, a unit struct named after it in PascalCase with a
+/// `Tool` suffix is generated; pass it to `ToolRegistry::add`.
+///
+/// ```ignore
+/// /// Add two numbers
+/// #[lancor::tool]
+/// async fn add(
+///     /// The first number
+///     a: i64,
+///     /// The second number
+///     b: i64,
+/// ) -> anyhow::Result<i64> {
+///     Ok(a + b)
+/// }
+///
+/// registry.add(AddTool)?;
+/// ```
+#[proc_macro_attribute]
+pub fn tool(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "#[tool] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let function = parse_macro_input!(item as ItemFn);
+    match expand(function) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(mut function: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let fn_name = function.sig.ident.clone();
+    let tool_name = fn_name.to_string();
+    let struct_name = format_ident!("{}Tool", pascal_case(&tool_name));
+    let vis = function.vis.clone();
+    let description = option_tokens(doc_string(&function.attrs));
+
+    let mut arg_names = Vec::new();
+    let mut arg_idents = Vec::new();
+    let mut arg_types = Vec::new();
+    let mut arg_docs = Vec::new();
+
+    for input in function.sig.inputs.iter_mut() {
+        let FnArg::Typed(arg) = input else {
+            return Err(syn::Error::new_spanned(
+                input,
+                "#[tool] functions cannot take self",
+            ));
+        };
+        let Pat::Ident(pat) = arg.pat.as_ref() else {
+            return Err(syn::Error::new_spanned(
+                &arg.pat,
+                "#[tool] parameters must be plain identifiers",
+            ));
+        };
+
+        let ident = pat.ident.clone();
+        arg_names.push(ident.to_string().trim_start_matches("r#").to_string());
+        arg_idents.push(ident);
+        arg_types.push(arg.ty.as_ref().clone());
+        arg_docs.push(option_tokens(doc_string(&arg.attrs)));
+
+        // Doc comments are not allowed on parameters once the macro is done
+        arg.attrs.retain(|attr| !attr.path().is_ident("doc"));
+    }
+
+    let call = if function.sig.asyncness.is_some() {
+        quote! { #fn_name(#(#arg_idents),*).await }
+    } else {
+        quote! { #fn_name(#(#arg_idents),*) }
+    };
+    let struct_doc = format!("Tool definition generated for [`{}`]", tool_name);
+
+    Ok(quote! {
+        #function
+
+        #[doc = #struct_doc]
+        #[derive(Debug, Clone, Copy, Default)]
+        #vis struct #struct_name;
+
+        impl ::lancor::agent::ToolHandler for #struct_name {
+            fn definition(&self) -> ::lancor::FunctionDefinition {
+                let mut properties = ::lancor::__private::serde_json::Map::new();
+                let mut required = ::std::vec::Vec::<::std::string::String>::new();
+                #(
+                    let mut schema = <#arg_types as ::lancor::agent::ToolParam>::schema();
+                    if let (Some(description), Some(object)) = (#arg_docs, schema.as_object_mut()) {
+                        object.insert(
+                            "description".into(),
+                            ::lancor::__private::serde_json::Value::String(description.into()),
+                        );
+                    }
+                    properties.insert(#arg_names.into(), schema);
+                    if <#arg_types as ::lancor::agent::ToolParam>::REQUIRED {
+                        required.push(#arg_names.into());
+                    }
+                )*
+
+                ::lancor::FunctionDefinition {
+                    name: #tool_name.into(),
+                    description: #description.map(::std::convert::Into::into),
+                    parameters: ::lancor::__private::serde_json::json!({
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                    }),
+                }
+            }
+
+            fn call(
+                &self,
+                arguments: ::lancor::__private::serde_json::Value,
+            ) -> ::lancor::__private::BoxFuture<'static, ::lancor::__private::Result<::std::string::String>> {
+                ::std::boxed::Box::pin(async move {
+                    #(
+                        let #arg_idents: #arg_types =
+                            ::lancor::agent::tool_arg(&arguments, #arg_names)?;
+                    )*
+                    ::lancor::agent::ToolOutput::into_output(#call)
+                })
+            }
+        }
+    })
+}
+
+/// The text of all `///` comments on an item, joined into one string
+fn doc_string(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(s) => Some(s.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let text = lines.join("\n").trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn option_tokens(value: Option<String>) -> proc_macro2::TokenStream {
+    match value {
+        Some(text) => quote! { ::std::option::Option::Some(#text) },
+        None => quote! { ::std::option::Option::<&str>::None },
+    }
+}
+
+fn pascal_case(name: &str) -> Ident {
+    let name: String = name
+        .trim_start_matches("r#")
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    Ident::new(&name, Span::call_site())
+}
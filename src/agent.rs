@@ -0,0 +1,418 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::schema::CompiledSchema;
+use crate::session::ChatSession;
+use crate::{FunctionDefinition, Message, Tool, ToolCall};
+
+// ============================================================================
+// Tool Registry
+// ============================================================================
+
+type Handler = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<String>> + Send + Sync>;
+
+struct RegisteredTool {
+    definition: FunctionDefinition,
+    schema: CompiledSchema,
+    handler: Handler,
+}
+
+/// Tools the model may call, each with its schema and an async handler
+#[derive(Clone)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Arc<RegisteredTool>>,
+    max_concurrent: usize,
+    max_argument_retries: usize,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self {
+            tools: BTreeMap::new(),
+            max_concurrent: 4,
+            max_argument_retries: 2,
+        }
+    }
+}
+
+impl std::fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ToolRegistry")
+            .field("tools", &self.tools.keys().collect::<Vec<_>>())
+            .field("max_concurrent", &self.max_concurrent)
+            .field("max_argument_retries", &self.max_argument_retries)
+            .finish()
+    }
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run at most `limit` of the calls in one model turn at once; 4 by
+    /// default, and 1 runs them one after another
+    pub fn max_concurrent(&mut self, limit: usize) -> &mut Self {
+        self.max_concurrent = limit.max(1);
+        self
+    }
+
+    /// Let [`run_agent`] ask the model to fix invalid tool arguments this
+    /// many turns in a row before giving up; 2 by default
+    pub fn max_argument_retries(&mut self, retries: usize) -> &mut Self {
+        self.max_argument_retries = retries;
+        self
+    }
+
+    /// Register a tool. `parameters` is the JSON schema of the arguments
+    /// object passed to `handler`, whose output is sent back to the model.
+    /// Registering a name twice replaces the earlier tool.
+    ///
+    /// Fails with [`InvalidSchema`](crate::schema::InvalidSchema) if
+    /// `parameters` cannot be used to check arguments, such as a schema with
+    /// `pattern` or `format` without the `jsonschema` feature.
+    pub fn register<F, Fut>(
+        &mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: Value,
+        handler: F,
+    ) -> Result<&mut Self>
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |args| Box::pin(handler(args)));
+        self.insert(
+            FunctionDefinition {
+                name: name.into(),
+                description: Some(description.into()),
+                parameters,
+            },
+            handler,
+        )
+    }
+
+    /// Register a tool whose arguments are deserialized into `T`, with the
+    /// JSON schema derived from `T` as its parameters
+    #[cfg(feature = "schemars")]
+    pub fn register_typed<T, F, Fut>(
+        &mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        handler: F,
+    ) -> Result<&mut Self>
+    where
+        T: DeserializeOwned + schemars::JsonSchema,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        let name = name.into();
+        let context = format!("Invalid arguments for {}", name);
+        self.register(name, description, crate::schema::of::<T>(), move |args| {
+            let call = serde_json::from_value(args)
+                .with_context(|| context.clone())
+                .map(&handler);
+            async move { call?.await }
+        })
+    }
+
+    /// Register a tool defined by a [`ToolHandler`], such as the structs
+    /// generated by `#[lancor::tool]`
+    ///
+    /// Fails like [`ToolRegistry::register`] if the tool's schema cannot be
+    /// used to check arguments.
+    pub fn add<T: ToolHandler>(&mut self, tool: T) -> Result<&mut Self> {
+        let definition = tool.definition();
+        let tool = Arc::new(tool);
+        let handler: Handler = Arc::new(move |args| tool.call(args));
+        self.insert(definition, handler)
+    }
+
+    fn insert(&mut self, definition: FunctionDefinition, handler: Handler) -> Result<&mut Self> {
+        let schema = CompiledSchema::compile(&definition.parameters).map_err(|err| {
+            anyhow::Error::new(err).context(format!(
+                "Cannot check arguments for tool {}",
+                definition.name
+            ))
+        })?;
+        self.tools.insert(
+            definition.name.clone(),
+            Arc::new(RegisteredTool {
+                definition,
+                schema,
+                handler,
+            }),
+        );
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// The tool definitions to send with a request
+    pub fn definitions(&self) -> Vec<Tool> {
+        self.tools
+            .values()
+            .map(|tool| Tool {
+                kind: "function".to_string(),
+                function: tool.definition.clone(),
+            })
+            .collect()
+    }
+
+    /// Run the handler for `call`
+    ///
+    /// Arguments that are not JSON or do not match the tool's schema fail
+    /// with [`InvalidArguments`] without running the handler. A `null` for an
+    /// optional argument counts as leaving it out.
+    pub async fn call(&self, call: &ToolCall) -> Result<String> {
+        let name = &call.function.name;
+        let tool = self
+            .tools
+            .get(name)
+            .with_context(|| format!("Unknown tool {:?}", name))?;
+
+        let arguments = if call.function.arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            serde_json::from_str(&call.function.arguments).map_err(|err| InvalidArguments {
+                tool: name.clone(),
+                problems: vec![format!("not valid JSON: {}", err)],
+            })?
+        };
+        let violations = tool.schema.validate(&without_null_options(
+            &tool.definition.parameters,
+            &arguments,
+        ));
+        if !violations.is_empty() {
+            return Err(InvalidArguments {
+                tool: name.clone(),
+                problems: violations.iter().map(ToString::to_string).collect(),
+            }
+            .into());
+        }
+
+        (tool.handler)(arguments).await
+    }
+
+    /// Run the handlers for `calls` concurrently, within the
+    /// [`ToolRegistry::max_concurrent`] limit, returning their results in
+    /// the order of `calls`
+    pub async fn call_all(&self, calls: &[ToolCall]) -> Vec<Result<String>> {
+        futures::stream::iter(calls)
+            .map(|call| self.call(call))
+            .buffered(self.max_concurrent)
+            .collect()
+            .await
+    }
+}
+
+/// `arguments` without the `null` properties its schema does not require
+fn without_null_options(parameters: &Value, arguments: &Value) -> Value {
+    let required = parameters["required"].as_array();
+    let is_required = |name: &str| required.is_some_and(|names| names.iter().any(|n| n == name));
+    match arguments {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(name, value)| !value.is_null() || is_required(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// The model called a tool with arguments that are not JSON or do not
+/// match the tool's schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidArguments {
+    pub tool: String,
+    /// What is wrong, e.g. `/city: expected string, got 7`
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for InvalidArguments {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid arguments for {}: {}",
+            self.tool,
+            self.problems.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for InvalidArguments {}
+
+// ============================================================================
+// Tool Definitions
+// ============================================================================
+
+/// A tool with a fixed definition, usually generated by `#[lancor::tool]`
+pub trait ToolHandler: Send + Sync + 'static {
+    fn definition(&self) -> FunctionDefinition;
+
+    fn call(&self, arguments: Value) -> BoxFuture<'static, Result<String>>;
+}
+
+/// A type usable as a tool argument, with the JSON schema it accepts
+pub trait ToolParam: DeserializeOwned {
+    /// Whether the model must always supply the argument
+    const REQUIRED: bool = true;
+
+    fn schema() -> Value;
+}
+
+macro_rules! tool_param {
+    ($schema_type:literal: $($ty:ty),*) => {
+        $(
+            impl ToolParam for $ty {
+                fn schema() -> Value {
+                    json!({ "type": $schema_type })
+                }
+            }
+        )*
+    };
+}
+
+tool_param!("string": String, char);
+tool_param!("boolean": bool);
+tool_param!("integer": i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+tool_param!("number": f32, f64);
+
+impl<T: ToolParam> ToolParam for Vec<T> {
+    fn schema() -> Value {
+        json!({ "type": "array", "items": T::schema() })
+    }
+}
+
+impl<T: ToolParam> ToolParam for Option<T> {
+    const REQUIRED: bool = false;
+
+    fn schema() -> Value {
+        T::schema()
+    }
+}
+
+impl ToolParam for Value {
+    fn schema() -> Value {
+        json!({})
+    }
+}
+
+/// Extract and deserialize the argument `name`, treating a missing argument
+/// as `null`
+pub fn tool_arg<T: DeserializeOwned>(arguments: &Value, name: &str) -> Result<T> {
+    let value = arguments.get(name).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value).with_context(|| format!("Invalid argument {:?}", name))
+}
+
+/// The return value of a tool function, turned into the text sent back to
+/// the model. Strings are sent as-is and anything else as JSON.
+pub trait ToolOutput {
+    fn into_output(self) -> Result<String>;
+}
+
+impl<T, E> ToolOutput for std::result::Result<T, E>
+where
+    T: Serialize,
+    E: Into<anyhow::Error>,
+{
+    fn into_output(self) -> Result<String> {
+        match serde_json::to_value(self.map_err(Into::into)?)? {
+            Value::String(text) => Ok(text),
+            value => Ok(value.to_string()),
+        }
+    }
+}
+
+// ============================================================================
+// Agent Loop
+// ============================================================================
+
+/// Let the model call tools until it gives a final answer
+///
+/// Each iteration sends the session's conversation along with the registry's
+/// tools. Tool calls in the reply run concurrently, up to the registry's
+/// [`ToolRegistry::max_concurrent`], and their results are appended as `tool`
+/// messages in the order of the calls; tool errors are reported to the model
+/// rather than aborting the loop. Arguments that break a tool's schema are
+/// sent back with the schema and a request to call again, for up to
+/// [`ToolRegistry::max_argument_retries`] turns in a row. Returns the text of
+/// the first reply without tool calls, or an error after `max_iters` model
+/// turns.
+///
+/// Push the user's message onto the session before calling this.
+pub async fn run_agent(
+    session: &mut ChatSession,
+    registry: &ToolRegistry,
+    max_iters: usize,
+) -> Result<String> {
+    let tools = registry.definitions();
+    let mut argument_retries = 0;
+
+    for _ in 0..max_iters {
+        let reply = session.complete_with_tools(Some(tools.clone())).await?;
+        let calls = match reply.tool_calls {
+            Some(calls) if !calls.is_empty() => calls,
+            _ => return Ok(reply.content.text()),
+        };
+
+        let outputs = registry.call_all(&calls).await;
+        let mut invalid = None;
+        for (call, output) in calls.iter().zip(outputs) {
+            let output = match output {
+                Ok(output) => output,
+                Err(err) => match err.downcast::<InvalidArguments>() {
+                    Ok(err) => {
+                        let output = correction(registry, &err);
+                        invalid = Some(err);
+                        output
+                    }
+                    Err(err) => format!("Error: {:#}", err),
+                },
+            };
+            session.push(Message::tool(call.id.clone(), output));
+        }
+
+        match invalid {
+            Some(err) if argument_retries == registry.max_argument_retries => {
+                return Err(anyhow::Error::new(err).context(format!(
+                    "Model still called tools with invalid arguments after {} retries",
+                    argument_retries
+                )));
+            }
+            Some(_) => argument_retries += 1,
+            None => argument_retries = 0,
+        }
+    }
+
+    anyhow::bail!("Agent did not finish within {} iterations", max_iters)
+}
+
+/// The tool message asking the model to call again with valid arguments
+fn correction(registry: &ToolRegistry, err: &InvalidArguments) -> String {
+    let schema = registry
+        .tools
+        .get(&err.tool)
+        .map(|tool| tool.definition.parameters.to_string())
+        .unwrap_or_default();
+    format!(
+        "Error: {}. Call {} again with arguments matching this JSON schema: {}",
+        err, err.tool, schema
+    )
+}
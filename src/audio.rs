@@ -0,0 +1,258 @@
+//! Speech to text with whisper.cpp's server.
+//!
+//! whisper.cpp's `whisper-server`, started with
+//! `--inference-path /v1/audio/transcriptions`, serves the same
+//! transcription endpoint as OpenAI, so one client can talk to both it and
+//! llama.cpp. [`LlamaCppClient::transcribe`] uploads an audio file and
+//! returns the text along with timestamped segments.
+//!
+//! ```no_run
+//! use lancor::LlamaCppClient;
+//! use lancor::audio::TranscriptionOptions;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let whisper = LlamaCppClient::new("http://localhost:8081")?;
+//! let transcript = whisper
+//!     .transcribe("meeting.wav", TranscriptionOptions::new().language("en"))
+//!     .await?;
+//! for segment in &transcript.segments {
+//!     println!("[{:>7.2}s] {}", segment.start, segment.text.trim());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::{LlamaCppClient, compat, transport};
+
+// ============================================================================
+// Options
+// ============================================================================
+
+/// Settings for a transcription; everything is optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranscriptionOptions {
+    /// Required by OpenAI (`whisper-1`); whisper.cpp uses its loaded model
+    pub model: Option<String>,
+    /// The spoken language as an ISO-639-1 code; detected if unset
+    pub language: Option<String>,
+    /// Text to condition the model on, such as names and jargon
+    pub prompt: Option<String>,
+    pub temperature: Option<f32>,
+    /// Ask for word-level timestamps as well as segments
+    pub word_timestamps: bool,
+}
+
+impl TranscriptionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn word_timestamps(mut self, word_timestamps: bool) -> Self {
+        self.word_timestamps = word_timestamps;
+        self
+    }
+
+    /// The form fields sent along with the file
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("response_format", "verbose_json".to_string())];
+        if let Some(model) = &self.model {
+            fields.push(("model", model.clone()));
+        }
+        if let Some(language) = &self.language {
+            fields.push(("language", language.clone()));
+        }
+        if let Some(prompt) = &self.prompt {
+            fields.push(("prompt", prompt.clone()));
+        }
+        if let Some(temperature) = self.temperature {
+            fields.push(("temperature", temperature.to_string()));
+        }
+        if self.word_timestamps {
+            fields.push(("timestamp_granularities[]", "segment".to_string()));
+            fields.push(("timestamp_granularities[]", "word".to_string()));
+        }
+        fields
+    }
+}
+
+// ============================================================================
+// Responses
+// ============================================================================
+
+/// A transcript with its timestamped segments
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Transcription {
+    pub text: String,
+    /// The language spoken, as detected or given
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Length of the audio in seconds
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub segments: Vec<TranscriptionSegment>,
+    /// Word timestamps, when asked for and supported; whisper.cpp puts them
+    /// on the segments instead
+    #[serde(default)]
+    pub words: Vec<TranscriptionWord>,
+}
+
+/// A stretch of speech, with times in seconds from the start of the audio
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TranscriptionSegment {
+    #[serde(default)]
+    pub id: u32,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    #[serde(default)]
+    pub avg_logprob: Option<f64>,
+    /// How likely the segment is silence or noise
+    #[serde(default)]
+    pub no_speech_prob: Option<f64>,
+    #[serde(default)]
+    pub words: Vec<TranscriptionWord>,
+}
+
+/// A word, with times in seconds from the start of the audio
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TranscriptionWord {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+    #[serde(default)]
+    pub probability: Option<f64>,
+}
+
+// ============================================================================
+// Requests
+// ============================================================================
+
+/// The MIME type of an audio file, by extension
+fn audio_mime_type(file_name: &str) -> &'static str {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("wav") => "audio/wav",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") | Some("oga") => "audio/ogg",
+        Some("flac") => "audio/flac",
+        Some("m4a") => "audio/mp4",
+        Some("webm") => "audio/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A `multipart/form-data` body of `fields` followed by the file, and its
+/// content type
+fn multipart(fields: &[(&str, String)], file_name: &str, audio: &[u8]) -> (Vec<u8>, String) {
+    let boundary = format!(
+        "lancor-{:016x}{:016x}",
+        compat::random_u64(),
+        compat::random_u64()
+    );
+    let mut body = Vec::with_capacity(audio.len() + 512);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary,
+            file_name.replace('"', "'"),
+            audio_mime_type(file_name)
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(audio);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    (body, format!("multipart/form-data; boundary={}", boundary))
+}
+
+impl LlamaCppClient {
+    /// Transcribe the audio file at `path`
+    pub async fn transcribe(
+        &self,
+        path: impl AsRef<Path>,
+        options: TranscriptionOptions,
+    ) -> Result<Transcription> {
+        let path = path.as_ref();
+        let audio = std::fs::read(path)
+            .with_context(|| format!("Failed to read audio from {}", path.display()))?;
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("audio");
+        self.transcribe_bytes(file_name, &audio, options).await
+    }
+
+    /// Transcribe audio already in memory; `file_name`'s extension tells the
+    /// server the format
+    pub async fn transcribe_bytes(
+        &self,
+        file_name: &str,
+        audio: &[u8],
+        options: TranscriptionOptions,
+    ) -> Result<Transcription> {
+        let config = self.config();
+        let model = options.model.clone().unwrap_or_default();
+        let observation = self.observe("transcription", &model)?;
+
+        let (body, content_type) = multipart(&options.fields(), file_name, audio);
+        let request = transport::HttpRequest {
+            method: "POST".to_string(),
+            url: String::new(),
+            headers: vec![("Content-Type".to_string(), content_type)],
+            body,
+        };
+
+        let response = async {
+            let (response, _) = self
+                .dispatch(
+                    &config,
+                    request,
+                    "/v1/audio/transcriptions",
+                    "transcription",
+                )
+                .await?;
+            response
+                .json::<Transcription>()
+                .await
+                .context("Failed to parse transcription response")
+        }
+        .await;
+
+        observation.finish(response, |_, _| {})
+    }
+}
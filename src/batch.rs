@@ -0,0 +1,162 @@
+//! Running many requests with bounded concurrency.
+//!
+//! [`LlamaCppClient::map_chat`] and [`LlamaCppClient::map_embeddings`] send a
+//! list of requests, at most `concurrency` at a time, and return one result
+//! per request in input order; a failed request does not stop the others.
+//! The `_with_progress` variants report each finished request.
+//!
+//! ```no_run
+//! use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+//!
+//! # async fn example(client: LlamaCppClient) {
+//! let prompts = ["What is 2 + 2?", "Name a prime number.", "Spell 'cat' backwards."];
+//! let requests = prompts
+//!     .iter()
+//!     .map(|prompt| ChatCompletionRequest::new("qwen").message(Message::user(*prompt)));
+//!
+//! let results = client
+//!     .map_chat_with_progress(requests, 4, |progress| {
+//!         eprintln!("{}/{} done, {} failed", progress.completed, progress.total, progress.failed)
+//!     })
+//!     .await;
+//! for (prompt, result) in prompts.iter().zip(results) {
+//!     match result {
+//!         Ok(response) => println!("{}: {}", prompt, response.choices[0].message.content.text()),
+//!         Err(err) => println!("{}: failed: {}", prompt, err),
+//!     }
+//! }
+//! # }
+//! ```
+
+use anyhow::Result;
+use futures::future::Future;
+use futures::stream::{self, StreamExt};
+
+use crate::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse,
+    LlamaCppClient,
+};
+
+// ============================================================================
+// Progress
+// ============================================================================
+
+/// How far a batch has got, reported after each request finishes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchProgress {
+    /// Requests finished, successfully or not
+    pub completed: usize,
+    /// Requests that failed
+    pub failed: usize,
+    pub total: usize,
+    /// The position in the input of the request that just finished
+    pub index: usize,
+}
+
+/// Run `send` on every item, at most `concurrency` at a time, returning the
+/// results in input order
+async fn map_ordered<T, R, F, Fut>(
+    items: Vec<T>,
+    concurrency: usize,
+    send: F,
+    mut progress: impl FnMut(&BatchProgress),
+) -> Vec<Result<R>>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R>>,
+{
+    let total = items.len();
+    let mut results: Vec<Option<Result<R>>> = (0..total).map(|_| None).collect();
+    let mut finished = stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| {
+            let sent = send(item);
+            async move { (index, sent.await) }
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut report = BatchProgress {
+        completed: 0,
+        failed: 0,
+        total,
+        index: 0,
+    };
+    while let Some((index, result)) = finished.next().await {
+        report.completed += 1;
+        report.failed += usize::from(result.is_err());
+        report.index = index;
+        progress(&report);
+        results[index] = Some(result);
+    }
+
+    results
+        .into_iter()
+        .map(|result| result.expect("every request finishes"))
+        .collect()
+}
+
+// ============================================================================
+// Client Methods
+// ============================================================================
+
+impl LlamaCppClient {
+    /// Send every chat request, at most `concurrency` at a time, returning
+    /// their results in the order of `requests`
+    pub async fn map_chat(
+        &self,
+        requests: impl IntoIterator<Item = ChatCompletionRequest>,
+        concurrency: usize,
+    ) -> Vec<Result<ChatCompletionResponse>> {
+        self.map_chat_with_progress(requests, concurrency, |_| {})
+            .await
+    }
+
+    /// Like [`LlamaCppClient::map_chat`], calling `progress` as each request
+    /// finishes
+    pub async fn map_chat_with_progress(
+        &self,
+        requests: impl IntoIterator<Item = ChatCompletionRequest>,
+        concurrency: usize,
+        progress: impl FnMut(&BatchProgress),
+    ) -> Vec<Result<ChatCompletionResponse>> {
+        let requests = requests.into_iter().collect();
+        map_ordered(
+            requests,
+            concurrency,
+            |request| self.chat_completion(request),
+            progress,
+        )
+        .await
+    }
+
+    /// Send every embedding request, at most `concurrency` at a time,
+    /// returning their results in the order of `requests`
+    ///
+    /// To embed many texts with one model, [`LlamaCppClient::embedding_batch`]
+    /// sends them in a single request instead.
+    pub async fn map_embeddings(
+        &self,
+        requests: impl IntoIterator<Item = EmbeddingRequest>,
+        concurrency: usize,
+    ) -> Vec<Result<EmbeddingResponse>> {
+        self.map_embeddings_with_progress(requests, concurrency, |_| {})
+            .await
+    }
+
+    /// Like [`LlamaCppClient::map_embeddings`], calling `progress` as each
+    /// request finishes
+    pub async fn map_embeddings_with_progress(
+        &self,
+        requests: impl IntoIterator<Item = EmbeddingRequest>,
+        concurrency: usize,
+        progress: impl FnMut(&BatchProgress),
+    ) -> Vec<Result<EmbeddingResponse>> {
+        let requests = requests.into_iter().collect();
+        map_ordered(
+            requests,
+            concurrency,
+            |request| self.embedding(request),
+            progress,
+        )
+        .await
+    }
+}
@@ -0,0 +1,351 @@
+//! Measuring a server's latency and throughput under load.
+//!
+//! A [`Benchmark`] sends the same request a number of times, keeping a
+//! number of them in flight, and returns what it measured as a
+//! [`BenchReport`] rather than printing it, for eval frameworks and
+//! dashboards to use. The `lancor bench` command is built on it.
+//!
+//! ```no_run
+//! use lancor::bench::Benchmark;
+//! use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+//!
+//! # async fn example(client: LlamaCppClient) {
+//! let report = Benchmark::new(client)
+//!     .chat(ChatCompletionRequest::new("qwen").message(Message::user("Tell me a story")))
+//!     .requests(100)
+//!     .concurrency(8)
+//!     .run()
+//!     .await;
+//! if let Some(latency) = report.latency {
+//!     println!("p99 latency: {:?}", latency.p99);
+//! }
+//! println!("{:.1} tokens/s", report.tokens_per_second);
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt};
+use std::time::Duration;
+
+use crate::compat::Instant;
+use crate::stream::ChatStreamExt;
+use crate::{ChatCompletionRequest, CompletionRequest, LlamaCppClient, Message};
+
+/// How much more throughput a doubling of concurrency must bring for the
+/// server to count as not yet saturated
+const SATURATION_GAIN: f64 = 1.1;
+
+/// The prompt benchmarked when none is given
+pub const DEFAULT_PROMPT: &str = "Write a short story about a robot learning to paint.";
+
+// ============================================================================
+// Reports
+// ============================================================================
+
+/// The measurements of one benchmark request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchSample {
+    /// From sending the request to reading the end of the reply
+    pub latency: Duration,
+    /// Time to the first generated token; not measured for text
+    /// completions, which are not streamed
+    pub ttft: Option<Duration>,
+    /// Tokens generated, as the server counted them when it said
+    pub tokens: u32,
+}
+
+/// A distribution of durations, by nearest-rank percentiles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// The percentiles of `values`, or `None` if there are none
+    pub fn of(values: impl IntoIterator<Item = Duration>) -> Option<Self> {
+        let mut values: Vec<Duration> = values.into_iter().collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort();
+        let at = |p: f64| {
+            let rank = ((p / 100.0) * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+        Some(Self {
+            mean: values.iter().sum::<Duration>() / values.len() as u32,
+            p50: at(50.0),
+            p90: at(90.0),
+            p95: at(95.0),
+            p99: at(99.0),
+            max: at(100.0),
+        })
+    }
+}
+
+/// What a [`Benchmark`] measured
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// Requests sent
+    pub requests: usize,
+    /// Requests kept in flight
+    pub concurrency: usize,
+    /// Each request that succeeded, in the order they finished
+    pub samples: Vec<BenchSample>,
+    /// The message of each request that failed
+    pub errors: Vec<String>,
+    /// From the first request sent to the last one finished
+    pub wall_time: Duration,
+    /// Tokens generated by all successful requests
+    pub tokens: u64,
+    /// Successful requests per second of wall time
+    pub requests_per_second: f64,
+    /// Generated tokens per second of wall time
+    pub tokens_per_second: f64,
+    pub latency: Option<Percentiles>,
+    pub ttft: Option<Percentiles>,
+    /// The median generation speed of a single request once its first
+    /// token arrived
+    pub tokens_per_second_per_request: Option<f64>,
+}
+
+impl BenchReport {
+    fn new(requests: usize, concurrency: usize, wall_time: Duration) -> Self {
+        Self {
+            requests,
+            concurrency,
+            samples: Vec::new(),
+            errors: Vec::new(),
+            wall_time,
+            tokens: 0,
+            requests_per_second: 0.0,
+            tokens_per_second: 0.0,
+            latency: None,
+            ttft: None,
+            tokens_per_second_per_request: None,
+        }
+    }
+
+    /// Requests that succeeded
+    pub fn succeeded(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The fraction of requests that failed, from 0 to 1
+    pub fn error_rate(&self) -> f64 {
+        self.errors.len() as f64 / self.requests.max(1) as f64
+    }
+}
+
+/// A benchmark repeated at rising concurrency; see [`Benchmark::sweep`]
+#[derive(Debug, Clone)]
+pub struct SweepReport {
+    /// One report per concurrency level, lowest first
+    pub levels: Vec<BenchReport>,
+    /// The concurrency past which throughput stopped growing, or `None` if
+    /// it grew up to the highest level tried
+    pub saturation: Option<usize>,
+}
+
+impl SweepReport {
+    fn new(levels: Vec<BenchReport>) -> Self {
+        // Tokens per second when the server reports tokens, requests otherwise
+        let throughput = |report: &BenchReport| match report.tokens {
+            0 => report.requests_per_second,
+            _ => report.tokens_per_second,
+        };
+        let saturation = levels
+            .windows(2)
+            .find(|pair| {
+                throughput(&pair[1]) < throughput(&pair[0]) * SATURATION_GAIN
+                    || pair[1].error_rate() > pair[0].error_rate()
+            })
+            .map(|pair| pair[0].concurrency);
+        Self { levels, saturation }
+    }
+
+    /// The report at the saturation point, or at the highest level if the
+    /// server never saturated
+    pub fn saturated(&self) -> Option<&BenchReport> {
+        match self.saturation {
+            Some(concurrency) => self.levels.iter().find(|r| r.concurrency == concurrency),
+            None => self.levels.last(),
+        }
+    }
+}
+
+/// 1, 2, 4, … up to `max`, ending with `max` itself
+pub fn sweep_levels(max: usize) -> Vec<usize> {
+    let mut levels: Vec<usize> = std::iter::successors(Some(1usize), |n| n.checked_mul(2))
+        .take_while(|&n| n < max)
+        .collect();
+    levels.push(max.max(1));
+    levels
+}
+
+// ============================================================================
+// Benchmarks
+// ============================================================================
+
+#[derive(Debug, Clone)]
+enum Workload {
+    Chat(ChatCompletionRequest),
+    Completion(CompletionRequest),
+}
+
+/// A load test of one request against a client's server; see the
+/// [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    client: LlamaCppClient,
+    workload: Workload,
+    requests: usize,
+    concurrency: usize,
+}
+
+impl Benchmark {
+    /// Benchmark 20 streamed chats of [`DEFAULT_PROMPT`] with the server's
+    /// model, capped at 128 tokens, 4 at a time
+    pub fn new(client: LlamaCppClient) -> Self {
+        let request = ChatCompletionRequest::new(crate::AUTO_MODEL)
+            .message(Message::user(DEFAULT_PROMPT))
+            .max_tokens(128)
+            .temperature(0.7);
+        Self {
+            client,
+            workload: Workload::Chat(request),
+            requests: 20,
+            concurrency: 4,
+        }
+    }
+
+    /// Send `request` as a streamed chat completion, measuring time to first
+    /// token
+    pub fn chat(mut self, request: ChatCompletionRequest) -> Self {
+        self.workload = Workload::Chat(request);
+        self
+    }
+
+    /// Send `request` as a text completion
+    pub fn completion(mut self, request: CompletionRequest) -> Self {
+        self.workload = Workload::Completion(request);
+        self
+    }
+
+    /// How many requests to send in all
+    pub fn requests(mut self, requests: usize) -> Self {
+        self.requests = requests;
+        self
+    }
+
+    /// How many requests to keep in flight; at least 1
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Send the requests and measure them
+    pub async fn run(&self) -> BenchReport {
+        let started = Instant::now();
+        let results: Vec<Result<BenchSample>> = stream::iter(0..self.requests)
+            .map(|_| self.sample())
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+        let mut report = BenchReport::new(self.requests, self.concurrency, started.elapsed());
+
+        for result in results {
+            match result {
+                Ok(sample) => report.samples.push(sample),
+                Err(err) => report.errors.push(format!("{:#}", err)),
+            }
+        }
+
+        let seconds = report.wall_time.as_secs_f64();
+        report.tokens = report.samples.iter().map(|s| u64::from(s.tokens)).sum();
+        if seconds > 0.0 {
+            report.requests_per_second = report.samples.len() as f64 / seconds;
+            report.tokens_per_second = report.tokens as f64 / seconds;
+        }
+        report.latency = Percentiles::of(report.samples.iter().map(|s| s.latency));
+        report.ttft = Percentiles::of(report.samples.iter().filter_map(|s| s.ttft));
+
+        let mut speeds: Vec<f64> = report
+            .samples
+            .iter()
+            .filter_map(|s| {
+                let generating = s.latency.saturating_sub(s.ttft.unwrap_or_default());
+                (s.tokens > 1 && !generating.is_zero())
+                    .then(|| f64::from(s.tokens) / generating.as_secs_f64())
+            })
+            .collect();
+        speeds.sort_by(f64::total_cmp);
+        report.tokens_per_second_per_request = speeds.get(speeds.len() / 2).copied();
+        report
+    }
+
+    /// Run the benchmark at concurrency 1, 2, 4, … up to `max_concurrency`
+    /// and find where the server saturates: the level past which doubling
+    /// the concurrency raises throughput by less than 10%, or raises the
+    /// error rate
+    ///
+    /// Each level sends the configured number of requests, or four per slot
+    /// if that is more, so that every level runs at full concurrency for a
+    /// while. This is a good way to choose llama.cpp's `--parallel` slot
+    /// count.
+    pub async fn sweep(&self, max_concurrency: usize) -> SweepReport {
+        self.sweep_at(sweep_levels(max_concurrency)).await
+    }
+
+    /// Run the benchmark at each of `levels` of concurrency in turn; see
+    /// [`Benchmark::sweep`]
+    pub async fn sweep_at(&self, levels: impl IntoIterator<Item = usize>) -> SweepReport {
+        let mut reports = Vec::new();
+        for concurrency in levels {
+            let benchmark = self
+                .clone()
+                .concurrency(concurrency)
+                .requests(self.requests.max(concurrency * 4));
+            reports.push(benchmark.run().await);
+        }
+        SweepReport::new(reports)
+    }
+
+    /// Send one request and time it
+    async fn sample(&self) -> Result<BenchSample> {
+        let started = Instant::now();
+        match &self.workload {
+            Workload::Chat(request) => {
+                let request = request.clone().stream(true);
+                let mut stream = self
+                    .client
+                    .chat_completion_stream(request)
+                    .await?
+                    .with_stats();
+                let connected = started.elapsed();
+                while let Some(chunk) = stream.next().await {
+                    chunk?;
+                }
+                let stats = stream.stats().context("Stream ended without statistics")?;
+                Ok(BenchSample {
+                    latency: started.elapsed(),
+                    ttft: stats.time_to_first_token.map(|ttft| connected + ttft),
+                    tokens: stats.completion_tokens.unwrap_or(stats.tokens),
+                })
+            }
+            Workload::Completion(request) => {
+                let response = self.client.completion(request.clone()).await?;
+                Ok(BenchSample {
+                    latency: started.elapsed(),
+                    ttft: None,
+                    tokens: response.tokens_predicted.unwrap_or(0),
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! A synchronous client for programs that don't run an async runtime.
+//!
+//! Each client owns a small single-threaded Tokio runtime and blocks on it
+//! for every call, so it must not be used from inside an async context.
+
+use anyhow::{Context, Result};
+use futures::stream::{BoxStream, StreamExt};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+use crate::structured::OutputSchema;
+use crate::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, CompletionRequest,
+    CompletionResponse, EmbeddingRequest, EmbeddingResponse, LancorConfig, ModelInfo,
+    RerankRequest, RerankResponse, TokenizeRequest, TokenizeResponse,
+};
+
+// ============================================================================
+// Client
+// ============================================================================
+
+/// Blocking counterpart of [`crate::LlamaCppClient`]
+#[derive(Debug, Clone)]
+pub struct LlamaCppClient {
+    inner: crate::LlamaCppClient,
+    runtime: Arc<Runtime>,
+}
+
+impl LlamaCppClient {
+    fn wrap(inner: crate::LlamaCppClient) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to build runtime")?;
+
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Create a new client with the specified base URL
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        Self::wrap(crate::LlamaCppClient::new(base_url)?)
+    }
+
+    /// Create a new client with the specified base URL and API key
+    pub fn with_api_key(base_url: impl Into<String>, api_key: impl Into<String>) -> Result<Self> {
+        Self::wrap(crate::LlamaCppClient::with_api_key(base_url, api_key)?)
+    }
+
+    /// Create a new client from a [`LancorConfig`]
+    pub fn from_config(config: LancorConfig) -> Result<Self> {
+        Self::wrap(crate::LlamaCppClient::from_config(config)?)
+    }
+
+    /// Create a client connecting to localhost:8080
+    #[allow(clippy::should_implement_trait)]
+    pub fn default() -> Result<Self> {
+        Self::wrap(crate::LlamaCppClient::default()?)
+    }
+
+    /// The underlying async client, sharing configuration with this one
+    pub fn async_client(&self) -> &crate::LlamaCppClient {
+        &self.inner
+    }
+
+    /// Send a chat completion request
+    pub fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        self.runtime.block_on(self.inner.chat_completion(request))
+    }
+
+    /// Send a streaming chat completion request and iterate over the chunks
+    pub fn chat_completion_stream(&self, request: ChatCompletionRequest) -> Result<ChunkIter> {
+        let stream = self
+            .runtime
+            .block_on(self.inner.chat_completion_stream(request))?;
+
+        Ok(ChunkIter {
+            stream: stream.boxed(),
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    /// Send a text completion request
+    pub fn completion(&self, request: CompletionRequest) -> Result<CompletionResponse> {
+        self.runtime.block_on(self.inner.completion(request))
+    }
+
+    /// Send an embedding request
+    pub fn embedding(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        self.runtime.block_on(self.inner.embedding(request))
+    }
+
+    /// Score documents by relevance to a query with a reranker model
+    pub fn rerank(&self, request: RerankRequest) -> Result<RerankResponse> {
+        self.runtime.block_on(self.inner.rerank(request))
+    }
+
+    /// Tokenize text with the server's model
+    pub fn tokenize(&self, request: TokenizeRequest) -> Result<TokenizeResponse> {
+        self.runtime.block_on(self.inner.tokenize(request))
+    }
+
+    /// List the models the server offers
+    pub fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        self.runtime.block_on(self.inner.list_models())
+    }
+
+    /// Ask `model` for a `T` and parse the reply; see
+    /// [`crate::LlamaCppClient::generate`]
+    pub fn generate<T: OutputSchema>(
+        &self,
+        model: impl Into<String>,
+        prompt: impl Into<String>,
+    ) -> Result<T> {
+        self.runtime.block_on(self.inner.generate(model, prompt))
+    }
+}
+
+/// Iterator over the chunks of a streaming chat completion
+pub struct ChunkIter {
+    stream: BoxStream<'static, Result<ChatCompletionChunk>>,
+    runtime: Arc<Runtime>,
+}
+
+impl std::fmt::Debug for ChunkIter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkIter").finish_non_exhaustive()
+    }
+}
+
+impl Iterator for ChunkIter {
+    type Item = Result<ChatCompletionChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
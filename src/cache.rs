@@ -0,0 +1,560 @@
+//! Reusing responses to repeated deterministic requests.
+//!
+//! With a [`ResponseCache`] installed through
+//! [`crate::LlamaCppClient::with_cache`], embedding requests, and chat or text
+//! completion requests with temperature 0 or a fixed seed, are answered from
+//! the cache when the same request was sent before. Requests are compared
+//! after presets are applied, by model, messages and every sampling
+//! parameter, the seed included. Other requests always go to the server,
+//! since their replies are meant to vary.
+//!
+//! Responses are kept in memory, and with [`ResponseCache::directory`] also
+//! as one file per request, so they survive a restart of the process.
+//!
+//! An [`EmbeddingCache`], with the `embedding-cache` feature, instead
+//! remembers one vector per model and text, so re-indexing a document set
+//! only embeds the texts that changed.
+
+use anyhow::Result;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::compat::Instant;
+use crate::transport::HttpResponse;
+
+/// Entries kept by a new [`ResponseCache`]
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+// ============================================================================
+// Keys
+// ============================================================================
+
+/// A request in canonical form, and its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CacheKey {
+    hash: u64,
+    request: String,
+}
+
+impl CacheKey {
+    /// The key of sending `body` to `path`
+    ///
+    /// The body goes through a `serde_json::Value`, whose object keys are
+    /// sorted, so the key does not depend on field order.
+    pub(crate) fn new(path: &str, body: &impl Serialize) -> Result<Self> {
+        let request = format!("{} {}", path, serde_json::to_value(body)?);
+        Ok(Self {
+            hash: fnv1a(request.as_bytes()),
+            request,
+        })
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `std`'s hasher is the same in every process
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// ============================================================================
+// Cache
+// ============================================================================
+
+/// Hits, misses and size of a [`ResponseCache`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The full key, to tell apart requests whose hashes collide
+    request: String,
+    response: Vec<u8>,
+    /// When the entry was added to memory, and how old it was then
+    loaded: Instant,
+    age: Duration,
+    last_used: u64,
+}
+
+impl Entry {
+    fn is_fresh(&self, ttl: Option<Duration>) -> bool {
+        ttl.is_none_or(|ttl| self.age + self.loaded.elapsed() < ttl)
+    }
+}
+
+#[derive(Debug)]
+struct CacheState {
+    entries: HashMap<u64, Entry>,
+    ttl: Option<Duration>,
+    max_entries: usize,
+    hits: u64,
+    misses: u64,
+    /// Incremented on every access, to find the least recently used entry
+    clock: u64,
+    #[cfg(not(target_arch = "wasm32"))]
+    directory: Option<PathBuf>,
+}
+
+/// A cache of response bodies, shared by all clones
+///
+/// Entries live until they are older than the TTL, if one is set. In memory
+/// they are also dropped when the cache is full and they are the least
+/// recently used.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CacheState {
+                entries: HashMap::new(),
+                ttl: None,
+                max_entries: DEFAULT_MAX_ENTRIES,
+                hits: 0,
+                misses: 0,
+                clock: 0,
+                #[cfg(not(target_arch = "wasm32"))]
+                directory: None,
+            })),
+        }
+    }
+}
+
+impl ResponseCache {
+    /// A cache of up to 1000 responses that never expire
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat responses older than `ttl` as missing
+    pub fn ttl(self, ttl: Duration) -> Self {
+        self.lock().ttl = Some(ttl);
+        self
+    }
+
+    /// Also store responses as files in `dir`, and look for them there when
+    /// they are not in memory
+    ///
+    /// The directory is created when the first response is stored. Files are
+    /// named by the hash of their request, hold the request to guard against
+    /// collisions, and are deleted once they are found to be past the TTL;
+    /// `max_entries` only limits the responses kept in memory. Failing to
+    /// write a file does not fail the request.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn directory(self, dir: impl Into<PathBuf>) -> Self {
+        self.lock().directory = Some(dir.into());
+        self
+    }
+
+    /// Keep at most `max_entries` responses in memory, evicting the least
+    /// recently used
+    pub fn max_entries(self, max_entries: usize) -> Self {
+        let mut state = self.lock();
+        state.max_entries = max_entries;
+        state.evict();
+        drop(state);
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.lock();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every cached response, including the files in the cache
+    /// directory
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.entries.clear();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(dir) = &state.directory
+            && let Ok(files) = std::fs::read_dir(dir)
+        {
+            for file in files.flatten() {
+                if file.path().extension().is_some_and(|ext| ext == "json") {
+                    let _ = std::fs::remove_file(file.path());
+                }
+            }
+        }
+    }
+
+    /// The cached response for `key`, if there is a fresh one that parses
+    pub(crate) fn get<T: DeserializeOwned>(&self, key: &CacheKey) -> Option<T> {
+        let mut state = self.lock();
+        state.clock += 1;
+        let (clock, ttl) = (state.clock, state.ttl);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if !state.entries.contains_key(&key.hash)
+            && let Some(dir) = &state.directory
+            && let Some(mut entry) = disk::load(dir, key, ttl)
+        {
+            entry.last_used = clock;
+            state.entries.insert(key.hash, entry);
+            state.evict();
+        }
+
+        let response = match state.entries.get_mut(&key.hash) {
+            Some(entry) if entry.request == key.request && entry.is_fresh(ttl) => {
+                entry.last_used = clock;
+                serde_json::from_slice(&entry.response).ok()
+            }
+            _ => None,
+        };
+
+        match response {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        response
+    }
+
+    pub(crate) fn put(&self, key: CacheKey, response: Vec<u8>) {
+        let mut state = self.lock();
+        state.clock += 1;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(dir) = &state.directory {
+            let _ = disk::store(dir, &key, &response);
+        }
+
+        let entry = Entry {
+            request: key.request,
+            response,
+            loaded: Instant::now(),
+            age: Duration::ZERO,
+            last_used: state.clock,
+        };
+        state.entries.insert(key.hash, entry);
+        state.evict();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl CacheState {
+    fn evict(&mut self) {
+        if let Some(ttl) = self.ttl {
+            self.entries.retain(|_, entry| entry.is_fresh(Some(ttl)));
+        }
+        while self.entries.len() > self.max_entries {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| *hash)
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+// ============================================================================
+// Embedding Cache
+// ============================================================================
+
+/// One line of an embedding cache file
+#[cfg(all(feature = "embedding-cache", not(target_arch = "wasm32")))]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct StoredEmbedding {
+    /// Hex SHA-256 digest of the model and text
+    key: String,
+    embedding: Vec<f32>,
+}
+
+/// SHA-256 of a model and text
+#[cfg(feature = "embedding-cache")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct EmbeddingKey([u8; 32]);
+
+#[cfg(feature = "embedding-cache")]
+impl EmbeddingKey {
+    fn new(model: &str, text: &str) -> Self {
+        use sha2::{Digest, Sha256};
+
+        // The length prefix keeps ("a", "bc") apart from ("ab", "c")
+        let digest = Sha256::new()
+            .chain_update((model.len() as u64).to_le_bytes())
+            .chain_update(model)
+            .chain_update(text)
+            .finalize();
+        Self(digest.into())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn to_hex(self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut key = [0; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Self(key))
+    }
+}
+
+#[cfg(feature = "embedding-cache")]
+#[derive(Debug, Default)]
+struct EmbeddingState {
+    vectors: HashMap<EmbeddingKey, Vec<f32>>,
+    hits: u64,
+    misses: u64,
+    #[cfg(not(target_arch = "wasm32"))]
+    file: Option<PathBuf>,
+}
+
+/// Embedding vectors by model and text, shared by all clones
+///
+/// Install it with [`crate::LlamaCppClient::with_embedding_cache`] and
+/// [`crate::LlamaCppClient::embedding`] answers texts it has embedded before
+/// without contacting the server, reporting zero tokens used. Texts are keyed
+/// by a SHA-256 digest of their content, so the cache holds no document text
+/// and two texts never share a vector.
+#[cfg(feature = "embedding-cache")]
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddingCache {
+    state: Arc<Mutex<EmbeddingState>>,
+}
+
+#[cfg(feature = "embedding-cache")]
+impl EmbeddingCache {
+    /// An empty cache kept in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache backed by the JSON Lines file at `path`
+    ///
+    /// Vectors already in the file are loaded, and new ones are appended as
+    /// they are computed. Failing to append does not fail the request.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        use anyhow::Context;
+
+        let path = path.into();
+        let mut vectors = HashMap::new();
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            for (number, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let stored: StoredEmbedding = serde_json::from_str(line).with_context(|| {
+                    format!(
+                        "Invalid embedding on line {} of {}",
+                        number + 1,
+                        path.display()
+                    )
+                })?;
+                let key = EmbeddingKey::from_hex(&stored.key)
+                    .with_context(|| format!("Invalid key on line {}", number + 1))?;
+                vectors.insert(key, stored.embedding);
+            }
+        }
+
+        Ok(Self {
+            state: Arc::new(Mutex::new(EmbeddingState {
+                vectors,
+                file: Some(path),
+                ..EmbeddingState::default()
+            })),
+        })
+    }
+
+    /// The vector of `text` under `model`, if it has been stored
+    pub fn get(&self, model: &str, text: &str) -> Option<Vec<f32>> {
+        let mut state = self.lock();
+        let vector = state.vectors.get(&EmbeddingKey::new(model, text)).cloned();
+        match vector {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        vector
+    }
+
+    pub fn insert(&self, model: &str, text: &str, embedding: Vec<f32>) {
+        let key = EmbeddingKey::new(model, text);
+        let mut state = self.lock();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = &state.file
+            && state.vectors.get(&key) != Some(&embedding)
+        {
+            let _ = append_line(
+                path,
+                &StoredEmbedding {
+                    key: key.to_hex(),
+                    embedding: embedding.clone(),
+                },
+            );
+        }
+
+        state.vectors.insert(key, embedding);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.lock();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.vectors.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, EmbeddingState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(all(feature = "embedding-cache", not(target_arch = "wasm32")))]
+fn append_line(path: &std::path::Path, value: &impl Serialize) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+    Ok(())
+}
+
+// ============================================================================
+// Disk Storage
+// ============================================================================
+
+#[cfg(not(target_arch = "wasm32"))]
+mod disk {
+    use anyhow::Result;
+    use serde::{Deserialize, Serialize};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{CacheKey, Entry};
+    use crate::compat::Instant;
+
+    /// One cached response on disk
+    #[derive(Debug, Serialize, Deserialize)]
+    struct CacheFile {
+        request: String,
+        /// Seconds since the Unix epoch
+        stored_at: u64,
+        response: serde_json::Value,
+    }
+
+    fn path(dir: &Path, key: &CacheKey) -> PathBuf {
+        dir.join(format!("{:016x}.json", key.hash))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    }
+
+    /// The entry stored for `key`, deleting its file if it has expired
+    pub(super) fn load(dir: &Path, key: &CacheKey, ttl: Option<Duration>) -> Option<Entry> {
+        let path = path(dir, key);
+        let file: CacheFile = serde_json::from_slice(&std::fs::read(&path).ok()?).ok()?;
+        if file.request != key.request {
+            return None;
+        }
+
+        let age = Duration::from_secs(now().saturating_sub(file.stored_at));
+        if ttl.is_some_and(|ttl| age >= ttl) {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+
+        Some(Entry {
+            request: file.request,
+            response: serde_json::to_vec(&file.response).ok()?,
+            loaded: Instant::now(),
+            age,
+            last_used: 0,
+        })
+    }
+
+    /// Write the response for `key`, replacing the file in one step so
+    /// readers never see half of it
+    pub(super) fn store(dir: &Path, key: &CacheKey, response: &[u8]) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let file = CacheFile {
+            request: key.request.clone(),
+            stored_at: now(),
+            response: serde_json::from_slice(response)?,
+        };
+
+        let path = path(dir, key);
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&partial, serde_json::to_vec(&file)?)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Client Integration
+// ============================================================================
+
+/// Read a JSON response, storing its body under `key` if it parses
+pub(crate) async fn read_json<T: DeserializeOwned>(
+    response: HttpResponse,
+    cache: Option<(ResponseCache, CacheKey)>,
+) -> Result<T> {
+    let Some((cache, key)) = cache else {
+        return response.json().await;
+    };
+
+    let body = response.bytes().await?;
+    let parsed = serde_json::from_slice(&body)?;
+    cache.put(key, body);
+    Ok(parsed)
+}
@@ -0,0 +1,234 @@
+//! Splitting text into pieces small enough to embed.
+//!
+//! Every splitter returns slices of the input in order, trimmed of
+//! surrounding whitespace, with neighbouring chunks sharing up to `overlap`
+//! characters or tokens so that a passage cut in two can still be found from
+//! either half. [`by_characters`] cuts at a fixed length; [`by_sentences`]
+//! and the token-based splitters pack whole sentences and only break a
+//! sentence apart, at word boundaries, when it does not fit on its own.
+
+use anyhow::Result;
+use std::ops::Range;
+
+use crate::LlamaCppClient;
+use crate::history::{TokenCounter, token_estimate};
+
+// ============================================================================
+// Characters
+// ============================================================================
+
+/// Split `text` into chunks of at most `size` characters, with `overlap`
+/// characters shared between neighbouring chunks. Chunks end on whitespace
+/// where possible.
+pub fn by_characters(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let size = size.max(1);
+    let overlap = overlap.min(size - 1);
+    let chars: Vec<char> = text.chars().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = (start + size).min(chars.len());
+        if end < chars.len()
+            && let Some(pos) = chars[start..end].iter().rposition(|c| c.is_whitespace())
+            && pos > overlap
+        {
+            end = start + pos;
+        }
+
+        let chunk: String = chars[start..end].iter().collect();
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk.trim().to_string());
+        }
+        if end == chars.len() {
+            break;
+        }
+        start = end - overlap;
+    }
+
+    chunks
+}
+
+// ============================================================================
+// Sentences
+// ============================================================================
+
+/// The sentences of `text`
+///
+/// A sentence ends at `.`, `!` or `?` (and any closing quotes or brackets)
+/// followed by whitespace, or at a blank line. This is a heuristic: it will
+/// also split after abbreviations such as "e.g.".
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    sentence_ranges(text)
+        .into_iter()
+        .map(|range| &text[range])
+        .collect()
+}
+
+/// Split `text` into chunks of whole sentences of at most `max_chars`
+/// characters, repeating up to `overlap` characters of trailing sentences at
+/// the start of the next chunk
+pub fn by_sentences(text: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+    let count = |unit: &str| unit.chars().count() as u32;
+    let units = units(text, max_chars as u32, count);
+    let counts: Vec<u32> = units
+        .iter()
+        .map(|unit| count(&text[unit.clone()]))
+        .collect();
+    pack(text, &units, &counts, max_chars as u32, overlap as u32)
+}
+
+// ============================================================================
+// Tokens
+// ============================================================================
+
+/// Split `text` into chunks of whole sentences of at most `max_tokens`
+/// tokens by estimate, repeating up to `overlap` tokens at the start of the
+/// next chunk
+///
+/// Counts with [`token_estimate`], like [`TokenCounter::Estimate`], so it
+/// needs no server.
+pub fn by_estimated_tokens(text: &str, max_tokens: u32, overlap: u32) -> Vec<String> {
+    let units = units(text, max_tokens, token_estimate);
+    let counts: Vec<u32> = units
+        .iter()
+        .map(|unit| token_estimate(&text[unit.clone()]))
+        .collect();
+    pack(text, &units, &counts, max_tokens, overlap)
+}
+
+/// Like [`by_estimated_tokens`], counting tokens with `counter`
+///
+/// With [`TokenCounter::Server`] the sizes are exact for the server's model,
+/// at the cost of one `/tokenize` request per sentence, and per word of
+/// sentences that are too long by themselves.
+pub async fn by_tokens(
+    client: &LlamaCppClient,
+    counter: TokenCounter,
+    text: &str,
+    max_tokens: u32,
+    overlap: u32,
+) -> Result<Vec<String>> {
+    let mut units = Vec::new();
+    let mut counts = Vec::new();
+    for sentence in sentence_ranges(text) {
+        let tokens = counter.count_text(client, &text[sentence.clone()]).await?;
+        if tokens <= max_tokens {
+            units.push(sentence);
+            counts.push(tokens);
+            continue;
+        }
+        for word in word_ranges(text, sentence) {
+            counts.push(counter.count_text(client, &text[word.clone()]).await?);
+            units.push(word);
+        }
+    }
+    Ok(pack(text, &units, &counts, max_tokens, overlap))
+}
+
+// ============================================================================
+// Packing
+// ============================================================================
+
+/// Sentences of `text`, with those over `max` split into words
+fn units(text: &str, max: u32, count: impl Fn(&str) -> u32) -> Vec<Range<usize>> {
+    sentence_ranges(text)
+        .into_iter()
+        .flat_map(|sentence| {
+            if count(&text[sentence.clone()]) <= max {
+                vec![sentence]
+            } else {
+                word_ranges(text, sentence)
+            }
+        })
+        .collect()
+}
+
+/// Group consecutive `units` into chunks whose `counts` add up to at most
+/// `max`; a unit larger than `max` becomes a chunk of its own
+fn pack(text: &str, units: &[Range<usize>], counts: &[u32], max: u32, overlap: u32) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < units.len() {
+        let mut end = start;
+        let mut total = 0;
+        while end < units.len() && (end == start || total + counts[end] <= max) {
+            total += counts[end];
+            end += 1;
+        }
+        chunks.push(text[units[start].start..units[end - 1].end].to_string());
+        if end == units.len() {
+            break;
+        }
+
+        // Carry trailing units over, always moving forward by at least one
+        let mut next = end;
+        let mut carried = 0;
+        while next > start + 1 && carried + counts[next - 1] <= overlap {
+            carried += counts[next - 1];
+            next -= 1;
+        }
+        start = next;
+    }
+
+    chunks
+}
+
+fn sentence_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            '.' | '!' | '?' => {
+                let mut end = i + c.len_utf8();
+                while let Some(&(j, next)) = chars.peek() {
+                    if !matches!(next, '"' | '\'' | ')' | ']' | '”' | '’') {
+                        break;
+                    }
+                    end = j + next.len_utf8();
+                    chars.next();
+                }
+                match chars.peek() {
+                    Some((_, next)) if next.is_whitespace() => end,
+                    None => end,
+                    _ => continue,
+                }
+            }
+            '\n' if text[i + 1..]
+                .trim_start_matches([' ', '\t', '\r'])
+                .starts_with('\n') =>
+            {
+                i
+            }
+            _ => continue,
+        };
+        push_trimmed(text, start..end, &mut ranges);
+        start = end;
+    }
+    push_trimmed(text, start..text.len(), &mut ranges);
+
+    ranges
+}
+
+fn word_ranges(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let base = range.start;
+    text[range]
+        .split_whitespace()
+        .map(|word| {
+            let start = base + (word.as_ptr() as usize - text[base..].as_ptr() as usize);
+            start..start + word.len()
+        })
+        .collect()
+}
+
+fn push_trimmed(text: &str, range: Range<usize>, ranges: &mut Vec<Range<usize>>) {
+    let slice = &text[range.clone()];
+    let trimmed = slice.trim();
+    if !trimmed.is_empty() {
+        let start = range.start + (slice.len() - slice.trim_start().len());
+        ranges.push(start..start + trimmed.len());
+    }
+}
@@ -0,0 +1,140 @@
+//! Differences between native targets and `wasm32`, where futures from the
+//! browser's fetch API are not `Send`.
+
+use futures::future::{Future, FutureExt};
+use futures::stream::{Stream, StreamExt};
+
+/// A boxed stream, `Send` everywhere except on `wasm32`
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxStream<'a, T> = futures::stream::BoxStream<'a, T>;
+
+/// A boxed stream, `Send` everywhere except on `wasm32`
+#[cfg(target_arch = "wasm32")]
+pub type BoxStream<'a, T> = futures::stream::LocalBoxStream<'a, T>;
+
+/// A boxed future, `Send` everywhere except on `wasm32`
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxFuture<'a, T> = futures::future::BoxFuture<'a, T>;
+
+/// A boxed future, `Send` everywhere except on `wasm32`
+#[cfg(target_arch = "wasm32")]
+pub type BoxFuture<'a, T> = futures::future::LocalBoxFuture<'a, T>;
+
+/// `Send` on native targets and implemented by every type on `wasm32`
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+
+/// `Send` on native targets and implemented by every type on `wasm32`
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+/// `Sync` on native targets and implemented by every type on `wasm32`
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSync: Sync {}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Sync> MaybeSync for T {}
+
+/// `Sync` on native targets and implemented by every type on `wasm32`
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSync {}
+
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSync for T {}
+
+/// Box a stream as a [`BoxStream`]
+pub(crate) fn boxed<'a, S>(stream: S) -> BoxStream<'a, S::Item>
+where
+    S: Stream + MaybeSend + 'a,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    return stream.boxed();
+
+    #[cfg(target_arch = "wasm32")]
+    return stream.boxed_local();
+}
+
+/// Box a future as a [`BoxFuture`]
+pub(crate) fn boxed_future<'a, F>(future: F) -> BoxFuture<'a, F::Output>
+where
+    F: Future + MaybeSend + 'a,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    return future.boxed();
+
+    #[cfg(target_arch = "wasm32")]
+    return future.boxed_local();
+}
+
+/// A point in time for measuring durations; `std::time::Instant` panics on
+/// `wasm32-unknown-unknown`, so the browser clock is used there
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Instant {
+    #[cfg(not(target_arch = "wasm32"))]
+    inner: std::time::Instant,
+    #[cfg(target_arch = "wasm32")]
+    millis: f64,
+}
+
+impl Instant {
+    pub(crate) fn now() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            inner: std::time::Instant::now(),
+            #[cfg(target_arch = "wasm32")]
+            millis: js_sys::Date::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> std::time::Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.inner.elapsed();
+
+        #[cfg(target_arch = "wasm32")]
+        return std::time::Duration::from_secs_f64(
+            (js_sys::Date::now() - self.millis).max(0.0) / 1000.0,
+        );
+    }
+}
+
+/// Time since the Unix epoch, from the browser clock on `wasm32`
+pub(crate) fn unix_time() -> std::time::Duration {
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    #[cfg(target_arch = "wasm32")]
+    return std::time::Duration::from_secs_f64(js_sys::Date::now().max(0.0) / 1000.0);
+}
+
+/// Whole seconds since the Unix epoch
+pub(crate) fn unix_seconds() -> u64 {
+    unix_time().as_secs()
+}
+
+/// 64 random bits for identifiers; not suitable for cryptography
+pub(crate) fn random_u64() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::hash::BuildHasher;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // Every RandomState has fresh keys seeded from the OS
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        std::collections::hash_map::RandomState::new()
+            .hash_one(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let half = || (js_sys::Math::random() * 4294967296.0) as u64;
+        (half() << 32) | half()
+    }
+}
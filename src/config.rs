@@ -0,0 +1,619 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+use std::path::PathBuf;
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+use std::time::{Duration, SystemTime};
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+use crate::LlamaCppClient;
+use crate::limits::RateLimit;
+use crate::pool::LoadBalancing;
+use crate::presets::Presets;
+use crate::transport::HttpRequest;
+use crate::{ChatCompletionRequest, CompletionRequest, ResponseFormat, Tool, ToolChoice};
+
+// ============================================================================
+// Configuration
+// ============================================================================
+
+fn default_base_url() -> String {
+    "http://localhost:8080".to_string()
+}
+
+fn default_failover_cooldown_secs() -> u64 {
+    30
+}
+
+/// The Azure OpenAI API version used unless another is configured
+pub const AZURE_API_VERSION: &str = "2024-10-21";
+
+/// Azure paths that belong to a deployment, after `/v1/`
+const AZURE_DEPLOYMENT_PATHS: &[&str] =
+    &["chat/completions", "completions", "embeddings", "audio/"];
+
+/// The kind of server behind `base_url`, for the places where servers
+/// disagree on the request format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dialect {
+    /// llama.cpp's server and other OpenAI-compatible servers that accept
+    /// its extensions
+    #[default]
+    LlamaCpp,
+    /// The OpenAI API: `max_tokens` is sent as `max_completion_tokens`, and
+    /// `response_format` schemas use the `json_schema` form
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// Azure OpenAI: the OpenAI format, sent to deployment URLs with an
+    /// `api-version` query and an `api-key` header
+    Azure,
+    /// vLLM's OpenAI-compatible server: `max_tokens` as is, and
+    /// `response_format` schemas in the `json_schema` form
+    Vllm,
+}
+
+/// How the API key is sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <key>`
+    Bearer,
+    /// The key as the whole value of a header, such as `api-key` (Azure) or
+    /// `x-api-key`
+    Header(String),
+    /// The key as a query parameter, such as `?key=<key>`
+    Query(String),
+    /// HTTP Basic auth, with the key as the password
+    Basic { username: String },
+}
+
+/// Request fields only llama.cpp understands, left out for other dialects
+const LLAMA_CPP_PARAMS: &[&str] = &[
+    "cache_prompt",
+    "id_slot",
+    "n_probs",
+    "repeat_penalty",
+    "timings_per_token",
+    "speculative.n_max",
+    "speculative.n_min",
+    "speculative.p_min",
+];
+
+impl Dialect {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the token limit is called `max_completion_tokens` rather
+    /// than `max_tokens`
+    fn renames_max_tokens(&self) -> bool {
+        matches!(self, Self::OpenAi | Self::Azure)
+    }
+
+    /// Adjust a serialized request's fields to what this dialect expects
+    fn adapt(&self, body: &mut Map<String, Value>) {
+        if *self == Self::LlamaCpp {
+            return;
+        }
+        for param in LLAMA_CPP_PARAMS {
+            body.remove(*param);
+        }
+        if self.renames_max_tokens()
+            && let Some(max_tokens) = body.remove("max_tokens")
+        {
+            body.insert("max_completion_tokens".to_string(), max_tokens);
+        }
+    }
+}
+
+/// Client settings that can be changed while the client is in use
+///
+/// Requests take a snapshot of the configuration when they start, so
+/// reloading never affects requests that are already in flight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LancorConfig {
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    /// Servers to try, in order, when `base_url` fails with a connection
+    /// error or a 5xx status
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_urls: Vec<String>,
+    /// Spread requests over `base_url` and the fallbacks instead of always
+    /// starting with `base_url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_balancing: Option<LoadBalancing>,
+    /// How long a failed server is skipped before it is tried again
+    #[serde(default = "default_failover_cooldown_secs")]
+    pub failover_cooldown_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// How `api_key` is sent; a bearer token, or the `api-key` header for
+    /// Azure, if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthScheme>,
+    #[serde(default, skip_serializing_if = "Dialect::is_default")]
+    pub dialect: Dialect,
+    /// Sent as `OpenAI-Organization`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+    /// Sent as `OpenAI-Project`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Headers sent with every request, e.g. a gateway's attribution headers
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// The Azure deployment requests go to; the request's model if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    /// The Azure `api-version`; [`AZURE_API_VERSION`] if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    /// Where the OpenAI-compatible API is mounted, such as `/api/v1`, or
+    /// `""` for the root; `/v1` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_prefix: Option<String>,
+    /// The model used by requests that leave theirs empty or set it to
+    /// [`crate::AUTO_MODEL`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
+    #[serde(default)]
+    pub presets: Presets,
+    /// Give chat requests without `max_tokens` whatever the context window
+    /// has left after the prompt, less this many tokens; llama.cpp only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_max_tokens: Option<u32>,
+    /// Limits on requests per second and tokens per minute, shared by all
+    /// clones of the client; none if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for LancorConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+            fallback_urls: Vec::new(),
+            load_balancing: None,
+            failover_cooldown_secs: default_failover_cooldown_secs(),
+            api_key: None,
+            auth: None,
+            dialect: Dialect::default(),
+            organization: None,
+            project: None,
+            headers: BTreeMap::new(),
+            deployment: None,
+            api_version: None,
+            api_prefix: None,
+            default_model: None,
+            presets: Presets::default(),
+            auto_max_tokens: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl LancorConfig {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            ..Self::default()
+        }
+    }
+
+    /// A configuration for the OpenAI API at `https://api.openai.com`
+    pub fn openai(api_key: impl Into<String>) -> Self {
+        Self::new("https://api.openai.com")
+            .api_key(api_key)
+            .dialect(Dialect::OpenAi)
+    }
+
+    /// A configuration for OpenRouter at `https://openrouter.ai/api`
+    ///
+    /// Use [`Self::referer`] and [`Self::app_title`] to attribute requests to
+    /// your app.
+    pub fn openrouter(api_key: impl Into<String>) -> Self {
+        Self::new("https://openrouter.ai/api")
+            .api_key(api_key)
+            .dialect(Dialect::OpenAi)
+    }
+
+    /// A configuration for an Azure OpenAI resource, e.g.
+    /// `https://my-resource.openai.azure.com`
+    ///
+    /// Requests go to the deployment named by their model unless
+    /// [`Self::deployment`] is set.
+    pub fn azure(resource_url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self::new(resource_url)
+            .api_key(api_key)
+            .dialect(Dialect::Azure)
+    }
+
+    /// Add a server to fail over to, after `base_url` and any earlier
+    /// fallbacks
+    pub fn fallback_url(mut self, url: impl Into<String>) -> Self {
+        self.fallback_urls.push(url.into());
+        self
+    }
+
+    pub fn load_balancing(mut self, strategy: LoadBalancing) -> Self {
+        self.load_balancing = Some(strategy);
+        self
+    }
+
+    pub fn failover_cooldown(mut self, cooldown: std::time::Duration) -> Self {
+        self.failover_cooldown_secs = cooldown.as_secs();
+        self
+    }
+
+    /// `base_url` followed by the fallbacks, in the order they are tried
+    ///
+    /// Trailing slashes are dropped, and so is the API prefix if a URL ends
+    /// with it, as OpenAI SDKs expect (`http://host:8080/v1`).
+    pub fn endpoints(&self) -> impl Iterator<Item = &str> {
+        let prefix = self.mount_path();
+        std::iter::once(self.base_url.as_str())
+            .chain(self.fallback_urls.iter().map(String::as_str))
+            .map(move |url| {
+                let url = url.trim_end_matches('/');
+                match url.strip_suffix(prefix.as_str()) {
+                    Some(root) if !prefix.is_empty() => root,
+                    _ => url,
+                }
+            })
+    }
+
+    /// Mount the OpenAI-compatible API at `prefix` rather than `/v1`
+    ///
+    /// llama.cpp's own endpoints, such as `/health` and `/tokenize`, stay
+    /// at the root.
+    pub fn api_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.api_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send the API key with `scheme` rather than the dialect's usual one
+    pub fn auth(mut self, scheme: AuthScheme) -> Self {
+        self.auth = Some(scheme);
+        self
+    }
+
+    /// The scheme the API key is sent with
+    fn auth_scheme(&self) -> AuthScheme {
+        match (&self.auth, self.dialect) {
+            (Some(scheme), _) => scheme.clone(),
+            (None, Dialect::Azure) => AuthScheme::Header("api-key".to_string()),
+            (None, _) => AuthScheme::Bearer,
+        }
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    pub fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Send `name: value` with every request, replacing an earlier value
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Send `HTTP-Referer`, which OpenRouter uses to identify your app
+    pub fn referer(self, url: impl Into<String>) -> Self {
+        self.header("HTTP-Referer", url)
+    }
+
+    /// Send `X-Title`, the app name OpenRouter shows in its rankings
+    pub fn app_title(self, title: impl Into<String>) -> Self {
+        self.header("X-Title", title)
+    }
+
+    pub fn deployment(mut self, deployment: impl Into<String>) -> Self {
+        self.deployment = Some(deployment.into());
+        self
+    }
+
+    pub fn api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = Some(api_version.into());
+        self
+    }
+
+    pub fn default_model(mut self, model: impl Into<String>) -> Self {
+        self.default_model = Some(model.into());
+        self
+    }
+
+    /// Derive `max_tokens` for chat requests that leave it unset, keeping
+    /// `margin` tokens of the context window spare
+    ///
+    /// The window comes from `/props` and is looked up once; the prompt is
+    /// counted with `/tokenize`, or estimated if that fails. A margin of a
+    /// few dozen tokens covers what the chat template and tools add. Other
+    /// dialects than [`Dialect::LlamaCpp`] ignore this.
+    pub fn auto_max_tokens(mut self, margin: u32) -> Self {
+        self.auto_max_tokens = Some(margin);
+        self
+    }
+
+    /// Hold requests back to stay within `limit`; see [`RateLimit`]
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    pub fn presets(mut self, presets: Presets) -> Self {
+        self.presets = presets;
+        self
+    }
+
+    /// Replace an empty or `"auto"` model with the default model, if there
+    /// is one
+    pub(crate) fn fill_model(&self, model: &mut String) {
+        if (model.is_empty() || model == crate::AUTO_MODEL)
+            && let Some(default) = &self.default_model
+        {
+            model.clone_from(default);
+        }
+    }
+
+    /// Add the authorization, account and static headers to `request`
+    pub(crate) fn authorize(&self, mut request: HttpRequest) -> HttpRequest {
+        if let Some(api_key) = &self.api_key {
+            request = match self.auth_scheme() {
+                AuthScheme::Bearer => {
+                    request.header("Authorization", format!("Bearer {}", api_key))
+                }
+                AuthScheme::Header(name) => request.header(name, api_key.clone()),
+                AuthScheme::Basic { username } => {
+                    let credentials = BASE64.encode(format!("{}:{}", username, api_key));
+                    request.header("Authorization", format!("Basic {}", credentials))
+                }
+                // Added to the URL by `Self::with_auth_query`
+                AuthScheme::Query(_) => request,
+            };
+        }
+        if let Some(organization) = &self.organization {
+            request = request.header("OpenAI-Organization", organization.clone());
+        }
+        if let Some(project) = &self.project {
+            request = request.header("OpenAI-Project", project.clone());
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name.clone(), value.clone());
+        }
+        request
+    }
+
+    /// `url`, with the API key added to its query if it is sent there
+    pub(crate) fn with_auth_query(&self, url: String) -> String {
+        match (&self.api_key, self.auth_scheme()) {
+            (Some(api_key), AuthScheme::Query(name)) => {
+                let separator = if url.contains('?') { '&' } else { '?' };
+                format!(
+                    "{}{}{}={}",
+                    url,
+                    separator,
+                    percent_encode(&name),
+                    percent_encode(api_key)
+                )
+            }
+            _ => url,
+        }
+    }
+
+    /// The JSON body of a chat request in this configuration's dialect
+    ///
+    /// Requests use llama.cpp's field names; other dialects get the names
+    /// they expect and lose the fields they would reject.
+    pub(crate) fn chat_body(&self, request: &ChatCompletionRequest) -> Result<Value> {
+        let mut body = serde_json::to_value(request)?;
+        // llama.cpp takes `tool_choice` only as a string
+        if self.dialect == Dialect::LlamaCpp
+            && let Some(ToolChoice::Function(name)) = &request.tool_choice
+            && let Some(body) = body.as_object_mut()
+        {
+            let tools = request.tools.iter().flatten();
+            let named: Vec<&Tool> = tools.filter(|tool| tool.function.name == *name).collect();
+            if named.is_empty() {
+                anyhow::bail!(
+                    "Tool choice names function {}, which is not among the tools",
+                    name
+                );
+            }
+            body.insert("tools".to_string(), serde_json::to_value(named)?);
+            body.insert("tool_choice".to_string(), "required".into());
+        }
+        if self.dialect != Dialect::LlamaCpp
+            && let Some(body) = body.as_object_mut()
+        {
+            self.dialect.adapt(body);
+            if let Some(ResponseFormat::JsonObject {
+                schema: Some(schema),
+            }) = &request.response_format
+            {
+                let format = ResponseFormat::json_schema("response", schema.clone());
+                body.insert("response_format".to_string(), serde_json::to_value(format)?);
+            }
+            // Extra fields are sent as given, even llama.cpp-only ones
+            body.extend(request.extra_body.clone());
+        }
+        Ok(body)
+    }
+
+    /// The JSON body of a text completion request in this configuration's
+    /// dialect; see [`Self::chat_body`]
+    pub(crate) fn completion_body(&self, request: &CompletionRequest) -> Result<Value> {
+        let mut body = serde_json::to_value(request)?;
+        if let Some(body) = body.as_object_mut() {
+            self.dialect.adapt(body);
+            body.extend(request.extra_body.clone());
+        }
+        Ok(body)
+    }
+
+    /// The API prefix with one leading slash and none trailing
+    fn mount_path(&self) -> String {
+        match self
+            .api_prefix
+            .as_deref()
+            .map(|prefix| prefix.trim_matches('/'))
+        {
+            None => "/v1".to_string(),
+            Some("") => String::new(),
+            Some(prefix) => format!("/{}", prefix),
+        }
+    }
+
+    /// The path to send `request` to in place of `path`
+    ///
+    /// Paths under `/v1` move to the configured API prefix. Azure puts the
+    /// deployment in the path and the API version in the query, so
+    /// `/v1/chat/completions` becomes
+    /// `/openai/deployments/{deployment}/chat/completions?api-version=...`.
+    pub(crate) fn route(&self, path: &str, request: &HttpRequest) -> Result<String> {
+        if self.dialect != Dialect::Azure {
+            return Ok(match path.strip_prefix("/v1/") {
+                Some(rest) => format!("{}/{}", self.mount_path(), rest),
+                None => path.to_string(),
+            });
+        }
+        let Some(rest) = path.strip_prefix("/v1/") else {
+            return Ok(path.to_string());
+        };
+
+        let api_version = self.api_version.as_deref().unwrap_or(AZURE_API_VERSION);
+        if !AZURE_DEPLOYMENT_PATHS
+            .iter()
+            .any(|prefix| rest.starts_with(prefix))
+        {
+            return Ok(format!("/openai/{}?api-version={}", rest, api_version));
+        }
+
+        let deployment = match &self.deployment {
+            Some(deployment) => deployment.clone(),
+            None => request
+                .json::<Value>()
+                .ok()
+                .and_then(|body| body.get("model")?.as_str().map(str::to_string))
+                .filter(|model| !model.is_empty())
+                .context("Azure requests need a deployment or a model")?,
+        };
+        Ok(format!(
+            "/openai/deployments/{}/{}?api-version={}",
+            deployment, rest, api_version
+        ))
+    }
+
+    /// Load a configuration from a JSON file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))
+    }
+}
+
+/// Percent-encode everything but unreserved characters, for a query string
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// ============================================================================
+// File Watching
+// ============================================================================
+
+/// A background task that reloads a client's configuration when its file
+/// changes. The task stops when the watcher is dropped.
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl ConfigWatcher {
+    /// Poll `path` every `interval` and reload `client` whenever the file's
+    /// modification time changes
+    ///
+    /// The file is loaded once up front so that a broken configuration is
+    /// reported immediately. Later load errors keep the previous
+    /// configuration and, with the `tracing` feature, are logged as
+    /// warnings.
+    pub fn spawn(
+        client: LlamaCppClient,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> Result<Self> {
+        let path = path.into();
+        client.reload_config(LancorConfig::from_file(&path)?);
+        let mut last_modified = modified(&path);
+
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+
+                match LancorConfig::from_file(&path) {
+                    Ok(config) => client.reload_config(config),
+                    #[cfg(feature = "tracing")]
+                    Err(err) => {
+                        tracing::warn!(
+                            path = %path.display(),
+                            error = format!("{:#}", err),
+                            "keeping previous config"
+                        )
+                    }
+                    #[cfg(not(feature = "tracing"))]
+                    Err(_) => {}
+                }
+            }
+        });
+
+        Ok(Self { task })
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
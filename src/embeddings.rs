@@ -0,0 +1,14 @@
+//! Working with embedding vectors.
+
+mod index;
+pub mod math;
+mod storage;
+
+pub use index::{VectorEntry, VectorIndex, VectorMatch};
+pub use storage::VectorStorage;
+
+impl AsRef<[f32]> for crate::EmbeddingData {
+    fn as_ref(&self) -> &[f32] {
+        &self.embedding
+    }
+}
@@ -0,0 +1,298 @@
+//! A small in-memory vector index.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::math;
+use super::storage::{VectorStorage, Vectors};
+use crate::{EmbeddingRequest, LlamaCppClient};
+
+/// A stored vector with its id and metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorEntry {
+    pub id: String,
+    pub embedding: Vec<f32>,
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+/// A search result
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorMatch {
+    pub id: String,
+    /// Cosine similarity to the query
+    pub score: f32,
+    pub metadata: Value,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexFile {
+    #[serde(default)]
+    storage: VectorStorage,
+    entries: Vec<VectorEntry>,
+}
+
+/// An entry's id and metadata; its vector is the matching row of
+/// [`Vectors`]
+#[derive(Debug, Clone)]
+struct Record {
+    id: String,
+    metadata: Value,
+}
+
+/// Vectors searched by cosine similarity, for when a vector database would
+/// be overkill
+///
+/// Search is exhaustive, which is fast enough for tens of thousands of
+/// vectors. All vectors must have the same dimension. For large indexes,
+/// [`VectorIndex::with_storage`] keeps them as `f16` or `i8` to save memory.
+///
+/// ```no_run
+/// use lancor::{LlamaCppClient, VectorIndex};
+/// use serde_json::json;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let client = LlamaCppClient::default()?;
+/// let mut index = VectorIndex::new();
+/// index
+///     .insert_text(&client, "embed", "faq-1", "Keys rotate every 90 days", json!({"page": 3}))
+///     .await?;
+///
+/// for hit in index.query_text(&client, "embed", "When do keys expire?", 5).await? {
+///     println!("{} ({:.2}) {}", hit.id, hit.score, hit.metadata);
+/// }
+/// index.save("index.json")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VectorIndex {
+    records: Vec<Record>,
+    vectors: Vectors,
+    dimension: usize,
+    positions: HashMap<String, usize>,
+}
+
+impl VectorIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An index of `entries`; later entries replace earlier ones with the
+    /// same id
+    pub fn from_entries(entries: impl IntoIterator<Item = VectorEntry>) -> Result<Self> {
+        Self::new().with_entries(entries)
+    }
+
+    /// Store vectors as `storage`, converting any already in the index
+    ///
+    /// `F16` halves the memory taken by vectors and `I8` divides it by
+    /// nearly four, at the cost of a little precision in scores.
+    pub fn with_storage(mut self, storage: VectorStorage) -> Self {
+        if storage != self.storage() {
+            let mut vectors = Vectors::new(storage);
+            for row in 0..self.len() {
+                vectors.push(&self.vectors.get(row, self.dimension));
+            }
+            self.vectors = vectors;
+        }
+        self
+    }
+
+    fn with_entries(mut self, entries: impl IntoIterator<Item = VectorEntry>) -> Result<Self> {
+        for entry in entries {
+            self.insert(entry.id, entry.embedding, entry.metadata)?;
+        }
+        Ok(self)
+    }
+
+    pub fn storage(&self) -> VectorStorage {
+        self.vectors.storage()
+    }
+
+    /// Bytes taken by the stored vectors, leaving out ids and metadata
+    pub fn vector_bytes(&self) -> usize {
+        self.vectors.bytes()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// The length of the stored vectors, or `None` while the index is empty
+    pub fn dimension(&self) -> Option<usize> {
+        (!self.is_empty()).then_some(self.dimension)
+    }
+
+    /// Every entry, with its vector decoded to `f32`
+    pub fn entries(&self) -> impl Iterator<Item = VectorEntry> + '_ {
+        (0..self.len()).map(|row| self.entry(row))
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.records.iter().map(|record| record.id.as_str())
+    }
+
+    /// The entry stored under `id`, with its vector decoded to `f32`
+    pub fn get(&self, id: &str) -> Option<VectorEntry> {
+        self.positions.get(id).map(|&row| self.entry(row))
+    }
+
+    /// The metadata of the entry stored under `id`, without decoding its
+    /// vector
+    pub fn metadata(&self, id: &str) -> Option<&Value> {
+        self.positions
+            .get(id)
+            .map(|&row| &self.records[row].metadata)
+    }
+
+    /// Add a vector, replacing any entry with the same id
+    pub fn insert(
+        &mut self,
+        id: impl Into<String>,
+        embedding: Vec<f32>,
+        metadata: Value,
+    ) -> Result<()> {
+        let id = id.into();
+        match self.dimension() {
+            Some(dimension) if embedding.len() != dimension => {
+                anyhow::bail!(
+                    "Vector '{}' has {} dimensions, the index has {}",
+                    id,
+                    embedding.len(),
+                    dimension
+                );
+            }
+            Some(_) => {}
+            None => self.dimension = embedding.len(),
+        }
+
+        match self.positions.get(&id) {
+            Some(&row) => {
+                self.vectors.set(row, self.dimension, &embedding);
+                self.records[row].metadata = metadata;
+            }
+            None => {
+                self.positions.insert(id.clone(), self.records.len());
+                self.vectors.push(&embedding);
+                self.records.push(Record { id, metadata });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<VectorEntry> {
+        let row = *self.positions.get(id)?;
+        let entry = self.entry(row);
+        self.positions.remove(id);
+        self.vectors.swap_remove(row, self.dimension);
+        self.records.swap_remove(row);
+        if let Some(moved) = self.records.get(row) {
+            self.positions.insert(moved.id.clone(), row);
+        }
+        Some(entry)
+    }
+
+    /// The `k` entries most similar to `vector`, best first
+    pub fn query(&self, vector: &[f32], k: usize) -> Vec<VectorMatch> {
+        self.query_above(vector, k, f32::NEG_INFINITY)
+    }
+
+    /// Like [`VectorIndex::query`], leaving out entries scoring below
+    /// `min_score`
+    pub fn query_above(&self, vector: &[f32], k: usize, min_score: f32) -> Vec<VectorMatch> {
+        let scores = self.vectors.scores(vector, self.len(), self.dimension);
+        math::best(scores, k)
+            .into_iter()
+            .filter(|(_, score)| *score >= min_score)
+            .map(|(row, score)| {
+                let record = &self.records[row];
+                VectorMatch {
+                    id: record.id.clone(),
+                    score,
+                    metadata: record.metadata.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Embed `text` with `model` and add it under `id`
+    pub async fn insert_text(
+        &mut self,
+        client: &LlamaCppClient,
+        model: &str,
+        id: impl Into<String>,
+        text: &str,
+        metadata: Value,
+    ) -> Result<()> {
+        let id = id.into();
+        let embedding = embed(client, model, text)
+            .await
+            .with_context(|| format!("Failed to embed '{}'", id))?;
+        self.insert(id, embedding, metadata)
+    }
+
+    /// Embed `text` with `model` and return the `k` most similar entries
+    pub async fn query_text(
+        &self,
+        client: &LlamaCppClient,
+        model: &str,
+        text: &str,
+        k: usize,
+    ) -> Result<Vec<VectorMatch>> {
+        let vector = embed(client, model, text).await?;
+        Ok(self.query(&vector, k))
+    }
+
+    /// Write the index to `path` as JSON, noting its storage so
+    /// [`VectorIndex::load`] restores it
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = IndexFile {
+            storage: self.storage(),
+            entries: self.entries().collect(),
+        };
+        std::fs::write(path, serde_json::to_vec(&file)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Read an index written by [`VectorIndex::save`]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: IndexFile = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Invalid vector index {}", path.display()))?;
+        Self::new()
+            .with_storage(file.storage)
+            .with_entries(file.entries)
+    }
+
+    fn entry(&self, row: usize) -> VectorEntry {
+        let record = &self.records[row];
+        VectorEntry {
+            id: record.id.clone(),
+            embedding: self.vectors.get(row, self.dimension),
+            metadata: record.metadata.clone(),
+        }
+    }
+}
+
+async fn embed(client: &LlamaCppClient, model: &str, text: &str) -> Result<Vec<f32>> {
+    let response = client.embedding(EmbeddingRequest::new(model, text)).await?;
+    response
+        .data
+        .into_iter()
+        .next()
+        .map(|data| data.embedding)
+        .context("Embedding response contained no data")
+}
@@ -0,0 +1,191 @@
+//! Vector math on embeddings.
+//!
+//! Functions on single vectors take slices; the search functions take
+//! anything that is `AsRef<[f32]>`, including `Vec<f32>` and
+//! [`crate::EmbeddingData`]. Vectors of different lengths are compared over
+//! their common prefix.
+//!
+//! Sums run over eight lanes at once. With the `simd` feature they use the
+//! `wide` crate's vector types, which compile to AVX with a target CPU that
+//! has it (e.g. `RUSTFLAGS="-C target-cpu=native"`), to SSE2 on other
+//! x86_64 CPUs, to NEON on aarch64 and to plain arithmetic elsewhere; without
+//! it, eight independent accumulators leave the compiler free to vectorize.
+
+// ============================================================================
+// Kernels
+// ============================================================================
+
+/// Sums over equal-length slices
+#[cfg(feature = "simd")]
+mod kernel {
+    use wide::f32x8;
+
+    fn lanes(chunk: &[f32]) -> f32x8 {
+        f32x8::new(chunk.try_into().expect("chunks of eight"))
+    }
+
+    pub(super) fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+        let tail = super::tail_dot(a_chunks.remainder(), b_chunks.remainder());
+        let mut sum = f32x8::ZERO;
+        for (x, y) in a_chunks.zip(b_chunks) {
+            sum = lanes(x).mul_add(lanes(y), sum);
+        }
+        sum.reduce_add() + tail
+    }
+
+    /// `a · b` and `b · b` in one pass
+    pub(super) fn dot_norm(a: &[f32], b: &[f32]) -> (f32, f32) {
+        let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+        let (a_tail, b_tail) = (a_chunks.remainder(), b_chunks.remainder());
+        let (mut ab, mut bb) = (f32x8::ZERO, f32x8::ZERO);
+        for (x, y) in a_chunks.zip(b_chunks) {
+            let y = lanes(y);
+            ab = lanes(x).mul_add(y, ab);
+            bb = y.mul_add(y, bb);
+        }
+        (
+            ab.reduce_add() + super::tail_dot(a_tail, b_tail),
+            bb.reduce_add() + super::tail_dot(b_tail, b_tail),
+        )
+    }
+}
+
+/// Sums over equal-length slices
+#[cfg(not(feature = "simd"))]
+mod kernel {
+    pub(super) fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+        let tail = super::tail_dot(a_chunks.remainder(), b_chunks.remainder());
+        let mut sum = [0.0f32; 8];
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for lane in 0..8 {
+                sum[lane] += x[lane] * y[lane];
+            }
+        }
+        sum.iter().sum::<f32>() + tail
+    }
+
+    /// `a · b` and `b · b` in one pass
+    pub(super) fn dot_norm(a: &[f32], b: &[f32]) -> (f32, f32) {
+        let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+        let (a_tail, b_tail) = (a_chunks.remainder(), b_chunks.remainder());
+        let (mut ab, mut bb) = ([0.0f32; 8], [0.0f32; 8]);
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for lane in 0..8 {
+                ab[lane] += x[lane] * y[lane];
+                bb[lane] += y[lane] * y[lane];
+            }
+        }
+        (
+            ab.iter().sum::<f32>() + super::tail_dot(a_tail, b_tail),
+            bb.iter().sum::<f32>() + super::tail_dot(b_tail, b_tail),
+        )
+    }
+}
+
+fn tail_dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+// ============================================================================
+// Single Vectors
+// ============================================================================
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    kernel::dot(&a[..len], &b[..len])
+}
+
+/// The Euclidean length of `v`
+pub fn l2_norm(v: &[f32]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+/// Scale `v` to unit length in place; all-zero vectors are left alone
+pub fn normalize(v: &mut [f32]) {
+    let norm = l2_norm(v);
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// A unit-length copy of `v`
+pub fn normalized(v: &[f32]) -> Vec<f32> {
+    let mut v = v.to_vec();
+    normalize(&mut v);
+    v
+}
+
+/// Cosine similarity of two vectors, or 0.0 if either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    cosine_with_norm(a, l2_norm(a), b)
+}
+
+/// [`cosine_similarity`] for a `query` whose length is already known, as
+/// when comparing it with many candidates
+pub(crate) fn cosine_with_norm(query: &[f32], query_norm: f32, candidate: &[f32]) -> f32 {
+    if query_norm == 0.0 {
+        return 0.0;
+    }
+    let (dot, squared_norm) = if query.len() == candidate.len() {
+        kernel::dot_norm(query, candidate)
+    } else {
+        (dot(query, candidate), dot(candidate, candidate))
+    };
+    if squared_norm == 0.0 {
+        return 0.0;
+    }
+    dot / (query_norm * squared_norm.sqrt())
+}
+
+// ============================================================================
+// Search
+// ============================================================================
+
+/// The indices and cosine similarities of the `k` candidates most similar
+/// to `query`, best first
+///
+/// Equal scores rank in candidate order.
+pub fn top_k<V: AsRef<[f32]>>(query: &[f32], candidates: &[V], k: usize) -> Vec<(usize, f32)> {
+    let query_norm = l2_norm(query);
+    let scores = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| (i, cosine_with_norm(query, query_norm, candidate.as_ref())))
+        .collect();
+    best(scores, k)
+}
+
+/// The `k` highest of `scores`, best first and ties in index order
+pub(crate) fn best(mut scores: Vec<(usize, f32)>, k: usize) -> Vec<(usize, f32)> {
+    let best_first = |a: &(usize, f32), b: &(usize, f32)| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0));
+    // Only the best `k` need sorting
+    if k < scores.len() {
+        scores.select_nth_unstable_by(k, best_first);
+        scores.truncate(k);
+    }
+    scores.sort_by(best_first);
+    scores
+}
+
+/// [`top_k`] for each of `queries`
+pub fn pairwise_top_k<Q: AsRef<[f32]>, V: AsRef<[f32]>>(
+    queries: &[Q],
+    candidates: &[V],
+    k: usize,
+) -> Vec<Vec<(usize, f32)>> {
+    queries
+        .iter()
+        .map(|query| top_k(query.as_ref(), candidates, k))
+        .collect()
+}
+
+/// The cosine similarity of every pair of `vectors`, as a square matrix
+pub fn similarity_matrix<V: AsRef<[f32]>>(vectors: &[V]) -> Vec<Vec<f32>> {
+    let normalized: Vec<Vec<f32>> = vectors.iter().map(|v| normalized(v.as_ref())).collect();
+    normalized
+        .iter()
+        .map(|a| normalized.iter().map(|b| dot(a, b)).collect())
+        .collect()
+}
@@ -0,0 +1,239 @@
+//! How a [`super::VectorIndex`] keeps its vectors in memory.
+
+use serde::{Deserialize, Serialize};
+
+use super::math;
+
+/// The number format of the vectors in a [`super::VectorIndex`]
+///
+/// Vectors go in and come out as `f32`; the smaller formats are decoded on
+/// the fly while searching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorStorage {
+    /// Four bytes per dimension, exactly as inserted
+    #[default]
+    F32,
+    /// Two bytes per dimension, as IEEE half-precision floats with about
+    /// three significant digits
+    F16,
+    /// One byte per dimension and four per vector, each vector scaled so its
+    /// largest component is ±127
+    I8,
+}
+
+impl VectorStorage {
+    /// Bytes taken by one vector of `dimension` components
+    pub fn bytes_per_vector(self, dimension: usize) -> usize {
+        match self {
+            Self::F32 => 4 * dimension,
+            Self::F16 => 2 * dimension,
+            Self::I8 => dimension + 4,
+        }
+    }
+}
+
+/// Equal-length vectors stored back to back
+#[derive(Debug, Clone)]
+pub(super) enum Vectors {
+    F32(Vec<f32>),
+    F16(Vec<u16>),
+    I8 { values: Vec<i8>, scales: Vec<f32> },
+}
+
+impl Default for Vectors {
+    fn default() -> Self {
+        Self::new(VectorStorage::default())
+    }
+}
+
+impl Vectors {
+    pub(super) fn new(storage: VectorStorage) -> Self {
+        match storage {
+            VectorStorage::F32 => Self::F32(Vec::new()),
+            VectorStorage::F16 => Self::F16(Vec::new()),
+            VectorStorage::I8 => Self::I8 {
+                values: Vec::new(),
+                scales: Vec::new(),
+            },
+        }
+    }
+
+    pub(super) fn storage(&self) -> VectorStorage {
+        match self {
+            Self::F32(_) => VectorStorage::F32,
+            Self::F16(_) => VectorStorage::F16,
+            Self::I8 { .. } => VectorStorage::I8,
+        }
+    }
+
+    pub(super) fn bytes(&self) -> usize {
+        match self {
+            Self::F32(values) => 4 * values.len(),
+            Self::F16(values) => 2 * values.len(),
+            Self::I8 { values, scales } => values.len() + 4 * scales.len(),
+        }
+    }
+
+    pub(super) fn push(&mut self, vector: &[f32]) {
+        match self {
+            Self::F32(values) => values.extend_from_slice(vector),
+            Self::F16(values) => values.extend(vector.iter().map(|&x| f32_to_f16(x))),
+            Self::I8 { values, scales } => {
+                let scale = i8_scale(vector);
+                values.extend(vector.iter().map(|&x| to_i8(x, scale)));
+                scales.push(scale);
+            }
+        }
+    }
+
+    /// Overwrite vector `row`
+    pub(super) fn set(&mut self, row: usize, dimension: usize, vector: &[f32]) {
+        let range = row * dimension..(row + 1) * dimension;
+        match self {
+            Self::F32(values) => values[range].copy_from_slice(vector),
+            Self::F16(values) => {
+                for (slot, &x) in values[range].iter_mut().zip(vector) {
+                    *slot = f32_to_f16(x);
+                }
+            }
+            Self::I8 { values, scales } => {
+                let scale = i8_scale(vector);
+                for (slot, &x) in values[range].iter_mut().zip(vector) {
+                    *slot = to_i8(x, scale);
+                }
+                scales[row] = scale;
+            }
+        }
+    }
+
+    /// Remove vector `row`, moving the last vector into its place
+    pub(super) fn swap_remove(&mut self, row: usize, dimension: usize) {
+        fn swap_remove_row<T: Copy>(values: &mut Vec<T>, row: usize, dimension: usize) {
+            let last = values.len() - dimension;
+            values.copy_within(last.., row * dimension);
+            values.truncate(last);
+        }
+        match self {
+            Self::F32(values) => swap_remove_row(values, row, dimension),
+            Self::F16(values) => swap_remove_row(values, row, dimension),
+            Self::I8 { values, scales } => {
+                swap_remove_row(values, row, dimension);
+                scales.swap_remove(row);
+            }
+        }
+    }
+
+    /// Vector `row` as `f32`
+    pub(super) fn get(&self, row: usize, dimension: usize) -> Vec<f32> {
+        let mut vector = vec![0.0; dimension];
+        self.decode(row, dimension, &mut vector);
+        vector
+    }
+
+    fn decode(&self, row: usize, dimension: usize, out: &mut [f32]) {
+        let range = row * dimension..(row + 1) * dimension;
+        match self {
+            Self::F32(values) => out.copy_from_slice(&values[range]),
+            Self::F16(values) => {
+                for (slot, &x) in out.iter_mut().zip(&values[range]) {
+                    *slot = f16_to_f32(x);
+                }
+            }
+            Self::I8 { values, scales } => {
+                let scale = scales[row];
+                for (slot, &x) in out.iter_mut().zip(&values[range]) {
+                    *slot = x as f32 * scale;
+                }
+            }
+        }
+    }
+
+    /// The cosine similarity of `query` to each of the first `rows` vectors,
+    /// decoding one vector at a time
+    pub(super) fn scores(&self, query: &[f32], rows: usize, dimension: usize) -> Vec<(usize, f32)> {
+        let query_norm = math::l2_norm(query);
+        let mut buffer = vec![0.0; dimension];
+        (0..rows)
+            .map(|row| {
+                let vector = match self {
+                    Self::F32(values) => &values[row * dimension..(row + 1) * dimension],
+                    _ => {
+                        self.decode(row, dimension, &mut buffer);
+                        &buffer
+                    }
+                };
+                (row, math::cosine_with_norm(query, query_norm, vector))
+            })
+            .collect()
+    }
+}
+
+// ============================================================================
+// Number Formats
+// ============================================================================
+
+fn i8_scale(vector: &[f32]) -> f32 {
+    vector.iter().fold(0.0f32, |max, x| max.max(x.abs())) / 127.0
+}
+
+fn to_i8(x: f32, scale: f32) -> i8 {
+    if scale == 0.0 {
+        0
+    } else {
+        (x / scale).round().clamp(-127.0, 127.0) as i8
+    }
+}
+
+/// The nearest half-precision float, ties to even
+fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small for even that
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = 1 << (shift - 1);
+        let rest = mantissa & ((1 << shift) - 1);
+        let mut rounded = mantissa >> shift;
+        if rest > half || (rest == half && rounded & 1 == 1) {
+            rounded += 1;
+        }
+        return sign | rounded as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity
+    let mut half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let rest = mantissa & 0x1fff;
+    if rest > 0x1000 || (rest == 0x1000 && half & 1 == 1) {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 / (1 << 24) as f32;
+            if sign == 0 { magnitude } else { -magnitude }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
@@ -0,0 +1,213 @@
+//! Choosing which server a request goes to.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::compat::Instant;
+use crate::config::LancorConfig;
+use crate::limits::{CircuitBreaker, CircuitState};
+use crate::pool::{EndpointStatus, LoadBalancing};
+
+// ============================================================================
+// Endpoint Selection
+// ============================================================================
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    failed_at: HashMap<String, Instant>,
+    in_flight: HashMap<String, usize>,
+    health: HashMap<String, Health>,
+    circuits: HashMap<String, Circuit>,
+}
+
+/// Consecutive failures of one server, and when its circuit last opened
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl Circuit {
+    fn state(&self, breaker: &CircuitBreaker) -> CircuitState {
+        match self.opened_at {
+            None => CircuitState::Closed,
+            Some(_) if self.probing => CircuitState::HalfOpen,
+            Some(opened) if opened.elapsed() < breaker.open_for => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+/// The result of the last health probe of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Health {
+    pub(crate) ready: bool,
+    /// `None` if the server does not expose `/slots`
+    pub(crate) free_slots: Option<usize>,
+}
+
+/// Failures and in-flight requests per server, shared by all clones of a
+/// client
+#[derive(Debug, Default)]
+pub(crate) struct Endpoints {
+    state: Mutex<EndpointState>,
+    next: AtomicUsize,
+    breaker: Option<CircuitBreaker>,
+}
+
+impl Endpoints {
+    pub(crate) fn with_circuit_breaker(breaker: CircuitBreaker) -> Self {
+        Self {
+            breaker: Some(breaker),
+            ..Self::default()
+        }
+    }
+
+    /// The configured servers in the order to try them
+    ///
+    /// Without load balancing the configured order is kept; otherwise the
+    /// strategy picks the first server and the rest follow as fallbacks.
+    /// Ready servers with free slots come first, then busy ones, and servers
+    /// that failed a probe or are cooling down after a failed request last.
+    pub(crate) fn order(&self, config: &LancorConfig) -> Vec<String> {
+        let mut urls: Vec<&str> = config.endpoints().collect();
+        let state = self.lock();
+
+        if let Some(strategy) = config.load_balancing
+            && !urls.is_empty()
+        {
+            let start = self.next.fetch_add(1, Ordering::Relaxed) % urls.len();
+            urls.rotate_left(start);
+            if strategy == LoadBalancing::LeastInFlight {
+                urls.sort_by_key(|url| state.in_flight.get(*url).copied().unwrap_or(0));
+            }
+        }
+
+        let cooldown = Duration::from_secs(config.failover_cooldown_secs);
+        urls.sort_by_key(|url| {
+            let cooling = state
+                .failed_at
+                .get(*url)
+                .is_some_and(|failed| failed.elapsed() < cooldown);
+            match state.health.get(*url) {
+                _ if cooling => 2,
+                Some(health) if !health.ready => 2,
+                Some(Health {
+                    free_slots: Some(0),
+                    ..
+                }) => 1,
+                _ => 0,
+            }
+        });
+        urls.into_iter().map(str::to_string).collect()
+    }
+
+    /// Whether a request may be sent to `url` under the circuit breaker
+    ///
+    /// Once an open circuit has waited long enough, the first caller is let
+    /// through as the probe and the circuit stays shut to everyone else until
+    /// the probe finishes or another `open_for` has passed.
+    pub(crate) fn admit(&self, url: &str) -> bool {
+        let Some(breaker) = &self.breaker else {
+            return true;
+        };
+        let mut state = self.lock();
+        let Some(circuit) = state.circuits.get_mut(url) else {
+            return true;
+        };
+        match circuit.opened_at {
+            None => true,
+            Some(opened) if opened.elapsed() < breaker.open_for => false,
+            Some(_) => {
+                circuit.opened_at = Some(Instant::now());
+                circuit.probing = true;
+                true
+            }
+        }
+    }
+
+    pub(crate) fn mark_failed(&self, url: &str) {
+        let mut state = self.lock();
+        state.failed_at.insert(url.to_string(), Instant::now());
+
+        if let Some(breaker) = &self.breaker {
+            let circuit = state.circuits.entry(url.to_string()).or_default();
+            circuit.failures += 1;
+            if circuit.probing || circuit.failures >= breaker.failure_threshold {
+                circuit.opened_at = Some(Instant::now());
+                circuit.probing = false;
+            }
+        }
+    }
+
+    pub(crate) fn mark_ok(&self, url: &str) {
+        let mut state = self.lock();
+        state.failed_at.remove(url);
+        state.circuits.remove(url);
+        if let Some(health) = state.health.get_mut(url) {
+            health.ready = true;
+        }
+    }
+
+    /// Record the result of probing `url`
+    pub(crate) fn set_health(&self, url: &str, health: Health) {
+        self.lock().health.insert(url.to_string(), health);
+    }
+
+    /// Count a request to `url` as in flight until the guard is dropped
+    pub(crate) fn start(self: &std::sync::Arc<Self>, url: &str) -> InFlight {
+        *self.lock().in_flight.entry(url.to_string()).or_default() += 1;
+        InFlight {
+            endpoints: self.clone(),
+            url: url.to_string(),
+        }
+    }
+
+    /// What is known about each configured server
+    pub(crate) fn status(&self, config: &LancorConfig) -> Vec<EndpointStatus> {
+        let state = self.lock();
+        let cooldown = Duration::from_secs(config.failover_cooldown_secs);
+        config
+            .endpoints()
+            .map(|url| {
+                let health = state.health.get(url);
+                EndpointStatus {
+                    url: url.to_string(),
+                    in_flight: state.in_flight.get(url).copied().unwrap_or(0),
+                    ready: health.map(|health| health.ready),
+                    free_slots: health.and_then(|health| health.free_slots),
+                    cooling_down: state
+                        .failed_at
+                        .get(url)
+                        .is_some_and(|failed| failed.elapsed() < cooldown),
+                    circuit: match (&self.breaker, state.circuits.get(url)) {
+                        (Some(breaker), Some(circuit)) => circuit.state(breaker),
+                        _ => CircuitState::Closed,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, EndpointState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A request counted as in flight; see [`Endpoints::start`]
+pub(crate) struct InFlight {
+    endpoints: std::sync::Arc<Endpoints>,
+    url: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut state = self.endpoints.lock();
+        if let Some(count) = state.in_flight.get_mut(&self.url) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Errors returned by the server.
+//!
+//! A non-success response becomes an [`ApiError`] inside the `anyhow::Error`
+//! the request returns, with the fields of the OpenAI-style error body
+//! (`{"error": {"message", "type", "code", "param"}}`) that llama.cpp, the
+//! OpenAI API and most proxies send. Recover it with `downcast_ref`:
+//!
+//! ```no_run
+//! use lancor::{ApiError, ChatCompletionRequest, LlamaCppClient, Message};
+//!
+//! # async fn example(client: LlamaCppClient) {
+//! let request = ChatCompletionRequest::new("gpt-4o-mini").message(Message::user("Hi"));
+//! if let Err(err) = client.chat_completion(request).await
+//!     && let Some(api_error) = err.downcast_ref::<ApiError>()
+//!     && api_error.code.as_deref() == Some("rate_limit_exceeded")
+//! {
+//!     // back off and retry
+//! }
+//! # }
+//! ```
+
+use serde_json::Value;
+use std::time::Duration;
+
+/// An error status and the body the server sent with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: u16,
+    /// The error's message, or the whole body if it was not an error object
+    pub message: String,
+    /// The error's `type`, e.g. `invalid_request_error`
+    pub kind: Option<String>,
+    /// The error's `code`, e.g. `invalid_api_key`; numeric codes become
+    /// strings
+    pub code: Option<String>,
+    /// The request parameter the error is about
+    pub param: Option<String>,
+    /// The raw response body
+    pub body: String,
+    /// How long the server asked the client to wait before trying again,
+    /// from the `Retry-After` header of a 429 or 503 response
+    pub retry_after: Option<Duration>,
+    /// The id the client sent the request with, in `X-Request-Id`
+    pub request_id: Option<String>,
+    /// The id the server or a gateway reported for the request
+    pub server_request_id: Option<String>,
+}
+
+impl ApiError {
+    /// Parse an error response body, which may be an OpenAI-style error
+    /// object, `{"error": "message"}`, or anything else
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        let body = body.into();
+        let mut error = Self {
+            status,
+            message: body.clone(),
+            kind: None,
+            code: None,
+            param: None,
+            body,
+            retry_after: None,
+            request_id: None,
+            server_request_id: None,
+        };
+
+        let Ok(parsed) = serde_json::from_str::<Value>(&error.body) else {
+            return error;
+        };
+        match parsed.get("error") {
+            Some(Value::String(message)) => error.message = message.clone(),
+            Some(object @ Value::Object(_)) => {
+                let field = |name: &str| match object.get(name) {
+                    Some(Value::String(value)) => Some(value.clone()),
+                    Some(Value::Number(value)) => Some(value.to_string()),
+                    _ => None,
+                };
+                if let Some(message) = field("message") {
+                    error.message = message;
+                }
+                error.kind = field("type");
+                error.code = field("code");
+                error.param = field("param");
+            }
+            _ => {}
+        }
+        error
+    }
+
+    /// The error in a streamed event such as `{"error": {...}}`, which
+    /// gateways send when the upstream provider fails mid-stream
+    ///
+    /// The status is the error's numeric code if it has one, otherwise 500.
+    pub fn from_stream(data: &str) -> Option<Self> {
+        let parsed: Value = serde_json::from_str(data).ok()?;
+        let error = parsed.get("error")?;
+        let status = error
+            .get("code")
+            .and_then(Value::as_u64)
+            .and_then(|code| u16::try_from(code).ok())
+            .filter(|code| (400..600).contains(code))
+            .unwrap_or(500);
+        Some(Self::new(status, data))
+    }
+
+    /// Set [`ApiError::retry_after`] from a `Retry-After` header, given in
+    /// seconds or as an HTTP date
+    pub(crate) fn with_retry_after(mut self, header: Option<&str>) -> Self {
+        self.retry_after = header.and_then(parse_retry_after);
+        self
+    }
+}
+
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    // The browser has no system clock to compare a date against
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let at = std::time::UNIX_EPOCH + Duration::from_secs(parse_http_date(value)?);
+        Some(
+            at.duration_since(std::time::SystemTime::now())
+                .unwrap_or_default(),
+        )
+    }
+    #[cfg(target_arch = "wasm32")]
+    None
+}
+
+/// Seconds since the Unix epoch of an IMF-fixdate such as
+/// `Wed, 21 Oct 2015 07:28:00 GMT`
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (_, rest) = value.split_once(", ")?;
+    let parts: Vec<&str> = rest.split(' ').collect();
+    let [day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Days from the epoch to the civil date, after Howard Hinnant's
+    // days_from_civil
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let year_of_era = y % 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "API error ({}): {}", self.status, self.message)?;
+        match (&self.kind, &self.code) {
+            (_, Some(code)) if !code.chars().all(|c| c.is_ascii_digit()) => {
+                write!(f, " [{}]", code)
+            }
+            (Some(kind), _) => write!(f, " [{}]", kind),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
@@ -0,0 +1,297 @@
+//! Recording real server traffic to fixture files and replaying it in tests.
+//!
+//! A [`FixtureTransport`] sits in front of another [`Transport`]. When
+//! recording, it forwards every request and saves the request/response pair
+//! to a JSON file; when replaying, it answers from that file without touching
+//! the network. Streaming responses are saved with their full SSE body, so
+//! replayed streams parse exactly like the recorded ones.
+//!
+//! ```no_run
+//! use lancor::LlamaCppClient;
+//! use lancor::fixtures::FixtureMode;
+//!
+//! # fn example() -> anyhow::Result<()> {
+//! // Records on the first run, replays afterwards. `LANCOR_FIXTURE_MODE`
+//! // can force `record`, `replay` or `off`.
+//! let client = LlamaCppClient::default()?
+//!     .with_fixtures("tests/fixtures/chat.json", FixtureMode::from_env()?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::compat::{self, BoxFuture};
+use crate::transport::{HttpRequest, HttpResponse, Transport};
+
+/// Environment variable read by [`FixtureMode::from_env`]
+pub const FIXTURE_MODE_ENV: &str = "LANCOR_FIXTURE_MODE";
+
+// ============================================================================
+// Fixture Files
+// ============================================================================
+
+/// A recorded request/response pair
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// The parts of a request that replay matches on
+///
+/// Only the path is kept from the URL so fixtures work against any server,
+/// and headers are left out so API keys never end up in a fixture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// The body as JSON, or as a string if it was not JSON
+    #[serde(default)]
+    pub body: Value,
+}
+
+impl RecordedRequest {
+    fn from_request(request: &HttpRequest) -> Self {
+        let body = if request.body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&request.body).unwrap_or_else(|_| {
+                Value::String(String::from_utf8_lossy(&request.body).into_owned())
+            })
+        };
+
+        Self {
+            method: request.method.clone(),
+            path: request.path().to_string(),
+            body,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FixtureFile {
+    interactions: Vec<Interaction>,
+}
+
+/// Load the interactions recorded in a fixture file
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Interaction>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read fixture {}", path.display()))?;
+    let file: FixtureFile = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid fixture {}", path.display()))?;
+    Ok(file.interactions)
+}
+
+fn save(path: &Path, interactions: &[Interaction]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let file = FixtureFile {
+        interactions: interactions.to_vec(),
+    };
+    let mut text = serde_json::to_string_pretty(&file)?;
+    text.push('\n');
+    std::fs::write(path, text)
+        .with_context(|| format!("Failed to write fixture {}", path.display()))
+}
+
+// ============================================================================
+// Fixture Transport
+// ============================================================================
+
+/// Whether a [`FixtureTransport`] records, replays or passes requests through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FixtureMode {
+    /// Send requests to the server and overwrite the fixture file
+    Record,
+    /// Answer from the fixture file; unmatched requests are errors
+    Replay,
+    /// Replay if the fixture file exists, otherwise record it
+    #[default]
+    Auto,
+    /// Send requests to the server without recording
+    Off,
+}
+
+impl FixtureMode {
+    /// The mode named by `LANCOR_FIXTURE_MODE` (`record`, `replay`, `auto` or
+    /// `off`), or [`FixtureMode::Auto`] if it is not set
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(FIXTURE_MODE_ENV) {
+            Ok(value) if !value.is_empty() => value.parse(),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+impl std::str::FromStr for FixtureMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            "auto" => Ok(Self::Auto),
+            "off" => Ok(Self::Off),
+            _ => anyhow::bail!(
+                "Unknown fixture mode '{}' (expected record, replay, auto or off)",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FixtureState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+/// A transport that records traffic to, or replays it from, a fixture file
+#[derive(Debug, Clone)]
+pub struct FixtureTransport {
+    inner: Arc<dyn Transport>,
+    path: PathBuf,
+    recording: bool,
+    passthrough: bool,
+    state: Arc<Mutex<FixtureState>>,
+}
+
+impl FixtureTransport {
+    /// Wrap `inner`, recording to or replaying from `path` according to
+    /// `mode`
+    ///
+    /// Replaying loads the file up front, so a missing fixture is reported
+    /// here rather than on the first request.
+    pub fn new(
+        inner: impl Transport + 'static,
+        path: impl Into<PathBuf>,
+        mode: FixtureMode,
+    ) -> Result<Self> {
+        Self::wrap(Arc::new(inner), path.into(), mode)
+    }
+
+    pub(crate) fn wrap(
+        inner: Arc<dyn Transport>,
+        path: PathBuf,
+        mode: FixtureMode,
+    ) -> Result<Self> {
+        let mode = match mode {
+            FixtureMode::Auto if path.exists() => FixtureMode::Replay,
+            FixtureMode::Auto => FixtureMode::Record,
+            mode => mode,
+        };
+        let interactions = match mode {
+            FixtureMode::Replay => load(&path)?,
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            inner,
+            path,
+            recording: mode == FixtureMode::Record,
+            passthrough: mode == FixtureMode::Off,
+            state: Arc::new(Mutex::new(FixtureState {
+                used: vec![false; interactions.len()],
+                interactions,
+            })),
+        })
+    }
+
+    /// Whether requests are being sent to the server and saved
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// The interactions recorded or loaded so far
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.lock().interactions.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FixtureState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn record(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let recorded = RecordedRequest::from_request(&request);
+        let response = self.inner.send(request).await?;
+        let status = response.status;
+        let headers = response.headers.clone();
+        let body = response.text().await?;
+
+        {
+            let mut state = self.lock();
+            state.interactions.push(Interaction {
+                request: recorded,
+                response: RecordedResponse {
+                    status,
+                    headers: headers.clone(),
+                    body: body.clone(),
+                },
+            });
+            state.used.push(true);
+            save(&self.path, &state.interactions)?;
+        }
+
+        let mut response = HttpResponse::new(status, body);
+        response.headers = headers;
+        Ok(response)
+    }
+
+    /// Answer with the first unused interaction matching `request`, so
+    /// identical requests replay their responses in recorded order
+    fn replay(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let wanted = RecordedRequest::from_request(request);
+        let mut state = self.lock();
+        let FixtureState { interactions, used } = &mut *state;
+
+        let index = interactions
+            .iter()
+            .zip(used.iter())
+            .position(|(interaction, used)| !used && interaction.request == wanted)
+            .with_context(|| {
+                format!(
+                    "No recorded response for {} {} in {}",
+                    wanted.method,
+                    wanted.path,
+                    self.path.display()
+                )
+            })?;
+        used[index] = true;
+
+        let recorded = &interactions[index].response;
+        let mut response = HttpResponse::new(recorded.status, recorded.body.clone());
+        response.headers = recorded.headers.clone();
+        Ok(response)
+    }
+}
+
+impl Transport for FixtureTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        if self.passthrough {
+            return self.inner.send(request);
+        }
+        if self.recording {
+            return compat::boxed_future(self.record(request));
+        }
+
+        let response = self.replay(&request);
+        compat::boxed_future(async move { response })
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::Result;
+
+use crate::{LlamaCppClient, Message, TokenizeRequest};
+
+// ============================================================================
+// History Policy
+// ============================================================================
+
+/// Tokens added per message for role markers and separators by typical chat
+/// templates
+pub(crate) const MESSAGE_OVERHEAD: u32 = 4;
+
+/// How to count tokens when trimming history to a budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenCounter {
+    /// Estimate with [`token_estimate`]. Fast and offline.
+    #[default]
+    Estimate,
+    /// Ask the server's `/tokenize` endpoint. Exact, but one request per
+    /// message. [`HistoryPolicy`] falls back to [`token_estimate`] if the
+    /// server cannot tokenize.
+    Server,
+}
+
+impl TokenCounter {
+    /// Count the tokens `message` takes up in the prompt
+    pub async fn count(&self, client: &LlamaCppClient, message: &Message) -> Result<u32> {
+        Ok(self.count_text(client, &message.content.text()).await? + MESSAGE_OVERHEAD)
+    }
+
+    /// Count the tokens in `text` alone
+    pub async fn count_text(&self, client: &LlamaCppClient, text: &str) -> Result<u32> {
+        Ok(match self {
+            TokenCounter::Estimate => token_estimate(text),
+            TokenCounter::Server => {
+                let request = TokenizeRequest::new(text).add_special(false);
+                client.tokenize(request).await?.tokens.len() as u32
+            }
+        })
+    }
+}
+
+/// Estimate how many tokens `text` takes up, without a tokenizer
+///
+/// Follows how BPE tokenizers for current models tend to split text: a
+/// common word with its leading space is one token and longer words take
+/// one per six letters or so, numbers split into groups of three digits,
+/// punctuation and symbols are a token each, and CJK characters about one
+/// each. Closer than four characters per token for code, numbers and
+/// non-English text, but still an estimate; use [`TokenCounter::Server`]
+/// when exact counts matter.
+pub fn token_estimate(text: &str) -> u32 {
+    let mut tokens = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphabetic() && !is_cjk(c) {
+            // Letters outside ASCII take more of a tokenizer's vocabulary
+            let mut weight: u32 = if c.is_ascii() { 1 } else { 2 };
+            while let Some(&next) = chars.peek() {
+                if !next.is_alphabetic() || is_cjk(next) {
+                    break;
+                }
+                weight += if next.is_ascii() { 1 } else { 2 };
+                chars.next();
+            }
+            tokens += weight.div_ceil(6);
+        } else if c.is_ascii_digit() {
+            let mut digits: u32 = 1;
+            while chars.next_if(char::is_ascii_digit).is_some() {
+                digits += 1;
+            }
+            tokens += digits.div_ceil(3);
+        } else if c == '\n' {
+            while chars.next_if_eq(&'\n').is_some() {}
+            tokens += 1;
+        } else if c.is_whitespace() {
+            // A single space joins the next word; longer runs, such as
+            // indentation, take a token of their own
+            if chars
+                .next_if(|next| *next != '\n' && next.is_whitespace())
+                .is_some()
+            {
+                while chars
+                    .next_if(|next| *next != '\n' && next.is_whitespace())
+                    .is_some()
+                {}
+                tokens += 1;
+            }
+        } else {
+            tokens += 1;
+        }
+    }
+    tokens
+}
+
+/// Whether `c` is written without spaces between words, so tokenizers
+/// split it about one character at a time
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{3040}'..='\u{30FF}'   // Hiragana and Katakana
+            | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+            | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+            | '\u{AC00}'..='\u{D7AF}' // Hangul syllables
+            | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+    )
+}
+
+/// Which part of a conversation to send to the model
+///
+/// Every policy keeps the system prompt and drops the oldest messages first.
+/// The latest message is always kept, even if it alone exceeds the limit.
+/// Tool results are never sent without the assistant message that called
+/// the tools: when the cut falls between them, the results are dropped too,
+/// or if nothing would be left, the call is kept with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistoryPolicy {
+    /// Send the whole conversation
+    #[default]
+    KeepAll,
+    /// Keep only the last `n` messages
+    LastMessages(usize),
+    /// Keep only the last `n` user turns and everything after each of them
+    LastTurns(usize),
+    /// Keep as many recent messages as fit in `max_tokens`, system prompt included
+    TokenBudget {
+        max_tokens: u32,
+        counter: TokenCounter,
+    },
+}
+
+impl HistoryPolicy {
+    /// Keep recent messages within a token budget, counted with `counter`
+    pub fn token_budget(max_tokens: u32, counter: TokenCounter) -> Self {
+        HistoryPolicy::TokenBudget {
+            max_tokens,
+            counter,
+        }
+    }
+
+    /// Select the messages to send, in order
+    pub async fn apply(
+        &self,
+        client: &LlamaCppClient,
+        system: Option<&Message>,
+        history: &[Message],
+    ) -> Result<Vec<Message>> {
+        let start = match *self {
+            HistoryPolicy::KeepAll => 0,
+            HistoryPolicy::LastMessages(n) => history.len().saturating_sub(n.max(1)),
+            HistoryPolicy::LastTurns(n) => history
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, message)| message.role == "user")
+                .nth(n.max(1) - 1)
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+            HistoryPolicy::TokenBudget {
+                max_tokens,
+                mut counter,
+            } => {
+                let mut used = match system {
+                    Some(message) => count_or_estimate(&mut counter, client, message).await?,
+                    None => 0,
+                };
+                let mut start = history.len();
+                for (i, message) in history.iter().enumerate().rev() {
+                    used += count_or_estimate(&mut counter, client, message).await?;
+                    if used > max_tokens && start < history.len() {
+                        break;
+                    }
+                    start = i;
+                }
+                start
+            }
+        };
+        let start = skip_orphaned_tool_results(history, start);
+
+        Ok(system
+            .into_iter()
+            .chain(&history[start..])
+            .cloned()
+            .collect())
+    }
+}
+
+/// Move `start` past `tool` messages whose assistant message was cut off,
+/// which servers reject, or back to that message if only tool results follow
+fn skip_orphaned_tool_results(history: &[Message], start: usize) -> usize {
+    if start == 0 {
+        return 0;
+    }
+    let is_tool = |i: &usize| history[*i].role == "tool";
+    match (start..history.len()).find(|i| !is_tool(i)) {
+        Some(i) => i,
+        None => (0..start).rev().find(|i| !is_tool(i)).unwrap_or(0),
+    }
+}
+
+/// Count with `counter`, switching it to [`TokenCounter::Estimate`] for the
+/// remaining messages if the server cannot tokenize, such as an OpenAI-style
+/// service without `/tokenize`
+async fn count_or_estimate(
+    counter: &mut TokenCounter,
+    client: &LlamaCppClient,
+    message: &Message,
+) -> Result<u32> {
+    match counter.count(client, message).await {
+        Err(_) if *counter == TokenCounter::Server => {
+            *counter = TokenCounter::Estimate;
+            counter.count(client, message).await
+        }
+        result => result,
+    }
+}
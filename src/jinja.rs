@@ -0,0 +1,382 @@
+//! Rendering a model's Jinja chat template on the client.
+//!
+//! Chat endpoints apply the model's chat template on the server. To drive the
+//! native `/completion` endpoint with a conversation instead, render the
+//! template here with [`JinjaTemplate`], taken from the server's `/props`, a
+//! GGUF file or a string:
+//!
+//! ```no_run
+//! use lancor::{CompletionRequest, LlamaCppClient, Message};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = LlamaCppClient::new("http://localhost:8080")?;
+//! let template = client.jinja_template().await?;
+//! let prompt = template.render(&[
+//!     Message::system("You are terse."),
+//!     Message::user("Name a prime number."),
+//! ])?;
+//! let response = client.completion(CompletionRequest::new("qwen", prompt)).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Templates are rendered the way Hugging Face's `apply_chat_template` does:
+//! with `trim_blocks` and `lstrip_blocks`, Python string methods such as
+//! `.strip()`, and the `raise_exception` and `strftime_now` functions.
+
+use anyhow::{Context, Result};
+use minijinja::{Environment, ErrorKind};
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::props::ServerProps;
+use crate::{LlamaCppClient, Message, Tool, compat};
+
+/// The name the template is stored under in its environment
+const TEMPLATE_NAME: &str = "chat";
+
+// ============================================================================
+// Templates
+// ============================================================================
+
+/// A compiled Jinja chat template; see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct JinjaTemplate {
+    env: Environment<'static>,
+    bos_token: String,
+    eos_token: String,
+    add_generation_prompt: bool,
+}
+
+impl JinjaTemplate {
+    /// Compile a template, failing on syntax errors
+    pub fn new(source: impl Into<String>) -> Result<Self> {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", raise_exception);
+        env.add_function("strftime_now", strftime_now);
+        env.add_template_owned(TEMPLATE_NAME, source.into())
+            .context("Invalid chat template")?;
+        Ok(Self {
+            env,
+            bos_token: String::new(),
+            eos_token: String::new(),
+            add_generation_prompt: true,
+        })
+    }
+
+    /// The template and special tokens a llama.cpp server reports
+    pub fn from_props(props: &ServerProps) -> Result<Self> {
+        let source = props
+            .chat_template
+            .clone()
+            .context("The server does not report a chat template")?;
+        let mut template = Self::new(source)?;
+        template.bos_token = props.bos_token.clone().unwrap_or_default();
+        template.eos_token = props.eos_token.clone().unwrap_or_default();
+        Ok(template)
+    }
+
+    /// The template and special tokens stored in a GGUF model file
+    pub fn from_gguf(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let metadata = GgufMetadata::read(path)
+            .with_context(|| format!("Failed to read GGUF metadata from {}", path.display()))?;
+        let source = metadata
+            .chat_template
+            .with_context(|| format!("{} has no chat template", path.display()))?;
+        let token = |id: Option<usize>| {
+            id.and_then(|id| metadata.tokens.get(id))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let mut template = Self::new(source)?;
+        template.bos_token = token(metadata.bos_token_id);
+        template.eos_token = token(metadata.eos_token_id);
+        Ok(template)
+    }
+
+    /// The `bos_token` the template may insert; a leading one is removed
+    /// from the output, since llama.cpp adds it when tokenizing the prompt
+    pub fn bos_token(mut self, token: impl Into<String>) -> Self {
+        self.bos_token = token.into();
+        self
+    }
+
+    pub fn eos_token(mut self, token: impl Into<String>) -> Self {
+        self.eos_token = token.into();
+        self
+    }
+
+    /// Whether to end the prompt with the start of an assistant turn, for
+    /// the model to complete; on by default
+    pub fn add_generation_prompt(mut self, add_generation_prompt: bool) -> Self {
+        self.add_generation_prompt = add_generation_prompt;
+        self
+    }
+
+    /// Render `messages` into a prompt
+    ///
+    /// Messages are passed with their content as text, so images and audio
+    /// are left out, and with tool call arguments as objects.
+    pub fn render(&self, messages: &[Message]) -> Result<String> {
+        self.render_with_tools(messages, &[])
+    }
+
+    /// Render `messages` into a prompt offering `tools` to the model, for
+    /// templates that describe tools themselves
+    pub fn render_with_tools(&self, messages: &[Message], tools: &[Tool]) -> Result<String> {
+        let messages = messages
+            .iter()
+            .map(template_message)
+            .collect::<Result<Vec<_>>>()?;
+        let mut context = json!({
+            "messages": messages,
+            "add_generation_prompt": self.add_generation_prompt,
+            "bos_token": self.bos_token,
+            "eos_token": self.eos_token,
+        });
+        if !tools.is_empty() {
+            context["tools"] = serde_json::to_value(tools)?;
+        }
+
+        let prompt = self
+            .env
+            .get_template(TEMPLATE_NAME)?
+            .render(&context)
+            .context("Failed to render chat template")?;
+        Ok(match prompt.strip_prefix(&self.bos_token) {
+            Some(rest) if !self.bos_token.is_empty() => rest.to_string(),
+            _ => prompt,
+        })
+    }
+}
+
+/// A message as chat templates expect it
+fn template_message(message: &Message) -> Result<Value> {
+    let mut value = serde_json::to_value(message)?;
+    value["content"] = message.content.text().into();
+    if let Some(calls) = value.get_mut("tool_calls").and_then(Value::as_array_mut) {
+        for call in calls {
+            let arguments = &mut call["function"]["arguments"];
+            if let Some(parsed) = arguments
+                .as_str()
+                .and_then(|arguments| serde_json::from_str::<Value>(arguments).ok())
+            {
+                *arguments = parsed;
+            }
+        }
+    }
+    Ok(value)
+}
+
+fn raise_exception(message: String) -> std::result::Result<(), minijinja::Error> {
+    Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+}
+
+/// Python's `strftime` on the current UTC time, for `%Y`, `%m`, `%d`, `%B`,
+/// `%b`, `%H`, `%M` and `%S`
+fn strftime_now(format: String) -> String {
+    const MONTHS: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    let seconds = compat::unix_seconds();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let time = seconds % 86_400;
+    let month_name = MONTHS[month as usize - 1];
+
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => output.push_str(&year.to_string()),
+            Some('m') => output.push_str(&format!("{:02}", month)),
+            Some('d') => output.push_str(&format!("{:02}", day)),
+            Some('B') => output.push_str(month_name),
+            Some('b') => output.push_str(&month_name[..3]),
+            Some('H') => output.push_str(&format!("{:02}", time / 3600)),
+            Some('M') => output.push_str(&format!("{:02}", time / 60 % 60)),
+            Some('S') => output.push_str(&format!("{:02}", time % 60)),
+            Some(other) => {
+                output.push('%');
+                output.push(other);
+            }
+            None => output.push('%'),
+        }
+    }
+    output
+}
+
+/// The year, month and day of a count of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// ============================================================================
+// GGUF Metadata
+// ============================================================================
+
+const GGUF_STRING: u32 = 8;
+const GGUF_ARRAY: u32 = 9;
+
+/// Longest string read from a GGUF file, to fail cleanly on corrupt ones
+const MAX_GGUF_STRING: u64 = 64 << 20;
+
+/// The parts of a GGUF file's metadata a chat template needs
+#[derive(Debug, Default)]
+struct GgufMetadata {
+    chat_template: Option<String>,
+    bos_token_id: Option<usize>,
+    eos_token_id: Option<usize>,
+    tokens: Vec<String>,
+}
+
+impl GgufMetadata {
+    fn read(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if &read_bytes::<4>(&mut reader)? != b"GGUF" {
+            anyhow::bail!("Not a GGUF file");
+        }
+        let version = u32::from_le_bytes(read_bytes(&mut reader)?);
+        if version < 2 {
+            anyhow::bail!("GGUF version {} is not supported", version);
+        }
+        let _tensor_count = read_u64(&mut reader)?;
+        let metadata_count = read_u64(&mut reader)?;
+
+        let mut metadata = Self::default();
+        for _ in 0..metadata_count {
+            let key = read_string(&mut reader)?;
+            let kind = u32::from_le_bytes(read_bytes(&mut reader)?);
+            match (key.as_str(), kind) {
+                ("tokenizer.chat_template", GGUF_STRING) => {
+                    metadata.chat_template = Some(read_string(&mut reader)?);
+                }
+                ("tokenizer.ggml.bos_token_id", _) => {
+                    metadata.bos_token_id = read_integer(&mut reader, kind)?;
+                }
+                ("tokenizer.ggml.eos_token_id", _) => {
+                    metadata.eos_token_id = read_integer(&mut reader, kind)?;
+                }
+                ("tokenizer.ggml.tokens", GGUF_ARRAY) => {
+                    let item = u32::from_le_bytes(read_bytes(&mut reader)?);
+                    let len = read_u64(&mut reader)?;
+                    for _ in 0..len {
+                        match item {
+                            GGUF_STRING => metadata.tokens.push(read_string(&mut reader)?),
+                            _ => skip_value(&mut reader, item)?,
+                        }
+                    }
+                }
+                _ => skip_value(&mut reader, kind)?,
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_u64(reader)?;
+    if len > MAX_GGUF_STRING {
+        anyhow::bail!("GGUF string of {} bytes is too long", len);
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A value of integer type `kind` as an index, or `None` for other types
+fn read_integer(reader: &mut impl Read, kind: u32) -> Result<Option<usize>> {
+    let value = match kind {
+        0 => u64::from(read_bytes::<1>(reader)?[0]),
+        1 => i8::from_le_bytes(read_bytes(reader)?) as u64,
+        2 => u64::from(u16::from_le_bytes(read_bytes(reader)?)),
+        3 => i16::from_le_bytes(read_bytes(reader)?) as u64,
+        4 => u64::from(u32::from_le_bytes(read_bytes(reader)?)),
+        5 => i32::from_le_bytes(read_bytes(reader)?) as u64,
+        10 => read_u64(reader)?,
+        11 => i64::from_le_bytes(read_bytes(reader)?) as u64,
+        _ => {
+            skip_value(reader, kind)?;
+            return Ok(None);
+        }
+    };
+    Ok(usize::try_from(value).ok())
+}
+
+fn skip_value(reader: &mut impl Read, kind: u32) -> Result<()> {
+    let size = match kind {
+        0 | 1 | 7 => 1,
+        2 | 3 => 2,
+        4..=6 => 4,
+        10..=12 => 8,
+        GGUF_STRING => read_u64(reader)?,
+        GGUF_ARRAY => {
+            let item = u32::from_le_bytes(read_bytes(reader)?);
+            for _ in 0..read_u64(reader)? {
+                skip_value(reader, item)?;
+            }
+            return Ok(());
+        }
+        other => anyhow::bail!("Unknown GGUF value type {}", other),
+    };
+    let skipped = std::io::copy(&mut reader.take(size), &mut std::io::sink())?;
+    if skipped < size {
+        anyhow::bail!("GGUF file ends mid-value");
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Client
+// ============================================================================
+
+impl LlamaCppClient {
+    /// The chat template of the server's model, from `/props`
+    pub async fn jinja_template(&self) -> Result<JinjaTemplate> {
+        JinjaTemplate::from_props(&self.server_props().await?)
+    }
+}
@@ -0,0 +1,453 @@
+//! A persistent queue of chat and completion jobs.
+//!
+//! A [`JobQueue`] keeps its jobs in a JSON Lines file, appending a line each
+//! time a job changes, so a batch survives a crash: jobs that were running
+//! when the process died are queued again when the file is reopened.
+//! [`JobQueue::run`] sends queued jobs, highest priority first and in
+//! submission order within a priority, at most `concurrency` at a time, and
+//! picks up jobs submitted while it runs.
+//!
+//! ```no_run
+//! use lancor::jobs::JobQueue;
+//! use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+//!
+//! # async fn example(client: LlamaCppClient) -> anyhow::Result<()> {
+//! let queue = JobQueue::open("jobs.jsonl")?;
+//! for prompt in ["Summarize chapter 1.", "Summarize chapter 2."] {
+//!     queue.submit(ChatCompletionRequest::new("qwen").message(Message::user(prompt)))?;
+//! }
+//! let urgent = queue.submit_with_priority(
+//!     ChatCompletionRequest::new("qwen").message(Message::user("Write the blurb.")),
+//!     10,
+//! )?;
+//!
+//! let counts = queue.run(&client, 4).await?;
+//! println!("{} succeeded, {} failed", counts.succeeded, counts.failed);
+//! if let Some(output) = queue.get(&urgent).and_then(|job| job.output) {
+//!     println!("{}", output.text());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use futures::future::{AbortHandle, Abortable};
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::compat;
+use crate::{
+    ChatCompletionRequest, ChatCompletionResponse, CompletionRequest, CompletionResponse,
+    LlamaCppClient,
+};
+
+// ============================================================================
+// Jobs
+// ============================================================================
+
+/// The request a job sends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "body", rename_all = "snake_case")]
+pub enum JobRequest {
+    Chat(ChatCompletionRequest),
+    Completion(CompletionRequest),
+}
+
+impl From<ChatCompletionRequest> for JobRequest {
+    fn from(request: ChatCompletionRequest) -> Self {
+        Self::Chat(request)
+    }
+}
+
+impl From<CompletionRequest> for JobRequest {
+    fn from(request: CompletionRequest) -> Self {
+        Self::Completion(request)
+    }
+}
+
+/// The response a finished job got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "body", rename_all = "snake_case")]
+pub enum JobOutput {
+    Chat(ChatCompletionResponse),
+    Completion(CompletionResponse),
+}
+
+impl JobOutput {
+    /// The generated text, of the first choice for chat responses
+    pub fn text(&self) -> String {
+        match self {
+            Self::Chat(response) => response
+                .choices
+                .first()
+                .map(|choice| choice.message.content.text())
+                .unwrap_or_default(),
+            Self::Completion(response) => response.content.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job will not run again unless requeued
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// A job and, once it has finished, its outcome
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    /// Higher priorities run first
+    pub priority: i32,
+    pub status: JobStatus,
+    pub request: JobRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<JobOutput>,
+    /// Why the last attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Times the job has been started, including attempts cut short by a
+    /// crash
+    #[serde(default)]
+    pub attempts: u32,
+    /// Seconds since the Unix epoch
+    pub submitted_at: u64,
+    /// Seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+/// How many jobs are in each state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JobCounts {
+    pub queued: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+}
+
+// ============================================================================
+// Queue
+// ============================================================================
+
+struct QueueState {
+    path: PathBuf,
+    file: std::fs::File,
+    /// In submission order
+    jobs: Vec<Job>,
+    index: HashMap<String, usize>,
+    running: HashMap<String, AbortHandle>,
+}
+
+impl QueueState {
+    /// Append the current record of the job at `index` to the file
+    fn persist(&mut self, index: usize) -> Result<()> {
+        let mut line = serde_json::to_vec(&self.jobs[index])?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .and_then(|_| self.file.flush())
+            .with_context(|| format!("Failed to write to {}", self.path.display()))
+    }
+
+    fn job_mut(&mut self, id: &str) -> Option<(usize, &mut Job)> {
+        let index = *self.index.get(id)?;
+        Some((index, &mut self.jobs[index]))
+    }
+}
+
+/// Jobs kept in a JSON Lines file, shared by all clones
+#[derive(Clone)]
+pub struct JobQueue {
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl std::fmt::Debug for JobQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("JobQueue")
+            .field("path", &state.path)
+            .field("jobs", &state.jobs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl JobQueue {
+    /// Open the queue stored at `path`, creating the file if needed
+    ///
+    /// The latest record of each job is kept and the file is rewritten with
+    /// just those. Jobs that were running are queued again, and an
+    /// incomplete last line, left by a crash mid-write, is dropped.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut jobs: Vec<Job> = Vec::new();
+        let mut index = HashMap::new();
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let lines: Vec<&str> = text.lines().collect();
+            for (number, line) in lines.iter().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let mut job: Job = match serde_json::from_str(line) {
+                    Ok(job) => job,
+                    Err(_) if number + 1 == lines.len() && !text.ends_with('\n') => break,
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!("Invalid job on line {} of {}", number + 1, path.display())
+                        });
+                    }
+                };
+                if job.status == JobStatus::Running {
+                    job.status = JobStatus::Queued;
+                }
+                match index.get(&job.id) {
+                    Some(&i) => jobs[i] = job,
+                    None => {
+                        index.insert(job.id.clone(), jobs.len());
+                        jobs.push(job);
+                    }
+                }
+            }
+        }
+
+        let file = write_compacted(&path, &jobs)?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(QueueState {
+                path,
+                file,
+                jobs,
+                index,
+                running: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Queue `request` with priority 0, returning the job's id
+    pub fn submit(&self, request: impl Into<JobRequest>) -> Result<String> {
+        self.submit_with_priority(request, 0)
+    }
+
+    /// Queue `request`, to run before jobs of lower priority
+    pub fn submit_with_priority(
+        &self,
+        request: impl Into<JobRequest>,
+        priority: i32,
+    ) -> Result<String> {
+        let id = format!("job_{:016x}", compat::random_u64());
+        let mut state = self.lock();
+        let index = state.jobs.len();
+        state.jobs.push(Job {
+            id: id.clone(),
+            priority,
+            status: JobStatus::Queued,
+            request: request.into(),
+            output: None,
+            error: None,
+            attempts: 0,
+            submitted_at: compat::unix_seconds(),
+            finished_at: None,
+        });
+        state.index.insert(id.clone(), index);
+        if let Err(err) = state.persist(index) {
+            state.jobs.pop();
+            state.index.remove(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        let state = self.lock();
+        state.index.get(id).map(|&index| state.jobs[index].clone())
+    }
+
+    /// Every job, in submission order
+    pub fn jobs(&self) -> Vec<Job> {
+        self.lock().jobs.clone()
+    }
+
+    pub fn counts(&self) -> JobCounts {
+        let mut counts = JobCounts::default();
+        for job in &self.lock().jobs {
+            *match job.status {
+                JobStatus::Queued => &mut counts.queued,
+                JobStatus::Running => &mut counts.running,
+                JobStatus::Succeeded => &mut counts.succeeded,
+                JobStatus::Failed => &mut counts.failed,
+                JobStatus::Cancelled => &mut counts.cancelled,
+            } += 1;
+        }
+        counts
+    }
+
+    /// Cancel a queued or running job, dropping its request if it is in
+    /// flight; returns whether the job was cancelled
+    pub fn cancel(&self, id: &str) -> Result<bool> {
+        let mut state = self.lock();
+        let Some((index, job)) = state.job_mut(id) else {
+            return Ok(false);
+        };
+        if job.status.is_finished() {
+            return Ok(false);
+        }
+        job.status = JobStatus::Cancelled;
+        job.finished_at = Some(compat::unix_seconds());
+        if let Some(handle) = state.running.remove(id) {
+            handle.abort();
+        }
+        state.persist(index)?;
+        Ok(true)
+    }
+
+    /// Queue every failed job again, returning how many were requeued
+    pub fn retry_failed(&self) -> Result<usize> {
+        let mut state = self.lock();
+        let failed: Vec<usize> = (0..state.jobs.len())
+            .filter(|&index| state.jobs[index].status == JobStatus::Failed)
+            .collect();
+        for &index in &failed {
+            let job = &mut state.jobs[index];
+            job.status = JobStatus::Queued;
+            job.finished_at = None;
+            state.persist(index)?;
+        }
+        Ok(failed.len())
+    }
+
+    /// Rewrite the file with only the latest record of each job
+    pub fn compact(&self) -> Result<()> {
+        let mut state = self.lock();
+        state.file = write_compacted(&state.path, &state.jobs)?;
+        Ok(())
+    }
+
+    /// Run queued jobs against `client`, at most `concurrency` at a time,
+    /// until none are left
+    ///
+    /// A job that fails is marked [`JobStatus::Failed`] with its error; the
+    /// others carry on. Only failing to write the queue file stops the run.
+    pub async fn run(&self, client: &LlamaCppClient, concurrency: usize) -> Result<JobCounts> {
+        let mut in_flight = FuturesUnordered::new();
+        loop {
+            while in_flight.len() < concurrency.max(1) {
+                let Some((id, request, registration)) = self.start_next()? else {
+                    break;
+                };
+                let sent = Abortable::new(send(client, request), registration);
+                in_flight.push(async move { (id, sent.await) });
+            }
+            let Some((id, result)) = in_flight.next().await else {
+                break;
+            };
+            // An aborted job was cancelled, which already recorded it
+            if let Ok(result) = result {
+                self.finish(&id, result)?;
+            }
+        }
+        Ok(self.counts())
+    }
+
+    /// Mark the next job to run as running
+    fn start_next(
+        &self,
+    ) -> Result<Option<(String, JobRequest, futures::future::AbortRegistration)>> {
+        let mut state = self.lock();
+        // The first queued job among those of the highest priority
+        let next = state
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.status == JobStatus::Queued)
+            .min_by_key(|(index, job)| (std::cmp::Reverse(job.priority), *index))
+            .map(|(index, _)| index);
+        let Some(index) = next else {
+            return Ok(None);
+        };
+
+        let job = &mut state.jobs[index];
+        job.status = JobStatus::Running;
+        job.attempts += 1;
+        job.error = None;
+        let (id, request) = (job.id.clone(), job.request.clone());
+        state.persist(index)?;
+        let (handle, registration) = AbortHandle::new_pair();
+        state.running.insert(id.clone(), handle);
+        Ok(Some((id, request, registration)))
+    }
+
+    fn finish(&self, id: &str, result: Result<JobOutput>) -> Result<()> {
+        let mut state = self.lock();
+        state.running.remove(id);
+        let Some((index, job)) = state.job_mut(id) else {
+            return Ok(());
+        };
+        if job.status != JobStatus::Running {
+            return Ok(());
+        }
+        match result {
+            Ok(output) => {
+                job.status = JobStatus::Succeeded;
+                job.output = Some(output);
+            }
+            Err(err) => {
+                job.status = JobStatus::Failed;
+                job.error = Some(format!("{:#}", err));
+            }
+        }
+        job.finished_at = Some(compat::unix_seconds());
+        state.persist(index)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+async fn send(client: &LlamaCppClient, request: JobRequest) -> Result<JobOutput> {
+    match request {
+        JobRequest::Chat(request) => client.chat_completion(request).await.map(JobOutput::Chat),
+        JobRequest::Completion(request) => {
+            client.completion(request).await.map(JobOutput::Completion)
+        }
+    }
+}
+
+/// Write `jobs` to `path` through a temporary file, returning the file
+/// opened for appending
+fn write_compacted(path: &Path, jobs: &[Job]) -> Result<std::fs::File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut text = Vec::new();
+    for job in jobs {
+        serde_json::to_writer(&mut text, job)?;
+        text.push(b'\n');
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    std::fs::write(&tmp, &text)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
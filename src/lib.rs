@@ -1,14 +1,120 @@
 use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use futures::stream::StreamExt;
-use reqwest::Client as HttpClient;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+
+#[cfg(feature = "agent")]
+pub mod agent;
+pub mod audio;
+#[cfg(feature = "batch")]
+pub mod batch;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
+pub mod cache;
+#[cfg(feature = "chunking")]
+pub mod chunking;
+mod compat;
+pub mod config;
+pub mod embeddings;
+mod endpoints;
+pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fixtures;
+pub mod history;
+#[cfg(feature = "jinja")]
+pub mod jinja;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jobs;
+pub mod limits;
+pub mod logging;
+#[cfg(all(feature = "mcp", not(target_arch = "wasm32")))]
+pub mod mcp;
+pub mod metrics;
+#[cfg(feature = "ollama")]
+pub mod ollama;
+pub mod pool;
+pub mod presets;
+#[cfg(feature = "profiles")]
+pub mod profiles;
+pub mod props;
+pub mod provider;
+#[cfg(feature = "rag")]
+pub mod rag;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(all(feature = "server", not(target_arch = "wasm32")))]
+pub mod server;
+pub mod session;
+pub mod shutdown;
+pub mod stream;
+pub mod structured;
+#[cfg(feature = "templates")]
+pub mod templates;
+#[cfg(feature = "transcript")]
+pub mod transcript;
+pub mod transport;
+pub mod usage;
+
+#[cfg(feature = "agent")]
+pub use agent::{InvalidArguments, ToolHandler, ToolRegistry, run_agent};
+#[cfg(feature = "batch")]
+pub use batch::BatchProgress;
+#[cfg(feature = "embedding-cache")]
+pub use cache::EmbeddingCache;
+pub use cache::ResponseCache;
+pub use compat::{BoxFuture, BoxStream, MaybeSend, MaybeSync};
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+pub use config::ConfigWatcher;
+pub use config::{AuthScheme, Dialect, LancorConfig};
+pub use embeddings::{VectorIndex, VectorStorage};
+pub use error::ApiError;
+pub use history::{HistoryPolicy, TokenCounter, token_estimate};
+#[cfg(feature = "macros")]
+pub use lancor_macros::tool;
+pub use limits::{CircuitBreaker, CircuitState, RateLimit, Timeouts};
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+pub use limits::{RequestKind, RetryPolicy};
+pub use metrics::MetricsObserver;
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+pub use pool::HealthMonitor;
+pub use pool::{ClientPool, EndpointStatus, LoadBalancing};
+pub use presets::{Preset, Presets, Resolution};
+#[cfg(feature = "profiles")]
+pub use profiles::{Profile, Profiles};
+pub use props::ServerProps;
+pub use provider::Provider;
+#[cfg(feature = "schemars")]
+pub use schemars;
+pub use session::{ChatSession, MessageMetadata, SlotPinnedSession};
+pub use shutdown::{AbortedRequest, ShutdownReport};
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+pub use stream::BoundedStream;
+pub use stream::{
+    ChatEvent, ChatStreamExt, Overflow, StatsStream, StreamStats, StreamSummary,
+    ToolCallAccumulator,
+};
+pub use structured::OutputSchema;
+#[cfg(feature = "templates")]
+pub use templates::{ChatTemplate, PromptTemplate};
+pub use transport::Transport;
+pub use usage::UsageTracker;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow::Result;
+    pub use futures::future::BoxFuture;
+    pub use serde_json;
+}
 
 // ============================================================================
 // Request Types
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<Message>,
@@ -19,312 +125,2247 @@ pub struct ChatCompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// Penalize repeating recent tokens; 1.0 disables it (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    /// Reuse the KV cache of a matching prompt prefix (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_prompt: Option<bool>,
+    /// Run on this server slot rather than any idle one (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_slot: Option<u32>,
+    /// Attach [`Timings`] to every streamed chunk (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings_per_token: Option<bool>,
+    /// Named preset from the client's [`Presets`], resolved before sending
+    #[serde(skip)]
+    pub preset: Option<String>,
+    /// Fields sent as given, for server parameters this type does not model
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Constrains the shape of the model's reply
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Any JSON object, or one matching `schema` (llama.cpp extension)
+    JsonObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        schema: Option<serde_json::Value>,
+    },
+    /// JSON matching a named schema (OpenAI style)
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    /// Require JSON matching `schema`
+    pub fn json_schema(name: impl Into<String>, schema: serde_json::Value) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: name.into(),
+                schema,
+                strict: Some(true),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    #[serde(deserialize_with = "null_as_empty")]
+    pub content: MessageContent,
+    /// Who sent the message, to tell apart participants sharing a role
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 impl Message {
-    pub fn system(content: impl Into<String>) -> Self {
+    pub fn new(role: impl Into<String>, content: impl Into<MessageContent>) -> Self {
         Self {
-            role: "system".to_string(),
+            role: role.into(),
             content: content.into(),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
         }
     }
 
-    pub fn user(content: impl Into<String>) -> Self {
+    pub fn system(content: impl Into<MessageContent>) -> Self {
+        Self::new("system", content)
+    }
+
+    pub fn user(content: impl Into<MessageContent>) -> Self {
+        Self::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<MessageContent>) -> Self {
+        Self::new("assistant", content)
+    }
+
+    /// The result of the tool call with id `tool_call_id`
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<MessageContent>) -> Self {
         Self {
-            role: "user".to_string(),
-            content: content.into(),
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new("tool", content)
         }
     }
 
-    pub fn assistant(content: impl Into<String>) -> Self {
+    /// A user message from the participant `name`
+    pub fn named_user(name: impl Into<String>, content: impl Into<MessageContent>) -> Self {
+        Self::user(content).name(name)
+    }
+
+    /// Attribute the message to `name`, e.g. one of several agents
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+/// Assistant messages that only call tools have `null` content
+fn null_as_empty<'de, D>(deserializer: D) -> std::result::Result<MessageContent, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<MessageContent>::deserialize(deserializer)?
+        .unwrap_or_else(|| MessageContent::Text(String::new())))
+}
+
+// ============================================================================
+// Tool Types
+// ============================================================================
+
+/// A tool the model may call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+impl Tool {
+    pub fn function(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: serde_json::Value,
+    ) -> Self {
         Self {
-            role: "assistant".to_string(),
-            content: content.into(),
+            kind: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.into(),
+                description: Some(description.into()),
+                parameters,
+            },
         }
     }
+
+    /// A function whose parameters are the JSON schema of `T`
+    #[cfg(feature = "schemars")]
+    pub fn function_for<T: schemars::JsonSchema>(
+        name: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        Self::function(name, description, schema::of::<T>())
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Whether and how the model calls tools
+///
+/// Serializes to `"none"`, `"auto"`, `"required"` or
+/// `{"type": "function", "function": {"name": ...}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "serde_json::Value", try_from = "serde_json::Value")]
+pub enum ToolChoice {
+    /// Answer in text without calling tools
+    None,
+    /// Call tools or not, as the model sees fit; the default
+    Auto,
+    /// Call at least one tool
+    Required,
+    /// Call the function with this name, e.g. to extract its arguments
+    Function(String),
+}
+
+impl ToolChoice {
+    pub fn function(name: impl Into<String>) -> Self {
+        Self::Function(name.into())
+    }
+}
+
+impl From<ToolChoice> for serde_json::Value {
+    fn from(choice: ToolChoice) -> Self {
+        match choice {
+            ToolChoice::None => "none".into(),
+            ToolChoice::Auto => "auto".into(),
+            ToolChoice::Required => "required".into(),
+            ToolChoice::Function(name) => {
+                serde_json::json!({ "type": "function", "function": { "name": name } })
+            }
+        }
+    }
+}
+
+impl TryFrom<serde_json::Value> for ToolChoice {
+    type Error = String;
+
+    fn try_from(value: serde_json::Value) -> std::result::Result<Self, String> {
+        match value.as_str() {
+            Some("none") => return Ok(Self::None),
+            Some("auto") => return Ok(Self::Auto),
+            Some("required") | Some("any") => return Ok(Self::Required),
+            _ => {}
+        }
+        value["function"]["name"]
+            .as_str()
+            .map(Self::function)
+            .ok_or_else(|| format!("Invalid tool_choice: {}", value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments object
+    pub parameters: serde_json::Value,
+}
+
+/// A call to a tool requested by the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as a JSON-encoded string
+    pub arguments: String,
+}
+
+/// Message content: either plain text or a list of typed parts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// All text in the content, with text parts concatenated
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The content parts, treating plain text as a single text part
+    pub fn parts(&self) -> Vec<ContentPart> {
+        match self {
+            MessageContent::Text(text) => vec![ContentPart::text(text.clone())],
+            MessageContent::Parts(parts) => parts.clone(),
+        }
+    }
+
+    /// All audio in the content
+    pub fn audio(&self) -> Vec<&InputAudio> {
+        match self {
+            MessageContent::Text(_) => Vec::new(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::InputAudio { input_audio } => Some(input_audio),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// All images in the content
+    pub fn images(&self) -> Vec<&ImageUrl> {
+        match self {
+            MessageContent::Text(_) => Vec::new(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::ImageUrl { image_url } => Some(image_url),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for MessageContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&String> for MessageContent {
+    fn from(text: &String) -> Self {
+        MessageContent::Text(text.clone())
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ContentPart>> for MessageContent {
+    fn from(parts: Vec<ContentPart>) -> Self {
+        MessageContent::Parts(parts)
+    }
+}
+
+/// A single part of a multimodal message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    /// Audio for models that take it, such as llama.cpp with an audio
+    /// projector loaded
+    InputAudio {
+        input_audio: InputAudio,
+    },
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl::new(url),
+        }
+    }
+
+    /// Build an inline image part from raw bytes
+    pub fn image_bytes(mime_type: &str, bytes: &[u8]) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl::from_bytes(mime_type, bytes),
+        }
+    }
+
+    /// Build an audio part from raw bytes in `format`, e.g. `"wav"` or `"mp3"`
+    pub fn audio_bytes(format: &str, bytes: &[u8]) -> Self {
+        ContentPart::InputAudio {
+            input_audio: InputAudio::from_bytes(format, bytes),
+        }
+    }
+
+    /// Read a WAV or MP3 file into an audio part
+    ///
+    /// The format comes from the file's contents, or its extension when the
+    /// contents are not recognised.
+    pub fn audio_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read audio from {}", path.display()))?;
+        let format = audio_format(&bytes)
+            .or_else(|| {
+                let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+                ["wav", "mp3"]
+                    .into_iter()
+                    .find(|format| *format == extension)
+            })
+            .with_context(|| format!("{} is not a WAV or MP3 file", path.display()))?;
+        Ok(Self::audio_bytes(format, &bytes))
+    }
+}
+
+/// Recognise WAV and MP3 data by its first bytes
+fn audio_format(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'A',
+            b'V',
+            b'E',
+            ..,
+        ] => Some("wav"),
+        // An ID3 tag, or the sync word of a bare MPEG audio frame
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some("mp3"),
+        _ => None,
+    }
+}
+
+/// Base64-encoded audio and its format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    pub data: String,
+    pub format: String,
+}
+
+impl InputAudio {
+    /// Encode raw audio bytes
+    pub fn from_bytes(format: &str, bytes: &[u8]) -> Self {
+        Self {
+            data: BASE64.encode(bytes),
+            format: format.to_string(),
+        }
+    }
+
+    /// Decode the raw audio bytes
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        BASE64
+            .decode(self.data.trim())
+            .context("Failed to decode base64 audio")
+    }
+}
+
+/// An image reference, either a remote URL or an inline `data:` URL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ImageUrl {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            detail: None,
+        }
+    }
+
+    /// Encode raw image bytes as a base64 `data:` URL
+    pub fn from_bytes(mime_type: &str, bytes: &[u8]) -> Self {
+        Self::new(format!(
+            "data:{};base64,{}",
+            mime_type,
+            BASE64.encode(bytes)
+        ))
+    }
+
+    /// Whether the image is embedded in the URL rather than hosted remotely
+    pub fn is_inline(&self) -> bool {
+        !self.url.starts_with("http://") && !self.url.starts_with("https://")
+    }
+
+    /// The MIME type of an inline `data:` URL image
+    pub fn mime_type(&self) -> Option<&str> {
+        let header = self.url.strip_prefix("data:")?.split(',').next()?;
+        header.split(';').next().filter(|mime| !mime.is_empty())
+    }
+
+    /// Decode the raw bytes of an inline image
+    ///
+    /// Accepts `data:` URLs as well as bare base64 payloads, which some
+    /// backends return without the URL wrapper.
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        if !self.is_inline() {
+            anyhow::bail!("Image is hosted remotely at {}", self.url);
+        }
+
+        let payload = match self.url.strip_prefix("data:") {
+            Some(rest) => {
+                let (header, data) = rest.split_once(',').context("Malformed data URL")?;
+                if !header.ends_with(";base64") {
+                    anyhow::bail!("Only base64 data URLs are supported");
+                }
+                data
+            }
+            None => self.url.as_str(),
+        };
+
+        BASE64
+            .decode(payload.trim())
+            .context("Failed to decode base64 image")
+    }
+
+    /// Decode an inline image and write it to `path`
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.bytes()?)
+            .with_context(|| format!("Failed to write image to {}", path.display()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompletionRequest {
     pub model: String,
     pub prompt: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    /// Reuse the KV cache of a matching prompt prefix (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_prompt: Option<bool>,
+    /// Run on this server slot rather than any idle one (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_slot: Option<u32>,
+    /// Attach [`Timings`] to every streamed chunk (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings_per_token: Option<bool>,
+    /// Report this many most likely candidates for every generated token
+    /// (llama.cpp extension)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_probs: Option<u32>,
+    /// Draft-model settings, for servers started with `--model-draft`
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub speculative: Option<Speculative>,
+    /// Fields sent as given, for server parameters this type does not model
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+}
+
+/// llama.cpp's per-request speculative decoding settings
+///
+/// With a draft model loaded, the server lets the draft propose up to
+/// `n_max` tokens at a time (and at least `n_min`), keeping only proposals
+/// the draft is at least `p_min` sure of, for the main model to verify in
+/// one pass. Unset values use the server's defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Speculative {
+    #[serde(rename = "speculative.n_max", skip_serializing_if = "Option::is_none")]
+    pub n_max: Option<u32>,
+    #[serde(rename = "speculative.n_min", skip_serializing_if = "Option::is_none")]
+    pub n_min: Option<u32>,
+    #[serde(rename = "speculative.p_min", skip_serializing_if = "Option::is_none")]
+    pub p_min: Option<f32>,
+}
+
+impl Speculative {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draft at most `n_max` tokens per step
+    pub fn n_max(mut self, n_max: u32) -> Self {
+        self.n_max = Some(n_max);
+        self
+    }
+
+    /// Draft at least `n_min` tokens per step
+    pub fn n_min(mut self, n_min: u32) -> Self {
+        self.n_min = Some(n_min);
+        self
+    }
+
+    /// Stop drafting at a token the draft model gives less than `p_min`
+    /// probability
+    pub fn p_min(mut self, p_min: f32) -> Self {
+        self.p_min = Some(p_min);
+        self
+    }
+}
+
+/// How the server should send embedding vectors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    /// JSON arrays of numbers
+    Float,
+    /// Base64 of little-endian `f32`s, about half the size of JSON numbers
+    Base64,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct EmbeddingRequest {
     pub model: String,
     pub input: String,
+    /// Decoded transparently either way; leave `None` for servers that do
+    /// not accept the field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<EncodingFormat>,
+    /// Keep only the first this many dimensions of each vector, applied by
+    /// the client
+    #[serde(skip)]
+    pub truncate: Option<usize>,
+    /// Scale each vector to unit length after any truncation, applied by the
+    /// client
+    #[serde(skip)]
+    pub normalize: bool,
+    /// Fields sent as given, for server parameters this type does not model
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenizeRequest {
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_special: Option<bool>,
+    /// Fields sent as given, for server parameters this type does not model
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Scoring documents against a query with a reranker model, on servers
+/// started with `--reranking`
+#[derive(Debug, Clone, Serialize)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<String>,
+    /// Return only the best this many results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<usize>,
+    /// Fields sent as given, for server parameters this type does not model
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
 }
 
 // ============================================================================
 // Response Types
 // ============================================================================
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionResponse {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    #[serde(default)]
     pub created: u64,
+    #[serde(default)]
     pub model: String,
     pub choices: Vec<ChatChoice>,
+    /// Zero when the server does not report usage
+    #[serde(default)]
     pub usage: Usage,
+    /// The upstream provider a gateway such as OpenRouter routed the request to
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// llama.cpp's measurements of the request
+    #[serde(default)]
+    pub timings: Option<Timings>,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// The id the client sent the request with, in `X-Request-Id`; `None`
+    /// for responses served from a cache
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// The id the server or a gateway reported for the request
+    #[serde(skip)]
+    pub server_request_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatChoice {
+    #[serde(default)]
     pub index: u32,
     pub message: Message,
-    pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub finish_reason: Option<FinishReason>,
+    /// The upstream provider's own finish reason, sent by gateways
+    #[serde(default)]
+    pub native_finish_reason: Option<String>,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Why the model stopped generating
+///
+/// Parsed from the `finish_reason` string; reasons this enum does not know
+/// are kept as [`FinishReason::Other`]. Serializes back to the same string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum FinishReason {
+    /// A natural end or a stop sequence
+    Stop,
+    /// The token limit was reached, so the reply is cut short
+    Length,
+    /// The model called tools; `function_call` from older APIs maps here too
+    ToolCalls,
+    /// The provider withheld content
+    ContentFilter,
+    /// Any other reason, as the server sent it
+    Other(String),
+}
+
+impl FinishReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Stop => "stop",
+            Self::Length => "length",
+            Self::ToolCalls => "tool_calls",
+            Self::ContentFilter => "content_filter",
+            Self::Other(reason) => reason,
+        }
+    }
+}
+
+impl From<String> for FinishReason {
+    fn from(reason: String) -> Self {
+        match reason.as_str() {
+            "stop" => Self::Stop,
+            "length" => Self::Length,
+            "tool_calls" | "function_call" => Self::ToolCalls,
+            "content_filter" => Self::ContentFilter,
+            _ => Self::Other(reason),
+        }
+    }
+}
+
+impl From<&str> for FinishReason {
+    fn from(reason: &str) -> Self {
+        Self::from(reason.to_string())
+    }
+}
+
+impl From<FinishReason> for String {
+    fn from(reason: FinishReason) -> Self {
+        match reason {
+            FinishReason::Other(reason) => reason,
+            reason => reason.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChatCompletionChunk {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    #[serde(default)]
     pub created: u64,
+    #[serde(default)]
     pub model: String,
     pub choices: Vec<ChatChoiceDelta>,
+    /// Token usage, sent by some servers with the final chunk
+    #[serde(default)]
+    pub usage: Option<Usage>,
+    /// The upstream provider a gateway routed the request to
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// llama.cpp's measurements so far, on the last chunk or on every
+    /// chunk with [`ChatCompletionRequest::timings_per_token`]
+    #[serde(default)]
+    pub timings: Option<Timings>,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChatChoiceDelta {
+    #[serde(default)]
     pub index: u32,
+    #[serde(default)]
     pub delta: Delta,
-    pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub finish_reason: Option<FinishReason>,
+    /// The upstream provider's own finish reason, sent by gateways
+    #[serde(default)]
+    pub native_finish_reason: Option<String>,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Delta {
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub role: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub content: Option<String>,
+    /// Reasoning text from thinking models, sent by llama.cpp as
+    /// `reasoning_content` and by gateways as `reasoning`
+    #[serde(default, alias = "reasoning")]
+    pub reasoning_content: Option<String>,
+    /// Fragments of the tool calls being generated
+    #[serde(default)]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A fragment of a streamed tool call
+///
+/// The first fragment of a call carries its `id` and function name, and the
+/// rest carry pieces of the JSON arguments; `index` says which call a
+/// fragment belongs to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+/// The function name and argument fragment of a [`ToolCallDelta`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    pub content: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub stop: Option<bool>,
+    #[serde(default)]
+    pub tokens_predicted: Option<u32>,
+    #[serde(default)]
+    pub tokens_evaluated: Option<u32>,
+    /// One entry per generated token when the request set `n_probs`
+    #[serde(default)]
+    pub completion_probabilities: Option<Vec<TokenProbabilities>>,
+    /// llama.cpp's measurements of the request
+    #[serde(default)]
+    pub timings: Option<Timings>,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// The id the client sent the request with, in `X-Request-Id`; `None`
+    /// for responses served from a cache
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// The id the server or a gateway reported for the request
+    #[serde(skip)]
+    pub server_request_id: Option<String>,
+}
+
+/// How long llama.cpp spent on a request, as it reports in `timings`
+///
+/// `prompt_n` counts the prompt tokens evaluated, which leaves out those
+/// reused from the KV cache (`cache_n`). The `draft_` fields are set with
+/// speculative decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timings {
+    pub prompt_n: u32,
+    pub prompt_ms: f64,
+    pub prompt_per_token_ms: f64,
+    pub prompt_per_second: f64,
+    pub predicted_n: u32,
+    pub predicted_ms: f64,
+    pub predicted_per_token_ms: f64,
+    pub predicted_per_second: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draft_n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draft_n_accepted: Option<u32>,
+}
+
+impl Timings {
+    /// Time spent evaluating the prompt
+    pub fn prompt_time(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.prompt_ms.max(0.0) / 1000.0)
+    }
+
+    /// Time spent generating
+    pub fn predicted_time(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.predicted_ms.max(0.0) / 1000.0)
+    }
+
+    /// The fraction of drafted tokens the main model accepted
+    pub fn draft_acceptance(&self) -> Option<f64> {
+        match (self.draft_n?, self.draft_n_accepted?) {
+            (0, _) => None,
+            (drafted, accepted) => Some(f64::from(accepted) / f64::from(drafted)),
+        }
+    }
+}
+
+/// A generated token and the candidates the model weighed for its position
+///
+/// Recent llama.cpp servers report log-probabilities, or plain probabilities
+/// when the request asked for `post_sampling_probs`; older ones report
+/// `content` with a `probs` list of `tok_str`/`prob` pairs. All three shapes
+/// parse into this type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenProbabilities {
+    #[serde(default)]
+    pub id: Option<u32>,
+    #[serde(alias = "content")]
+    pub token: String,
+    #[serde(default)]
+    pub logprob: Option<f64>,
+    #[serde(default)]
+    pub prob: Option<f64>,
+    /// The most likely candidates, best first
+    #[serde(default, alias = "top_probs", alias = "probs")]
+    pub top_logprobs: Vec<TokenProbability>,
+}
+
+/// One candidate token in [`TokenProbabilities::top_logprobs`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenProbability {
+    #[serde(default)]
+    pub id: Option<u32>,
+    #[serde(alias = "tok_str")]
+    pub token: String,
+    #[serde(default)]
+    pub logprob: Option<f64>,
+    #[serde(default)]
+    pub prob: Option<f64>,
+}
+
+impl TokenProbabilities {
+    /// The generated token's probability, from whichever form the server sent
+    ///
+    /// Older servers only report candidates, so this falls back to the
+    /// candidate matching the generated token.
+    pub fn probability(&self) -> Option<f64> {
+        probability(self.prob, self.logprob).or_else(|| {
+            self.top_logprobs
+                .iter()
+                .find(|candidate| candidate.token == self.token)
+                .and_then(TokenProbability::probability)
+        })
+    }
+}
+
+impl TokenProbability {
+    /// The candidate's probability, from whichever form the server sent
+    pub fn probability(&self) -> Option<f64> {
+        probability(self.prob, self.logprob)
+    }
+}
+
+fn probability(prob: Option<f64>, logprob: Option<f64>) -> Option<f64> {
+    prob.or_else(|| logprob.map(f64::exp))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingResponse {
+    #[serde(default)]
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    #[serde(default)]
+    pub model: String,
+    /// Zero when the server does not report usage
+    #[serde(default)]
+    pub usage: Usage,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// The id the client sent the request with, in `X-Request-Id`; `None`
+    /// for responses served from a cache
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// The id the server or a gateway reported for the request
+    #[serde(skip)]
+    pub server_request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingData {
+    #[serde(default)]
+    pub object: String,
+    /// Decoded from base64 when the server sent it that way
+    #[serde(deserialize_with = "floats_or_base64")]
+    pub embedding: Vec<f32>,
+    pub index: u32,
+}
+
+/// An embedding sent as a JSON array or, for
+/// [`EncodingFormat::Base64`], as base64 of little-endian `f32`s
+fn floats_or_base64<'de, D>(deserializer: D) -> std::result::Result<Vec<f32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct Embedding;
+
+    impl<'de> serde::de::Visitor<'de> for Embedding {
+        type Value = Vec<f32>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("an array of numbers or a base64 string")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> std::result::Result<Vec<f32>, A::Error> {
+            let mut vector = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(x) = seq.next_element()? {
+                vector.push(x);
+            }
+            Ok(vector)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, text: &str) -> std::result::Result<Vec<f32>, E> {
+            let bytes = BASE64.decode(text).map_err(E::custom)?;
+            if bytes.len() % 4 != 0 {
+                return Err(E::custom(format!(
+                    "base64 embedding has {} bytes, not a whole number of f32s",
+                    bytes.len()
+                )));
+            }
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+    }
+
+    deserializer.deserialize_any(Embedding)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenizeResponse {
+    pub tokens: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RerankResponse {
+    #[serde(default)]
+    pub model: String,
+    /// Best first, whatever order the server sent them in
+    pub results: Vec<RerankResult>,
+    /// Zero when the server does not report usage
+    #[serde(default)]
+    pub usage: Usage,
+    /// Fields this type does not model yet, such as ones added by newer
+    /// servers
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// The id the client sent the request with, in `X-Request-Id`
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// The id the server or a gateway reported for the request
+    #[serde(skip)]
+    pub server_request_id: Option<String>,
+}
+
+/// A document's relevance to the query
+#[derive(Debug, Clone, Deserialize)]
+pub struct RerankResult {
+    /// The document's position in [`RerankRequest::documents`]
+    pub index: usize,
+    /// Higher is more relevant; the scale depends on the model
+    pub relevance_score: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: Option<u32>,
+    #[serde(default)]
+    pub total_tokens: u32,
+    /// What the request cost, as reported by gateways that bill per request
+    /// (OpenRouter reports credits)
+    #[serde(default)]
+    pub cost: Option<f64>,
+    /// Details such as `prompt_tokens_details.cached_tokens`
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A model listed by `/v1/models`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(default)]
+    pub object: Option<String>,
+    #[serde(default)]
+    pub created: Option<u64>,
+    #[serde(default)]
+    pub owned_by: Option<String>,
+    /// Server-specific details, such as llama.cpp's context size and
+    /// parameter count
+    #[serde(default)]
+    pub meta: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct CompletionResponse {
-    pub content: String,
-    pub model: Option<String>,
-    pub stop: Option<bool>,
-    pub tokens_predicted: Option<u32>,
-    pub tokens_evaluated: Option<u32>,
-}
+#[derive(Debug, Clone, Deserialize)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+/// The model name that asks the client to use the server's loaded model
+pub const AUTO_MODEL: &str = "auto";
+
+// ============================================================================
+// Client
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct LlamaCppClient {
+    transport: Arc<dyn Transport>,
+    metrics: Vec<Arc<dyn MetricsObserver>>,
+    usage: Option<UsageTracker>,
+    cache: Option<ResponseCache>,
+    #[cfg(feature = "embedding-cache")]
+    embedding_cache: Option<EmbeddingCache>,
+    endpoints: Arc<endpoints::Endpoints>,
+    concurrency: Option<Arc<limits::ConcurrencyLimit>>,
+    queue_timeout: Option<std::time::Duration>,
+    timeouts: limits::Timeouts,
+    lifecycle: Arc<shutdown::Lifecycle>,
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    rate_limiter: Arc<limits::RateLimiter>,
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    retry: limits::Retries,
+    config: Arc<RwLock<Arc<LancorConfig>>>,
+    /// The seed of [`LlamaCppClient::deterministic`], applied to every config
+    /// this client loads
+    deterministic: Option<u64>,
+    /// The server's model, once discovered for an `"auto"` request
+    discovered_model: Arc<Mutex<Option<String>>>,
+    /// The server's context size, once looked up for automatic `max_tokens`
+    context_window: Arc<Mutex<Option<u32>>>,
+}
+
+impl LlamaCppClient {
+    /// Create a new client with the specified base URL
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        Self::from_config(LancorConfig::new(base_url))
+    }
+
+    /// Create a new client with the specified base URL and API key
+    pub fn with_api_key(base_url: impl Into<String>, api_key: impl Into<String>) -> Result<Self> {
+        Self::from_config(LancorConfig::new(base_url).api_key(api_key))
+    }
+
+    /// Create a client for the OpenAI API
+    ///
+    /// See [`LancorConfig::openai`]; set the organization or project on a
+    /// config and use [`Self::from_config`] when they are needed.
+    pub fn openai(api_key: impl Into<String>) -> Result<Self> {
+        Self::from_config(LancorConfig::openai(api_key))
+    }
+
+    /// Create a client for an Azure OpenAI resource
+    ///
+    /// See [`LancorConfig::azure`].
+    pub fn azure(resource_url: impl Into<String>, api_key: impl Into<String>) -> Result<Self> {
+        Self::from_config(LancorConfig::azure(resource_url, api_key))
+    }
+
+    /// Create a new client from a [`LancorConfig`]
+    pub fn from_config(config: LancorConfig) -> Result<Self> {
+        Ok(Self {
+            transport: Arc::new(transport::ReqwestTransport::new()?),
+            metrics: Vec::new(),
+            usage: None,
+            cache: None,
+            #[cfg(feature = "embedding-cache")]
+            embedding_cache: None,
+            endpoints: Arc::default(),
+            concurrency: None,
+            queue_timeout: None,
+            timeouts: limits::Timeouts::default(),
+            lifecycle: Arc::default(),
+            #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+            rate_limiter: Arc::new(limits::RateLimiter::new(config.rate_limit.as_ref())),
+            #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+            retry: limits::Retries::default(),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            deterministic: None,
+            discovered_model: Arc::default(),
+            context_window: Arc::default(),
+        })
+    }
+
+    /// Create a client from the named profile in the user's config file
+    ///
+    /// See [`profiles`] for the file's location and format.
+    #[cfg(feature = "profiles")]
+    pub fn from_profile(name: &str) -> Result<Self> {
+        Profiles::load()?.client(name)
+    }
+
+    /// Create a client connecting to localhost:8080
+    #[allow(clippy::should_implement_trait)]
+    pub fn default() -> Result<Self> {
+        Self::new("http://localhost:8080")
+    }
+
+    /// Use `presets` to fill in and override chat request parameters
+    pub fn with_presets(self, presets: Presets) -> Self {
+        let config = LancorConfig::clone(&self.config()).presets(presets);
+        self.with_own_config(config)
+    }
+
+    /// Sample chat requests deterministically with `seed` unless they set
+    /// their own sampling parameters; see
+    /// [`ChatCompletionRequest::deterministic`]
+    ///
+    /// This fills the client's default [`Preset`], so named presets and
+    /// per-model overrides still apply. The setting belongs to the client,
+    /// not its config, so [`LlamaCppClient::reload_config`] keeps it.
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.deterministic = Some(seed);
+        let config = LancorConfig::clone(&self.config());
+        self.with_own_config(config)
+    }
+
+    /// Send requests through `transport` instead of the default reqwest client
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Allow at most `max` requests in progress across this client and its
+    /// clones; further requests wait for a free slot
+    ///
+    /// A streaming request holds its slot until the stream is dropped. A
+    /// `max` of 0 is treated as 1.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.concurrency = Some(Arc::new(limits::ConcurrencyLimit::new(max)));
+        self
+    }
+
+    /// Fail requests that waited longer than `timeout` for a slot under
+    /// [`LlamaCppClient::max_concurrent_requests`]; needs the `runtime-tokio`
+    /// feature and is not applied on `wasm32`
+    pub fn queue_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit how long requests may take; see [`Timeouts`]
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Answer repeated embedding requests, and chat and text completion
+    /// requests with temperature 0 or a fixed seed, from `cache`; see
+    /// [`cache`]
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn cache(&self) -> Option<&ResponseCache> {
+        self.cache.as_ref()
+    }
+
+    /// Look up and store embedding vectors in `cache`; see [`EmbeddingCache`]
+    #[cfg(feature = "embedding-cache")]
+    pub fn with_embedding_cache(mut self, cache: EmbeddingCache) -> Self {
+        self.embedding_cache = Some(cache);
+        self
+    }
+
+    #[cfg(feature = "embedding-cache")]
+    pub fn embedding_cache(&self) -> Option<&EmbeddingCache> {
+        self.embedding_cache.as_ref()
+    }
+
+    /// Stop sending requests to servers that keep failing; see
+    /// [`CircuitBreaker`]
+    ///
+    /// Resets what the client has learned about its servers' health, so call
+    /// it while building the client.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.endpoints = Arc::new(endpoints::Endpoints::with_circuit_breaker(breaker));
+        self
+    }
+
+    /// Hold requests back to stay within `limit`; see [`RateLimit`]
+    ///
+    /// Sets [`LancorConfig::rate_limit`], so a later
+    /// [`LlamaCppClient::reload_config`] replaces it.
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    pub fn with_rate_limit(self, limit: RateLimit) -> Self {
+        let config = LancorConfig::clone(&self.config()).rate_limit(limit);
+        self.with_own_config(config)
+    }
+
+    /// Retry requests that fail with connection errors or overload
+    /// responses, honouring `Retry-After`; see [`RetryPolicy`]
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry.default = Some(policy);
+        self
+    }
+
+    /// Retry requests of `kind` with `policy` instead of the policy set with
+    /// [`LlamaCppClient::with_retry`]
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    pub fn with_retry_for(mut self, kind: RequestKind, policy: RetryPolicy) -> Self {
+        self.retry.by_kind.insert(kind, Some(policy));
+        self
+    }
+
+    /// Never retry requests of `kind`, e.g. chat requests whose tool calls
+    /// have side effects
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    pub fn without_retry_for(mut self, kind: RequestKind) -> Self {
+        self.retry.by_kind.insert(kind, None);
+        self
+    }
+
+    /// Report every request to `observer`, in addition to any observers
+    /// already installed; see [`metrics`]
+    pub fn with_metrics(mut self, observer: impl MetricsObserver + 'static) -> Self {
+        self.metrics.push(Arc::new(observer));
+        self
+    }
+
+    /// Record token usage in `tracker` and refuse requests once its budget is
+    /// spent; see [`usage`]
+    pub fn with_usage_tracker(mut self, tracker: UsageTracker) -> Self {
+        self.metrics.push(Arc::new(tracker.clone()));
+        self.usage = Some(tracker);
+        self
+    }
+
+    /// The usage tracker installed with [`LlamaCppClient::with_usage_tracker`]
+    pub fn usage_tracker(&self) -> Option<&UsageTracker> {
+        self.usage.as_ref()
+    }
+
+    /// Check the budget and start reporting a request to the observers
+    fn observe(&self, endpoint: &'static str, model: &str) -> Result<metrics::Observation> {
+        if let Some(usage) = &self.usage {
+            usage.check_budget()?;
+        }
+        #[allow(unused_mut)]
+        let mut observers = self.metrics.clone();
+        // The rate limiter charges tokens from the usage each request reports
+        #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+        observers.push(self.rate_limiter.clone());
+        Ok(metrics::Observation::start(
+            observers,
+            metrics::RequestInfo::new(endpoint, model),
+        ))
+    }
+
+    /// Write every request and response to `sink`, scrubbed by `redactor`;
+    /// see [`logging`]
+    pub fn with_logging(
+        mut self,
+        sink: impl logging::LogSink + 'static,
+        redactor: logging::Redactor,
+    ) -> Self {
+        self.transport = Arc::new(logging::LoggingTransport::wrap(
+            self.transport,
+            Arc::new(sink),
+            redactor,
+        ));
+        self
+    }
+
+    /// Record requests to, or replay them from, the fixture file at `path`;
+    /// see [`fixtures`]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_fixtures(
+        mut self,
+        path: impl Into<std::path::PathBuf>,
+        mode: fixtures::FixtureMode,
+    ) -> Result<Self> {
+        let transport = fixtures::FixtureTransport::wrap(self.transport, path.into(), mode)?;
+        self.transport = Arc::new(transport);
+        Ok(self)
+    }
+
+    /// Load and health of each configured server, as used for routing
+    pub fn endpoint_status(&self) -> Vec<pool::EndpointStatus> {
+        self.endpoints.status(&self.config())
+    }
+
+    /// Probe `/health` and `/slots` on every configured server and route new
+    /// requests accordingly; see [`pool::HealthMonitor`] to do this
+    /// periodically
+    pub async fn probe_health(&self) {
+        let config = self.config();
+        let probes = config.endpoints().map(|base_url| async {
+            let health = self.probe(&config, base_url).await;
+            self.endpoints.set_health(base_url, health);
+        });
+        futures::future::join_all(probes).await;
+    }
+
+    async fn probe(&self, config: &LancorConfig, base_url: &str) -> endpoints::Health {
+        let ready = match self.get(config, &format!("{}/health", base_url)).await {
+            Ok(response) => response.is_success(),
+            Err(_) => false,
+        };
+        if !ready {
+            return endpoints::Health {
+                ready,
+                free_slots: None,
+            };
+        }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct EmbeddingResponse {
-    pub object: String,
-    pub data: Vec<EmbeddingData>,
-    pub model: String,
-    pub usage: Usage,
-}
+        // /slots is disabled on some servers; then the slot count is unknown
+        let free_slots = match self.get(config, &format!("{}/slots", base_url)).await {
+            Ok(response) if response.is_success() => response
+                .json::<Vec<serde_json::Value>>()
+                .await
+                .ok()
+                .map(|slots| slots.iter().filter(|slot| slot_is_idle(slot)).count()),
+            _ => None,
+        };
+        endpoints::Health { ready, free_slots }
+    }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct EmbeddingData {
-    pub object: String,
-    pub embedding: Vec<f32>,
-    pub index: u32,
-}
+    /// GET `url` without failover, returning whatever status the server sends
+    async fn get(&self, config: &LancorConfig, url: &str) -> Result<transport::HttpResponse> {
+        let url = config.with_auth_query(url.to_string());
+        let request = config.authorize(transport::HttpRequest::get(url));
+        self.transport.send(request).await
+    }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct Usage {
-    pub prompt_tokens: u32,
-    pub completion_tokens: Option<u32>,
-    pub total_tokens: u32,
-}
+    /// A snapshot of the current configuration
+    pub fn config(&self) -> Arc<LancorConfig> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
 
-// ============================================================================
-// Client
-// ============================================================================
+    /// Replace the configuration of this client and all of its clones
+    ///
+    /// Requests already in flight finish with the configuration they started
+    /// with. The rate limiter switches to the new [`LancorConfig::rate_limit`]
+    /// at once, for requests still waiting for it as well. A seed set with
+    /// [`LlamaCppClient::deterministic`] is applied to the new config too.
+    pub fn reload_config(&self, mut config: LancorConfig) {
+        if let Some(seed) = self.deterministic {
+            config.presets.defaults = config.presets.defaults.deterministic(seed);
+        }
+        #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+        self.rate_limiter.set(config.rate_limit.as_ref());
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+        // The new servers may have other models loaded
+        self.forget_discovered_model();
+    }
 
-#[derive(Debug, Clone)]
-pub struct LlamaCppClient {
-    http_client: HttpClient,
-    base_url: String,
-    api_key: Option<String>,
-}
+    /// Switch this client, and clones made from it later, to `config`,
+    /// leaving earlier clones as they are
+    ///
+    /// Builders use this so that `client.clone().with_presets(...)` does not
+    /// change `client`; [`LlamaCppClient::reload_config`] is for changing
+    /// every clone at once.
+    fn with_own_config(mut self, config: LancorConfig) -> Self {
+        self.config = Arc::new(RwLock::new(self.config()));
+        #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+        {
+            self.rate_limiter = Arc::new(limits::RateLimiter::unlimited());
+        }
+        self.discovered_model = Arc::default();
+        self.reload_config(config);
+        self
+    }
 
-impl LlamaCppClient {
-    /// Create a new client with the specified base URL
-    pub fn new(base_url: impl Into<String>) -> Result<Self> {
-        let http_client = HttpClient::builder()
-            .timeout(Duration::from_secs(300))
-            .connect_timeout(Duration::from_secs(10))
-            .build()
-            .context("Failed to build HTTP client")?;
+    /// List the models the server offers (`/v1/models`)
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let config = self.config();
+        let observation = self.observe("list_models", "")?;
+        let response = async {
+            let request = transport::HttpRequest::get(String::new());
+            let list: ModelList = self
+                .dispatch(&config, request, "/v1/models", "model list")
+                .await?
+                .0
+                .json()
+                .await
+                .context("Failed to parse model list")?;
+            Ok(list.data)
+        }
+        .await;
+        observation.finish(response, |_, _| {})
+    }
 
-        Ok(Self {
-            http_client,
-            base_url: base_url.into(),
-            api_key: None,
-        })
+    /// The model the server has loaded: the first one `/v1/models` lists
+    ///
+    /// Requests whose model is empty or [`AUTO_MODEL`], and for which no
+    /// [`LancorConfig::default_model`] is set, are sent with this model. It
+    /// is looked up once and remembered until the configuration is reloaded
+    /// or [`LlamaCppClient::forget_discovered_model`] is called.
+    pub async fn discover_model(&self) -> Result<String> {
+        if let Some(model) = self.lock_discovered_model().clone() {
+            return Ok(model);
+        }
+        let model = self
+            .list_models()
+            .await?
+            .into_iter()
+            .next()
+            .map(|model| model.id)
+            .context("The server lists no models")?;
+        *self.lock_discovered_model() = Some(model.clone());
+        Ok(model)
     }
 
-    /// Create a new client with the specified base URL and API key
-    pub fn with_api_key(base_url: impl Into<String>, api_key: impl Into<String>) -> Result<Self> {
-        let http_client = HttpClient::builder()
-            .timeout(Duration::from_secs(300))
-            .connect_timeout(Duration::from_secs(10))
-            .build()
-            .context("Failed to build HTTP client")?;
+    /// Look the server's model, and its context size, up again on the next
+    /// request that needs them, e.g. after it has been restarted with
+    /// another model
+    pub fn forget_discovered_model(&self) {
+        *self.lock_discovered_model() = None;
+        *self
+            .context_window
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+    }
 
-        Ok(Self {
-            http_client,
-            base_url: base_url.into(),
-            api_key: Some(api_key.into()),
-        })
+    fn lock_discovered_model(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        self.discovered_model
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
     }
 
-    /// Create a client connecting to localhost:8080
-    pub fn default() -> Result<Self> {
-        Self::new("http://localhost:8080")
+    /// Fill in an empty or `"auto"` model from the configuration or else the
+    /// server
+    async fn resolve_model(&self, config: &LancorConfig, model: &mut String) -> Result<()> {
+        config.fill_model(model);
+        if model.is_empty() || model == AUTO_MODEL {
+            *model = self.discover_model().await?;
+        }
+        Ok(())
+    }
+
+    /// The chat request as it will be sent, after defaults, the selected
+    /// preset and per-model overrides are applied
+    pub fn effective_request(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionRequest> {
+        Ok(self.explain_request(request)?.request)
+    }
+
+    /// Like [`LlamaCppClient::effective_request`], but also reports which
+    /// layer changed which parameter
+    pub fn explain_request(&self, request: &ChatCompletionRequest) -> Result<Resolution> {
+        let config = self.config();
+        let mut request = request.clone();
+        config.fill_model(&mut request.model);
+        config.presets.resolve(&request)
     }
 
     /// Send a chat completion request
     pub async fn chat_completion(
         &self,
-        request: ChatCompletionRequest,
+        mut request: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse> {
-        let url = format!("{}/v1/chat/completions", self.base_url);
-
-        let mut req = self.http_client.post(&url).json(&request);
-
-        if let Some(api_key) = &self.api_key {
-            req = req.header("Authorization", format!("Bearer {}", api_key));
+        let config = self.config();
+        self.resolve_model(&config, &mut request.model).await?;
+        let mut request = config.presets.resolve(&request)?.request;
+        let path = "/v1/chat/completions";
+        let deterministic = request.temperature == Some(0.0) || request.seed.is_some();
+        let cache = self.cache_entry(path, &request, deterministic);
+        if let Some((cache, key)) = &cache
+            && let Some(response) = cache.get(key)
+        {
+            return Ok(response);
         }
-
-        let response = req
-            .send()
-            .await
-            .context("Failed to send chat completion request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("API error ({}): {}", status, error_text);
+        self.fill_max_tokens(&config, &mut request).await?;
+        let observation = self.observe("chat_completion", &request.model)?;
+
+        let response = async {
+            let body = config.chat_body(&request)?;
+            let (response, request_id) = self.post(&config, path, &body, "chat completion").await?;
+            let server_request_id = response.request_id().map(str::to_string);
+            let mut parsed: ChatCompletionResponse = cache::read_json(response, cache)
+                .await
+                .context("Failed to parse chat completion response")?;
+            parsed.request_id = Some(request_id);
+            parsed.server_request_id = server_request_id;
+            Ok(parsed)
         }
+        .await;
 
-        response
-            .json()
-            .await
-            .context("Failed to parse chat completion response")
+        observation.finish(response, |o, r: &ChatCompletionResponse| o.usage(&r.usage))
     }
 
     /// Send a streaming chat completion request
     pub async fn chat_completion_stream(
         &self,
-        request: ChatCompletionRequest,
-    ) -> Result<impl futures::Stream<Item = Result<ChatCompletionChunk>>> {
-        let url = format!("{}/v1/chat/completions", self.base_url);
+        mut request: ChatCompletionRequest,
+    ) -> Result<impl futures::Stream<Item = Result<ChatCompletionChunk>> + use<>> {
+        let config = self.config();
+        self.resolve_model(&config, &mut request.model).await?;
+        let mut request = config.presets.resolve(&request)?.request;
+        self.fill_max_tokens(&config, &mut request).await?;
+
+        let body = config.chat_body(&request)?;
+        let observation = self.observe("chat_completion_stream", &request.model)?;
+
+        let response = self
+            .post_stream(
+                &config,
+                "/v1/chat/completions",
+                &body,
+                "streaming chat completion",
+            )
+            .await;
+        let (response, request_id) = observation.finish(response, |_, _| {})?;
+        let server_request_id = response.request_id().map(str::to_string);
+
+        // The observation lives in the closure, so the request ends when the
+        // stream is dropped
+        let chunks = sse_chunks::<ChatCompletionChunk>(response, request_id, server_request_id);
+        let stream = chunks.map(move |chunk| {
+            match &chunk {
+                Ok(chunk) => {
+                    if let Some(usage) = &chunk.usage {
+                        observation.usage(usage);
+                    }
+                }
+                Err(err) => observation.error(err),
+            }
+            chunk
+        });
 
-        let mut req = self.http_client.post(&url).json(&request);
+        Ok(stream)
+    }
 
-        if let Some(api_key) = &self.api_key {
-            req = req.header("Authorization", format!("Bearer {}", api_key));
+    /// Send a text completion request
+    pub async fn completion(&self, mut request: CompletionRequest) -> Result<CompletionResponse> {
+        let config = self.config();
+        self.resolve_model(&config, &mut request.model).await?;
+        let path = "/v1/completions";
+        let deterministic = request.temperature == Some(0.0) || request.seed.is_some();
+        let cache = self.cache_entry(path, &request, deterministic);
+        if let Some((cache, key)) = &cache
+            && let Some(response) = cache.get(key)
+        {
+            return Ok(response);
         }
-
-        let response = req
-            .send()
-            .await
-            .context("Failed to send streaming chat completion request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("API error({}): {}", status, error_text);
+        let observation = self.observe("completion", &request.model)?;
+
+        let response = async {
+            let body = config.completion_body(&request)?;
+            let (response, request_id) = self.post(&config, path, &body, "completion").await?;
+            let server_request_id = response.request_id().map(str::to_string);
+            let mut parsed: CompletionResponse = cache::read_json(response, cache)
+                .await
+                .context("Failed to parse completion response")?;
+            parsed.request_id = Some(request_id);
+            parsed.server_request_id = server_request_id;
+            Ok(parsed)
         }
+        .await;
 
-        let stream = response.bytes_stream().map(|result| {
-            let bytes = result.context("Failed to read stream chunk")?;
-            let text = String::from_utf8_lossy(&bytes);
+        observation.finish(response, |o, r: &CompletionResponse| {
+            o.tokens(
+                r.tokens_evaluated.unwrap_or(0),
+                r.tokens_predicted.unwrap_or(0),
+            )
+        })
+    }
 
-            for line in text.lines() {
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
-                    if data == "[DONE]" {
-                        continue;
+    /// Send a streaming text completion request
+    ///
+    /// Each item carries the newly generated text and, when the request set
+    /// `n_probs`, the probabilities of the tokens in it. The last item has
+    /// `stop` set along with the token counts.
+    pub async fn completion_stream(
+        &self,
+        mut request: CompletionRequest,
+    ) -> Result<impl futures::Stream<Item = Result<CompletionResponse>> + use<>> {
+        let config = self.config();
+        self.resolve_model(&config, &mut request.model).await?;
+        let request = request.stream(true);
+        let body = config.completion_body(&request)?;
+        let observation = self.observe("completion_stream", &request.model)?;
+
+        let response = self
+            .post_stream(&config, "/v1/completions", &body, "streaming completion")
+            .await;
+        let (response, request_id) = observation.finish(response, |_, _| {})?;
+        let server_request_id = response.request_id().map(str::to_string);
+
+        let chunks = sse_chunks::<CompletionResponse>(response, request_id, server_request_id);
+        let stream = chunks.map(move |chunk| {
+            match &chunk {
+                Ok(chunk) => {
+                    if let (Some(evaluated), Some(predicted)) =
+                        (chunk.tokens_evaluated, chunk.tokens_predicted)
+                    {
+                        observation.tokens(evaluated, predicted);
                     }
-                    let chunk: ChatCompletionChunk =
-                        serde_json::from_str(data).context("Failed to parse chunk")?;
-                    return Ok(chunk);
                 }
+                Err(err) => observation.error(err),
             }
-
-            anyhow::bail!("No valid data in chunk")
+            chunk
         });
 
         Ok(stream)
     }
 
-    /// Send a text completion request
-    pub async fn completion(&self, request: CompletionRequest) -> Result<CompletionResponse> {
-        let url = format!("{}/v1/completions", self.base_url);
+    /// Send an embedding request
+    pub async fn embedding(&self, mut request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        let config = self.config();
+        self.resolve_model(&config, &mut request.model).await?;
+        // The embedding cache is keyed by model and text only
+        #[cfg(feature = "embedding-cache")]
+        let embedding_cache = self
+            .embedding_cache
+            .as_ref()
+            .filter(|_| request.extra_body.is_empty());
+        #[cfg(feature = "embedding-cache")]
+        if let Some(cache) = embedding_cache
+            && let Some(mut embedding) = cache.get(&request.model, &request.input)
+        {
+            request.post_process(&mut embedding);
+            return Ok(EmbeddingResponse {
+                object: "list".to_string(),
+                data: vec![EmbeddingData {
+                    object: "embedding".to_string(),
+                    embedding,
+                    index: 0,
+                }],
+                model: request.model,
+                usage: Usage::default(),
+                extra: Default::default(),
+                request_id: None,
+                server_request_id: None,
+            });
+        }
+
+        let path = "/v1/embeddings";
+        let cache = self.cache_entry(path, &request, true);
+        if let Some((cache, key)) = &cache
+            && let Some(mut response) = cache.get::<EmbeddingResponse>(key)
+        {
+            for data in &mut response.data {
+                request.post_process(&mut data.embedding);
+            }
+            return Ok(response);
+        }
+        let observation = self.observe("embedding", &request.model)?;
+
+        let response = async {
+            let (response, request_id) = self.post(&config, path, &request, "embedding").await?;
+            let server_request_id = response.request_id().map(str::to_string);
+            let mut parsed: EmbeddingResponse = cache::read_json(response, cache)
+                .await
+                .context("Failed to parse embedding response")?;
+            parsed.request_id = Some(request_id);
+            parsed.server_request_id = server_request_id;
+            Ok(parsed)
+        }
+        .await;
+
+        let mut response =
+            observation.finish(response, |o, r: &EmbeddingResponse| o.usage(&r.usage))?;
+        // The embedding cache keeps vectors as the server sent them
+        #[cfg(feature = "embedding-cache")]
+        if let Some(cache) = embedding_cache
+            && let [data] = response.data.as_slice()
+        {
+            cache.insert(&request.model, &request.input, data.embedding.clone());
+        }
+        for data in &mut response.data {
+            request.post_process(&mut data.embedding);
+        }
+        Ok(response)
+    }
+
+    /// Embed several texts with `model` in one request, returning their
+    /// vectors in order
+    ///
+    /// Texts found in the [`EmbeddingCache`] are not sent; the rest are
+    /// added to it.
+    pub async fn embedding_batch<S: AsRef<str>>(
+        &self,
+        model: &str,
+        inputs: &[S],
+    ) -> Result<Vec<Vec<f32>>> {
+        let config = self.config();
+        let mut model = model.to_string();
+        self.resolve_model(&config, &mut model).await?;
+        let model = model.as_str();
+        #[cfg(feature = "embedding-cache")]
+        let mut vectors: Vec<Option<Vec<f32>>> = inputs
+            .iter()
+            .map(|input| {
+                let cache = self.embedding_cache.as_ref()?;
+                cache.get(model, input.as_ref())
+            })
+            .collect();
+        #[cfg(not(feature = "embedding-cache"))]
+        let mut vectors: Vec<Option<Vec<f32>>> = vec![None; inputs.len()];
+        let missing: Vec<usize> = (0..inputs.len())
+            .filter(|&i| vectors[i].is_none())
+            .collect();
+        if missing.is_empty() {
+            return Ok(vectors.into_iter().flatten().collect());
+        }
 
-        let mut req = self.http_client.post(&url).json(&request);
+        #[derive(Serialize)]
+        struct BatchRequest<'a> {
+            model: &'a str,
+            input: Vec<&'a str>,
+        }
+        let request = BatchRequest {
+            model,
+            input: missing.iter().map(|&i| inputs[i].as_ref()).collect(),
+        };
+
+        let observation = self.observe("embedding_batch", model)?;
+        let response = async {
+            self.post(&config, "/v1/embeddings", &request, "embedding")
+                .await?
+                .0
+                .json()
+                .await
+                .context("Failed to parse embedding response")
+        }
+        .await;
+        let response =
+            observation.finish(response, |o, r: &EmbeddingResponse| o.usage(&r.usage))?;
+
+        if response.data.len() != missing.len() {
+            anyhow::bail!(
+                "Expected {} embeddings, the server returned {}",
+                missing.len(),
+                response.data.len()
+            );
+        }
+        for data in response.data {
+            let i = *missing
+                .get(data.index as usize)
+                .with_context(|| format!("Embedding index {} out of range", data.index))?;
+            #[cfg(feature = "embedding-cache")]
+            if let Some(cache) = &self.embedding_cache {
+                cache.insert(model, inputs[i].as_ref(), data.embedding.clone());
+            }
+            vectors[i] = Some(data.embedding);
+        }
+        vectors
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .context("Server returned duplicate embedding indices")
+    }
 
-        if let Some(api_key) = &self.api_key {
-            req = req.header("Authorization", format!("Bearer {}", api_key));
+    /// Score `request.documents` by relevance to `request.query` with a
+    /// reranker model (`/v1/rerank`), best first
+    pub async fn rerank(&self, mut request: RerankRequest) -> Result<RerankResponse> {
+        let config = self.config();
+        self.resolve_model(&config, &mut request.model).await?;
+        let observation = self.observe("rerank", &request.model)?;
+
+        let response = async {
+            let (response, request_id) =
+                self.post(&config, "/v1/rerank", &request, "rerank").await?;
+            let server_request_id = response.request_id().map(str::to_string);
+            let mut parsed: RerankResponse = response
+                .json()
+                .await
+                .context("Failed to parse rerank response")?;
+            if let Some(result) = parsed
+                .results
+                .iter()
+                .find(|result| result.index >= request.documents.len())
+            {
+                anyhow::bail!("Rerank result index {} out of range", result.index);
+            }
+            parsed
+                .results
+                .sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+            parsed.request_id = Some(request_id);
+            parsed.server_request_id = server_request_id;
+            Ok(parsed)
         }
+        .await;
 
-        let response = req
-            .send()
-            .await
-            .context("Failed to send completion request")?;
+        observation.finish(response, |o, r: &RerankResponse| o.usage(&r.usage))
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("API error ({}): {}", status, error_text);
+    /// Tokenize text with the server's model (llama.cpp native `/tokenize` endpoint)
+    pub async fn tokenize(&self, request: TokenizeRequest) -> Result<TokenizeResponse> {
+        let config = self.config();
+        let observation = self.observe("tokenize", "")?;
+
+        let response = async {
+            self.post(&config, "/tokenize", &request, "tokenize")
+                .await?
+                .0
+                .json()
+                .await
+                .context("Failed to parse tokenize response")
         }
+        .await;
 
-        response
-            .json()
-            .await
-            .context("Failed to parse completion response")
+        observation.finish(response, |_, _| {})
     }
 
-    /// Send an embedding request
-    pub async fn embedding(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
-        let url = format!("{}/v1/embeddings", self.base_url);
+    /// The cache and key to use for sending `body` to `path`, if it is
+    /// `deterministic` and a cache is installed
+    fn cache_entry(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+        deterministic: bool,
+    ) -> Option<(ResponseCache, cache::CacheKey)> {
+        let cache = self.cache.as_ref().filter(|_| deterministic)?;
+        let key = cache::CacheKey::new(path, body).ok()?;
+        Some((cache.clone(), key))
+    }
 
-        let mut req = self.http_client.post(&url).json(&request);
+    /// POST `body` as JSON to `path` and fail on a non-success status
+    ///
+    /// Connection errors and 5xx responses fail over to the next configured
+    /// server.
+    async fn post(
+        &self,
+        config: &LancorConfig,
+        path: &str,
+        body: &impl Serialize,
+        action: &str,
+    ) -> Result<(transport::HttpResponse, String)> {
+        let request = transport::HttpRequest::post_json(String::new(), body)?;
+        self.dispatch(config, request, path, action).await
+    }
 
-        if let Some(api_key) = &self.api_key {
-            req = req.header("Authorization", format!("Bearer {}", api_key));
-        }
+    /// Post `body` as JSON to `path` for a server-sent event stream
+    async fn post_stream(
+        &self,
+        config: &LancorConfig,
+        path: &str,
+        body: &impl Serialize,
+        action: &str,
+    ) -> Result<(transport::HttpResponse, String)> {
+        let request = transport::HttpRequest::post_json(String::new(), body)?
+            .header("Accept", "text/event-stream");
+        self.dispatch(config, request, path, action).await
+    }
+
+    /// Send `request` to `path` on the first server that accepts it, failing
+    /// over on connection errors and 5xx responses
+    ///
+    /// The request is sent with a new id in `X-Request-Id`, unless it already
+    /// has one; the id is returned with the response.
+    async fn dispatch(
+        &self,
+        config: &LancorConfig,
+        request: transport::HttpRequest,
+        path: &str,
+        action: &str,
+    ) -> Result<(transport::HttpResponse, String)> {
+        let mut request = config.authorize(request);
+        let path = &config.with_auth_query(config.route(path, &request)?);
+        let request_id = match request.header_value(transport::REQUEST_ID_HEADER) {
+            Some(id) => id.to_string(),
+            None => {
+                let id = transport::new_request_id();
+                request = request.header(transport::REQUEST_ID_HEADER, id.clone());
+                id
+            }
+        };
+
+        let outstanding = self.lifecycle.start(&request_id, action)?;
+
+        let send = async {
+            #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+            if let Some(retry) = self.retry.policy(path) {
+                let mut attempt = 0;
+                loop {
+                    let err = match self.send_once(config, &request, path, action).await {
+                        Ok(response) => return Ok(response),
+                        Err(err) => err,
+                    };
+                    let Some(delay) = retry.delay(attempt, &err) else {
+                        return Err(err);
+                    };
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                }
+            }
+
+            self.send_once(config, &request, path, action).await
+        };
+
+        #[cfg(feature = "tracing")]
+        let send = {
+            use tracing::Instrument;
+            let span = tracing::info_span!(
+                "lancor_request",
+                request_id = %request_id,
+                action,
+                path = %path,
+                server_request_id = tracing::field::Empty,
+                status = tracing::field::Empty,
+            );
+            let recorder = span.clone();
+            async move {
+                let result = send.await;
+                let (status, server_request_id) = match &result {
+                    Ok(response) => (Some(response.status), response.request_id()),
+                    Err(err) => match err.downcast_ref::<ApiError>() {
+                        Some(api_error) => (
+                            Some(api_error.status),
+                            api_error.server_request_id.as_deref(),
+                        ),
+                        None => (None, None),
+                    },
+                };
+                if let Some(status) = status {
+                    recorder.record("status", status);
+                }
+                if let Some(id) = server_request_id {
+                    recorder.record("server_request_id", id);
+                }
+                if let Err(err) = &result {
+                    tracing::warn!(parent: &recorder, error = %err, "request failed");
+                }
+                result
+            }
+            .instrument(span)
+        };
 
-        let response = req
-            .send()
-            .await
-            .context("Failed to send embedding request")?;
+        let response = outstanding.run(send).await??;
+        Ok((outstanding.track(response), request_id))
+    }
+
+    /// Send `request` to the first server that takes it, within the client's
+    /// limits
+    async fn send_once(
+        &self,
+        config: &LancorConfig,
+        request: &transport::HttpRequest,
+        path: &str,
+        action: &str,
+    ) -> Result<transport::HttpResponse> {
+        let request_id = request
+            .header_value(transport::REQUEST_ID_HEADER)
+            .map(str::to_string);
+        let mut permit = match &self.concurrency {
+            Some(concurrency) => Some(concurrency.acquire(self.queue_timeout).await?),
+            None => None,
+        };
+
+        #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+        self.rate_limiter.acquire().await;
+
+        let mut last_error = None;
+        for base_url in self.endpoints.order(config) {
+            let mut request = request.clone();
+            request.url = format!("{}{}", base_url, path);
+            if !self.endpoints.admit(&base_url) {
+                last_error.get_or_insert_with(|| {
+                    anyhow::anyhow!(
+                        "Circuit breaker open for {}; {} request not sent",
+                        base_url,
+                        action
+                    )
+                });
+                continue;
+            }
+            let in_flight = self.endpoints.start(&base_url);
+
+            #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+            let sent = {
+                let streaming = request.header_value("accept") == Some("text/event-stream");
+                self.timeouts
+                    .apply(path, streaming, self.transport.send(request))
+                    .await
+            };
+            #[cfg(not(all(feature = "runtime-tokio", not(target_arch = "wasm32"))))]
+            let sent = self.transport.send(request).await;
+            let response = match sent {
+                Ok(response) => response,
+                Err(err) => {
+                    self.endpoints.mark_failed(&base_url);
+                    last_error = Some(err.context(format!("Failed to send {} request", action)));
+                    continue;
+                }
+            };
+
+            if !response.is_success() {
+                let status = response.status;
+                let retry_after = response.header_value("retry-after").map(str::to_string);
+                let server_request_id = response.request_id().map(str::to_string);
+                let error_text = response.text().await.unwrap_or_default();
+                let mut api_error =
+                    ApiError::new(status, error_text).with_retry_after(retry_after.as_deref());
+                api_error.request_id = request_id.clone();
+                api_error.server_request_id = server_request_id;
+                let err = anyhow::Error::new(api_error);
+                if status < 500 {
+                    // The server is up; the request itself was at fault
+                    self.endpoints.mark_ok(&base_url);
+                    return Err(err);
+                }
+                self.endpoints.mark_failed(&base_url);
+                last_error = Some(err);
+                continue;
+            }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("API error ({}): {}", status, error_text);
+            self.endpoints.mark_ok(&base_url);
+
+            // The request stays in flight, holding its concurrency slot, until
+            // its body has been read
+            let permit = permit.take();
+            let body = response.body.map(move |chunk| {
+                let _in_flight = (&in_flight, &permit);
+                chunk
+            });
+            return Ok(transport::HttpResponse {
+                status: response.status,
+                headers: response.headers,
+                body: compat::boxed(body),
+            });
         }
 
-        response
-            .json()
-            .await
-            .context("Failed to parse embedding response")
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No server configured")))
+    }
+}
+
+/// Whether a `/slots` entry is free, in the formats of old and new llama.cpp
+/// servers
+fn slot_is_idle(slot: &serde_json::Value) -> bool {
+    match slot
+        .get("is_processing")
+        .and_then(serde_json::Value::as_bool)
+    {
+        Some(processing) => !processing,
+        None => slot.get("state").and_then(serde_json::Value::as_u64) == Some(0),
     }
 }
 
+/// Turn a server-sent events response into a stream of parsed `data:`
+/// payloads, ending at the `[DONE]` sentinel; see [`stream::SseDecoder`]
+///
+/// A payload that does not parse as `T` but carries an error object becomes
+/// an [`ApiError`] with the request's ids.
+fn sse_chunks<T>(
+    response: transport::HttpResponse,
+    request_id: String,
+    server_request_id: Option<String>,
+) -> BoxStream<'static, Result<T>>
+where
+    T: serde::de::DeserializeOwned + compat::MaybeSend + 'static,
+{
+    let parse = move |data: &[u8]| {
+        serde_json::from_slice::<T>(data).map_err(|err| {
+            match ApiError::from_stream(&String::from_utf8_lossy(data)) {
+                Some(mut api_error) => {
+                    api_error.request_id = Some(request_id.clone());
+                    api_error.server_request_id = server_request_id.clone();
+                    anyhow::Error::new(api_error)
+                }
+                None => anyhow::Error::new(err).context("Failed to parse chunk"),
+            }
+        })
+    };
+    let state = (response.body, stream::SseDecoder::new(), parse, false);
+
+    compat::boxed(futures::stream::unfold(
+        state,
+        |(mut bytes, mut decoder, parse, mut done)| async move {
+            loop {
+                let item = match decoder.next_event() {
+                    Some(b"[DONE]") => return None,
+                    Some(data) => Some(parse(data)),
+                    None => None,
+                };
+                if let Some(item) = item {
+                    return Some((item, (bytes, decoder, parse, done)));
+                }
+
+                if done {
+                    return None;
+                }
+
+                match bytes.next().await {
+                    Some(Ok(chunk)) => decoder.push(&chunk),
+                    Some(Err(err)) => {
+                        let err = err.context("Failed to read stream chunk");
+                        return Some((Err(err), (bytes, decoder, parse, true)));
+                    }
+                    None => {
+                        done = true;
+                        decoder.finish();
+                    }
+                }
+            }
+        },
+    ))
+}
+
 // ============================================================================
 // Builder Pattern for Requests
 // ============================================================================
@@ -337,8 +2378,20 @@ impl ChatCompletionRequest {
             temperature: None,
             max_tokens: None,
             top_p: None,
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            repeat_penalty: None,
             stream: None,
             stop: None,
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            cache_prompt: None,
+            id_slot: None,
+            timings_per_token: None,
+            preset: None,
+            extra_body: serde_json::Map::new(),
         }
     }
 
@@ -362,11 +2415,53 @@ impl ChatCompletionRequest {
         self
     }
 
+    /// The same limit as [`Self::max_tokens`], under OpenAI's newer name;
+    /// the client sends whichever name the configured [`Dialect`] expects
+    pub fn max_completion_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
     pub fn top_p(mut self, top_p: f32) -> Self {
         self.top_p = Some(top_p);
         self
     }
 
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    pub fn repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    /// Sample greedily with a fixed `seed` and no penalties, so the same
+    /// prompt gets the same reply from the same server, for tests and evals
+    ///
+    /// Sets temperature 0, top_p 1, the seed, and neutral presence,
+    /// frequency and repeat penalties.
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.temperature = Some(0.0);
+        self.top_p = Some(1.0);
+        self.seed = Some(seed);
+        self.presence_penalty = Some(0.0);
+        self.frequency_penalty = Some(0.0);
+        self.repeat_penalty = Some(1.0);
+        self
+    }
+
     pub fn stream(mut self, stream: bool) -> Self {
         self.stream = Some(stream);
         self
@@ -376,6 +2471,92 @@ impl ChatCompletionRequest {
         self.stop = Some(stop);
         self
     }
+
+    pub fn response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    /// Ask for any JSON object as the reply
+    pub fn json_mode(self) -> Self {
+        self.response_format(ResponseFormat::JsonObject { schema: None })
+    }
+
+    /// Like [`Self::json_mode`], also telling the model in the system prompt
+    /// to reply with JSON only, for servers that do not enforce the format
+    ///
+    /// The hint, [`structured::JSON_MODE_HINT`], is appended to a leading
+    /// system message, or sent as one if there is none. Call this after
+    /// adding the messages.
+    pub fn json_mode_with_hint(mut self) -> Self {
+        match self.messages.first_mut() {
+            Some(Message {
+                role,
+                content: MessageContent::Text(text),
+                ..
+            }) if role == "system" => {
+                text.push_str("\n\n");
+                text.push_str(structured::JSON_MODE_HINT);
+            }
+            _ => self
+                .messages
+                .insert(0, Message::system(structured::JSON_MODE_HINT)),
+        }
+        self.json_mode()
+    }
+
+    pub fn tools(mut self, tools: Vec<Tool>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+
+    pub fn tool(mut self, tool: Tool) -> Self {
+        self.tools.get_or_insert_with(Vec::new).push(tool);
+        self
+    }
+
+    /// Whether the model may, must or must not call tools, or which one it
+    /// must call
+    pub fn tool_choice(mut self, choice: ToolChoice) -> Self {
+        self.tool_choice = Some(choice);
+        self
+    }
+
+    /// Let the server reuse the KV cache of the longest prefix this prompt
+    /// shares with the slot's previous one
+    pub fn cache_prompt(mut self, cache_prompt: bool) -> Self {
+        self.cache_prompt = Some(cache_prompt);
+        self
+    }
+
+    /// Process this request on slot `id_slot`, whose cache holds the
+    /// previous prompt sent there
+    pub fn id_slot(mut self, id_slot: u32) -> Self {
+        self.id_slot = Some(id_slot);
+        self
+    }
+
+    /// Ask llama.cpp for [`Timings`] on every streamed chunk, not just the
+    /// last
+    pub fn timings_per_token(mut self, timings_per_token: bool) -> Self {
+        self.timings_per_token = Some(timings_per_token);
+        self
+    }
+
+    pub fn preset(mut self, preset: impl Into<String>) -> Self {
+        self.preset = Some(preset.into());
+        self
+    }
+
+    /// Send `key` with `value` along with the request's own fields, such as
+    /// a sampler this type has no builder for
+    ///
+    /// Extra fields are sent whatever the [`Dialect`], and replace a field
+    /// of the same name.
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.extra_body.insert(key.into(), value.into());
+        self
+    }
 }
 
 impl CompletionRequest {
@@ -384,8 +2565,15 @@ impl CompletionRequest {
             model: model.into(),
             prompt: prompt.into(),
             temperature: None,
+            seed: None,
             max_tokens: None,
             stream: None,
+            cache_prompt: None,
+            id_slot: None,
+            timings_per_token: None,
+            n_probs: None,
+            speculative: None,
+            extra_body: serde_json::Map::new(),
         }
     }
 
@@ -394,6 +2582,11 @@ impl CompletionRequest {
         self
     }
 
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
     pub fn max_tokens(mut self, max_tokens: u32) -> Self {
         self.max_tokens = Some(max_tokens);
         self
@@ -403,6 +2596,47 @@ impl CompletionRequest {
         self.stream = Some(stream);
         self
     }
+
+    /// Let the server reuse the KV cache of the longest prefix this prompt
+    /// shares with the slot's previous one
+    pub fn cache_prompt(mut self, cache_prompt: bool) -> Self {
+        self.cache_prompt = Some(cache_prompt);
+        self
+    }
+
+    /// Process this request on slot `id_slot`, whose cache holds the
+    /// previous prompt sent there
+    pub fn id_slot(mut self, id_slot: u32) -> Self {
+        self.id_slot = Some(id_slot);
+        self
+    }
+
+    /// Ask llama.cpp for [`Timings`] on every streamed chunk, not just the
+    /// last
+    pub fn timings_per_token(mut self, timings_per_token: bool) -> Self {
+        self.timings_per_token = Some(timings_per_token);
+        self
+    }
+
+    /// Report the `n_probs` most likely tokens at every position, in
+    /// [`CompletionResponse::completion_probabilities`]
+    pub fn n_probs(mut self, n_probs: u32) -> Self {
+        self.n_probs = Some(n_probs);
+        self
+    }
+
+    /// Tune speculative decoding for this request; see [`Speculative`]
+    pub fn speculative(mut self, speculative: Speculative) -> Self {
+        self.speculative = Some(speculative);
+        self
+    }
+
+    /// Send `key` with `value` along with the request's own fields; see
+    /// [`ChatCompletionRequest::extra`]
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.extra_body.insert(key.into(), value.into());
+        self
+    }
 }
 
 impl EmbeddingRequest {
@@ -410,6 +2644,95 @@ impl EmbeddingRequest {
         Self {
             model: model.into(),
             input: input.into(),
+            encoding_format: None,
+            truncate: None,
+            normalize: false,
+            extra_body: serde_json::Map::new(),
+        }
+    }
+
+    pub fn encoding_format(mut self, format: EncodingFormat) -> Self {
+        self.encoding_format = Some(format);
+        self
+    }
+
+    /// Keep the first `dimensions` of each vector, for models trained with
+    /// Matryoshka Representation Learning to work at several sizes
+    ///
+    /// Truncated vectors are no longer unit length; combine with
+    /// [`EmbeddingRequest::normalize`] where that matters.
+    pub fn truncate(mut self, dimensions: usize) -> Self {
+        self.truncate = Some(dimensions);
+        self
+    }
+
+    /// Scale each vector to unit length, after any truncation
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Send `key` with `value` along with the request's own fields
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.extra_body.insert(key.into(), value.into());
+        self
+    }
+
+    /// Apply the client-side truncation and normalization to `embedding`
+    pub(crate) fn post_process(&self, embedding: &mut Vec<f32>) {
+        if let Some(dimensions) = self.truncate {
+            embedding.truncate(dimensions);
+        }
+        if self.normalize {
+            embeddings::math::normalize(embedding);
+        }
+    }
+}
+
+impl TokenizeRequest {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            add_special: None,
+            extra_body: serde_json::Map::new(),
+        }
+    }
+
+    pub fn add_special(mut self, add_special: bool) -> Self {
+        self.add_special = Some(add_special);
+        self
+    }
+
+    /// Send `key` with `value` along with the request's own fields
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.extra_body.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl RerankRequest {
+    pub fn new<S: Into<String>>(
+        model: impl Into<String>,
+        query: impl Into<String>,
+        documents: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self {
+            model: model.into(),
+            query: query.into(),
+            documents: documents.into_iter().map(Into::into).collect(),
+            top_n: None,
+            extra_body: serde_json::Map::new(),
         }
     }
+
+    pub fn top_n(mut self, top_n: usize) -> Self {
+        self.top_n = Some(top_n);
+        self
+    }
+
+    /// Send `key` with `value` along with the request's own fields
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.extra_body.insert(key.into(), value.into());
+        self
+    }
 }
@@ -0,0 +1,623 @@
+//! Client-side limits on how hard the client pushes its servers.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// ============================================================================
+// Concurrency
+// ============================================================================
+
+/// Caps the requests in progress across all clones of a client; the rest
+/// wait in line
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+}
+
+impl ConcurrencyLimit {
+    /// A limit of `max` requests at once; 0 counts as 1, since no slots at
+    /// all would leave every request waiting forever
+    pub(crate) fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+        }
+    }
+
+    /// Wait up to `timeout` for a free slot; the request holds it until the
+    /// permit is dropped
+    ///
+    /// The timeout needs a Tokio timer, so it is only applied with the
+    /// `runtime-tokio` feature and never on `wasm32`.
+    pub(crate) async fn acquire(&self, timeout: Option<Duration>) -> Result<OwnedSemaphorePermit> {
+        let acquire = self.semaphore.clone().acquire_owned();
+
+        #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+        if let Some(timeout) = timeout {
+            return match tokio::time::timeout(timeout, acquire).await {
+                Ok(permit) => Ok(permit?),
+                Err(_) => anyhow::bail!(
+                    "Timed out after {:?} waiting for one of {} request slots",
+                    timeout,
+                    self.max
+                ),
+            };
+        }
+
+        #[cfg(not(all(feature = "runtime-tokio", not(target_arch = "wasm32"))))]
+        let _ = (timeout, self.max);
+
+        Ok(acquire.await?)
+    }
+}
+
+// ============================================================================
+// Timeouts
+// ============================================================================
+
+/// How long requests may take, by kind of request
+///
+/// Non-streaming requests are limited as a whole, reading the response
+/// included: chat, text completion and transcription requests by
+/// `generation`, and embedding, tokenize and other requests that generate
+/// nothing by `embedding`. Streams have no overall limit, so long
+/// generations can run; instead their first chunk must arrive within
+/// `first_byte`, which covers prompt processing, and each later one within
+/// `idle` of the one before.
+///
+/// The timeouts need a Tokio timer. They are only applied with the
+/// `runtime-tokio` feature and when running on a Tokio runtime, so driving
+/// the client from another executor leaves requests unlimited; they are
+/// never applied on `wasm32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub generation: Option<Duration>,
+    pub embedding: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub idle: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    /// Five minutes for generation and for a stream's first chunk, one
+    /// minute for embeddings and between chunks
+    fn default() -> Self {
+        Self {
+            generation: Some(Duration::from_secs(300)),
+            embedding: Some(Duration::from_secs(60)),
+            first_byte: Some(Duration::from_secs(300)),
+            idle: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No timeouts at all
+    pub fn none() -> Self {
+        Self {
+            generation: None,
+            embedding: None,
+            first_byte: None,
+            idle: None,
+        }
+    }
+
+    pub fn generation(mut self, timeout: Duration) -> Self {
+        self.generation = Some(timeout);
+        self
+    }
+
+    pub fn embedding(mut self, timeout: Duration) -> Self {
+        self.embedding = Some(timeout);
+        self
+    }
+
+    pub fn first_byte(mut self, timeout: Duration) -> Self {
+        self.first_byte = Some(timeout);
+        self
+    }
+
+    pub fn idle(mut self, timeout: Duration) -> Self {
+        self.idle = Some(timeout);
+        self
+    }
+
+    /// Send with `send` and read the response to `path` within these limits
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    pub(crate) async fn apply(
+        &self,
+        path: &str,
+        streaming: bool,
+        send: impl std::future::Future<Output = Result<crate::transport::HttpResponse>>,
+    ) -> Result<crate::transport::HttpResponse> {
+        use futures::StreamExt;
+        use tokio::time::{Instant, timeout_at};
+
+        if tokio::runtime::Handle::try_current().is_err() {
+            return send.await;
+        }
+
+        let path = path.split('?').next().unwrap_or_default();
+        let generates = path.ends_with("/completions") || path.contains("/audio/");
+        let (first, idle) = match (streaming, generates) {
+            (true, _) => (self.first_byte, self.idle),
+            (false, true) => (self.generation, None),
+            (false, false) => (self.embedding, None),
+        };
+        let timed_out = move |after: Duration| {
+            anyhow::anyhow!(
+                "Timed out after {:?} waiting for {}",
+                after,
+                if streaming {
+                    "the stream"
+                } else {
+                    "the response"
+                }
+            )
+        };
+
+        let Some(first) = first else {
+            return send.await;
+        };
+        let deadline = Instant::now() + first;
+        let mut response = timeout_at(deadline, send)
+            .await
+            .map_err(|_| timed_out(first))??;
+
+        // Non-streaming bodies share the deadline; streams get it for their
+        // first chunk and `idle` for each after
+        let state = (response.body, Some(deadline), first, false);
+        let body =
+            futures::stream::unfold(state, move |(mut body, deadline, limit, done)| async move {
+                if done {
+                    return None;
+                }
+                let next = match deadline {
+                    Some(deadline) => match timeout_at(deadline, body.next()).await {
+                        Ok(next) => next,
+                        Err(_) => return Some((Err(timed_out(limit)), (body, None, limit, true))),
+                    },
+                    None => body.next().await,
+                };
+                let (deadline, limit) = match (streaming, idle) {
+                    (true, Some(idle)) => (Some(Instant::now() + idle), idle),
+                    (true, None) => (None, limit),
+                    (false, _) => (deadline, limit),
+                };
+                next.map(|chunk| (chunk, (body, deadline, limit, false)))
+            });
+        response.body = crate::compat::boxed(body);
+        Ok(response)
+    }
+}
+
+// ============================================================================
+// Circuit Breaking
+// ============================================================================
+
+/// Stops sending requests to a server after repeated failures
+///
+/// After `failure_threshold` consecutive failed requests (connection errors
+/// or 5xx responses) the server's circuit opens and requests to it fail
+/// immediately, or move on to a fallback server, instead of waiting on a
+/// server that is down. Once `open_for` has passed a single probe request is
+/// let through: if it succeeds the circuit closes, otherwise it opens again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub(crate) failure_threshold: u32,
+    pub(crate) open_for: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_for: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_for,
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    /// Open after 5 failures for 30 seconds
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+/// The state of a server's circuit; see [`CircuitBreaker`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CircuitState {
+    /// Requests are sent normally
+    #[default]
+    Closed,
+    /// Requests fail without being sent
+    Open,
+    /// The next request is sent as a probe, or a probe is in progress
+    HalfOpen,
+}
+
+// ============================================================================
+// Rate Limiting
+// ============================================================================
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    per_second: f64,
+    available: f64,
+    updated: std::time::Instant,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Bucket {
+    fn new(capacity: f64, per_second: f64) -> Self {
+        Self {
+            capacity,
+            per_second,
+            available: capacity,
+            updated: std::time::Instant::now(),
+        }
+    }
+
+    /// Replace `bucket` with one of `capacity` filling at `per_second`,
+    /// keeping what was left in it
+    fn resize(bucket: &mut Option<Bucket>, shape: Option<(f64, f64)>) {
+        match (bucket.as_mut(), shape) {
+            (Some(old), Some((capacity, per_second)))
+                if old.capacity == capacity && old.per_second == per_second => {}
+            (old, Some((capacity, per_second))) => {
+                let mut new = Bucket::new(capacity, per_second);
+                if let Some(old) = old {
+                    old.refill();
+                    new.available = old.available.min(capacity);
+                }
+                *bucket = Some(new);
+            }
+            (_, None) => *bucket = None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// How long until `amount` is available
+    fn wait_for(&self, amount: f64) -> Duration {
+        if self.available >= amount {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((amount - self.available) / self.per_second)
+        }
+    }
+}
+
+/// Token-bucket limits on requests per second and tokens per minute, shared
+/// by all clones of a client
+///
+/// Tokens are charged from the usage the server reports once a request
+/// finishes. A request may therefore overdraw the token bucket, in which case
+/// the following requests wait until it has refilled.
+///
+/// Part of [`crate::LancorConfig`], so reloading the configuration changes
+/// the limits; only enforced with the `runtime-tokio` feature.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requests_per_second: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tokens_per_minute: Option<u64>,
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start at most `rate` requests per second on average, in bursts of up
+    /// to one second's worth
+    pub fn requests_per_second(mut self, rate: f64) -> Self {
+        self.requests_per_second = Some(rate);
+        self
+    }
+
+    /// Use at most `tokens` prompt and completion tokens per minute
+    pub fn tokens_per_minute(mut self, tokens: u64) -> Self {
+        self.tokens_per_minute = Some(tokens);
+        self
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    requests: std::sync::Mutex<Option<Bucket>>,
+    tokens: std::sync::Mutex<Option<Bucket>>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl RateLimiter {
+    /// A limiter that lets everything through until [`RateLimiter::set`]
+    pub(crate) fn unlimited() -> Self {
+        Self {
+            requests: std::sync::Mutex::new(None),
+            tokens: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// A limiter enforcing `limit`, or nothing for `None`
+    pub(crate) fn new(limit: Option<&RateLimit>) -> Self {
+        let limiter = Self::unlimited();
+        limiter.set(limit);
+        limiter
+    }
+
+    /// Switch to `limit`, or to no limits for `None`
+    ///
+    /// Buckets whose rate is unchanged keep their balance, so reloading an
+    /// unrelated setting does not grant a fresh burst.
+    pub(crate) fn set(&self, limit: Option<&RateLimit>) {
+        let requests = limit
+            .and_then(|limit| limit.requests_per_second)
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| (rate.max(1.0), rate));
+        let tokens = limit
+            .and_then(|limit| limit.tokens_per_minute)
+            .filter(|tokens| *tokens > 0)
+            .map(|tokens| (tokens as f64, tokens as f64 / 60.0));
+        Bucket::resize(&mut lock(&self.requests), requests);
+        Bucket::resize(&mut lock(&self.tokens), tokens);
+    }
+
+    /// Wait until a request may start, and take one request from the bucket
+    pub(crate) async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut requests = lock(&self.requests);
+                let mut tokens = lock(&self.tokens);
+
+                let mut wait = Duration::ZERO;
+                if let Some(bucket) = requests.as_mut() {
+                    bucket.refill();
+                    wait = wait.max(bucket.wait_for(1.0));
+                }
+                // Any positive token balance lets a request through
+                if let Some(bucket) = tokens.as_mut() {
+                    bucket.refill();
+                    wait = wait.max(bucket.wait_for(f64::MIN_POSITIVE));
+                }
+
+                if wait.is_zero() {
+                    if let Some(bucket) = requests.as_mut() {
+                        bucket.available -= 1.0;
+                    }
+                    return;
+                }
+                wait
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take `tokens` used by a finished request from the token bucket
+    pub(crate) fn charge(&self, tokens: u64) {
+        if let Some(bucket) = lock(&self.tokens).as_mut() {
+            bucket.refill();
+            bucket.available -= tokens as f64;
+        }
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl crate::metrics::MetricsObserver for RateLimiter {
+    fn on_tokens(
+        &self,
+        _request: &crate::metrics::RequestInfo,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) {
+        self.charge(u64::from(prompt_tokens) + u64::from(completion_tokens));
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// ============================================================================
+// Retries
+// ============================================================================
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+type Retryable = Arc<dyn Fn(&anyhow::Error) -> bool + Send + Sync>;
+
+/// Retries requests that failed for reasons that may pass: connection
+/// errors and 408, 429, 502, 503 and 504 responses
+///
+/// Retries back off exponentially from `initial_backoff`, unless the server
+/// said how long to wait with `Retry-After`, in which case the client waits
+/// that long, up to `max_retry_after`. Each retry goes through the
+/// concurrency and rate limits again, and tries every server in turn when
+/// the client has fallbacks.
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_retry_after: Duration,
+    retryable: Option<Retryable>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl RetryPolicy {
+    /// Retry up to `max_retries` times, backing off from half a second
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(60),
+            retryable: None,
+        }
+    }
+
+    /// Wait `initial` before the first retry, doubling each time up to `max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Wait at most `ceiling` when the server asks for longer with
+    /// `Retry-After`
+    pub fn max_retry_after(mut self, ceiling: Duration) -> Self {
+        self.max_retry_after = ceiling;
+        self
+    }
+
+    /// Retry the errors `retryable` accepts instead of those
+    /// [`RetryPolicy::is_transient`] accepts
+    ///
+    /// ```
+    /// use lancor::{ApiError, RetryPolicy};
+    ///
+    /// // Also retry the 500s a flaky proxy sends
+    /// let policy = RetryPolicy::new(3).retry_if(|error| {
+    ///     RetryPolicy::is_transient(error)
+    ///         || error.downcast_ref::<ApiError>().is_some_and(|e| e.status == 500)
+    /// });
+    /// ```
+    pub fn retry_if(
+        mut self,
+        retryable: impl Fn(&anyhow::Error) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retryable = Some(Arc::new(retryable));
+        self
+    }
+
+    /// Whether `error` is one retried by default: anything but an
+    /// [`ApiError`](crate::ApiError), or one with status 408, 429, 502, 503
+    /// or 504
+    pub fn is_transient(error: &anyhow::Error) -> bool {
+        error
+            .downcast_ref::<crate::ApiError>()
+            .is_none_or(|api_error| matches!(api_error.status, 408 | 429 | 502 | 503 | 504))
+    }
+
+    /// How long to wait before retrying after `error`, or `None` to give up;
+    /// `attempt` counts the retries made so far
+    pub(crate) fn delay(&self, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let retryable = match &self.retryable {
+            Some(retryable) => retryable(error),
+            None => Self::is_transient(error),
+        };
+        if !retryable {
+            return None;
+        }
+        if let Some(retry_after) = error
+            .downcast_ref::<crate::ApiError>()
+            .and_then(|api_error| api_error.retry_after)
+        {
+            return Some(retry_after.min(self.max_retry_after));
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt));
+        Some(backoff.min(self.max_backoff))
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Default for RetryPolicy {
+    /// Retry 3 times
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("retry_if", &self.retryable.is_some())
+            .finish()
+    }
+}
+
+/// The kinds of request a [`RetryPolicy`] can be set for with
+/// [`LlamaCppClient::with_retry_for`](crate::LlamaCppClient::with_retry_for)
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    /// Chat completions, streamed or not
+    Chat,
+    /// Text completions, streamed or not
+    Completion,
+    Embedding,
+    /// Tokenizing and detokenizing
+    Tokenize,
+    /// Transcription and speech
+    Audio,
+    /// Model lists, `/props` and anything else
+    Other,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl RequestKind {
+    /// The kind of a request to `path`
+    pub(crate) fn of(path: &str) -> Self {
+        let path = path.split('?').next().unwrap_or_default();
+        if path.ends_with("/chat/completions") {
+            Self::Chat
+        } else if path.ends_with("/completions") || path.ends_with("/completion") {
+            Self::Completion
+        } else if path.ends_with("/embeddings") || path.ends_with("/embedding") {
+            Self::Embedding
+        } else if path.ends_with("/tokenize") || path.ends_with("/detokenize") {
+            Self::Tokenize
+        } else if path.contains("/audio/") {
+            Self::Audio
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// The client's retry policy and the overrides for kinds of request
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Retries {
+    pub(crate) default: Option<RetryPolicy>,
+    /// `None` turns retries off for the kind
+    pub(crate) by_kind: std::collections::HashMap<RequestKind, Option<RetryPolicy>>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Retries {
+    pub(crate) fn policy(&self, path: &str) -> Option<&RetryPolicy> {
+        match self.by_kind.get(&RequestKind::of(path)) {
+            Some(policy) => policy.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Request and response logging with secret redaction.
+//!
+//! [`LlamaCppClient::with_logging`](crate::LlamaCppClient::with_logging)
+//! wraps the client's transport so that every exchange is written to a
+//! [`LogSink`] as a [`LogEntry`]: full request and response bodies, with
+//! streamed responses logged as the complete SSE transcript once the stream
+//! ends. A [`Redactor`] scrubs the entry first. It always hides credential
+//! headers and the tokens they carry, and can be configured to hide other
+//! secrets, JSON fields and PII patterns.
+//!
+//! ```no_run
+//! use lancor::LlamaCppClient;
+//! use lancor::logging::{JsonLinesSink, Redactor};
+//!
+//! # fn example() -> anyhow::Result<()> {
+//! let client = LlamaCppClient::with_api_key("http://localhost:8080", "secret")?.with_logging(
+//!     JsonLinesSink::new(std::io::stderr()),
+//!     Redactor::new().emails().field("user_id"),
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::Result;
+use futures::stream::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::compat::{self, BoxFuture, Instant, MaybeSend, MaybeSync};
+use crate::transport::{HttpRequest, HttpResponse, Transport};
+
+/// Replacement text for redacted values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Headers whose values are never logged
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "api-key",
+    "x-api-key",
+    "cookie",
+    "set-cookie",
+];
+
+/// Query parameters whose values are never logged
+const SECRET_QUERY_PARAMS: &[&str] = &["key", "api_key", "api-key", "apikey", "access_token"];
+
+// ============================================================================
+// Log Entries and Sinks
+// ============================================================================
+
+/// One logged request and its response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub method: String,
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: String,
+    /// `None` if no response was received
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    /// The whole body; for streams, the SSE transcript
+    pub response_body: String,
+    /// Time from sending the request until the body was read or dropped
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+/// Where log entries are written
+///
+/// Implemented for closures taking a `&LogEntry`.
+pub trait LogSink: MaybeSend + MaybeSync {
+    fn write(&self, entry: &LogEntry);
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(&LogEntry) + MaybeSend + MaybeSync,
+{
+    fn write(&self, entry: &LogEntry) {
+        self(entry)
+    }
+}
+
+/// Writes each entry as one line of JSON
+pub struct JsonLinesSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLinesSink {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Append to the file at `path`, creating it if needed
+    pub fn file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open log {}: {}", path.display(), e))?;
+        Ok(Self::new(file))
+    }
+}
+
+impl std::fmt::Debug for JsonLinesSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonLinesSink").finish_non_exhaustive()
+    }
+}
+
+impl LogSink for JsonLinesSink {
+    fn write(&self, entry: &LogEntry) {
+        let Ok(mut line) = serde_json::to_string(entry) else {
+            return;
+        };
+        line.push('\n');
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        // Logging must never make a request fail
+        let _ = writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.flush());
+    }
+}
+
+// ============================================================================
+// Redaction
+// ============================================================================
+
+type Pattern = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Removes secrets and PII from log entries
+///
+/// Credential headers (`Authorization`, `Api-Key`, cookies and similar) and
+/// key query parameters (`key`, `api_key` and similar) are always replaced,
+/// and the tokens they carry are also removed wherever they appear in URLs
+/// and bodies.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    secrets: Vec<String>,
+    fields: Vec<String>,
+    patterns: Vec<Pattern>,
+}
+
+impl std::fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redactor")
+            .field("secrets", &self.secrets.len())
+            .field("fields", &self.fields)
+            .field("patterns", &self.patterns.len())
+            .finish()
+    }
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace every occurrence of `secret`
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        let secret = secret.into();
+        if !secret.is_empty() {
+            self.secrets.push(secret);
+        }
+        self
+    }
+
+    /// Replace the value of every JSON object key called `name`, at any depth
+    pub fn field(mut self, name: impl Into<String>) -> Self {
+        self.fields.push(name.into());
+        self
+    }
+
+    /// Replace email addresses
+    pub fn emails(self) -> Self {
+        self.pattern(redact_emails)
+    }
+
+    /// Apply a custom rewrite, e.g. to mask phone or account numbers
+    pub fn pattern(mut self, rewrite: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.patterns.push(Arc::new(rewrite));
+        self
+    }
+
+    /// Redact a complete entry
+    pub fn redact(&self, entry: &mut LogEntry) {
+        let mut secrets = self.secrets.clone();
+        for (name, value) in entry
+            .request_headers
+            .iter()
+            .chain(entry.response_headers.iter())
+        {
+            if is_secret_header(name) {
+                let token = value
+                    .strip_prefix("Bearer ")
+                    .or_else(|| value.strip_prefix("Basic "))
+                    .unwrap_or(value);
+                if !token.is_empty() {
+                    secrets.push(token.to_string());
+                }
+            }
+        }
+
+        let query = entry.url.split_once('?').map_or("", |(_, query)| query);
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            if is_secret_query_param(name) && !value.is_empty() {
+                secrets.push(value.to_string());
+            }
+        }
+
+        for (name, value) in entry
+            .request_headers
+            .iter_mut()
+            .chain(entry.response_headers.iter_mut())
+        {
+            if is_secret_header(name) {
+                *value = REDACTED.to_string();
+            }
+        }
+
+        entry.url = self.redact_text(&entry.url, &secrets);
+        entry.request_body = self.redact_body(&entry.request_body, &secrets);
+        entry.response_body = self.redact_body(&entry.response_body, &secrets);
+        if let Some(error) = &entry.error {
+            entry.error = Some(self.redact_text(error, &secrets));
+        }
+    }
+
+    /// Redact a JSON body, an SSE transcript of JSON events, or plain text
+    fn redact_body(&self, body: &str, secrets: &[String]) -> String {
+        if self.fields.is_empty() {
+            return self.redact_text(body, secrets);
+        }
+
+        let body = if let Ok(mut value) = serde_json::from_str::<Value>(body) {
+            self.redact_fields(&mut value);
+            value.to_string()
+        } else {
+            body.split_inclusive('\n')
+                .map(|line| {
+                    let Some(data) = line.strip_prefix("data:") else {
+                        return line.to_string();
+                    };
+                    match serde_json::from_str::<Value>(data.trim()) {
+                        Ok(mut value) => {
+                            self.redact_fields(&mut value);
+                            let end = if line.ends_with('\n') { "\n" } else { "" };
+                            format!("data: {}{}", value, end)
+                        }
+                        Err(_) => line.to_string(),
+                    }
+                })
+                .collect()
+        };
+        self.redact_text(&body, secrets)
+    }
+
+    fn redact_fields(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.fields.iter().any(|field| field == key) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_fields(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_fields(item)),
+            _ => {}
+        }
+    }
+
+    fn redact_text(&self, text: &str, secrets: &[String]) -> String {
+        let mut text = text.to_string();
+        for secret in secrets {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+        for pattern in &self.patterns {
+            text = pattern(&text);
+        }
+        text
+    }
+}
+
+fn is_secret_header(name: &str) -> bool {
+    SECRET_HEADERS
+        .iter()
+        .any(|secret| secret.eq_ignore_ascii_case(name))
+}
+
+fn is_secret_query_param(name: &str) -> bool {
+    SECRET_QUERY_PARAMS
+        .iter()
+        .any(|secret| secret.eq_ignore_ascii_case(name))
+}
+
+/// Replace anything shaped like `local@domain.tld` with [`REDACTED`]
+pub fn redact_emails(text: &str) -> String {
+    let is_local = |c: char| c.is_ascii_alphanumeric() || "._%+-".contains(c);
+    let is_domain = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == '-';
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        let local_start = rest[..at].rfind(|c: char| !is_local(c)).map_or(0, |pos| {
+            pos + rest[pos..].chars().next().map_or(1, char::len_utf8)
+        });
+        let domain_len = rest[at + 1..]
+            .find(|c: char| !is_domain(c))
+            .unwrap_or(rest.len() - at - 1);
+        let domain = rest[at + 1..at + 1 + domain_len].trim_end_matches('.');
+
+        let is_email = local_start < at
+            && domain.contains('.')
+            && !domain.starts_with('.')
+            && !domain.starts_with('-');
+        if is_email {
+            out.push_str(&rest[..local_start]);
+            out.push_str(REDACTED);
+            rest = &rest[at + 1 + domain.len()..];
+        } else {
+            out.push_str(&rest[..=at]);
+            rest = &rest[at + 1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// ============================================================================
+// Logging Transport
+// ============================================================================
+
+/// A transport that logs every exchange of the transport it wraps
+#[derive(Clone)]
+pub struct LoggingTransport {
+    inner: Arc<dyn Transport>,
+    sink: Arc<dyn LogSink>,
+    redactor: Redactor,
+}
+
+impl std::fmt::Debug for LoggingTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoggingTransport")
+            .field("inner", &self.inner)
+            .field("redactor", &self.redactor)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LoggingTransport {
+    pub fn new(
+        inner: impl Transport + 'static,
+        sink: impl LogSink + 'static,
+        redactor: Redactor,
+    ) -> Self {
+        Self::wrap(Arc::new(inner), Arc::new(sink), redactor)
+    }
+
+    pub(crate) fn wrap(
+        inner: Arc<dyn Transport>,
+        sink: Arc<dyn LogSink>,
+        redactor: Redactor,
+    ) -> Self {
+        Self {
+            inner,
+            sink,
+            redactor,
+        }
+    }
+}
+
+/// An entry waiting for its response body; written when dropped
+struct PendingEntry {
+    entry: LogEntry,
+    body: Vec<u8>,
+    started: Instant,
+    sink: Arc<dyn LogSink>,
+    redactor: Redactor,
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        self.entry.response_body = String::from_utf8_lossy(&self.body).into_owned();
+        self.entry.elapsed = self.started.elapsed();
+        self.redactor.redact(&mut self.entry);
+        self.sink.write(&self.entry);
+    }
+}
+
+impl Transport for LoggingTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        let mut pending = PendingEntry {
+            entry: LogEntry {
+                method: request.method.clone(),
+                url: request.url.clone(),
+                request_headers: request.headers.clone(),
+                request_body: String::from_utf8_lossy(&request.body).into_owned(),
+                status: None,
+                response_headers: Vec::new(),
+                response_body: String::new(),
+                elapsed: Duration::ZERO,
+                error: None,
+            },
+            body: Vec::new(),
+            started: Instant::now(),
+            sink: self.sink.clone(),
+            redactor: self.redactor.clone(),
+        };
+
+        compat::boxed_future(async move {
+            let response = match self.inner.send(request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    pending.entry.error = Some(format!("{:#}", err));
+                    drop(pending);
+                    return Err(err);
+                }
+            };
+            pending.entry.status = Some(response.status);
+            pending.entry.response_headers = response.headers.clone();
+
+            // The entry is written once the body has been read or dropped
+            let body = response.body.map(move |chunk| {
+                match &chunk {
+                    Ok(bytes) => pending.body.extend_from_slice(bytes),
+                    Err(err) => pending.entry.error = Some(format!("{:#}", err)),
+                }
+                chunk
+            });
+
+            Ok(HttpResponse {
+                status: response.status,
+                headers: response.headers,
+                body: compat::boxed(body),
+            })
+        })
+    }
+}
@@ -1,65 +1,605 @@
-use anyhow::Result;
-use futures::stream::StreamExt;
-use lancor::{ChatCompletionRequest, CompletionRequest, EmbeddingRequest, LlamaCppClient, Message};
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use lancor::bench::{self, Benchmark, Percentiles};
+use lancor::rag::{self, Rag, RagAnswer};
+use lancor::{
+    ChatCompletionRequest, ChatSession, CompletionRequest, LancorConfig, LlamaCppClient, Message,
+    Profile, Profiles,
+};
+use std::io::{IsTerminal, Read, Write};
+use std::time::Duration;
+use tokio::io::AsyncBufReadExt;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize client
-    let client = LlamaCppClient::with_api_key("http://localhost:1337", "jafong")?;
-
-    // Example 1: Simple chat completion
-    println!("=== Chat Completion Example ===");
-    let request = ChatCompletionRequest::new("Qwen3-VL-2B-Instruct-IQ4_XS")
-        .message(Message::system("You are a helpful assistant."))
-        .message(Message::user("What is Rust programming language?"))
-        .max_tokens(100)
-        .temperature(0.7);
-
-    let response = client.chat_completion(request).await?;
-    println!("Response: {}", response.choices[0].message.content);
-    println!("Tokens used: {}", response.usage.total_tokens);
-
-    // Example 2: Streaming chat completion
-    println!("\n=== Streaming Chat Completion Example ===");
-    let streaming_request = ChatCompletionRequest::new("Qwen3-VL-2B-Instruct-IQ4_XS")
-        .message(Message::user("Count from 1 to 5."))
-        .stream(true)
-        .max_tokens(50);
-
-    let mut stream = client.chat_completion_stream(streaming_request).await?;
-    print!("Streaming response: ");
-    while let Some(chunk_result) = stream.next().await {
-        if let Ok(chunk) = chunk_result {
-            if let Some(content) = &chunk.choices[0].delta.content {
-                print!("{}", content);
+const USAGE: &str = "\
+Usage: lancor [OPTIONS] <COMMAND>
+
+Commands:
+  ask [PROMPT]                  Answer one prompt, with piped stdin appended
+  bench [PROMPT]                Measure latency and throughput under load
+  chat                          Chat interactively, streaming the replies
+  embed [FILES...]              Embed each file, or each line of stdin
+  rag --docs <DIR> <QUESTION>   Answer a question from the documents in DIR
+
+Options:
+  --profile <NAME>       Profile from ~/.config/lancor/config.toml [env: LANCOR_PROFILE]
+  --url <URL>            Server base URL [env: LANCOR_URL] [default: http://localhost:8080]
+  --api-key <KEY>        API key [env: LANCOR_API_KEY]
+  --model <NAME>         Model name [env: LANCOR_MODEL] [default: auto, the server's model]
+  --system <PROMPT>      System prompt
+  --temperature <T>      Sampling temperature
+  --max-tokens <N>       Maximum tokens per reply
+  --json                 Print the reply of ask as a JSON object
+  --embed-model <NAME>   Embedding model name [default: same as --model]
+  --lines                Embed each line of the files given to embed
+  --format <FORMAT>      Output of embed: jsonl or npy [default: jsonl]
+  --batch-size <N>       Texts per embedding request [default: 32]
+  --similarity           Print the cosine similarity of the two inputs to embed
+  --requests <N>         Requests sent by bench [default: 20]
+  --concurrency <N>      Requests bench keeps in flight [default: 4]
+  --sweep <N>            Run bench at concurrency 1, 2, 4, ... up to N and find where throughput stops growing
+  --endpoint <NAME>      What bench calls: chat or completion [default: chat]
+  --top-k <N>            Number of chunks to retrieve [default: 4]
+  --chunk-size <N>       Chunk size in characters [default: 1000]
+  --chunk-overlap <N>    Overlap between chunks in characters [default: 200]
+  -h, --help             Print help";
+
+/// Command line options shared by every subcommand
+struct Args {
+    profile: Option<Profile>,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+    system: Option<String>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    json: bool,
+    embed_model: Option<String>,
+    lines: bool,
+    format: String,
+    batch_size: usize,
+    similarity: bool,
+    requests: usize,
+    concurrency: usize,
+    sweep: Option<usize>,
+    endpoint: String,
+    docs: Option<String>,
+    top_k: usize,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut profile_name = std::env::var("LANCOR_PROFILE").ok();
+        let mut url = std::env::var("LANCOR_URL").ok();
+        let mut model = std::env::var("LANCOR_MODEL").ok();
+        let mut args = Args {
+            profile: None,
+            url: String::new(),
+            api_key: std::env::var("LANCOR_API_KEY").ok(),
+            model: String::new(),
+            system: None,
+            temperature: None,
+            max_tokens: None,
+            json: false,
+            embed_model: None,
+            lines: false,
+            format: "jsonl".into(),
+            batch_size: 32,
+            similarity: false,
+            requests: 20,
+            concurrency: 4,
+            sweep: None,
+            endpoint: "chat".into(),
+            docs: None,
+            top_k: 4,
+            chunk_size: 1000,
+            chunk_overlap: 200,
+            positional: Vec::new(),
+        };
+
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .with_context(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "--profile" => profile_name = Some(value()?),
+                "--url" => url = Some(value()?),
+                "--api-key" => args.api_key = Some(value()?),
+                "--model" => model = Some(value()?),
+                "--system" => args.system = Some(value()?),
+                "--temperature" => {
+                    args.temperature = Some(value()?.parse().context("Invalid --temperature")?)
+                }
+                "--max-tokens" => {
+                    args.max_tokens = Some(value()?.parse().context("Invalid --max-tokens")?)
+                }
+                "--json" => args.json = true,
+                "--embed-model" => args.embed_model = Some(value()?),
+                "--lines" => args.lines = true,
+                "--format" => args.format = value()?,
+                "--batch-size" => {
+                    args.batch_size = value()?.parse().context("Invalid --batch-size")?
+                }
+                "--similarity" => args.similarity = true,
+                "--requests" => args.requests = value()?.parse().context("Invalid --requests")?,
+                "--sweep" => args.sweep = Some(value()?.parse().context("Invalid --sweep")?),
+                "--concurrency" => {
+                    args.concurrency = value()?.parse().context("Invalid --concurrency")?
+                }
+                "--endpoint" => args.endpoint = value()?,
+                "--docs" => args.docs = Some(value()?),
+                "--top-k" => args.top_k = value()?.parse().context("Invalid --top-k")?,
+                "--chunk-size" => {
+                    args.chunk_size = value()?.parse().context("Invalid --chunk-size")?
+                }
+                "--chunk-overlap" => {
+                    args.chunk_overlap = value()?.parse().context("Invalid --chunk-overlap")?
+                }
+                flag if flag.starts_with("--") => anyhow::bail!("Unknown option {}", flag),
+                _ => args.positional.push(arg),
             }
         }
+
+        // Options and environment variables override the profile. A broken
+        // config file only matters when a profile is asked for by name;
+        // otherwise the options alone are enough
+        let profiles = match Profiles::load() {
+            Ok(profiles) => profiles,
+            Err(err) if profile_name.is_none() => {
+                eprintln!("Warning: ignoring profiles: {:#}", err);
+                Profiles::default()
+            }
+            Err(err) => return Err(err),
+        };
+        let profile = match &profile_name {
+            Some(name) => Some(profiles.get(name)?.clone()),
+            None => profiles.default_profile()?.cloned(),
+        };
+        if let Some(profile) = &profile {
+            url = url.or_else(|| profile.base_url.clone());
+            model = model.or_else(|| profile.model.clone());
+            if args.api_key.is_none() {
+                args.api_key = profile.resolve_api_key()?;
+            }
+            args.temperature = args.temperature.or(profile.temperature);
+            args.max_tokens = args.max_tokens.or(profile.max_tokens);
+        }
+        args.url = url.unwrap_or_else(|| "http://localhost:8080".into());
+        args.model = model.unwrap_or_else(|| lancor::AUTO_MODEL.into());
+        args.profile = profile;
+
+        Ok(args)
     }
-    println!();
 
-    // Example 3: Text completion
-    println!("\n=== Text Completion Example ===");
-    let completion_request =
-        CompletionRequest::new("Qwen3-VL-2B-Instruct-IQ4_XS", "The quick brown fox")
-            .max_tokens(20)
-            .temperature(0.8);
+    fn client(&self) -> Result<LlamaCppClient> {
+        // The key was already taken from --api-key or resolved from the
+        // profile while parsing, so the profile's is not looked up again
+        let mut config = match &self.profile {
+            Some(profile) => profile.config_with_api_key(None),
+            None => LancorConfig::default(),
+        };
+        config.base_url.clone_from(&self.url);
+        config.api_key.clone_from(&self.api_key);
+        LlamaCppClient::from_config(config)
+    }
+}
+
+async fn run_ask(args: &Args, prompt: &str) -> Result<()> {
+    // `cat notes.md | lancor ask "Summarize this"` sends the prompt followed
+    // by the piped text
+    let mut input = String::new();
+    if !std::io::stdin().is_terminal() {
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read stdin")?;
+    }
+    let prompt = match (prompt.is_empty(), input.trim().is_empty()) {
+        (true, true) => anyhow::bail!("ask requires a prompt or input on stdin"),
+        (false, true) => prompt.to_string(),
+        (true, false) => input,
+        (false, false) => format!("{}\n\n{}", prompt, input),
+    };
+
+    let mut request = ChatCompletionRequest::new(args.model.clone());
+    if let Some(system) = &args.system {
+        request = request.message(Message::system(system.clone()));
+    }
+    request = request.message(Message::user(prompt));
+    if let Some(temperature) = args.temperature {
+        request = request.temperature(temperature);
+    }
+    if let Some(max_tokens) = args.max_tokens {
+        request = request.max_tokens(max_tokens);
+    }
+
+    let response = args.client()?.chat_completion(request).await?;
+    let choice = response
+        .choices
+        .first()
+        .context("Response contained no choices")?;
+    let content = choice.message.content.text();
+
+    if args.json {
+        let output = serde_json::json!({
+            "model": response.model,
+            "content": content,
+            "finish_reason": choice.finish_reason,
+            "usage": {
+                "prompt_tokens": response.usage.prompt_tokens,
+                "completion_tokens": response.usage.completion_tokens,
+                "total_tokens": response.usage.total_tokens,
+            },
+        });
+        println!("{}", output);
+    } else {
+        println!("{}", content);
+    }
+    Ok(())
+}
+
+async fn run_embed(args: &Args, files: &[String]) -> Result<()> {
+    // (label, text) pairs: what to call each input in the output, and what
+    // to embed
+    let mut inputs: Vec<(String, String)> = Vec::new();
+    if files.is_empty() {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read stdin")?;
+        inputs.extend(lines(&text).map(|(_, line)| (line.clone(), line)));
+    }
+    for file in files {
+        let text =
+            std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+        if args.lines {
+            inputs
+                .extend(lines(&text).map(|(number, line)| (format!("{}:{}", file, number), line)));
+        } else {
+            inputs.push((file.clone(), text));
+        }
+    }
+    if inputs.is_empty() {
+        anyhow::bail!("Nothing to embed");
+    }
+
+    let client = args.client()?;
+    let model = args.embed_model.as_deref().unwrap_or(&args.model);
+    let mut vectors = Vec::with_capacity(inputs.len());
+    for batch in inputs.chunks(args.batch_size.max(1)) {
+        let texts: Vec<&str> = batch.iter().map(|(_, text)| text.as_str()).collect();
+        vectors.extend(client.embedding_batch(model, &texts).await?);
+    }
+
+    if args.similarity {
+        let [a, b] = vectors.as_slice() else {
+            anyhow::bail!(
+                "--similarity compares exactly two inputs, got {}",
+                inputs.len()
+            );
+        };
+        println!("{:.6}", lancor::embeddings::math::cosine_similarity(a, b));
+        return Ok(());
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    match args.format.as_str() {
+        "jsonl" => {
+            for ((input, _), embedding) in inputs.iter().zip(&vectors) {
+                let line = serde_json::json!({ "input": input, "embedding": embedding });
+                writeln!(stdout, "{}", line)?;
+            }
+        }
+        "npy" => {
+            if stdout.is_terminal() {
+                anyhow::bail!(
+                    "Refusing to write binary .npy data to a terminal; redirect it to a file"
+                );
+            }
+            write_npy(&mut stdout, &vectors)?;
+        }
+        format => anyhow::bail!("Unknown --format {} (expected jsonl or npy)", format),
+    }
+    Ok(())
+}
+
+/// The non-empty lines of `text` with their 1-based line numbers
+fn lines(text: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| ((i + 1).to_string(), line.to_string()))
+}
+
+/// Write `vectors` as a 2-D little-endian float32 array in NumPy's `.npy`
+/// format (version 1.0)
+fn write_npy(out: &mut impl Write, vectors: &[Vec<f32>]) -> Result<()> {
+    let dimension = vectors.first().map_or(0, Vec::len);
+    if vectors.iter().any(|v| v.len() != dimension) {
+        anyhow::bail!("Embeddings have different dimensions");
+    }
+
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        vectors.len(),
+        dimension
+    );
+    // Magic, version and length take 10 bytes; pad the whole preamble to a
+    // multiple of 64 bytes, ending in a newline
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
 
-    let completion_response = client.completion(completion_request).await?;
-    println!("Completion: {}", completion_response.content);
+    out.write_all(b"\x93NUMPY\x01\x00")?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    for value in vectors.iter().flatten() {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
 
-    // Example 4: Embeddings
-    println!("\n=== Embedding Example ===");
-    let embedding_request = EmbeddingRequest::new("Qwen3-VL-2B-Instruct-IQ4_XS", "Hello, world!");
+async fn run_bench(args: &Args, prompt: &str) -> Result<()> {
+    let prompt = if prompt.is_empty() {
+        bench::DEFAULT_PROMPT
+    } else {
+        prompt
+    };
+    let max_tokens = args.max_tokens.unwrap_or(128);
+    let temperature = args.temperature.unwrap_or(0.7);
+    let benchmark = Benchmark::new(args.client()?)
+        .requests(args.requests)
+        .concurrency(args.concurrency);
+    let benchmark = match args.endpoint.as_str() {
+        "chat" => benchmark.chat(
+            ChatCompletionRequest::new(args.model.clone())
+                .message(Message::user(prompt))
+                .max_tokens(max_tokens)
+                .temperature(temperature),
+        ),
+        "completion" => benchmark.completion(
+            CompletionRequest::new(args.model.clone(), prompt)
+                .max_tokens(max_tokens)
+                .temperature(temperature),
+        ),
+        other => anyhow::bail!("Unknown --endpoint {} (expected chat or completion)", other),
+    };
 
-    let embedding_response = client.embedding(embedding_request).await?;
+    if let Some(max) = args.sweep {
+        return run_sweep(args, &benchmark, max).await;
+    }
+
+    eprintln!(
+        "Sending {} {} requests to {} with concurrency {}...",
+        args.requests,
+        args.endpoint,
+        args.url,
+        args.concurrency.max(1)
+    );
+    let report = benchmark.run().await;
+
+    println!(
+        "Requests:    {} ok, {} failed ({:.1}% errors)",
+        report.succeeded(),
+        report.errors.len(),
+        100.0 * report.error_rate()
+    );
+    println!("Wall time:   {:.2}s", report.wall_time.as_secs_f64());
     println!(
-        "Embedding dimension: {:?}",
-        embedding_response.data[0].embedding.len()
+        "Throughput:  {:.2} requests/s, {:.1} tokens/s",
+        report.requests_per_second, report.tokens_per_second
+    );
+    print_percentiles("Latency:", report.latency);
+    print_percentiles("TTFT:", report.ttft);
+    if let Some(speed) = report.tokens_per_second_per_request {
+        println!("Per request: {:.1} tokens/s median", speed);
+    }
+
+    let mut errors = report.errors;
+    errors.sort();
+    errors.dedup();
+    for error in errors.iter().take(5) {
+        println!("Error:       {}", error);
+    }
+    Ok(())
+}
+
+async fn run_sweep(args: &Args, benchmark: &Benchmark, max: usize) -> Result<()> {
+    let levels = bench::sweep_levels(max);
+    eprintln!(
+        "Sending {} requests to {} at concurrency {:?}...",
+        args.endpoint, args.url, levels
     );
+    let sweep = benchmark.sweep_at(levels).await;
+
+    let ms = |value: Option<Duration>| {
+        value.map_or("-".to_string(), |value| {
+            format!("{:.0}", value.as_secs_f64() * 1000.0)
+        })
+    };
     println!(
-        "First 5 values: {:?}",
-        &embedding_response.data[0].embedding[..5]
+        "{:>11}  {:>8}  {:>10}  {:>8}  {:>8}  {:>9}  {:>7}",
+        "Concurrency", "req/s", "tokens/s", "p50 ms", "p99 ms", "TTFT p50", "errors"
     );
+    for report in &sweep.levels {
+        println!(
+            "{:>11}  {:>8.2}  {:>10.1}  {:>8}  {:>8}  {:>9}  {:>6.1}%",
+            report.concurrency,
+            report.requests_per_second,
+            report.tokens_per_second,
+            ms(report.latency.map(|p| p.p50)),
+            ms(report.latency.map(|p| p.p99)),
+            ms(report.ttft.map(|p| p.p50)),
+            100.0 * report.error_rate()
+        );
+    }
 
+    match sweep.saturation {
+        Some(concurrency) => println!(
+            "Saturation:  concurrency {}; going higher did not raise throughput",
+            concurrency
+        ),
+        None => println!(
+            "Saturation:  not reached; throughput grew up to concurrency {}",
+            max
+        ),
+    }
     Ok(())
 }
+
+fn print_percentiles(label: &str, percentiles: Option<Percentiles>) {
+    let Some(percentiles) = percentiles else {
+        return;
+    };
+    let ms = |value: Duration| value.as_secs_f64() * 1000.0;
+    println!(
+        "{:<12} p50 {:.0}ms, p90 {:.0}ms, p99 {:.0}ms, max {:.0}ms",
+        label,
+        ms(percentiles.p50),
+        ms(percentiles.p90),
+        ms(percentiles.p99),
+        ms(percentiles.max)
+    );
+}
+
+const CHAT_HELP: &str = "\
+Commands:
+  /clear   Forget the conversation so far
+  /exit    Quit (or press Ctrl-D)
+  /help    Show this help";
+
+async fn run_chat(args: &Args) -> Result<()> {
+    let mut session = ChatSession::new(args.client()?, args.model.clone());
+    if let Some(system) = &args.system {
+        session = session.system_prompt(system.clone());
+    }
+    if let Some(temperature) = args.temperature {
+        session = session.temperature(temperature);
+    }
+    if let Some(max_tokens) = args.max_tokens {
+        session = session.max_tokens(max_tokens);
+    }
+
+    eprintln!(
+        "Chatting with {} at {}. Type /help for commands.",
+        args.model, args.url
+    );
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        eprint!("> ");
+        std::io::stderr().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            eprintln!();
+            return Ok(());
+        };
+
+        match line.trim() {
+            "" => continue,
+            "/exit" | "/quit" => return Ok(()),
+            "/help" => {
+                eprintln!("{}", CHAT_HELP);
+                continue;
+            }
+            "/clear" => {
+                session.clear();
+                eprintln!("Conversation cleared.");
+                continue;
+            }
+            command if command.starts_with('/') => {
+                eprintln!("Unknown command {}. Type /help for commands.", command);
+                continue;
+            }
+            _ => {}
+        }
+
+        // A failed turn is dropped from the history, so the user can retry
+        let mut stdout = std::io::stdout();
+        match session.send_stream(line).await {
+            Ok(mut stream) => {
+                while let Some(delta) = stream.next().await {
+                    match delta {
+                        Ok(delta) => {
+                            print!("{}", delta);
+                            stdout.flush()?;
+                        }
+                        Err(err) => {
+                            eprintln!("\nError: {:#}", err);
+                            break;
+                        }
+                    }
+                }
+                println!();
+            }
+            Err(err) => eprintln!("Error: {:#}", err),
+        }
+    }
+}
+
+async fn run_rag(args: &Args, question: &str) -> Result<()> {
+    let dir = args.docs.as_deref().context("rag requires --docs <DIR>")?;
+    let client = args.client()?;
+    let embed_model = args.embed_model.as_deref().unwrap_or(&args.model);
+
+    let documents = rag::load_dir(dir)?;
+    if documents.is_empty() {
+        anyhow::bail!("No text documents found in {}", dir);
+    }
+
+    let mut pipeline = Rag::new(client, args.model.clone(), embed_model)
+        .chunk_size(args.chunk_size, args.chunk_overlap)
+        .top_k(args.top_k);
+    let chunks = pipeline.ingest(&documents).await?;
+    eprintln!(
+        "Indexed {} chunks from {} documents",
+        chunks,
+        documents.len()
+    );
+
+    let RagAnswer { answer, sources } = pipeline.answer(question).await?;
+
+    println!("{}", answer);
+    println!();
+    println!("Sources:");
+    for (i, hit) in sources.iter().enumerate() {
+        println!(
+            "  [{}] {} (chunk {}, score {:.3})",
+            i + 1,
+            hit.chunk.source,
+            hit.chunk.index,
+            hit.score
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+
+    match args.positional.split_first() {
+        Some((command, rest)) if command == "ask" => run_ask(&args, &rest.join(" ")).await,
+        Some((command, _)) if command == "chat" => run_chat(&args).await,
+        Some((command, rest)) if command == "embed" => run_embed(&args, rest).await,
+        Some((command, rest)) if command == "bench" => run_bench(&args, &rest.join(" ")).await,
+        Some((command, rest)) if command == "rag" => {
+            let question = rest.join(" ");
+            if question.is_empty() {
+                anyhow::bail!("rag requires a question");
+            }
+            run_rag(&args, &question).await
+        }
+        Some((command, _)) => anyhow::bail!("Unknown command {}\n\n{}", command, USAGE),
+        None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::agent::ToolRegistry;
+
+// ============================================================================
+// MCP Client
+// ============================================================================
+
+/// MCP protocol revision sent during initialization
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// A tool offered by an MCP server
+#[derive(Debug, Clone, Deserialize)]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema", default)]
+    pub input_schema: Value,
+}
+
+struct Connection {
+    // Kept so the server is killed when the last client handle is dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+impl Connection {
+    async fn write(&mut self, message: &Value) -> Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.stdin
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to write to MCP server")?;
+        self.stdin
+            .flush()
+            .await
+            .context("Failed to write to MCP server")
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.write(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))
+        .await?;
+
+        // Skip notifications and anything else until our response arrives
+        loop {
+            let line = self
+                .stdout
+                .next_line()
+                .await
+                .context("Failed to read from MCP server")?
+                .context("MCP server closed the connection")?;
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if message.get("id").and_then(Value::as_u64) != Some(id)
+                || message.get("method").is_some()
+            {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                anyhow::bail!("MCP error from {}: {}", method, error);
+            }
+            return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+}
+
+/// A connection to an MCP server running as a child process over stdio
+///
+/// Clones share the connection; requests are sent one at a time.
+#[derive(Clone)]
+pub struct McpClient {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl std::fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpClient").finish_non_exhaustive()
+    }
+}
+
+impl McpClient {
+    /// Start `program` with `args` and perform the MCP handshake
+    pub async fn spawn<I, S>(program: &str, args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start MCP server {}", program))?;
+
+        let stdin = child.stdin.take().context("MCP server has no stdin")?;
+        let stdout = child.stdout.take().context("MCP server has no stdout")?;
+        let mut connection = Connection {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            next_id: 0,
+        };
+
+        connection
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": "lancor",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await
+            .context("MCP initialization failed")?;
+        connection
+            .write(&json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/initialized",
+            }))
+            .await?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// List the tools the server offers
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self
+                .connection
+                .lock()
+                .await
+                .request("tools/list", params)
+                .await?;
+
+            let page: Vec<McpTool> = serde_json::from_value(
+                result
+                    .get("tools")
+                    .cloned()
+                    .unwrap_or(Value::Array(Vec::new())),
+            )
+            .context("Invalid tools/list response")?;
+            tools.extend(page);
+
+            cursor = result
+                .get("nextCursor")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// Call a tool and return its output as text
+    ///
+    /// Text content is concatenated; other content is included as JSON. A
+    /// result the server flags as an error is returned as `Err`.
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let result = self
+            .connection
+            .lock()
+            .await
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+
+        let output = result
+            .get("content")
+            .and_then(Value::as_array)
+            .map(|parts| {
+                parts
+                    .iter()
+                    .map(|part| match part.get("text").and_then(Value::as_str) {
+                        Some(text) => text.to_string(),
+                        None => part.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default();
+
+        if result.get("isError").and_then(Value::as_bool) == Some(true) {
+            anyhow::bail!("{}", output);
+        }
+        Ok(output)
+    }
+
+    /// Register every tool of the server in `registry`, so agents can call
+    /// them like local tools
+    ///
+    /// Fails if a tool's input schema cannot be used to check its arguments;
+    /// see [`ToolRegistry::register`].
+    pub async fn register_tools(&self, registry: &mut ToolRegistry) -> Result<usize> {
+        let tools = self.list_tools().await?;
+        let count = tools.len();
+
+        for tool in tools {
+            let client = self.clone();
+            let name = tool.name.clone();
+            registry.register(
+                tool.name,
+                tool.description.unwrap_or_default(),
+                tool.input_schema,
+                move |arguments| {
+                    let client = client.clone();
+                    let name = name.clone();
+                    async move { client.call_tool(&name, arguments).await }
+                },
+            )?;
+        }
+
+        Ok(count)
+    }
+}
@@ -0,0 +1,267 @@
+//! Hooks for collecting client-side statistics.
+//!
+//! Install a [`MetricsObserver`] with [`crate::LlamaCppClient::with_metrics`]
+//! and the client reports every request to it: when it starts and ends, how
+//! many tokens it used, and any error. With the `prometheus` feature,
+//! [`PrometheusMetrics`] aggregates these per endpoint and model and renders
+//! them in the Prometheus text format.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::compat::{MaybeSend, MaybeSync};
+
+// ============================================================================
+// Observer
+// ============================================================================
+
+/// The request a [`MetricsObserver`] callback is about
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestInfo {
+    /// The client method, e.g. `chat_completion` or `embedding`
+    pub endpoint: &'static str,
+    /// The requested model; empty for endpoints that don't take one
+    pub model: String,
+}
+
+impl RequestInfo {
+    pub fn new(endpoint: &'static str, model: impl Into<String>) -> Self {
+        Self {
+            endpoint,
+            model: model.into(),
+        }
+    }
+}
+
+/// Receives events for every request the client sends
+///
+/// All methods do nothing by default. Every started request gets exactly one
+/// `on_request_end`, whether it succeeded or not; streaming requests end when
+/// their stream is dropped.
+pub trait MetricsObserver: std::fmt::Debug + MaybeSend + MaybeSync {
+    fn on_request_start(&self, _request: &RequestInfo) {}
+
+    fn on_request_end(&self, _request: &RequestInfo, _elapsed: Duration) {}
+
+    /// Token usage reported by the server
+    fn on_tokens(&self, _request: &RequestInfo, _prompt_tokens: u32, _completion_tokens: u32) {}
+
+    fn on_error(&self, _request: &RequestInfo, _error: &anyhow::Error) {}
+}
+
+// ============================================================================
+// Prometheus
+// ============================================================================
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::PrometheusMetrics;
+
+#[cfg(feature = "prometheus")]
+mod prometheus {
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{MetricsObserver, RequestInfo};
+
+    /// Upper bounds of the request duration histogram, in seconds
+    const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+    #[derive(Debug, Default)]
+    struct Stats {
+        requests: u64,
+        errors: u64,
+        in_flight: i64,
+        duration_sum: f64,
+        duration_buckets: [u64; BUCKETS.len()],
+        prompt_tokens: u64,
+        completion_tokens: u64,
+    }
+
+    /// A [`MetricsObserver`] that keeps request counts, latencies and token
+    /// usage per endpoint and model
+    ///
+    /// Clones share their counters, so keep one to serve
+    /// [`PrometheusMetrics::render`] from your `/metrics` handler.
+    #[derive(Debug, Clone, Default)]
+    pub struct PrometheusMetrics {
+        stats: Arc<Mutex<BTreeMap<RequestInfo, Stats>>>,
+    }
+
+    impl PrometheusMetrics {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        fn update(&self, request: &RequestInfo, f: impl FnOnce(&mut Stats)) {
+            let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+            f(stats.entry(request.clone()).or_default());
+        }
+
+        /// All metrics in the Prometheus text exposition format
+        pub fn render(&self) -> String {
+            let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+            let mut out = String::new();
+
+            let counters = [
+                ("lancor_requests_total", "Requests sent by the client"),
+                ("lancor_request_errors_total", "Requests that failed"),
+                (
+                    "lancor_prompt_tokens_total",
+                    "Prompt tokens reported by the server",
+                ),
+                (
+                    "lancor_completion_tokens_total",
+                    "Completion tokens reported by the server",
+                ),
+            ];
+            for (i, (name, help)) in counters.into_iter().enumerate() {
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                for (request, s) in stats.iter() {
+                    let value = [s.requests, s.errors, s.prompt_tokens, s.completion_tokens][i];
+                    let _ = writeln!(out, "{}{{{}}} {}", name, labels(request), value);
+                }
+            }
+
+            let name = "lancor_requests_in_flight";
+            let _ = writeln!(out, "# HELP {} Requests currently in progress", name);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for (request, s) in stats.iter() {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(request), s.in_flight);
+            }
+
+            let name = "lancor_request_duration_seconds";
+            let _ = writeln!(out, "# HELP {} Request latency", name);
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for (request, s) in stats.iter() {
+                let labels = labels(request);
+                for (bound, count) in BUCKETS.iter().zip(s.duration_buckets) {
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{{{},le=\"{}\"}} {}",
+                        name, labels, bound, count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"+Inf\"}} {}",
+                    name, labels, s.requests
+                );
+                let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, s.duration_sum);
+                let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, s.requests);
+            }
+
+            out
+        }
+    }
+
+    impl MetricsObserver for PrometheusMetrics {
+        fn on_request_start(&self, request: &RequestInfo) {
+            self.update(request, |s| s.in_flight += 1);
+        }
+
+        fn on_request_end(&self, request: &RequestInfo, elapsed: Duration) {
+            let seconds = elapsed.as_secs_f64();
+            self.update(request, |s| {
+                s.in_flight -= 1;
+                s.requests += 1;
+                s.duration_sum += seconds;
+                for (bound, count) in BUCKETS.iter().zip(s.duration_buckets.iter_mut()) {
+                    if seconds <= *bound {
+                        *count += 1;
+                    }
+                }
+            });
+        }
+
+        fn on_tokens(&self, request: &RequestInfo, prompt_tokens: u32, completion_tokens: u32) {
+            self.update(request, |s| {
+                s.prompt_tokens += u64::from(prompt_tokens);
+                s.completion_tokens += u64::from(completion_tokens);
+            });
+        }
+
+        fn on_error(&self, request: &RequestInfo, _error: &anyhow::Error) {
+            self.update(request, |s| s.errors += 1);
+        }
+    }
+
+    fn labels(request: &RequestInfo) -> String {
+        format!(
+            "endpoint=\"{}\",model=\"{}\"",
+            escape(request.endpoint),
+            escape(&request.model)
+        )
+    }
+
+    fn escape(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+}
+
+// ============================================================================
+// Client Integration
+// ============================================================================
+
+/// One request being reported to the observers; ends the request on drop
+pub(crate) struct Observation {
+    observers: Vec<Arc<dyn MetricsObserver>>,
+    request: RequestInfo,
+    started: crate::compat::Instant,
+}
+
+impl Observation {
+    pub(crate) fn start(observers: Vec<Arc<dyn MetricsObserver>>, request: RequestInfo) -> Self {
+        for observer in &observers {
+            observer.on_request_start(&request);
+        }
+        Self {
+            observers,
+            request,
+            started: crate::compat::Instant::now(),
+        }
+    }
+
+    pub(crate) fn usage(&self, usage: &crate::Usage) {
+        self.tokens(usage.prompt_tokens, usage.completion_tokens.unwrap_or(0));
+    }
+
+    pub(crate) fn tokens(&self, prompt_tokens: u32, completion_tokens: u32) {
+        for observer in &self.observers {
+            observer.on_tokens(&self.request, prompt_tokens, completion_tokens);
+        }
+    }
+
+    pub(crate) fn error(&self, error: &anyhow::Error) {
+        for observer in &self.observers {
+            observer.on_error(&self.request, error);
+        }
+    }
+
+    /// Report the outcome of a finished request
+    pub(crate) fn finish<T>(
+        &self,
+        result: anyhow::Result<T>,
+        tokens: impl FnOnce(&Self, &T),
+    ) -> anyhow::Result<T> {
+        match &result {
+            Ok(value) => tokens(self, value),
+            Err(err) => self.error(err),
+        }
+        result
+    }
+}
+
+impl Drop for Observation {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        for observer in &self.observers {
+            observer.on_request_end(&self.request, elapsed);
+        }
+    }
+}
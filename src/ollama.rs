@@ -0,0 +1,707 @@
+//! A client for Ollama's native API.
+//!
+//! [`OllamaClient`] speaks `/api/chat`, `/api/generate` and `/api/embed` but
+//! takes and returns lancor's OpenAI-style types, and implements
+//! [`Provider`], so the same code can run against llama.cpp and Ollama. It
+//! also covers what only Ollama has: how long a model stays loaded
+//! (`keep_alive`), pulling models, and unloading them.
+//!
+//! ```no_run
+//! use lancor::ollama::OllamaClient;
+//! use lancor::{ChatCompletionRequest, Message};
+//! use std::time::Duration;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = OllamaClient::default()?
+//!     .keep_alive(Duration::from_secs(30 * 60))
+//!     .auto_pull(true);
+//! let request = ChatCompletionRequest::new("llama3.2").message(Message::user("Hello!"));
+//! let response = client.chat_completion(request).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use futures::stream::StreamExt;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::compat::{self, BoxFuture, BoxStream};
+use crate::provider::Provider;
+use crate::transport::{self, HttpRequest, HttpResponse, Transport};
+use crate::{
+    ChatChoice, ChatChoiceDelta, ChatCompletionChunk, ChatCompletionRequest,
+    ChatCompletionResponse, CompletionRequest, CompletionResponse, ContentPart, Delta,
+    EmbeddingData, EmbeddingRequest, EmbeddingResponse, FinishReason, FunctionCall,
+    FunctionCallDelta, Message, MessageContent, ResponseFormat, Tool, ToolCall, ToolCallDelta,
+    ToolChoice, Usage,
+};
+
+// ============================================================================
+// Client
+// ============================================================================
+
+/// A client for an Ollama server
+#[derive(Debug, Clone)]
+pub struct OllamaClient {
+    transport: Arc<dyn Transport>,
+    base_url: String,
+    keep_alive: Option<Value>,
+    auto_pull: bool,
+}
+
+/// A model installed on the Ollama server
+#[derive(Debug, Clone, Deserialize)]
+pub struct OllamaModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub modified_at: Option<String>,
+}
+
+impl OllamaClient {
+    /// Create a client for the server at `base_url`
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            transport: Arc::new(transport::ReqwestTransport::new()?),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            keep_alive: None,
+            auto_pull: false,
+        })
+    }
+
+    /// Create a client connecting to localhost:11434
+    #[allow(clippy::should_implement_trait)]
+    pub fn default() -> Result<Self> {
+        Self::new("http://localhost:11434")
+    }
+
+    /// Send requests through `transport` instead of reqwest
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Keep models loaded for `duration` after each request, instead of the
+    /// server's default of five minutes
+    pub fn keep_alive(mut self, duration: Duration) -> Self {
+        self.keep_alive = Some(json!(format!("{}s", duration.as_secs())));
+        self
+    }
+
+    /// Keep models loaded until they are unloaded or the server stops
+    pub fn keep_alive_forever(mut self) -> Self {
+        self.keep_alive = Some(json!(-1));
+        self
+    }
+
+    /// Pull a model the server does not have and retry, instead of failing
+    pub fn auto_pull(mut self, enabled: bool) -> Self {
+        self.auto_pull = enabled;
+        self
+    }
+
+    /// The models installed on the server (`/api/tags`)
+    pub async fn list_models(&self) -> Result<Vec<OllamaModel>> {
+        #[derive(Deserialize)]
+        struct Tags {
+            models: Vec<OllamaModel>,
+        }
+
+        let request = HttpRequest::get(format!("{}/api/tags", self.base_url));
+        let response = check(self.transport.send(request).await?, "model list").await?;
+        let tags: Tags = response
+            .json()
+            .await
+            .context("Failed to parse model list")?;
+        Ok(tags.models)
+    }
+
+    /// Download `model` to the server, waiting until it is complete
+    pub async fn pull(&self, model: &str) -> Result<()> {
+        let body = json!({ "model": model, "stream": false });
+        let request = HttpRequest::post_json(format!("{}/api/pull", self.base_url), &body)?;
+        let response = self
+            .transport
+            .send(request)
+            .await
+            .with_context(|| format!("Failed to pull {}", model))?;
+        let status: Value = check(response, "pull")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse pull response")?;
+        match status.get("status").and_then(Value::as_str) {
+            Some("success") => Ok(()),
+            _ => anyhow::bail!("Pulling {} failed: {}", model, status),
+        }
+    }
+
+    /// Unload `model` from memory now
+    pub async fn unload(&self, model: &str) -> Result<()> {
+        let body = json!({ "model": model, "keep_alive": 0 });
+        self.post("/api/generate", model, &body, "unload").await?;
+        Ok(())
+    }
+
+    /// Send a chat request to `/api/chat`
+    pub async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        let body = self.chat_body(&request, false)?;
+        let response: ChatResponse = self
+            .post("/api/chat", &request.model, &body, "chat")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse chat response")?;
+        Ok(response.into_completion())
+    }
+
+    /// Send a chat request to `/api/chat` and stream the reply
+    pub async fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<BoxStream<'static, Result<ChatCompletionChunk>>> {
+        let body = self.chat_body(&request, true)?;
+        let response = self
+            .post("/api/chat", &request.model, &body, "streaming chat")
+            .await?;
+
+        let stream = json_lines(response).map(|line| {
+            let line = line?;
+            if let Some(error) = line.get("error") {
+                anyhow::bail!("Ollama error: {}", error);
+            }
+            let response: ChatResponse =
+                serde_json::from_value(line).context("Failed to parse chunk")?;
+            Ok(response.into_chunk())
+        });
+        Ok(compat::boxed(stream))
+    }
+
+    /// Send a prompt to `/api/generate`
+    pub async fn completion(&self, request: CompletionRequest) -> Result<CompletionResponse> {
+        let mut body = json!({
+            "model": request.model,
+            "prompt": request.prompt,
+            "stream": false,
+        });
+        let options = options(request.temperature, None, request.max_tokens, None);
+        self.finish_body(&mut body, options, &request.extra_body);
+
+        let response: GenerateResponse = self
+            .post("/api/generate", &request.model, &body, "generate")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse generate response")?;
+        Ok(CompletionResponse {
+            content: response.response,
+            model: Some(response.model),
+            stop: Some(response.done),
+            tokens_predicted: response.eval_count,
+            tokens_evaluated: response.prompt_eval_count,
+            completion_probabilities: None,
+            timings: None,
+            extra: Default::default(),
+            request_id: None,
+            server_request_id: None,
+        })
+    }
+
+    /// Embed text with `/api/embed`
+    pub async fn embedding(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        #[derive(Deserialize)]
+        struct EmbedResponse {
+            model: String,
+            embeddings: Vec<Vec<f32>>,
+            #[serde(default)]
+            prompt_eval_count: Option<u32>,
+        }
+
+        let mut body = json!({ "model": request.model, "input": request.input });
+        self.finish_body(&mut body, None, &request.extra_body);
+        let response: EmbedResponse = self
+            .post("/api/embed", &request.model, &body, "embedding")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse embedding response")?;
+
+        let tokens = response.prompt_eval_count.unwrap_or(0);
+        Ok(EmbeddingResponse {
+            object: "list".to_string(),
+            data: response
+                .embeddings
+                .into_iter()
+                .enumerate()
+                .map(|(index, mut embedding)| {
+                    request.post_process(&mut embedding);
+                    EmbeddingData {
+                        object: "embedding".to_string(),
+                        embedding,
+                        index: index as u32,
+                    }
+                })
+                .collect(),
+            model: response.model,
+            usage: Usage {
+                prompt_tokens: tokens,
+                completion_tokens: None,
+                total_tokens: tokens,
+                cost: None,
+                extra: Default::default(),
+            },
+            extra: Default::default(),
+            request_id: None,
+            server_request_id: None,
+        })
+    }
+
+    /// POST `body` to `path`, pulling `model` and retrying once if the
+    /// server does not have it and `auto_pull` is on
+    async fn post(
+        &self,
+        path: &str,
+        model: &str,
+        body: &Value,
+        action: &str,
+    ) -> Result<HttpResponse> {
+        let url = format!("{}{}", self.base_url, path);
+        let send = || async {
+            let request = HttpRequest::post_json(url.clone(), body)?;
+            self.transport
+                .send(request)
+                .await
+                .with_context(|| format!("Failed to send {} request", action))
+        };
+
+        let response = send().await?;
+        if response.status == 404 && self.auto_pull {
+            let text = response.text().await.unwrap_or_default();
+            if !text.contains("not found") {
+                anyhow::bail!("Ollama error (404): {}", text);
+            }
+            self.pull(model).await?;
+            return check(send().await?, action).await;
+        }
+        check(response, action).await
+    }
+
+    fn chat_body(&self, request: &ChatCompletionRequest, stream: bool) -> Result<Value> {
+        let messages = request
+            .messages
+            .iter()
+            .map(message_json)
+            .collect::<Result<Vec<_>>>()?;
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": stream,
+        });
+
+        let format = match &request.response_format {
+            Some(ResponseFormat::JsonObject { schema: None }) => Some(json!("json")),
+            Some(ResponseFormat::JsonObject {
+                schema: Some(schema),
+            }) => Some(schema.clone()),
+            Some(ResponseFormat::JsonSchema { json_schema }) => Some(json_schema.schema.clone()),
+            Some(ResponseFormat::Text) | None => None,
+        };
+        if let Some(format) = format {
+            body["format"] = format;
+        }
+        // Ollama has no `tool_choice`; offer only the tools it allows
+        if let Some(tools) = &request.tools {
+            let allowed = |tool: &&Tool| match &request.tool_choice {
+                Some(ToolChoice::None) => false,
+                Some(ToolChoice::Function(name)) => tool.function.name == *name,
+                _ => true,
+            };
+            let tools: Vec<&Tool> = tools.iter().filter(allowed).collect();
+            if !tools.is_empty() {
+                body["tools"] = serde_json::to_value(tools)?;
+            }
+        }
+
+        let options = options(
+            request.temperature,
+            request.top_p,
+            request.max_tokens,
+            request.stop.as_ref(),
+        );
+        self.finish_body(&mut body, options, &request.extra_body);
+        Ok(body)
+    }
+
+    /// Add sampling `options`, the client's `keep_alive` and the request's
+    /// `extra` fields to `body`
+    fn finish_body(&self, body: &mut Value, options: Option<Value>, extra: &Map<String, Value>) {
+        if let Some(options) = options {
+            body["options"] = options;
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            body["keep_alive"] = keep_alive.clone();
+        }
+        if let Some(body) = body.as_object_mut() {
+            body.extend(extra.clone());
+        }
+    }
+}
+
+impl Provider for OllamaClient {
+    fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponse>> {
+        compat::boxed_future(OllamaClient::chat_completion(self, request))
+    }
+
+    fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, Result<ChatCompletionChunk>>>> {
+        compat::boxed_future(OllamaClient::chat_completion_stream(self, request))
+    }
+
+    fn completion(&self, request: CompletionRequest) -> BoxFuture<'_, Result<CompletionResponse>> {
+        compat::boxed_future(OllamaClient::completion(self, request))
+    }
+
+    fn embedding(&self, request: EmbeddingRequest) -> BoxFuture<'_, Result<EmbeddingResponse>> {
+        compat::boxed_future(OllamaClient::embedding(self, request))
+    }
+}
+
+// ============================================================================
+// Translation
+// ============================================================================
+
+/// Ollama's sampling options, or `None` if none are set
+fn options(
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    stop: Option<&Vec<String>>,
+) -> Option<Value> {
+    let mut options = serde_json::Map::new();
+    if let Some(temperature) = temperature {
+        options.insert("temperature".into(), json!(temperature));
+    }
+    if let Some(top_p) = top_p {
+        options.insert("top_p".into(), json!(top_p));
+    }
+    if let Some(max_tokens) = max_tokens {
+        options.insert("num_predict".into(), json!(max_tokens));
+    }
+    if let Some(stop) = stop {
+        options.insert("stop".into(), json!(stop));
+    }
+    (!options.is_empty()).then_some(Value::Object(options))
+}
+
+/// A message in Ollama's format: text content, images as bare base64 and
+/// tool call arguments as objects
+fn message_json(message: &Message) -> Result<Value> {
+    let mut out = json!({
+        "role": message.role,
+        "content": message.content.text(),
+    });
+
+    if let MessageContent::Parts(parts) = &message.content {
+        let mut images = Vec::new();
+        for part in parts {
+            if let ContentPart::InputAudio { .. } = part {
+                anyhow::bail!("Ollama does not accept audio input");
+            }
+            if let ContentPart::ImageUrl { image_url } = part {
+                if !image_url.is_inline() {
+                    anyhow::bail!("Ollama only accepts inline images, not {}", image_url.url);
+                }
+                let payload = image_url
+                    .url
+                    .split_once(',')
+                    .map_or(image_url.url.as_str(), |(_, data)| data);
+                images.push(payload.to_string());
+            }
+        }
+        if !images.is_empty() {
+            out["images"] = json!(images);
+        }
+    }
+
+    if let Some(calls) = &message.tool_calls {
+        let calls = calls
+            .iter()
+            .map(|call| {
+                let arguments: Value = serde_json::from_str(&call.function.arguments)
+                    .with_context(|| {
+                        format!("Invalid arguments for tool {}", call.function.name)
+                    })?;
+                Ok(json!({ "function": { "name": call.function.name, "arguments": arguments } }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        out["tool_calls"] = json!(calls);
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    model: String,
+    #[serde(default)]
+    message: Option<ResponseMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+    #[serde(default)]
+    eval_count: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<ResponseToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseToolCall {
+    function: ResponseFunction,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseFunction {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateResponse {
+    model: String,
+    #[serde(default)]
+    response: String,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+    #[serde(default)]
+    eval_count: Option<u32>,
+}
+
+impl ChatResponse {
+    fn usage(&self) -> Usage {
+        let prompt = self.prompt_eval_count.unwrap_or(0);
+        let completion = self.eval_count.unwrap_or(0);
+        Usage {
+            prompt_tokens: prompt,
+            completion_tokens: Some(completion),
+            total_tokens: prompt + completion,
+            cost: None,
+            extra: Default::default(),
+        }
+    }
+
+    /// `done_reason`, or `tool_calls` when the model called tools
+    fn finish_reason(&self, has_tool_calls: bool) -> Option<FinishReason> {
+        if !self.done {
+            return None;
+        }
+        if has_tool_calls {
+            return Some(FinishReason::ToolCalls);
+        }
+        Some(
+            self.done_reason
+                .as_deref()
+                .map_or(FinishReason::Stop, FinishReason::from),
+        )
+    }
+
+    fn into_completion(self) -> ChatCompletionResponse {
+        let usage = self.usage();
+        let message = self.message.as_ref();
+        let tool_calls: Vec<ToolCall> = message
+            .map(|m| m.tool_calls.iter().enumerate().map(tool_call).collect())
+            .unwrap_or_default();
+        let finish_reason = self.finish_reason(!tool_calls.is_empty());
+
+        ChatCompletionResponse {
+            id: String::new(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            choices: vec![ChatChoice {
+                index: 0,
+                message: Message {
+                    role: message
+                        .and_then(|m| m.role.clone())
+                        .unwrap_or_else(|| "assistant".to_string()),
+                    content: MessageContent::Text(
+                        message.map(|m| m.content.clone()).unwrap_or_default(),
+                    ),
+                    name: None,
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                    tool_call_id: None,
+                },
+                finish_reason,
+                native_finish_reason: None,
+                extra: Default::default(),
+            }],
+            model: self.model,
+            usage,
+            provider: None,
+            timings: None,
+            extra: Default::default(),
+            request_id: None,
+            server_request_id: None,
+        }
+    }
+
+    fn into_chunk(self) -> ChatCompletionChunk {
+        let has_tool_calls = self
+            .message
+            .as_ref()
+            .is_some_and(|m| !m.tool_calls.is_empty());
+        let finish_reason = self.finish_reason(has_tool_calls);
+        let usage = self.done.then(|| self.usage());
+        let message = self.message;
+
+        ChatCompletionChunk {
+            id: String::new(),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: self.model,
+            choices: vec![ChatChoiceDelta {
+                index: 0,
+                delta: Delta {
+                    role: message.as_ref().and_then(|m| m.role.clone()),
+                    tool_calls: message
+                        .as_ref()
+                        .filter(|m| !m.tool_calls.is_empty())
+                        .map(|m| {
+                            m.tool_calls
+                                .iter()
+                                .enumerate()
+                                .map(tool_call_delta)
+                                .collect()
+                        }),
+                    content: message
+                        .map(|m| m.content)
+                        .filter(|content| !content.is_empty()),
+                    reasoning_content: None,
+                    extra: Default::default(),
+                },
+                finish_reason,
+                native_finish_reason: None,
+                extra: Default::default(),
+            }],
+            usage,
+            provider: None,
+            timings: None,
+            extra: Default::default(),
+        }
+    }
+}
+
+fn tool_call((index, call): (usize, &ResponseToolCall)) -> ToolCall {
+    ToolCall {
+        id: format!("call_{}", index),
+        kind: "function".to_string(),
+        function: FunctionCall {
+            name: call.function.name.clone(),
+            arguments: call.function.arguments.to_string(),
+        },
+    }
+}
+
+/// Ollama streams each tool call whole, as a single fragment
+fn tool_call_delta(call: (usize, &ResponseToolCall)) -> ToolCallDelta {
+    let index = call.0 as u32;
+    let call = tool_call(call);
+    ToolCallDelta {
+        index,
+        id: Some(call.id),
+        kind: Some(call.kind),
+        function: Some(FunctionCallDelta {
+            name: Some(call.function.name),
+            arguments: Some(call.function.arguments),
+        }),
+    }
+}
+
+// ============================================================================
+// HTTP
+// ============================================================================
+
+/// Turn an error status into an error with Ollama's message
+async fn check(response: HttpResponse, action: &str) -> Result<HttpResponse> {
+    if response.is_success() {
+        return Ok(response);
+    }
+    let status = response.status;
+    let text = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|body| {
+            body.get("error")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .unwrap_or(text);
+    anyhow::bail!("Ollama {} error ({}): {}", action, status, message)
+}
+
+/// Split a newline-delimited JSON response into values
+fn json_lines(response: HttpResponse) -> BoxStream<'static, Result<Value>> {
+    let state = (response.body, Vec::<u8>::new(), false);
+
+    compat::boxed(futures::stream::unfold(
+        state,
+        |(mut bytes, mut buffer, mut done)| async move {
+            loop {
+                if let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    let value =
+                        serde_json::from_slice(&line).context("Invalid JSON line in stream");
+                    return Some((value, (bytes, buffer, done)));
+                }
+
+                if done {
+                    return None;
+                }
+
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(err)) => {
+                        done = true;
+                        buffer.clear();
+                        let err = err.context("Failed to read stream chunk");
+                        return Some((Err(err), (bytes, buffer, done)));
+                    }
+                    None => {
+                        done = true;
+                        if !buffer.is_empty() {
+                            buffer.push(b'\n');
+                        }
+                    }
+                }
+            }
+        },
+    ))
+}
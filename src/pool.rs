@@ -0,0 +1,154 @@
+//! Spreading requests over several identical servers.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::limits::CircuitState;
+use crate::{LancorConfig, LlamaCppClient};
+
+// ============================================================================
+// Client Pool
+// ============================================================================
+
+/// How a [`ClientPool`] picks the server for each request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    /// Take the servers in turn
+    #[default]
+    RoundRobin,
+    /// Take the server with the fewest requests in progress, including
+    /// streams that are still being read
+    LeastInFlight,
+}
+
+/// What a client knows about one of its servers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointStatus {
+    pub url: String,
+    /// Requests in progress, including streams that are still being read
+    pub in_flight: usize,
+    /// The result of the last health probe; `None` if never probed
+    pub ready: Option<bool>,
+    /// Idle slots at the last probe; `None` if unknown
+    pub free_slots: Option<usize>,
+    /// Whether the server is skipped after a failed request
+    pub cooling_down: bool,
+    /// Always [`CircuitState::Closed`] without a circuit breaker; see
+    /// [`crate::LlamaCppClient::with_circuit_breaker`]
+    pub circuit: CircuitState,
+}
+
+/// A client that distributes requests over a fleet of servers
+///
+/// The pool dereferences to a [`LlamaCppClient`], so it has the same methods
+/// and can be cloned into sessions and agents with [`ClientPool::client`].
+/// A server that fails is skipped for the failover cooldown and its request
+/// is retried on the next one, as with [`LancorConfig::fallback_url`].
+///
+/// ```no_run
+/// use lancor::{ClientPool, LoadBalancing};
+///
+/// # fn example() -> anyhow::Result<()> {
+/// let pool = ClientPool::new(
+///     ["http://node1:8080", "http://node2:8080", "http://node3:8080"],
+///     LoadBalancing::LeastInFlight,
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientPool {
+    client: LlamaCppClient,
+}
+
+impl ClientPool {
+    /// A pool over `base_urls` using `strategy`
+    pub fn new<I, S>(base_urls: I, strategy: LoadBalancing) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut urls = base_urls.into_iter().map(Into::into);
+        let Some(first) = urls.next() else {
+            anyhow::bail!("A client pool needs at least one server");
+        };
+
+        let mut config = LancorConfig::new(first).load_balancing(strategy);
+        config.fallback_urls.extend(urls);
+        Self::from_config(config)
+    }
+
+    /// A pool over the base URL and fallbacks of `config`, round-robin unless
+    /// the config picks another strategy
+    pub fn from_config(mut config: LancorConfig) -> Result<Self> {
+        config.load_balancing.get_or_insert_default();
+        Ok(Self {
+            client: LlamaCppClient::from_config(config)?,
+        })
+    }
+
+    /// Apply client builder methods, e.g.
+    /// `pool.configure(|client| client.with_metrics(metrics))`
+    pub fn configure(mut self, f: impl FnOnce(LlamaCppClient) -> LlamaCppClient) -> Self {
+        self.client = f(self.client);
+        self
+    }
+
+    /// A client sharing this pool's servers and state
+    pub fn client(&self) -> LlamaCppClient {
+        self.client.clone()
+    }
+}
+
+impl std::ops::Deref for ClientPool {
+    type Target = LlamaCppClient;
+
+    fn deref(&self) -> &LlamaCppClient {
+        &self.client
+    }
+}
+
+impl From<ClientPool> for LlamaCppClient {
+    fn from(pool: ClientPool) -> Self {
+        pool.client
+    }
+}
+
+// ============================================================================
+// Health Monitoring
+// ============================================================================
+
+/// A background task that probes a client's servers so requests only go to
+/// ready servers with free slots. The task stops when the monitor is dropped.
+///
+/// Servers that fail a probe are skipped until a later probe finds them ready
+/// again; they are still tried as a last resort when no other server is left.
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+pub struct HealthMonitor {
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl HealthMonitor {
+    /// Probe every server of `client` now and then every `interval`
+    pub fn spawn(client: impl Into<LlamaCppClient>, interval: std::time::Duration) -> Self {
+        let client = client.into();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                client.probe_health().await;
+            }
+        });
+        Self { task }
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
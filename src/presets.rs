@@ -0,0 +1,323 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ChatCompletionRequest;
+
+// ============================================================================
+// Presets
+// ============================================================================
+
+/// A set of sampling parameters. Unset fields leave the request alone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+}
+
+impl Preset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    pub fn repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    /// The sampling parameters of [`ChatCompletionRequest::deterministic`]
+    pub fn deterministic(self, seed: u64) -> Self {
+        self.temperature(0.0)
+            .top_p(1.0)
+            .seed(seed)
+            .presence_penalty(0.0)
+            .frequency_penalty(0.0)
+            .repeat_penalty(1.0)
+    }
+
+    /// Set the fields the request leaves unset
+    fn fill(&self, request: &mut ChatCompletionRequest) {
+        if request.temperature.is_none() {
+            request.temperature = self.temperature;
+        }
+        if request.max_tokens.is_none() {
+            request.max_tokens = self.max_tokens;
+        }
+        if request.top_p.is_none() {
+            request.top_p = self.top_p;
+        }
+        if request.stop.is_none() {
+            request.stop = self.stop.clone();
+        }
+        if request.seed.is_none() {
+            request.seed = self.seed;
+        }
+        if request.presence_penalty.is_none() {
+            request.presence_penalty = self.presence_penalty;
+        }
+        if request.frequency_penalty.is_none() {
+            request.frequency_penalty = self.frequency_penalty;
+        }
+        if request.repeat_penalty.is_none() {
+            request.repeat_penalty = self.repeat_penalty;
+        }
+    }
+
+    /// Set the fields this preset defines, replacing the request's values
+    fn force(&self, request: &mut ChatCompletionRequest) {
+        if self.temperature.is_some() {
+            request.temperature = self.temperature;
+        }
+        if self.max_tokens.is_some() {
+            request.max_tokens = self.max_tokens;
+        }
+        if self.top_p.is_some() {
+            request.top_p = self.top_p;
+        }
+        if self.stop.is_some() {
+            request.stop = self.stop.clone();
+        }
+        if self.seed.is_some() {
+            request.seed = self.seed;
+        }
+        if self.presence_penalty.is_some() {
+            request.presence_penalty = self.presence_penalty;
+        }
+        if self.frequency_penalty.is_some() {
+            request.frequency_penalty = self.frequency_penalty;
+        }
+        if self.repeat_penalty.is_some() {
+            request.repeat_penalty = self.repeat_penalty;
+        }
+    }
+}
+
+/// Where a parameter value came from during resolution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layer {
+    /// Client-wide defaults, used when nothing else sets a value
+    Defaults,
+    /// A named preset selected with [`ChatCompletionRequest::preset`]
+    Preset(String),
+    /// Per-model overrides, which win over the request itself
+    ModelOverride(String),
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layer::Defaults => write!(f, "defaults"),
+            Layer::Preset(name) => write!(f, "preset {:?}", name),
+            Layer::ModelOverride(model) => write!(f, "override for model {:?}", model),
+        }
+    }
+}
+
+/// The layered parameter configuration of a client
+///
+/// Resolution applies, in order: the request's preset and then the client
+/// defaults to fill unset fields, and finally the overrides for the request's
+/// model, which replace whatever the request asked for.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Presets {
+    #[serde(default)]
+    pub defaults: Preset,
+    #[serde(default)]
+    pub named: HashMap<String, Preset>,
+    #[serde(default)]
+    pub model_overrides: HashMap<String, Preset>,
+}
+
+impl Presets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn defaults(mut self, defaults: Preset) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    pub fn preset(mut self, name: impl Into<String>, preset: Preset) -> Self {
+        self.named.insert(name.into(), preset);
+        self
+    }
+
+    pub fn model_override(mut self, model: impl Into<String>, preset: Preset) -> Self {
+        self.model_overrides.insert(model.into(), preset);
+        self
+    }
+
+    /// Resolve `request` into the parameters that will actually be sent,
+    /// recording what each layer changed
+    pub fn resolve(&self, request: &ChatCompletionRequest) -> Result<Resolution> {
+        let mut resolved = request.clone();
+        let mut steps = Vec::new();
+
+        let mut apply = |layer: Layer, preset: &Preset, force: bool| {
+            let before = resolved.clone();
+            if force {
+                preset.force(&mut resolved);
+            } else {
+                preset.fill(&mut resolved);
+            }
+            let changes = diff(&before, &resolved);
+            if !changes.is_empty() {
+                steps.push((layer, changes));
+            }
+        };
+
+        if let Some(name) = &request.preset {
+            let preset = self
+                .named
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown preset {:?}", name))?;
+            apply(Layer::Preset(name.clone()), preset, false);
+        }
+        apply(Layer::Defaults, &self.defaults, false);
+        if let Some(preset) = self.model_overrides.get(&request.model) {
+            apply(Layer::ModelOverride(request.model.clone()), preset, true);
+        }
+
+        resolved.preset = None;
+        Ok(Resolution {
+            request: resolved,
+            steps,
+        })
+    }
+}
+
+// ============================================================================
+// Diffing
+// ============================================================================
+
+/// One parameter that differs between two requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamChange {
+    pub field: &'static str,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl fmt::Display for ParamChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "unset".into());
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.field,
+            show(&self.before),
+            show(&self.after)
+        )
+    }
+}
+
+fn params(request: &ChatCompletionRequest) -> [(&'static str, Option<String>); 9] {
+    [
+        ("model", Some(request.model.clone())),
+        ("temperature", request.temperature.map(|v| v.to_string())),
+        ("max_tokens", request.max_tokens.map(|v| v.to_string())),
+        ("top_p", request.top_p.map(|v| v.to_string())),
+        ("stop", request.stop.as_ref().map(|v| format!("{:?}", v))),
+        ("seed", request.seed.map(|v| v.to_string())),
+        (
+            "presence_penalty",
+            request.presence_penalty.map(|v| v.to_string()),
+        ),
+        (
+            "frequency_penalty",
+            request.frequency_penalty.map(|v| v.to_string()),
+        ),
+        (
+            "repeat_penalty",
+            request.repeat_penalty.map(|v| v.to_string()),
+        ),
+    ]
+}
+
+/// The sampling parameters that differ between `before` and `after`
+pub fn diff(before: &ChatCompletionRequest, after: &ChatCompletionRequest) -> Vec<ParamChange> {
+    params(before)
+        .into_iter()
+        .zip(params(after))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((field, old), (_, new))| ParamChange {
+            field,
+            before: old,
+            after: new,
+        })
+        .collect()
+}
+
+/// The outcome of resolving a request against a client's [`Presets`]
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// The request as it will be sent
+    pub request: ChatCompletionRequest,
+    /// What each layer changed, in the order the layers were applied
+    pub steps: Vec<(Layer, Vec<ParamChange>)>,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.steps.is_empty() {
+            return writeln!(f, "no parameters changed");
+        }
+        for (layer, changes) in &self.steps {
+            for change in changes {
+                writeln!(f, "{} ({})", change, layer)?;
+            }
+        }
+        Ok(())
+    }
+}
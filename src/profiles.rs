@@ -0,0 +1,189 @@
+//! Named connection profiles.
+//!
+//! Profiles live in `~/.config/lancor/config.toml` (or `$LANCOR_CONFIG`) and
+//! give a name to a server, its API key, a default model and sampling
+//! defaults:
+//!
+//! ```toml
+//! default = "local"
+//!
+//! [profiles.local]
+//! base_url = "http://localhost:8080"
+//!
+//! [profiles.work]
+//! base_url = "https://llm.example.com"
+//! api_key_env = "WORK_LLM_KEY"
+//! model = "qwen2.5-72b"
+//! temperature = 0.2
+//! max_tokens = 1024
+//! ```
+//!
+//! Use [`crate::LlamaCppClient::from_profile`] to build a client from one, or
+//! `lancor --profile work` on the command line.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::LlamaCppClient;
+use crate::config::LancorConfig;
+use crate::presets::{Preset, Presets};
+
+// ============================================================================
+// Profile
+// ============================================================================
+
+/// One named set of connection settings and defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// An environment variable to read the API key from, so the file need
+    /// not contain it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// The model used by requests that leave theirs empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+impl Profile {
+    /// The API key, from `api_key` or else the `api_key_env` variable
+    pub fn resolve_api_key(&self) -> Result<Option<String>> {
+        if let Some(key) = &self.api_key {
+            return Ok(Some(key.clone()));
+        }
+        match &self.api_key_env {
+            Some(var) => std::env::var(var)
+                .map(Some)
+                .with_context(|| format!("API key variable {} is not set", var)),
+            None => Ok(None),
+        }
+    }
+
+    /// The sampling defaults as a [`Preset`]
+    pub fn preset(&self) -> Preset {
+        Preset {
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            stop: self.stop.clone(),
+            ..Preset::default()
+        }
+    }
+
+    /// A client configuration with this profile's settings
+    pub fn config(&self) -> Result<LancorConfig> {
+        Ok(self.config_with_api_key(self.resolve_api_key()?))
+    }
+
+    /// A client configuration with this profile's settings but `api_key`
+    /// instead of the profile's own, which is not looked up, so an unset
+    /// `api_key_env` does not matter
+    pub fn config_with_api_key(&self, api_key: Option<String>) -> LancorConfig {
+        let mut config = match &self.base_url {
+            Some(url) => LancorConfig::new(url.clone()),
+            None => LancorConfig::default(),
+        };
+        config.api_key = api_key;
+        config.default_model = self.model.clone();
+        config.presets = Presets::new().defaults(self.preset());
+        config
+    }
+}
+
+// ============================================================================
+// Profiles File
+// ============================================================================
+
+/// The profiles defined in a config file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profiles {
+    /// The profile used when none is named
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Where profiles are read from: `$LANCOR_CONFIG`, else
+    /// `$XDG_CONFIG_HOME/lancor/config.toml`, else
+    /// `~/.config/lancor/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        if let Some(path) = var("LANCOR_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = var("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| {
+            var("HOME")
+                .or_else(|| var("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+        Some(config_dir.join("lancor").join("config.toml"))
+    }
+
+    /// Load the profiles at [`Profiles::default_path`]; a missing file has
+    /// no profiles
+    pub fn load() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::from_file(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Load profiles from a TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        Self::from_toml(&text).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// Parse profiles from TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// The profile called `name`
+    pub fn get(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).with_context(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("Unknown profile {:?}; no profiles are defined", name)
+            } else {
+                format!(
+                    "Unknown profile {:?}; expected one of {}",
+                    name,
+                    known.join(", ")
+                )
+            }
+        })
+    }
+
+    /// The profile named by `default`, if any
+    pub fn default_profile(&self) -> Result<Option<&Profile>> {
+        self.default
+            .as_deref()
+            .map(|name| self.get(name))
+            .transpose()
+    }
+
+    /// A client configured by the profile called `name`
+    pub fn client(&self, name: &str) -> Result<LlamaCppClient> {
+        LlamaCppClient::from_config(self.get(name)?.config()?)
+    }
+}
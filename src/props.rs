@@ -0,0 +1,186 @@
+//! What a llama.cpp server is running with.
+//!
+//! llama.cpp's `/props` endpoint reports the loaded model, its context size,
+//! chat template and default sampling settings. [`LlamaCppClient::server_props`]
+//! parses it, and [`LlamaCppClient::fits_in_context`] uses the context size
+//! to catch prompts the server would reject before sending them:
+//!
+//! ```no_run
+//! use lancor::{LlamaCppClient, Message};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = LlamaCppClient::new("http://localhost:8080")?;
+//! let messages = vec![Message::user("Summarise this report: ...")];
+//! if !client.fits_in_context(&messages).await? {
+//!     println!("Too long for {} tokens", client.context_window().await?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+use crate::history::MESSAGE_OVERHEAD;
+use crate::{
+    ChatCompletionRequest, Dialect, LancorConfig, LlamaCppClient, Message, TokenizeRequest,
+    token_estimate, transport,
+};
+
+// ============================================================================
+// Properties
+// ============================================================================
+
+/// The server's settings, as reported by `/props`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerProps {
+    #[serde(default)]
+    pub default_generation_settings: GenerationSettings,
+    /// Number of slots, from the server's `--parallel` setting
+    #[serde(default)]
+    pub total_slots: Option<u32>,
+    #[serde(default)]
+    pub model_path: Option<String>,
+    /// The model's Jinja chat template
+    #[serde(default)]
+    pub chat_template: Option<String>,
+    /// Input types beyond text that the loaded model accepts
+    #[serde(default)]
+    pub modalities: Modalities,
+    #[serde(default)]
+    pub bos_token: Option<String>,
+    #[serde(default)]
+    pub eos_token: Option<String>,
+    /// The llama.cpp build, such as `b5400-8a1d2062`
+    #[serde(default)]
+    pub build_info: Option<String>,
+}
+
+/// Settings each slot starts with
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct GenerationSettings {
+    /// Context size of one slot in tokens
+    #[serde(default)]
+    pub n_ctx: Option<u32>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Default sampling parameters; the fields vary between llama.cpp
+    /// versions
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+/// Input types the loaded model accepts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Modalities {
+    #[serde(default)]
+    pub vision: bool,
+    #[serde(default)]
+    pub audio: bool,
+}
+
+impl ServerProps {
+    /// Context size of one slot in tokens, the most a single request can use
+    pub fn context_window(&self) -> Option<u32> {
+        self.default_generation_settings.n_ctx
+    }
+}
+
+// ============================================================================
+// Requests
+// ============================================================================
+
+impl LlamaCppClient {
+    /// Fetch the server's properties (llama.cpp native `/props` endpoint)
+    pub async fn server_props(&self) -> Result<ServerProps> {
+        let config = self.config();
+        let observation = self.observe("props", "")?;
+        let response = async {
+            let request = transport::HttpRequest::get(String::new());
+            self.dispatch(&config, request, "/props", "props")
+                .await?
+                .0
+                .json()
+                .await
+                .context("Failed to parse server properties")
+        }
+        .await;
+        observation.finish(response, |_, _| {})
+    }
+
+    /// The most tokens one request can use, prompt and reply together
+    pub async fn context_window(&self) -> Result<u32> {
+        self.server_props()
+            .await?
+            .context_window()
+            .context("The server does not report its context size")
+    }
+
+    /// How many tokens `messages` take up in the prompt
+    ///
+    /// Counts their text with one `/tokenize` request, or with
+    /// [`crate::token_estimate`] if the server cannot tokenize, plus a few
+    /// tokens per message for the chat template.
+    pub async fn prompt_tokens(&self, messages: &[Message]) -> Result<u32> {
+        let texts: Vec<String> = messages.iter().map(|m| m.content.text()).collect();
+        let text = match self.tokenize(TokenizeRequest::new(texts.join("\n"))).await {
+            Ok(response) => response.tokens.len() as u32,
+            Err(_) => texts.iter().map(|text| token_estimate(text)).sum(),
+        };
+        Ok(text + MESSAGE_OVERHEAD * messages.len() as u32)
+    }
+
+    /// Whether `messages` fit in the server's context window
+    ///
+    /// Counts them as [`LlamaCppClient::prompt_tokens`] does. Leave room for
+    /// the reply on top: a prompt that only just fits gets cut off almost at
+    /// once.
+    pub async fn fits_in_context(&self, messages: &[Message]) -> Result<bool> {
+        Ok(self.prompt_tokens(messages).await? <= self.context_window().await?)
+    }
+
+    /// Set `max_tokens` to what the context window has left after the
+    /// prompt, if the configuration asks for it and the request has none
+    pub(crate) async fn fill_max_tokens(
+        &self,
+        config: &LancorConfig,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<()> {
+        let Some(margin) = config.auto_max_tokens else {
+            return Ok(());
+        };
+        if request.max_tokens.is_some() || config.dialect != Dialect::LlamaCpp {
+            return Ok(());
+        }
+
+        let cached = *self.lock_context_window();
+        let window = match cached {
+            Some(window) => window,
+            None => {
+                let window = self
+                    .context_window()
+                    .await
+                    .context("Failed to look up the context window for max_tokens")?;
+                *self.lock_context_window() = Some(window);
+                window
+            }
+        };
+        let prompt = self.prompt_tokens(&request.messages).await?;
+        let available = window.saturating_sub(prompt).saturating_sub(margin);
+        if available == 0 {
+            bail!(
+                "The prompt takes about {} tokens, leaving no room for a reply in the {}-token context window",
+                prompt,
+                window
+            );
+        }
+        request.max_tokens = Some(available);
+        Ok(())
+    }
+
+    fn lock_context_window(&self) -> std::sync::MutexGuard<'_, Option<u32>> {
+        self.context_window
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
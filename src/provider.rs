@@ -0,0 +1,71 @@
+//! A common interface over the servers lancor can talk to.
+//!
+//! [`Provider`] covers the requests every backend supports, so code written
+//! against it runs unchanged on llama.cpp ([`crate::LlamaCppClient`]) and,
+//! with the `ollama` feature, on Ollama's native API
+//! ([`crate::ollama::OllamaClient`]):
+//!
+//! ```no_run
+//! use lancor::provider::Provider;
+//! use lancor::{ChatCompletionRequest, Message};
+//!
+//! async fn summarize(provider: &dyn Provider, text: &str) -> anyhow::Result<String> {
+//!     let request = ChatCompletionRequest::new("auto")
+//!         .message(Message::system("Summarize in one sentence."))
+//!         .message(Message::user(text));
+//!     let response = provider.chat_completion(request).await?;
+//!     Ok(response.choices[0].message.content.text())
+//! }
+//! ```
+
+use anyhow::Result;
+
+use crate::compat::{self, BoxFuture, BoxStream, MaybeSend, MaybeSync};
+use crate::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, CompletionRequest,
+    CompletionResponse, EmbeddingRequest, EmbeddingResponse, LlamaCppClient,
+};
+
+/// The requests shared by every backend, in OpenAI-compatible types
+pub trait Provider: std::fmt::Debug + MaybeSend + MaybeSync {
+    fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponse>>;
+
+    fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, Result<ChatCompletionChunk>>>>;
+
+    fn completion(&self, request: CompletionRequest) -> BoxFuture<'_, Result<CompletionResponse>>;
+
+    fn embedding(&self, request: EmbeddingRequest) -> BoxFuture<'_, Result<EmbeddingResponse>>;
+}
+
+impl Provider for LlamaCppClient {
+    fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponse>> {
+        compat::boxed_future(LlamaCppClient::chat_completion(self, request))
+    }
+
+    fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, Result<ChatCompletionChunk>>>> {
+        compat::boxed_future(async move {
+            let stream = LlamaCppClient::chat_completion_stream(self, request).await?;
+            Ok(compat::boxed(stream))
+        })
+    }
+
+    fn completion(&self, request: CompletionRequest) -> BoxFuture<'_, Result<CompletionResponse>> {
+        compat::boxed_future(LlamaCppClient::completion(self, request))
+    }
+
+    fn embedding(&self, request: EmbeddingRequest) -> BoxFuture<'_, Result<EmbeddingResponse>> {
+        compat::boxed_future(LlamaCppClient::embedding(self, request))
+    }
+}
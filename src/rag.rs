@@ -0,0 +1,485 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::embeddings::{VectorIndex, VectorMatch};
+use crate::templates::ChatTemplate;
+use crate::{ChatCompletionRequest, EmbeddingRequest, LlamaCppClient, Message, RerankRequest};
+
+// ============================================================================
+// Documents
+// ============================================================================
+
+/// A text document and where it was loaded from
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub source: String,
+    pub text: String,
+}
+
+impl Document {
+    pub fn new(source: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            text: text.into(),
+        }
+    }
+
+    /// Load a single UTF-8 text file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self::new(path.display().to_string(), text))
+    }
+}
+
+/// Recursively load every UTF-8 text file below `dir`
+///
+/// Hidden files and directories are skipped, as are files that are not valid
+/// UTF-8. Documents are returned sorted by path.
+pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Document>> {
+    let mut paths = Vec::new();
+    collect_files(dir.as_ref(), &mut paths)?;
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| Document::load(path).ok())
+        .filter(|doc| !doc.text.trim().is_empty())
+        .collect())
+}
+
+fn collect_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(&path, paths)?;
+        } else if path.is_file() {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// ============================================================================
+// Chunking
+// ============================================================================
+
+/// A piece of a document small enough to embed
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub source: String,
+    pub index: usize,
+    pub text: String,
+}
+
+/// Split `text` into chunks of at most `size` characters, with `overlap`
+/// characters shared between neighbouring chunks; see
+/// [`crate::chunking::by_characters`]
+pub fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    crate::chunking::by_characters(text, size, overlap)
+}
+
+// ============================================================================
+// Index
+// ============================================================================
+
+pub use crate::embeddings::math::cosine_similarity;
+
+/// A retrieved chunk and its similarity to the query
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub chunk: Chunk,
+    /// Cosine similarity, or the reranker's relevance score for hits from
+    /// [`Rag::retrieve_and_rerank`]
+    pub score: f32,
+}
+
+/// An in-memory index of embedded document chunks
+#[derive(Debug, Clone)]
+pub struct Index {
+    client: LlamaCppClient,
+    model: String,
+    entries: Vec<(Chunk, Vec<f32>)>,
+}
+
+impl Index {
+    /// Create an empty index that embeds with `model`
+    pub fn new(client: LlamaCppClient, model: impl Into<String>) -> Self {
+        Self {
+            client,
+            model: model.into(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let request = EmbeddingRequest::new(self.model.clone(), text);
+        let response = self.client.embedding(request).await?;
+        response
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .context("Embedding response contained no data")
+    }
+
+    /// Chunk and embed a document
+    pub async fn add(&mut self, document: &Document, size: usize, overlap: usize) -> Result<()> {
+        for (index, text) in chunk_text(&document.text, size, overlap)
+            .into_iter()
+            .enumerate()
+        {
+            let embedding = self
+                .embed(&text)
+                .await
+                .with_context(|| format!("Failed to embed {}", document.source))?;
+            let chunk = Chunk {
+                source: document.source.clone(),
+                index,
+                text,
+            };
+            self.entries.push((chunk, embedding));
+        }
+        Ok(())
+    }
+
+    /// Return the `k` chunks most similar to `query`, best first
+    pub async fn search(&self, query: &str, k: usize) -> Result<Vec<Hit>> {
+        let query = self.embed(query).await?;
+
+        let mut hits: Vec<Hit> = self
+            .entries
+            .iter()
+            .map(|(chunk, embedding)| Hit {
+                chunk: chunk.clone(),
+                score: cosine_similarity(&query, embedding),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+
+        Ok(hits)
+    }
+}
+
+// ============================================================================
+// Answering
+// ============================================================================
+
+/// Build the messages asking `question` over the retrieved `hits`
+///
+/// Sources are numbered from 1 in the order given, and the model is asked to
+/// cite them as `[n]`.
+pub fn answer_messages(question: &str, hits: &[Hit]) -> Vec<Message> {
+    let context = format_sources(hits);
+
+    vec![
+        Message::system(
+            "Answer the question using only the numbered sources below. \
+             Cite the sources you use as [n]. If the sources do not contain \
+             the answer, say so.",
+        ),
+        Message::user(format!("Sources:\n\n{}Question: {}", context, question)),
+    ]
+}
+
+/// The retrieved `hits` as numbered sources, each followed by a blank line
+pub fn format_sources(hits: &[Hit]) -> String {
+    hits.iter()
+        .enumerate()
+        .map(|(i, hit)| format!("[{}] ({})\n{}\n\n", i + 1, hit.chunk.source, hit.chunk.text))
+        .collect()
+}
+
+/// Answer `question` with `model`, grounded in the retrieved `hits`
+pub async fn answer(
+    client: &LlamaCppClient,
+    model: impl Into<String>,
+    question: &str,
+    hits: &[Hit],
+) -> Result<String> {
+    let request = ChatCompletionRequest::new(model).messages(answer_messages(question, hits));
+    let response = client.chat_completion(request).await?;
+    response
+        .choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.message.content.text())
+        .context("Response contained no choices")
+}
+
+// ============================================================================
+// Pipeline
+// ============================================================================
+
+/// An answer and the chunks it was based on, numbered as cited
+#[derive(Debug, Clone)]
+pub struct RagAnswer {
+    pub answer: String,
+    pub sources: Vec<Hit>,
+}
+
+/// Chunking, embedding, retrieval and answering in one place
+///
+/// Documents are split into sentence-aligned chunks, embedded with the
+/// embedding model and stored in a [`VectorIndex`]. Each question retrieves
+/// the most similar chunks and asks the chat model to answer from them.
+///
+/// ```no_run
+/// use lancor::LlamaCppClient;
+/// use lancor::rag::{self, Rag};
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let client = LlamaCppClient::default()?;
+/// let mut rag = Rag::new(client, "chat-model", "embed-model").top_k(6);
+/// rag.ingest(&rag::load_dir("docs")?).await?;
+///
+/// let result = rag.answer("How do I rotate the API keys?").await?;
+/// println!("{}", result.answer);
+/// for (i, hit) in result.sources.iter().enumerate() {
+///     println!("[{}] {}", i + 1, hit.chunk.source);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rag {
+    client: LlamaCppClient,
+    chat_model: String,
+    embedding_model: String,
+    reranker: Option<String>,
+    index: VectorIndex,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    top_k: usize,
+    min_score: Option<f32>,
+    template: Option<ChatTemplate>,
+}
+
+impl Rag {
+    /// A pipeline answering with `chat_model` over chunks embedded with
+    /// `embedding_model`
+    pub fn new(
+        client: LlamaCppClient,
+        chat_model: impl Into<String>,
+        embedding_model: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            chat_model: chat_model.into(),
+            embedding_model: embedding_model.into(),
+            reranker: None,
+            index: VectorIndex::new(),
+            chunk_size: 1000,
+            chunk_overlap: 200,
+            top_k: 4,
+            min_score: None,
+            template: None,
+        }
+    }
+
+    /// Chunks of at most `size` characters sharing up to `overlap` with
+    /// their neighbours; 1000 and 200 by default
+    pub fn chunk_size(mut self, size: usize, overlap: usize) -> Self {
+        self.chunk_size = size;
+        self.chunk_overlap = overlap;
+        self
+    }
+
+    /// Retrieve `k` chunks per question; 4 by default
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.top_k = k;
+        self
+    }
+
+    /// Leave out chunks whose similarity to the question is below
+    /// `min_score`
+    pub fn min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    /// Rerank with `model` in [`Rag::retrieve_and_rerank`]
+    pub fn reranker(mut self, model: impl Into<String>) -> Self {
+        self.reranker = Some(model.into());
+        self
+    }
+
+    /// Ask with `template` instead of [`answer_messages`]
+    ///
+    /// The template gets the question as `{{question}}` and the sources,
+    /// formatted by [`format_sources`], as `{{context}}`.
+    pub fn template(mut self, template: ChatTemplate) -> Result<Self> {
+        template.expect_variables(&["context", "question"])?;
+        self.template = Some(template);
+        Ok(self)
+    }
+
+    /// Start from an index built earlier, e.g. one loaded with
+    /// [`VectorIndex::load`]
+    pub fn with_index(mut self, index: VectorIndex) -> Self {
+        self.index = index;
+        self
+    }
+
+    pub fn index(&self) -> &VectorIndex {
+        &self.index
+    }
+
+    /// Chunk, embed and store `documents`, returning the number of chunks
+    /// added
+    ///
+    /// A document whose source was ingested before replaces its old chunks.
+    pub async fn ingest(&mut self, documents: &[Document]) -> Result<usize> {
+        let mut added = 0;
+        for document in documents {
+            added += self.ingest_document(document).await?;
+        }
+        Ok(added)
+    }
+
+    async fn ingest_document(&mut self, document: &Document) -> Result<usize> {
+        let stale: Vec<String> = self
+            .index
+            .ids()
+            .filter(|id| {
+                self.index
+                    .metadata(id)
+                    .is_some_and(|metadata| metadata["source"] == document.source.as_str())
+            })
+            .map(String::from)
+            .collect();
+        for id in stale {
+            self.index.remove(&id);
+        }
+
+        let chunks =
+            crate::chunking::by_sentences(&document.text, self.chunk_size, self.chunk_overlap);
+        let count = chunks.len();
+        for (index, text) in chunks.into_iter().enumerate() {
+            let metadata = serde_json::json!({
+                "source": document.source,
+                "index": index,
+                "text": text,
+            });
+            self.index
+                .insert_text(
+                    &self.client,
+                    &self.embedding_model,
+                    format!("{}#{}", document.source, index),
+                    &text,
+                    metadata,
+                )
+                .await
+                .with_context(|| format!("Failed to embed {}", document.source))?;
+        }
+        Ok(count)
+    }
+
+    /// The chunks most relevant to `question`, best first
+    pub async fn retrieve(&self, question: &str) -> Result<Vec<Hit>> {
+        self.retrieve_top(question, self.top_k).await
+    }
+
+    /// Retrieve the `candidates` chunks most similar to `question`, then
+    /// keep the `k` that the [`Rag::reranker`] model finds most relevant,
+    /// best first
+    ///
+    /// Embedding similarity is quick but coarse; a reranker reads the
+    /// question and each chunk together, so it ranks the shortlist better.
+    pub async fn retrieve_and_rerank(
+        &self,
+        question: &str,
+        candidates: usize,
+        k: usize,
+    ) -> Result<Vec<Hit>> {
+        let model = self
+            .reranker
+            .clone()
+            .context("No reranker model; set one with Rag::reranker")?;
+        let hits = self.retrieve_top(question, candidates).await?;
+        if hits.is_empty() {
+            return Ok(hits);
+        }
+
+        let texts = hits.iter().map(|hit| hit.chunk.text.as_str());
+        let request = RerankRequest::new(model, question, texts).top_n(k);
+        let response = self.client.rerank(request).await?;
+        Ok(response
+            .results
+            .into_iter()
+            .take(k)
+            .map(|result| Hit {
+                chunk: hits[result.index].chunk.clone(),
+                score: result.relevance_score,
+            })
+            .collect())
+    }
+
+    async fn retrieve_top(&self, question: &str, k: usize) -> Result<Vec<Hit>> {
+        let matches = self
+            .index
+            .query_text(&self.client, &self.embedding_model, question, k)
+            .await?;
+        Ok(matches
+            .into_iter()
+            .filter(|hit| self.min_score.is_none_or(|min| hit.score >= min))
+            .map(hit_from_match)
+            .collect())
+    }
+
+    /// Retrieve chunks for `question` and answer it from them
+    pub async fn answer(&self, question: &str) -> Result<RagAnswer> {
+        let sources = self.retrieve(question).await?;
+        let messages = match &self.template {
+            Some(template) => {
+                let vars = std::collections::HashMap::from([
+                    ("context", format_sources(&sources)),
+                    ("question", question.to_string()),
+                ]);
+                template.render(&vars)?
+            }
+            None => answer_messages(question, &sources),
+        };
+
+        let request = ChatCompletionRequest::new(self.chat_model.clone()).messages(messages);
+        let response = self.client.chat_completion(request).await?;
+        let answer = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content.text())
+            .context("Response contained no choices")?;
+
+        Ok(RagAnswer { answer, sources })
+    }
+}
+
+fn hit_from_match(hit: VectorMatch) -> Hit {
+    let metadata = &hit.metadata;
+    Hit {
+        chunk: Chunk {
+            source: metadata["source"].as_str().unwrap_or_default().to_string(),
+            index: metadata["index"].as_u64().unwrap_or_default() as usize,
+            text: metadata["text"].as_str().unwrap_or_default().to_string(),
+        },
+        score: hit.score,
+    }
+}
@@ -0,0 +1,579 @@
+//! Checking JSON values against a JSON schema.
+//!
+//! With the `jsonschema` feature, values are checked by the
+//! [`jsonschema`](https://docs.rs/jsonschema) crate, which implements every
+//! draft in full, including `pattern` and the standard `format`s.
+//!
+//! Without it, a built-in validator covers the keywords tool and output
+//! schemas use in practice: `type`, `enum`, `const`, `properties`,
+//! `required`, `additionalProperties`, `items`, `minItems`, `maxItems`,
+//! `uniqueItems`, `minLength`, `maxLength`, `minimum`, `maximum`,
+//! `exclusiveMinimum`, `exclusiveMaximum`, `multipleOf`, `allOf`, `anyOf`,
+//! `oneOf`, `not` and local `$ref`s such as `#/$defs/Name`. Schemas that use
+//! any other assertion, such as `pattern` or `format: "date-time"`, are
+//! rejected rather than half checked: [`unchecked_keywords`] lists where, and
+//! [`validate`] reports them as violations. Formats outside the standard
+//! ones, like `uint8` from schemars, only annotate and are ignored.
+//!
+//! With the `schemars` feature, [`of`] derives a schema from a Rust type.
+//!
+//! ```
+//! use serde_json::json;
+//!
+//! let schema = json!({
+//!     "type": "object",
+//!     "properties": { "city": { "type": "string" } },
+//!     "required": ["city"]
+//! });
+//! let violations = lancor::schema::validate(&schema, &json!({ "city": 7 }));
+//! assert_eq!(violations[0].path, "/city");
+//! ```
+
+#[cfg(not(feature = "jsonschema"))]
+use serde_json::Map;
+use serde_json::Value;
+
+/// References followed in a row before giving up on a cyclic schema
+#[cfg(not(feature = "jsonschema"))]
+const MAX_REF_DEPTH: usize = 32;
+
+/// A place where a value breaks its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending part of the value; empty for the value
+    /// itself
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Every way `value` breaks `schema`; empty if it is valid
+///
+/// A schema that cannot be compiled, such as one using keywords the built-in
+/// validator cannot check, never validates; each of its problems is reported
+/// as a violation. Compile the schema once with [`CompiledSchema::compile`] to
+/// check many values, or to find out about such problems up front.
+pub fn validate(schema: &Value, value: &Value) -> Vec<SchemaViolation> {
+    match CompiledSchema::compile(schema) {
+        Ok(compiled) => compiled.validate(value),
+        Err(err) => err
+            .problems
+            .into_iter()
+            .map(|message| SchemaViolation {
+                path: String::new(),
+                message,
+            })
+            .collect(),
+    }
+}
+
+/// A schema ready to check values against
+#[derive(Clone)]
+pub struct CompiledSchema {
+    #[cfg(feature = "jsonschema")]
+    validator: std::sync::Arc<jsonschema::Validator>,
+    #[cfg(not(feature = "jsonschema"))]
+    schema: Value,
+}
+
+impl std::fmt::Debug for CompiledSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompiledSchema").finish_non_exhaustive()
+    }
+}
+
+impl CompiledSchema {
+    /// Compile `schema`, failing if it is not a valid schema or, without the
+    /// `jsonschema` feature, uses keywords listed by [`unchecked_keywords`]
+    #[cfg(feature = "jsonschema")]
+    pub fn compile(schema: &Value) -> Result<Self, InvalidSchema> {
+        let validator = jsonschema::options()
+            .should_validate_formats(true)
+            .build(schema)
+            .map_err(|err| InvalidSchema {
+                problems: vec![format!("invalid schema: {}", err)],
+            })?;
+        Ok(Self {
+            validator: std::sync::Arc::new(validator),
+        })
+    }
+
+    /// Compile `schema`, failing if it is not a valid schema or, without the
+    /// `jsonschema` feature, uses keywords listed by [`unchecked_keywords`]
+    #[cfg(not(feature = "jsonschema"))]
+    pub fn compile(schema: &Value) -> Result<Self, InvalidSchema> {
+        let unchecked = unchecked_keywords(schema);
+        if !unchecked.is_empty() {
+            return Err(InvalidSchema {
+                problems: unchecked
+                    .into_iter()
+                    .map(|keyword| {
+                        format!(
+                            "schema keyword {} can only be checked with the jsonschema feature",
+                            keyword
+                        )
+                    })
+                    .collect(),
+            });
+        }
+        Ok(Self {
+            schema: schema.clone(),
+        })
+    }
+
+    /// Every way `value` breaks the schema; empty if it is valid
+    #[cfg(feature = "jsonschema")]
+    pub fn validate(&self, value: &Value) -> Vec<SchemaViolation> {
+        self.validator
+            .iter_errors(value)
+            .map(|err| SchemaViolation {
+                path: err.instance_path.to_string(),
+                message: err.to_string(),
+            })
+            .collect()
+    }
+
+    /// Every way `value` breaks the schema; empty if it is valid
+    #[cfg(not(feature = "jsonschema"))]
+    pub fn validate(&self, value: &Value) -> Vec<SchemaViolation> {
+        let mut validator = Validator {
+            root: &self.schema,
+            violations: Vec::new(),
+        };
+        validator.check(&self.schema, value, "", 0);
+        validator.violations
+    }
+
+    /// Whether `value` matches the schema
+    pub fn is_valid(&self, value: &Value) -> bool {
+        self.validate(value).is_empty()
+    }
+}
+
+/// A schema that [`CompiledSchema::compile`] cannot use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSchema {
+    /// What is wrong, e.g. that a keyword cannot be checked
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for InvalidSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid schema: {}", self.problems.join("; "))
+    }
+}
+
+impl std::error::Error for InvalidSchema {}
+
+/// Where `schema` uses keywords that [`validate`] cannot check, as pointers
+/// into the schema such as `#/properties/code/pattern`
+///
+/// Always empty with the `jsonschema` feature.
+pub fn unchecked_keywords(schema: &Value) -> Vec<String> {
+    let mut found = Vec::new();
+    if cfg!(not(feature = "jsonschema")) {
+        find_unchecked(schema, "#", &mut found);
+    }
+    found
+}
+
+/// The JSON schema of `T`, for tool parameters or structured output
+///
+/// Types used by `T` are described under `$defs` and referenced from where
+/// they appear, which [`validate`] and llama.cpp's grammar conversion both
+/// follow.
+///
+/// ```
+/// # #[cfg(feature = "schemars")] {
+/// #[derive(schemars::JsonSchema)]
+/// struct Weather {
+///     /// The city to look up
+///     city: String,
+///     days: Option<u8>,
+/// }
+///
+/// let schema = lancor::schema::of::<Weather>();
+/// assert_eq!(schema["required"], serde_json::json!(["city"]));
+/// # }
+/// ```
+#[cfg(feature = "schemars")]
+pub fn of<T: schemars::JsonSchema>() -> Value {
+    schemars::generate::SchemaSettings::draft2020_12()
+        .with(|settings| settings.meta_schema = None)
+        .into_generator()
+        .into_root_schema_for::<T>()
+        .to_value()
+}
+
+/// Whether `value` matches `schema`
+pub fn is_valid(schema: &Value, value: &Value) -> bool {
+    validate(schema, value).is_empty()
+}
+
+#[cfg(not(feature = "jsonschema"))]
+struct Validator<'a> {
+    root: &'a Value,
+    violations: Vec<SchemaViolation>,
+}
+
+#[cfg(not(feature = "jsonschema"))]
+impl<'a> Validator<'a> {
+    fn fail(&mut self, path: &str, message: String) {
+        self.violations.push(SchemaViolation {
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    /// Whether `value` matches `schema`, without recording why not
+    fn matches(&self, schema: &'a Value, value: &Value, depth: usize) -> bool {
+        let mut inner = Validator {
+            root: self.root,
+            violations: Vec::new(),
+        };
+        inner.check(schema, value, "", depth);
+        inner.violations.is_empty()
+    }
+
+    fn check(&mut self, schema: &'a Value, value: &Value, path: &str, depth: usize) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return self.fail(path, "no value is allowed here".to_string()),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(Value::String(reference)) = schema.get("$ref") {
+            match self.resolve(reference) {
+                _ if depth >= MAX_REF_DEPTH => self.fail(
+                    path,
+                    format!("schema reference {} nests too deeply", reference),
+                ),
+                Some(target) => self.check(target, value, path, depth + 1),
+                None => self.fail(path, format!("unknown schema reference {}", reference)),
+            }
+        }
+
+        if let Some(expected) = schema.get("type")
+            && !type_matches(expected, value)
+        {
+            let expected = match expected {
+                Value::Array(types) => types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                other => other.as_str().unwrap_or_default().to_string(),
+            };
+            // A value of the wrong type would only add noise below
+            return self.fail(path, format!("expected {}, got {}", expected, value));
+        }
+        if let Some(Value::Array(options)) = schema.get("enum")
+            && !options.contains(value)
+        {
+            let options: Vec<String> = options.iter().map(Value::to_string).collect();
+            self.fail(
+                path,
+                format!("expected one of {}, got {}", options.join(", "), value),
+            );
+        }
+        if let Some(constant) = schema.get("const")
+            && constant != value
+        {
+            self.fail(path, format!("expected {}, got {}", constant, value));
+        }
+
+        match value {
+            Value::Object(object) => self.check_object(schema, object, path, depth),
+            Value::Array(items) => self.check_array(schema, items, path, depth),
+            Value::String(text) => self.check_string(schema, text, path),
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    self.check_number(schema, number, path)
+                }
+            }
+            _ => {}
+        }
+        self.check_combinators(schema, value, path, depth);
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+
+    fn check_object(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        object: &Map<String, Value>,
+        path: &str,
+        depth: usize,
+    ) {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    self.fail(path, format!("missing required property {:?}", name));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (name, item) in object {
+            let item_path = format!("{}/{}", path, escape(name));
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property) => self.check(property, item, &item_path, depth),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        self.fail(path, format!("unexpected property {:?}", name))
+                    }
+                    Some(additional) => self.check(additional, item, &item_path, depth),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    fn check_array(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        items: &[Value],
+        path: &str,
+        depth: usize,
+    ) {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+            && (items.len() as u64) < min
+        {
+            self.fail(
+                path,
+                format!("expected at least {} items, got {}", min, items.len()),
+            );
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+            && items.len() as u64 > max
+        {
+            self.fail(
+                path,
+                format!("expected at most {} items, got {}", max, items.len()),
+            );
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true))
+            && let Some(i) = (1..items.len()).find(|&i| items[..i].contains(&items[i]))
+        {
+            self.fail(path, format!("item {} repeats an earlier item", i));
+        }
+        if let Some(item_schema) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                self.check(item_schema, item, &format!("{}/{}", path, i), depth);
+            }
+        }
+    }
+
+    fn check_string(&mut self, schema: &Map<String, Value>, text: &str, path: &str) {
+        let length = text.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64)
+            && length < min
+        {
+            self.fail(
+                path,
+                format!("expected at least {} characters, got {}", min, length),
+            );
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64)
+            && length > max
+        {
+            self.fail(
+                path,
+                format!("expected at most {} characters, got {}", max, length),
+            );
+        }
+    }
+
+    fn check_number(&mut self, schema: &Map<String, Value>, number: f64, path: &str) {
+        let bound = |name: &str| schema.get(name).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum")
+            && number < min
+        {
+            self.fail(path, format!("expected at least {}, got {}", min, number));
+        }
+        if let Some(max) = bound("maximum")
+            && number > max
+        {
+            self.fail(path, format!("expected at most {}, got {}", max, number));
+        }
+        if let Some(min) = bound("exclusiveMinimum")
+            && number <= min
+        {
+            self.fail(path, format!("expected more than {}, got {}", min, number));
+        }
+        if let Some(max) = bound("exclusiveMaximum")
+            && number >= max
+        {
+            self.fail(path, format!("expected less than {}, got {}", max, number));
+        }
+        if let Some(factor) = bound("multipleOf")
+            && factor > 0.0
+            && (number / factor).fract() != 0.0
+        {
+            self.fail(
+                path,
+                format!("expected a multiple of {}, got {}", factor, number),
+            );
+        }
+    }
+
+    fn check_combinators(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        value: &Value,
+        path: &str,
+        depth: usize,
+    ) {
+        if let Some(Value::Array(all)) = schema.get("allOf") {
+            for sub in all {
+                self.check(sub, value, path, depth);
+            }
+        }
+        if let Some(Value::Array(any)) = schema.get("anyOf")
+            && !any.iter().any(|sub| self.matches(sub, value, depth))
+        {
+            self.fail(path, "matches none of the allowed schemas".to_string());
+        }
+        if let Some(Value::Array(one)) = schema.get("oneOf") {
+            let matching = one
+                .iter()
+                .filter(|sub| self.matches(sub, value, depth))
+                .count();
+            if matching != 1 {
+                self.fail(
+                    path,
+                    format!("expected to match exactly one schema, matches {}", matching),
+                );
+            }
+        }
+        if let Some(not) = schema.get("not")
+            && self.matches(not, value, depth)
+        {
+            self.fail(path, "matches a schema it must not".to_string());
+        }
+    }
+}
+
+#[cfg(not(feature = "jsonschema"))]
+fn type_matches(expected: &Value, value: &Value) -> bool {
+    match expected {
+        Value::String(name) => is_type(name, value),
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| is_type(name, value)),
+        _ => true,
+    }
+}
+
+#[cfg(not(feature = "jsonschema"))]
+fn is_type(name: &str, value: &Value) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        // 1.0 counts as an integer, as the JSON schema spec says
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => true,
+    }
+}
+
+/// Assertions the built-in validator does not implement
+const UNCHECKED: &[&str] = &[
+    "pattern",
+    "patternProperties",
+    "propertyNames",
+    "minProperties",
+    "maxProperties",
+    "dependentRequired",
+    "dependentSchemas",
+    "dependencies",
+    "prefixItems",
+    "additionalItems",
+    "contains",
+    "minContains",
+    "maxContains",
+    "if",
+    "then",
+    "else",
+    "unevaluatedProperties",
+    "unevaluatedItems",
+    "$dynamicRef",
+    "$recursiveRef",
+];
+
+/// The `format`s the JSON schema spec defines; others only annotate
+const STANDARD_FORMATS: &[&str] = &[
+    "date-time",
+    "date",
+    "time",
+    "duration",
+    "email",
+    "idn-email",
+    "hostname",
+    "idn-hostname",
+    "ipv4",
+    "ipv6",
+    "uri",
+    "uri-reference",
+    "iri",
+    "iri-reference",
+    "uuid",
+    "uri-template",
+    "json-pointer",
+    "relative-json-pointer",
+    "regex",
+];
+
+fn find_unchecked(schema: &Value, pointer: &str, found: &mut Vec<String>) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    for (keyword, value) in schema {
+        let here = format!("{}/{}", pointer, escape(keyword));
+        match keyword.as_str() {
+            "format"
+                if value
+                    .as_str()
+                    .is_some_and(|format| STANDARD_FORMATS.contains(&format)) =>
+            {
+                found.push(here)
+            }
+            // The older tuple form of `items`
+            "items" if value.is_array() => found.push(here),
+            "items" | "additionalProperties" | "not" => find_unchecked(value, &here, found),
+            "properties" | "$defs" | "definitions" => {
+                for (name, sub) in value.as_object().into_iter().flatten() {
+                    find_unchecked(sub, &format!("{}/{}", here, escape(name)), found);
+                }
+            }
+            "allOf" | "anyOf" | "oneOf" => {
+                for (i, sub) in value.as_array().into_iter().flatten().enumerate() {
+                    find_unchecked(sub, &format!("{}/{}", here, i), found);
+                }
+            }
+            keyword if UNCHECKED.contains(&keyword) => found.push(here),
+            _ => {}
+        }
+    }
+}
+
+/// A property name as a JSON pointer segment
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
@@ -0,0 +1,420 @@
+//! Running a local llama.cpp server.
+//!
+//! [`LlamaServer`] starts `llama-server` as a child process, waits until its
+//! `/health` endpoint reports ready, restarts it if it exits, and kills it
+//! when dropped, so one program can manage the server and talk to it:
+//!
+//! ```no_run
+//! use lancor::server::{LlamaServer, ServerConfig};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config = ServerConfig::new("models/qwen2.5-7b-instruct-q4_k_m.gguf")
+//!     .port(8081)
+//!     .ctx_size(8192)
+//!     .gpu_layers(99);
+//! let server = LlamaServer::spawn(config).await?;
+//! let client = server.client()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::oneshot;
+
+use crate::LlamaCppClient;
+
+/// Lines of server output kept for error messages
+const OUTPUT_LINES: usize = 20;
+
+// ============================================================================
+// Configuration
+// ============================================================================
+
+/// How to start `llama-server`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// The executable to run; looked up on `PATH` unless it is a path
+    pub binary: PathBuf,
+    /// The GGUF model file to load
+    pub model: PathBuf,
+    pub host: String,
+    pub port: u16,
+    /// Context size in tokens (`-c`); the model's default if unset
+    pub ctx_size: Option<u32>,
+    /// Layers to offload to the GPU (`-ngl`)
+    pub gpu_layers: Option<u32>,
+    /// Further command line arguments, passed as given
+    pub args: Vec<String>,
+    /// How long to wait for `/health` after each start
+    pub startup_timeout: Duration,
+    /// How many times to restart a server that exits; `None` for no limit
+    pub max_restarts: Option<u32>,
+    /// Pass the server's output through to this process's stdout and stderr
+    /// instead of keeping the last lines for error messages
+    pub inherit_output: bool,
+}
+
+impl ServerConfig {
+    pub fn new(model: impl Into<PathBuf>) -> Self {
+        Self {
+            binary: PathBuf::from("llama-server"),
+            model: model.into(),
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            ctx_size: None,
+            gpu_layers: None,
+            args: Vec::new(),
+            startup_timeout: Duration::from_secs(120),
+            max_restarts: Some(5),
+            inherit_output: false,
+        }
+    }
+
+    pub fn binary(mut self, binary: impl Into<PathBuf>) -> Self {
+        self.binary = binary.into();
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn ctx_size(mut self, ctx_size: u32) -> Self {
+        self.ctx_size = Some(ctx_size);
+        self
+    }
+
+    pub fn gpu_layers(mut self, layers: u32) -> Self {
+        self.gpu_layers = Some(layers);
+        self
+    }
+
+    /// Add one extra command line argument
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn startup_timeout(mut self, timeout: Duration) -> Self {
+        self.startup_timeout = timeout;
+        self
+    }
+
+    pub fn max_restarts(mut self, max_restarts: Option<u32>) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    pub fn inherit_output(mut self, inherit: bool) -> Self {
+        self.inherit_output = inherit;
+        self
+    }
+
+    /// The base URL the server will listen on
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.host, self.port)
+    }
+
+    /// The arguments `llama-server` is started with
+    pub fn command_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-m".to_string(),
+            self.model.display().to_string(),
+            "--host".to_string(),
+            self.host.clone(),
+            "--port".to_string(),
+            self.port.to_string(),
+        ];
+        if let Some(ctx_size) = self.ctx_size {
+            args.extend(["-c".to_string(), ctx_size.to_string()]);
+        }
+        if let Some(layers) = self.gpu_layers {
+            args.extend(["-ngl".to_string(), layers.to_string()]);
+        }
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
+
+// ============================================================================
+// Server
+// ============================================================================
+
+/// What a supervised server is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerStatus {
+    /// Started and waiting for `/health`
+    Starting,
+    Ready,
+    /// Exited and waiting to be started again
+    Restarting,
+    /// Exited more often than `max_restarts` allows, or shut down
+    Stopped,
+}
+
+#[derive(Debug)]
+struct ServerState {
+    status: ServerStatus,
+    restarts: u32,
+    last_exit: Option<String>,
+    restart_error: Option<String>,
+    output: VecDeque<String>,
+}
+
+type SharedState = Arc<Mutex<ServerState>>;
+
+fn lock(state: &SharedState) -> std::sync::MutexGuard<'_, ServerState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A supervised `llama-server` process; killed when dropped
+#[derive(Debug)]
+pub struct LlamaServer {
+    url: String,
+    state: SharedState,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl LlamaServer {
+    /// Start the server and wait until it is ready
+    ///
+    /// Fails if the process cannot be started, exits, or is not ready within
+    /// `startup_timeout`; the error includes the server's last output.
+    pub async fn spawn(config: ServerConfig) -> Result<Self> {
+        let url = config.url();
+        let client = LlamaCppClient::new(url.clone())?;
+        let state = Arc::new(Mutex::new(ServerState {
+            status: ServerStatus::Starting,
+            restarts: 0,
+            last_exit: None,
+            restart_error: None,
+            output: VecDeque::new(),
+        }));
+
+        let child = start(&config, &client, &state).await?;
+        lock(&state).status = ServerStatus::Ready;
+
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let task = tokio::spawn(supervise(config, client, state.clone(), child, shutdown_rx));
+        Ok(Self {
+            url,
+            state,
+            shutdown: Some(shutdown),
+            task,
+        })
+    }
+
+    /// The server's base URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A client for this server
+    pub fn client(&self) -> Result<LlamaCppClient> {
+        LlamaCppClient::new(self.url.clone())
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        lock(&self.state).status
+    }
+
+    /// How many times the server has been restarted
+    pub fn restarts(&self) -> u32 {
+        lock(&self.state).restarts
+    }
+
+    /// How the server last exited, e.g. `exit status: 1`
+    pub fn last_exit(&self) -> Option<String> {
+        lock(&self.state).last_exit.clone()
+    }
+
+    /// Why the last attempt to restart the server failed, if it did; cleared
+    /// once a restart succeeds
+    pub fn restart_error(&self) -> Option<String> {
+        lock(&self.state).restart_error.clone()
+    }
+
+    /// The last lines the server printed, unless its output is inherited
+    pub fn recent_output(&self) -> Vec<String> {
+        lock(&self.state).output.iter().cloned().collect()
+    }
+
+    /// Stop the server and wait for the process to exit
+    pub async fn shutdown(mut self) -> Result<()> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        (&mut self.task).await.context("Server supervisor panicked")
+    }
+}
+
+impl Drop for LlamaServer {
+    fn drop(&mut self) {
+        // Dropping the supervisor drops the child, which kills it
+        self.task.abort();
+    }
+}
+
+/// Start the process and wait for `/health`, killing it on failure
+async fn start(
+    config: &ServerConfig,
+    client: &LlamaCppClient,
+    state: &SharedState,
+) -> Result<Child> {
+    let mut command = Command::new(&config.binary);
+    command.args(config.command_args()).kill_on_drop(true);
+    if config.inherit_output {
+        command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    } else {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to start {}", config.binary.display()))?;
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(collect_output(stdout, state.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(collect_output(stderr, state.clone()));
+    }
+
+    let started = tokio::time::Instant::now();
+    let url = config.url();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            lock(state).last_exit = Some(status.to_string());
+            // Give the output readers a moment to catch the last lines
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            return Err(startup_error(
+                state,
+                format!("llama-server exited during startup ({})", status),
+            ));
+        }
+        if client.probe(&client.config(), &url).await.ready {
+            return Ok(child);
+        }
+        if started.elapsed() >= config.startup_timeout {
+            let _ = child.kill().await;
+            return Err(startup_error(
+                state,
+                format!(
+                    "llama-server was not ready within {:?}",
+                    config.startup_timeout
+                ),
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn startup_error(state: &SharedState, message: String) -> anyhow::Error {
+    let state = lock(state);
+    if state.output.is_empty() {
+        return anyhow::anyhow!(message);
+    }
+    let output: Vec<&str> = state.output.iter().map(String::as_str).collect();
+    anyhow::anyhow!("{}; last output:\n{}", message, output.join("\n"))
+}
+
+async fn collect_output(stream: impl AsyncRead + Unpin, state: SharedState) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut state = lock(&state);
+        if state.output.len() == OUTPUT_LINES {
+            state.output.pop_front();
+        }
+        state.output.push_back(line);
+    }
+}
+
+/// Wait for the server to exit and start it again, until it is shut down or
+/// has used up its restarts
+async fn supervise(
+    config: ServerConfig,
+    client: LlamaCppClient,
+    state: SharedState,
+    mut child: Child,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            status = child.wait() => {
+                let exit = match status {
+                    Ok(status) => status.to_string(),
+                    Err(err) => err.to_string(),
+                };
+                lock(&state).last_exit = Some(exit);
+            }
+            _ = &mut shutdown => {
+                let _ = child.kill().await;
+                lock(&state).status = ServerStatus::Stopped;
+                return;
+            }
+        }
+
+        // Keep restarting until a start succeeds or the restarts run out
+        loop {
+            let restarts = {
+                let mut state = lock(&state);
+                if config.max_restarts.is_some_and(|max| state.restarts >= max) {
+                    state.status = ServerStatus::Stopped;
+                    return;
+                }
+                state.restarts += 1;
+                state.status = ServerStatus::Restarting;
+                state.restarts
+            };
+
+            // Back off a little more after every restart, up to 30 seconds
+            let delay = Duration::from_millis(500 * u64::from(restarts.min(60)));
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut shutdown => {
+                    lock(&state).status = ServerStatus::Stopped;
+                    return;
+                }
+            }
+
+            lock(&state).status = ServerStatus::Starting;
+            let started = tokio::select! {
+                started = start(&config, &client, &state) => started,
+                _ = &mut shutdown => {
+                    lock(&state).status = ServerStatus::Stopped;
+                    return;
+                }
+            };
+            match started {
+                Ok(started) => {
+                    child = started;
+                    let mut state = lock(&state);
+                    state.status = ServerStatus::Ready;
+                    state.restart_error = None;
+                    break;
+                }
+                Err(err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        error = format!("{:#}", err),
+                        "failed to restart llama-server"
+                    );
+                    lock(&state).restart_error = Some(format!("{:#}", err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use anyhow::{Context, Result};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+
+use crate::compat::{self, BoxStream};
+use crate::history::{HistoryPolicy, token_estimate};
+use crate::{ChatCompletionRequest, LlamaCppClient, Message, MessageContent, Tool};
+
+// ============================================================================
+// Message Metadata
+// ============================================================================
+
+/// What a [`ChatSession`] records about each message in its history, for
+/// UIs and analytics; it is never sent to the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    /// An id unique to the message, such as `msg_3f9a…`
+    pub id: String,
+    /// When the message was added, in seconds since the Unix epoch
+    pub created_at: u64,
+    /// The message's length in tokens: the server's count for replies when
+    /// it reports usage, an estimate otherwise
+    pub tokens: u32,
+    /// For replies, the model the server says generated it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// For replies, the parameters of the request that generated it, such
+    /// as `temperature`; messages and tools are left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl MessageMetadata {
+    /// Metadata for `message`, added now, with an estimated token count
+    pub fn new(message: &Message) -> Self {
+        Self {
+            id: format!("msg_{:016x}", compat::random_u64()),
+            created_at: compat::unix_seconds(),
+            tokens: token_estimate(&message.content.text()),
+            model: None,
+            params: None,
+        }
+    }
+
+    /// Metadata for a reply `message` to `request`
+    fn reply(
+        message: &Message,
+        request: &ChatCompletionRequest,
+        model: Option<String>,
+        tokens: Option<u32>,
+    ) -> Self {
+        let mut params = match serde_json::to_value(request) {
+            Ok(serde_json::Value::Object(params)) => params,
+            _ => serde_json::Map::new(),
+        };
+        for field in ["messages", "tools", "stream"] {
+            params.remove(field);
+        }
+        let mut metadata = Self::new(message);
+        metadata.tokens = tokens
+            .filter(|&tokens| tokens > 0)
+            .unwrap_or(metadata.tokens);
+        metadata.model = model;
+        metadata.params = Some(params);
+        metadata
+    }
+}
+
+// ============================================================================
+// Chat Session
+// ============================================================================
+
+/// A multi-turn conversation that keeps its own message history.
+///
+/// The session owns an optional system prompt, the running history and a
+/// request template holding the default sampling options. Every call to
+/// [`ChatSession::send`] appends the user turn, sends the conversation as
+/// selected by the session's [`HistoryPolicy`] and appends the assistant reply.
+#[derive(Debug, Clone)]
+pub struct ChatSession {
+    client: LlamaCppClient,
+    system_prompt: Option<String>,
+    history: Vec<Message>,
+    /// One entry per message in `history`
+    metadata: Vec<MessageMetadata>,
+    defaults: ChatCompletionRequest,
+    policy: HistoryPolicy,
+}
+
+impl ChatSession {
+    /// Create a new session for the given model
+    pub fn new(client: LlamaCppClient, model: impl Into<String>) -> Self {
+        Self {
+            client,
+            system_prompt: None,
+            history: Vec::new(),
+            metadata: Vec::new(),
+            defaults: ChatCompletionRequest::new(model),
+            policy: HistoryPolicy::default(),
+        }
+    }
+
+    /// Set the system prompt sent at the start of every request
+    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Use `request` as the template for every turn. Its messages are ignored.
+    pub fn defaults(mut self, request: ChatCompletionRequest) -> Self {
+        self.defaults = request.messages(Vec::new());
+        self
+    }
+
+    /// Limit how much of the history is sent with each request
+    ///
+    /// The full history is still kept; see [`ChatSession::history`].
+    pub fn history_policy(mut self, policy: HistoryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.defaults = self.defaults.temperature(temperature);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.defaults = self.defaults.max_tokens(max_tokens);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.defaults = self.defaults.top_p(top_p);
+        self
+    }
+
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.defaults = self.defaults.stop(stop);
+        self
+    }
+
+    /// The conversation so far, without the system prompt
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+
+    /// What was recorded about each message in [`ChatSession::history`], in
+    /// the same order
+    pub fn metadata(&self) -> &[MessageMetadata] {
+        &self.metadata
+    }
+
+    /// The conversation so far, each message with its metadata
+    pub fn history_with_metadata(&self) -> impl Iterator<Item = (&Message, &MessageMetadata)> {
+        self.history.iter().zip(&self.metadata)
+    }
+
+    /// The full message list sent to the server, including the system prompt
+    pub fn messages(&self) -> Vec<Message> {
+        self.system_prompt
+            .iter()
+            .map(|prompt| Message::system(prompt.clone()))
+            .chain(self.history.iter().cloned())
+            .collect()
+    }
+
+    /// Append a message to the history without sending anything
+    pub fn push(&mut self, message: Message) {
+        let metadata = MessageMetadata::new(&message);
+        self.push_with_metadata(message, metadata);
+    }
+
+    /// Append a message to the history with metadata of its own, such as
+    /// when importing a conversation
+    pub fn push_with_metadata(&mut self, message: Message, metadata: MessageMetadata) {
+        self.history.push(message);
+        self.metadata.push(metadata);
+    }
+
+    /// Remove the last message, whose request failed
+    fn pop(&mut self) {
+        self.history.pop();
+        self.metadata.pop();
+    }
+
+    /// Forget the conversation, keeping the system prompt and defaults
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.metadata.clear();
+    }
+
+    async fn request(&self) -> Result<ChatCompletionRequest> {
+        let system = self.system_prompt.as_ref().map(Message::system);
+        let messages = self
+            .policy
+            .apply(&self.client, system.as_ref(), &self.history)
+            .await?;
+        Ok(self.defaults.clone().messages(messages))
+    }
+
+    /// Send a user message and return the assistant reply
+    ///
+    /// If the request fails the user message is removed again, so the call
+    /// can simply be retried.
+    pub async fn send(&mut self, text: impl Into<MessageContent>) -> Result<String> {
+        self.push(Message::user(text));
+
+        match self.complete().await {
+            Ok(message) => Ok(message.content.text()),
+            Err(err) => {
+                self.pop();
+                Err(err)
+            }
+        }
+    }
+
+    /// Ask the model to continue the conversation as it stands, append its
+    /// reply to the history and return it
+    pub async fn complete(&mut self) -> Result<Message> {
+        self.complete_with_tools(None).await
+    }
+
+    pub(crate) async fn complete_with_tools(
+        &mut self,
+        tools: Option<Vec<Tool>>,
+    ) -> Result<Message> {
+        let mut request = self.request().await?;
+        if tools.is_some() {
+            request.tools = tools;
+        }
+
+        let response = self.client.chat_completion(request.clone()).await?;
+        let tokens = response.usage.completion_tokens;
+        let model = Some(response.model);
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .context("Response contained no choices")?;
+
+        let metadata = MessageMetadata::reply(&message, &request, model, tokens);
+        self.push_with_metadata(message.clone(), metadata);
+        Ok(message)
+    }
+
+    /// Send a user message and stream the assistant reply as content deltas
+    ///
+    /// The reply is appended to the history once the stream has been read to
+    /// the end. Dropping the stream early discards both the partial reply and
+    /// the user message.
+    pub async fn send_stream(
+        &mut self,
+        text: impl Into<MessageContent>,
+    ) -> Result<BoxStream<'_, Result<String>>> {
+        self.push(Message::user(text));
+
+        let result = match self.request().await {
+            Ok(request) => {
+                let request = request.stream(true);
+                self.client
+                    .chat_completion_stream(request.clone())
+                    .await
+                    .map(|stream| (stream, request))
+            }
+            Err(err) => Err(err),
+        };
+        let (stream, request) = match result {
+            Ok(sent) => sent,
+            Err(err) => {
+                self.pop();
+                return Err(err);
+            }
+        };
+
+        let state = StreamState {
+            inner: stream,
+            session: self,
+            request,
+            reply: String::new(),
+            model: None,
+            tokens: None,
+            done: false,
+        };
+
+        Ok(compat::boxed(futures::stream::unfold(
+            state,
+            |mut state| async move {
+                if state.done {
+                    return None;
+                }
+                loop {
+                    match state.inner.next().await {
+                        Some(Ok(chunk)) => {
+                            state.model = Some(chunk.model.clone());
+                            if let Some(tokens) = chunk
+                                .usage
+                                .as_ref()
+                                .and_then(|usage| usage.completion_tokens)
+                            {
+                                state.tokens = Some(tokens);
+                            }
+                            let content = chunk
+                                .choices
+                                .into_iter()
+                                .next()
+                                .and_then(|choice| choice.delta.content);
+                            if let Some(content) = content
+                                && !content.is_empty()
+                            {
+                                state.reply.push_str(&content);
+                                return Some((Ok(content), state));
+                            }
+                        }
+                        Some(Err(err)) => {
+                            state.done = true;
+                            state.session.pop();
+                            return Some((Err(err), state));
+                        }
+                        None => {
+                            state.done = true;
+                            let message = Message::assistant(std::mem::take(&mut state.reply));
+                            let metadata = MessageMetadata::reply(
+                                &message,
+                                &state.request,
+                                state.model.take(),
+                                state.tokens,
+                            );
+                            state.session.push_with_metadata(message, metadata);
+                            return None;
+                        }
+                    }
+                }
+            },
+        )))
+    }
+}
+
+struct StreamState<'a, S> {
+    inner: S,
+    session: &'a mut ChatSession,
+    request: ChatCompletionRequest,
+    reply: String,
+    model: Option<String>,
+    tokens: Option<u32>,
+    done: bool,
+}
+
+impl<S> Drop for StreamState<'_, S> {
+    fn drop(&mut self) {
+        // The stream was abandoned before the reply was complete
+        if !self.done {
+            self.session.pop();
+        }
+    }
+}
+
+// ============================================================================
+// Persistence
+// ============================================================================
+
+/// Version of the file format written by [`ChatSession::save`]
+#[cfg(not(target_arch = "wasm32"))]
+const SESSION_FILE_VERSION: u32 = 1;
+
+/// A session as saved to disk
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    version: u32,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    messages: Vec<Message>,
+    /// One entry per message; files without it get fresh metadata
+    #[serde(default)]
+    metadata: Vec<MessageMetadata>,
+    /// The request template, whose messages are always empty
+    settings: ChatCompletionRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ChatSession {
+    /// Write the conversation with its metadata, the system prompt and the
+    /// request settings to `path` as JSON, replacing the file in one step so
+    /// a crash never leaves half of it
+    ///
+    /// The client and the [`HistoryPolicy`] are not saved.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = SessionFile {
+            version: SESSION_FILE_VERSION,
+            system_prompt: self.system_prompt.clone(),
+            messages: self.history.clone(),
+            metadata: self.metadata.clone(),
+            settings: self.defaults.clone(),
+            preset: self.defaults.preset.clone(),
+        };
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&partial, serde_json::to_vec_pretty(&file)?)
+            .and_then(|()| std::fs::rename(&partial, path))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Resume a session written by [`ChatSession::save`], sending its
+    /// requests through `client`
+    pub fn load(client: LlamaCppClient, path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: SessionFile = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Invalid session file {}", path.display()))?;
+        if file.version > SESSION_FILE_VERSION {
+            anyhow::bail!(
+                "Session file {} has version {}, newer than the supported {}",
+                path.display(),
+                file.version,
+                SESSION_FILE_VERSION
+            );
+        }
+
+        let mut metadata = file.metadata;
+        metadata.truncate(file.messages.len());
+        metadata.extend(
+            file.messages[metadata.len()..]
+                .iter()
+                .map(MessageMetadata::new),
+        );
+
+        let mut defaults = file.settings.messages(Vec::new());
+        defaults.preset = file.preset;
+        Ok(Self {
+            client,
+            system_prompt: file.system_prompt,
+            history: file.messages,
+            metadata,
+            defaults,
+            policy: HistoryPolicy::default(),
+        })
+    }
+}
+
+// ============================================================================
+// Slot-Pinned Session
+// ============================================================================
+
+/// A [`ChatSession`] whose requests all run on one llama.cpp server slot
+///
+/// Every turn resends the whole conversation, which starts with the same
+/// system prompt and earlier turns. Pinning the session to a slot with
+/// `cache_prompt` on lets the server keep that prefix in the slot's KV cache
+/// and only process what is new, so a long system prompt is evaluated once
+/// rather than on every turn. Give concurrent sessions different slots;
+/// the server has as many as its `--parallel` setting.
+///
+/// The session derefs to [`ChatSession`] for sending and reading history.
+#[derive(Debug, Clone)]
+pub struct SlotPinnedSession {
+    session: ChatSession,
+    slot: u32,
+}
+
+impl SlotPinnedSession {
+    /// Pin `session` to `slot`, keeping its other defaults
+    pub fn new(mut session: ChatSession, slot: u32) -> Self {
+        session.defaults = session.defaults.cache_prompt(true).id_slot(slot);
+        Self { session, slot }
+    }
+
+    /// The server slot every request is sent to
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+}
+
+impl Deref for SlotPinnedSession {
+    type Target = ChatSession;
+
+    fn deref(&self) -> &ChatSession {
+        &self.session
+    }
+}
+
+impl DerefMut for SlotPinnedSession {
+    fn deref_mut(&mut self) -> &mut ChatSession {
+        &mut self.session
+    }
+}
@@ -0,0 +1,231 @@
+//! Shutting a client down without losing track of its requests.
+//!
+//! [`LlamaCppClient::shutdown`] refuses new requests, gives those in flight,
+//! streams included, a grace period to finish, then aborts the rest and
+//! reports them:
+//!
+//! ```no_run
+//! # use lancor::LlamaCppClient;
+//! # use std::time::Duration;
+//! # #[cfg(feature = "runtime-tokio")]
+//! # async fn example(client: LlamaCppClient) {
+//! let report = client.shutdown(Duration::from_secs(10)).await;
+//! for request in &report.aborted {
+//!     eprintln!("aborted {} request {}", request.action, request.request_id);
+//! }
+//! # }
+//! ```
+
+use anyhow::Result;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::LlamaCppClient;
+use crate::compat::{self, Instant};
+use crate::transport::HttpResponse;
+
+/// What happened to the requests in flight when a client shut down
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownReport {
+    /// Requests that finished during the grace period
+    pub completed: usize,
+    /// Requests still running when it ran out, which were aborted
+    pub aborted: Vec<AbortedRequest>,
+}
+
+/// A request cut off by [`LlamaCppClient::shutdown`]
+#[derive(Debug, Clone)]
+pub struct AbortedRequest {
+    /// The id sent in `X-Request-Id`
+    pub request_id: String,
+    /// What the request was for, such as `"streaming chat completion"`
+    pub action: String,
+    /// How long it had been running
+    pub elapsed: Duration,
+}
+
+// ============================================================================
+// Request Tracking
+// ============================================================================
+
+/// A request in flight, as reported if it is aborted
+#[derive(Debug)]
+#[cfg_attr(
+    not(all(feature = "runtime-tokio", not(target_arch = "wasm32"))),
+    allow(dead_code)
+)]
+struct Entry {
+    request_id: String,
+    action: String,
+    started: Instant,
+}
+
+/// The requests in flight across all clones of a client, and whether it is
+/// shutting down
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    closed: AtomicBool,
+    next: AtomicU64,
+    requests: Mutex<HashMap<u64, Entry>>,
+    completed: AtomicUsize,
+    idle: tokio::sync::Notify,
+    cancel: watch::Sender<bool>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            closed: AtomicBool::new(false),
+            next: AtomicU64::new(0),
+            requests: Mutex::default(),
+            completed: AtomicUsize::new(0),
+            idle: tokio::sync::Notify::new(),
+            cancel: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Lifecycle {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Register a request, unless the client is shutting down
+    pub(crate) fn start(self: &Arc<Self>, request_id: &str, action: &str) -> Result<Outstanding> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let entry = Entry {
+            request_id: request_id.to_string(),
+            action: action.to_string(),
+            started: Instant::now(),
+        };
+        let mut requests = self.lock();
+        if self.closed.load(Ordering::SeqCst) {
+            anyhow::bail!("The client is shutting down; {} request not sent", action);
+        }
+        requests.insert(id, entry);
+        Ok(Outstanding {
+            lifecycle: self.clone(),
+            id,
+            action: action.to_string(),
+        })
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// A request in flight; it is finished when this is dropped
+#[derive(Debug)]
+pub(crate) struct Outstanding {
+    lifecycle: Arc<Lifecycle>,
+    id: u64,
+    action: String,
+}
+
+impl Outstanding {
+    /// Run `future` unless the client aborts the request first
+    pub(crate) async fn run<T>(&self, future: impl std::future::Future<Output = T>) -> Result<T> {
+        let mut cancel = self.lifecycle.cancel.subscribe();
+        let cancelled = cancel.wait_for(|cancelled| *cancelled);
+        match futures::future::select(std::pin::pin!(future), std::pin::pin!(cancelled)).await {
+            futures::future::Either::Left((output, _)) => Ok(output),
+            futures::future::Either::Right(_) => Err(anyhow::anyhow!(
+                "The client shut down; {} request aborted",
+                self.action
+            )),
+        }
+    }
+
+    /// Keep the request in flight until `response`'s body has been read,
+    /// ending the body early if the client aborts it
+    pub(crate) fn track(self, mut response: HttpResponse) -> HttpResponse {
+        let state = (response.body, Some(self));
+        let body = futures::stream::unfold(state, |(mut body, outstanding)| async move {
+            let outstanding = outstanding?;
+            match outstanding.run(body.next()).await {
+                Ok(Some(chunk)) => Some((chunk, (body, Some(outstanding)))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), (body, None))),
+            }
+        });
+        response.body = compat::boxed(body);
+        response
+    }
+}
+
+impl Drop for Outstanding {
+    fn drop(&mut self) {
+        let mut requests = self.lifecycle.lock();
+        if requests.remove(&self.id).is_some() && self.lifecycle.is_closed() {
+            self.lifecycle.completed.fetch_add(1, Ordering::SeqCst);
+        }
+        if requests.is_empty() {
+            self.lifecycle.idle.notify_waiters();
+        }
+    }
+}
+
+// ============================================================================
+// Shutdown
+// ============================================================================
+
+impl LlamaCppClient {
+    /// Stop taking requests and wait up to `grace_period` for those in
+    /// flight, including open streams, to finish; any still running are then
+    /// aborted, their streams ending with an error
+    ///
+    /// The client and all its clones refuse new requests from here on.
+    /// Calling this again waits for nothing and reports nothing.
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    pub async fn shutdown(&self, grace_period: Duration) -> ShutdownReport {
+        let lifecycle = &self.lifecycle;
+        let deadline = tokio::time::Instant::now() + grace_period;
+        {
+            let _requests = lifecycle.lock();
+            if lifecycle.closed.swap(true, Ordering::SeqCst) {
+                return ShutdownReport::default();
+            }
+        }
+
+        loop {
+            let idle = lifecycle.idle.notified();
+            if lifecycle.lock().is_empty() {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                break;
+            }
+        }
+
+        let aborted = {
+            let mut requests = lifecycle.lock();
+            let mut aborted: Vec<_> = requests
+                .drain()
+                .map(|(_, entry)| AbortedRequest {
+                    request_id: entry.request_id,
+                    action: entry.action,
+                    elapsed: entry.started.elapsed(),
+                })
+                .collect();
+            aborted.sort_by_key(|request| std::cmp::Reverse(request.elapsed));
+            aborted
+        };
+        lifecycle.cancel.send_replace(true);
+
+        ShutdownReport {
+            completed: lifecycle.completed.load(Ordering::SeqCst),
+            aborted,
+        }
+    }
+
+    /// Whether [`LlamaCppClient::shutdown`] has been called on this client
+    /// or a clone of it
+    pub fn is_shut_down(&self) -> bool {
+        self.lifecycle.is_closed()
+    }
+}
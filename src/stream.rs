@@ -0,0 +1,1235 @@
+//! Adapters over streamed chat completions.
+//!
+//! [`ChatStreamExt`] is implemented for every stream of
+//! [`ChatCompletionChunk`]s, such as the one returned by
+//! [`LlamaCppClient::chat_completion_stream`](crate::LlamaCppClient::chat_completion_stream),
+//! and turns it into something easier to consume than raw chunks.
+//! [`LlamaCppClient::chat_completion_with_callbacks`] drives the same events
+//! through closures instead.
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use lancor::{ChatCompletionRequest, ChatEvent, ChatStreamExt, LlamaCppClient, Message};
+//!
+//! # async fn example(client: LlamaCppClient) -> anyhow::Result<()> {
+//! let request = ChatCompletionRequest::new("qwen").message(Message::user("Hi"));
+//! let mut events = std::pin::pin!(client.chat_completion_stream(request).await?.events());
+//! while let Some(event) = events.next().await {
+//!     match event? {
+//!         ChatEvent::ContentDelta(text) => print!("{}", text),
+//!         ChatEvent::Done(reason) => println!("\n[{:?}]", reason),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::Result;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::compat::{self, BoxStream, Instant};
+use crate::{
+    ApiError, ChatChoiceDelta, ChatCompletionChunk, ChatCompletionRequest, Delta, FinishReason,
+    FunctionCall, LlamaCppClient, Message, ToolCall, ToolCallDelta, Usage,
+};
+
+// ============================================================================
+// Events
+// ============================================================================
+
+/// What a streamed chat completion chunk says, one thing at a time
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatEvent {
+    /// The role of the message being generated, normally `assistant`
+    Role(String),
+    /// A piece of the reply's text
+    ContentDelta(String),
+    /// A piece of a thinking model's reasoning
+    ReasoningDelta(String),
+    /// A fragment of a tool call
+    ToolCallDelta(ToolCallDelta),
+    /// A tool call whose arguments are complete, after its last fragment
+    ToolCall(ToolCall),
+    /// Token usage, which servers send with or after the last chunk
+    Usage(Usage),
+    /// The end of the stream, with the reason generation stopped if the
+    /// server gave one
+    Done(Option<FinishReason>),
+}
+
+/// Push the events of the first choice of `chunk` onto `events`, keeping
+/// its finish reason in `finish` for the final [`ChatEvent::Done`]
+fn chunk_events(
+    chunk: ChatCompletionChunk,
+    events: &mut VecDeque<ChatEvent>,
+    finish: &mut Option<FinishReason>,
+    tool_calls: &mut ToolCallAccumulator,
+) {
+    if let Some(choice) = chunk.choices.into_iter().find(|choice| choice.index == 0) {
+        let delta = choice.delta;
+        if let Some(role) = delta.role {
+            events.push_back(ChatEvent::Role(role));
+        }
+        if let Some(reasoning) = delta.reasoning_content.filter(|text| !text.is_empty()) {
+            events.push_back(ChatEvent::ReasoningDelta(reasoning));
+        }
+        if let Some(content) = delta.content.filter(|text| !text.is_empty()) {
+            events.push_back(ChatEvent::ContentDelta(content));
+        }
+        for call in delta.tool_calls.into_iter().flatten() {
+            let complete = tool_calls.push(&call);
+            events.push_back(ChatEvent::ToolCallDelta(call));
+            events.extend(complete.map(ChatEvent::ToolCall));
+        }
+        if choice.finish_reason.is_some() {
+            *finish = choice.finish_reason;
+        }
+    }
+    if let Some(usage) = chunk.usage {
+        events.push_back(ChatEvent::Usage(usage));
+    }
+}
+
+// ============================================================================
+// Tool Call Accumulation
+// ============================================================================
+
+#[derive(Debug, Clone)]
+struct PartialToolCall {
+    index: u32,
+    id: Option<String>,
+    kind: Option<String>,
+    name: String,
+    arguments: String,
+    returned: bool,
+}
+
+impl PartialToolCall {
+    fn to_call(&self) -> ToolCall {
+        ToolCall {
+            id: self
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("call_{}", self.index)),
+            kind: self.kind.clone().unwrap_or_else(|| "function".to_string()),
+            function: FunctionCall {
+                name: self.name.clone(),
+                arguments: if self.arguments.trim().is_empty() {
+                    "{}".to_string()
+                } else {
+                    self.arguments.clone()
+                },
+            },
+        }
+    }
+
+    /// Whether the arguments are a whole JSON object or array, which no
+    /// further fragment can extend
+    fn is_complete(&self) -> bool {
+        !self.name.is_empty()
+            && serde_json::from_str::<serde_json::Value>(&self.arguments)
+                .is_ok_and(|value| value.is_object() || value.is_array())
+    }
+}
+
+/// Stitches streamed [`ToolCallDelta`]s into whole [`ToolCall`]s
+///
+/// Fragments belong to the call with the same `index`, unless they carry a
+/// different `id`, which starts a new call. [`ChatStreamExt::events`] uses
+/// one to emit [`ChatEvent::ToolCall`]s; use it directly when consuming raw
+/// chunks.
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallAccumulator {
+    calls: Vec<PartialToolCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a fragment, returning its call if this fragment completed the
+    /// call's arguments
+    pub fn push(&mut self, delta: &ToolCallDelta) -> Option<ToolCall> {
+        let existing = self.calls.iter().rposition(|call| {
+            call.index == delta.index
+                && (delta.id.is_none() || call.id.is_none() || call.id == delta.id)
+        });
+        let position = match existing {
+            Some(position) => position,
+            None => {
+                self.calls.push(PartialToolCall {
+                    index: delta.index,
+                    id: None,
+                    kind: None,
+                    name: String::new(),
+                    arguments: String::new(),
+                    returned: false,
+                });
+                self.calls.len() - 1
+            }
+        };
+
+        let call = &mut self.calls[position];
+        if delta.id.is_some() {
+            call.id.clone_from(&delta.id);
+        }
+        if delta.kind.is_some() {
+            call.kind.clone_from(&delta.kind);
+        }
+        if let Some(function) = &delta.function {
+            if let Some(name) = &function.name {
+                call.name.push_str(name);
+            }
+            if let Some(arguments) = &function.arguments {
+                call.arguments.push_str(arguments);
+            }
+        }
+
+        if call.returned || !call.is_complete() {
+            return None;
+        }
+        call.returned = true;
+        Some(call.to_call())
+    }
+
+    /// The calls [`ToolCallAccumulator::push`] has not returned, such as
+    /// those whose arguments never became valid JSON, for when the stream
+    /// has ended; empty arguments become `{}`
+    pub fn finish(&mut self) -> Vec<ToolCall> {
+        self.calls
+            .iter_mut()
+            .filter(|call| !call.returned)
+            .map(|call| {
+                call.returned = true;
+                call.to_call()
+            })
+            .collect()
+    }
+
+    /// Every call seen so far, complete or not, in the order they started
+    pub fn calls(&self) -> Vec<ToolCall> {
+        self.calls.iter().map(PartialToolCall::to_call).collect()
+    }
+}
+
+// ============================================================================
+// Stop Sequences
+// ============================================================================
+
+/// Finds stop sequences in streamed text that may be split across chunks
+#[derive(Debug, Clone)]
+struct StopMatcher {
+    stops: Vec<String>,
+    held: String,
+}
+
+impl StopMatcher {
+    fn new(stops: Vec<String>) -> Self {
+        Self {
+            stops: stops.into_iter().filter(|stop| !stop.is_empty()).collect(),
+            held: String::new(),
+        }
+    }
+
+    /// Take in the next piece of text, returning the text that is safe to
+    /// pass on and whether a stop sequence ended it
+    ///
+    /// Text that could be the start of a stop sequence is held back until
+    /// the next piece shows whether it is.
+    fn push(&mut self, text: &str) -> (String, bool) {
+        self.held.push_str(text);
+        let found = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.held.find(stop.as_str()))
+            .min();
+        if let Some(position) = found {
+            self.held.truncate(position);
+            return (std::mem::take(&mut self.held), true);
+        }
+
+        let keep = self
+            .stops
+            .iter()
+            .flat_map(|stop| {
+                let held = &self.held;
+                (1..stop.len().min(held.len() + 1)).filter(move |&len| {
+                    held.is_char_boundary(held.len() - len)
+                        && stop.starts_with(&held[held.len() - len..])
+                })
+            })
+            .max()
+            .unwrap_or(0);
+        let rest = self.held.split_off(self.held.len() - keep);
+        (std::mem::replace(&mut self.held, rest), false)
+    }
+
+    /// Release the held-back text once no more is coming
+    fn flush(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+}
+
+// ============================================================================
+// Server-Sent Events
+// ============================================================================
+
+/// Where the data of the event being read is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventData {
+    None,
+    /// A single `data:` line, at this range of the buffer
+    Line(usize, usize),
+    /// Several lines, joined in `joined`
+    Joined,
+}
+
+/// Splits a server-sent events body into the data of its events
+///
+/// Bytes are pushed as they arrive and kept in one buffer that is reused
+/// for the whole stream; lines are found without copying, and the data of
+/// an event with a single `data:` line, as servers send chunks, is borrowed
+/// from the buffer. Only data lines spanning several lines are joined, with
+/// `\n` as the SSE format specifies. Comments and keep-alives (`:` lines),
+/// `event:`, `id:` and `retry:` fields, and events without data, which
+/// proxies such as nginx inject, are skipped. Text is never decoded here, so
+/// a character split across network chunks arrives whole.
+///
+/// ```
+/// use lancor::stream::SseDecoder;
+///
+/// let mut decoder = SseDecoder::new();
+/// decoder.push(b": keep-alive\n\ndata: {\"a\"");
+/// assert_eq!(decoder.next_event(), None);
+/// decoder.push(b":1}\n\ndata: [DONE]\n\n");
+/// assert_eq!(decoder.next_event(), Some(&br#"{"a":1}"#[..]));
+/// assert_eq!(decoder.next_event(), Some(&b"[DONE]"[..]));
+/// assert_eq!(decoder.next_event(), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    /// Where the unread part of `buffer` starts
+    start: usize,
+    data: EventData,
+    joined: Vec<u8>,
+}
+
+impl Default for SseDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            data: EventData::None,
+            joined: Vec::new(),
+        }
+    }
+
+    /// Add bytes read from the body
+    pub fn push(&mut self, bytes: &[u8]) {
+        // Drop what has been read, except a data line still waiting for the
+        // end of its event, moving the rest to the front
+        let keep = match self.data {
+            EventData::Line(from, _) => from,
+            _ => self.start,
+        };
+        if keep > 0 {
+            self.buffer.drain(..keep);
+            if let EventData::Line(from, to) = self.data {
+                self.data = EventData::Line(from - keep, to - keep);
+            }
+            self.start -= keep;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Mark the end of the body, ending a last line and event the server
+    /// left open
+    pub fn finish(&mut self) {
+        self.push(b"\n\n");
+    }
+
+    /// The data of the next complete event, or `None` until more bytes are
+    /// pushed
+    pub fn next_event(&mut self) -> Option<&[u8]> {
+        loop {
+            let unread = &self.buffer[self.start..];
+            let end = self.start + unread.iter().position(|&b| b == b'\n')?;
+            let line_start = self.start;
+            self.start = end + 1;
+            let line_end = match self.buffer[line_start..end] {
+                [.., b'\r'] => end - 1,
+                _ => end,
+            };
+
+            if line_start == line_end {
+                // A blank line ends the event
+                let event = std::mem::replace(&mut self.data, EventData::None);
+                let blank = |data: &[u8]| data.iter().all(u8::is_ascii_whitespace);
+                let skip = match event {
+                    EventData::None => true,
+                    EventData::Line(from, to) => blank(&self.buffer[from..to]),
+                    EventData::Joined => blank(&self.joined),
+                };
+                if skip {
+                    continue;
+                }
+                // Borrowed only once it is certain to be returned
+                return Some(match event {
+                    EventData::Line(from, to) => &self.buffer[from..to],
+                    _ => &self.joined,
+                });
+            }
+
+            let line = &self.buffer[line_start..line_end];
+            let Some(value) = line.strip_prefix(b"data") else {
+                continue;
+            };
+            let offset = match value {
+                [] => 0,
+                [b':', b' ', ..] => 2,
+                [b':', ..] => 1,
+                // A field whose name only starts with `data`
+                _ => continue,
+            };
+            let from = line_start + 4 + offset;
+            self.data = match self.data {
+                EventData::None => EventData::Line(from, line_end),
+                EventData::Line(first, first_end) => {
+                    self.joined.clear();
+                    self.joined
+                        .extend_from_slice(&self.buffer[first..first_end]);
+                    self.joined.push(b'\n');
+                    self.joined.extend_from_slice(&self.buffer[from..line_end]);
+                    EventData::Joined
+                }
+                EventData::Joined => {
+                    self.joined.push(b'\n');
+                    self.joined.extend_from_slice(&self.buffer[from..line_end]);
+                    EventData::Joined
+                }
+            };
+        }
+    }
+}
+
+// ============================================================================
+// Stream Statistics
+// ============================================================================
+
+/// Timings of a streamed chat completion, from [`ChatStreamExt::with_stats`]
+///
+/// Times are measured from when the adapter was created, which is normally
+/// straight after the response headers arrived. A "token" is a chunk that
+/// carries generated text, reasoning or a tool call fragment; llama.cpp
+/// sends one per token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamStats {
+    /// Until the first generated token, covering prompt processing
+    pub time_to_first_token: Option<Duration>,
+    /// Until the stream ended
+    pub total: Duration,
+    /// Chunks that carried generated output
+    pub tokens: u32,
+    /// The server's `completion_tokens`, when it reported usage
+    pub completion_tokens: Option<u32>,
+    /// Mean gap between consecutive tokens
+    pub mean_inter_token_latency: Option<Duration>,
+    /// Longest gap between consecutive tokens
+    pub max_inter_token_latency: Option<Duration>,
+    /// Generation speed after the first token, using the server's token
+    /// count when it sent one
+    pub tokens_per_second: Option<f64>,
+}
+
+/// A chunk stream that times its chunks; see [`ChatStreamExt::with_stats`]
+pub struct StatsStream<S> {
+    chunks: Pin<Box<S>>,
+    start: Instant,
+    first: Option<Duration>,
+    last: Option<Duration>,
+    tokens: u32,
+    gaps: Duration,
+    max_gap: Option<Duration>,
+    completion_tokens: Option<u32>,
+    stats: Option<StreamStats>,
+}
+
+impl<S> StatsStream<S> {
+    /// The statistics, once the stream has ended
+    pub fn stats(&self) -> Option<StreamStats> {
+        self.stats
+    }
+
+    fn record(&mut self, chunk: &ChatCompletionChunk) {
+        if let Some(usage) = &chunk.usage {
+            self.completion_tokens = usage.completion_tokens.or(self.completion_tokens);
+        }
+        let generated = chunk.choices.iter().any(|choice| {
+            let delta = &choice.delta;
+            delta.content.as_ref().is_some_and(|text| !text.is_empty())
+                || delta
+                    .reasoning_content
+                    .as_ref()
+                    .is_some_and(|text| !text.is_empty())
+                || delta
+                    .tool_calls
+                    .as_ref()
+                    .is_some_and(|calls| !calls.is_empty())
+        });
+        if !generated {
+            return;
+        }
+
+        let now = self.start.elapsed();
+        if let Some(last) = self.last {
+            let gap = now.saturating_sub(last);
+            self.gaps += gap;
+            self.max_gap = self.max_gap.max(Some(gap));
+        }
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+        self.tokens += 1;
+    }
+
+    fn finish(&mut self) -> StreamStats {
+        let total = self.start.elapsed();
+        let mean_inter_token_latency = (self.tokens > 1).then(|| self.gaps / (self.tokens - 1));
+        // The first token marks the end of prompt processing, so generation
+        // is timed from there, over the tokens that followed it
+        let tokens_per_second = self.first.and_then(|first| {
+            let generating = self.last?.saturating_sub(first).as_secs_f64();
+            let tokens = self
+                .completion_tokens
+                .unwrap_or(self.tokens)
+                .saturating_sub(1);
+            (generating > 0.0 && tokens > 0).then(|| f64::from(tokens) / generating)
+        });
+        StreamStats {
+            time_to_first_token: self.first,
+            total,
+            tokens: self.tokens,
+            completion_tokens: self.completion_tokens,
+            mean_inter_token_latency,
+            max_inter_token_latency: self.max_gap,
+            tokens_per_second,
+        }
+    }
+}
+
+impl<S> Stream for StatsStream<S>
+where
+    S: Stream<Item = Result<ChatCompletionChunk>>,
+{
+    type Item = Result<ChatCompletionChunk>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.stats.is_some() {
+            return Poll::Ready(None);
+        }
+        let item = futures::ready!(self.chunks.as_mut().poll_next(cx));
+        match &item {
+            Some(Ok(chunk)) => self.record(chunk),
+            Some(Err(_)) => {}
+            None => self.stats = Some(self.finish()),
+        }
+        Poll::Ready(item)
+    }
+}
+
+// ============================================================================
+// Stream Extension
+// ============================================================================
+
+/// Adapters for streams of [`ChatCompletionChunk`]s
+pub trait ChatStreamExt: Stream<Item = Result<ChatCompletionChunk>> + Sized {
+    /// The stream as [`ChatEvent`]s for its first choice
+    ///
+    /// Empty deltas are skipped. Tool call fragments are passed on, and
+    /// each call is also emitted whole as a [`ChatEvent::ToolCall`] once its
+    /// arguments are complete JSON (or when the stream ends). A stream that
+    /// runs out without errors ends with one [`ChatEvent::Done`], carrying
+    /// the last finish reason seen; errors are passed through and suppress
+    /// the `Done`.
+    fn events(self) -> impl Stream<Item = Result<ChatEvent>> {
+        struct State<S> {
+            chunks: std::pin::Pin<Box<S>>,
+            pending: VecDeque<ChatEvent>,
+            finish: Option<FinishReason>,
+            tool_calls: ToolCallAccumulator,
+            failed: bool,
+            ended: bool,
+        }
+
+        let state = State {
+            chunks: Box::pin(self),
+            pending: VecDeque::new(),
+            finish: None,
+            tool_calls: ToolCallAccumulator::new(),
+            failed: false,
+            ended: false,
+        };
+        stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(event) = state.pending.pop_front() {
+                    return Some((Ok(event), state));
+                }
+                if state.ended {
+                    return None;
+                }
+                match state.chunks.next().await {
+                    Some(Ok(chunk)) => chunk_events(
+                        chunk,
+                        &mut state.pending,
+                        &mut state.finish,
+                        &mut state.tool_calls,
+                    ),
+                    Some(Err(err)) => {
+                        state.failed = true;
+                        return Some((Err(err), state));
+                    }
+                    None => {
+                        state.ended = true;
+                        if !state.failed {
+                            let calls = state.tool_calls.finish();
+                            state
+                                .pending
+                                .extend(calls.into_iter().map(ChatEvent::ToolCall));
+                            let finish = state.finish.take();
+                            state.pending.push_back(ChatEvent::Done(finish));
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// End the stream at the first of `stops` in the first choice's text
+    ///
+    /// For templates that let stop strings leak into the output before the
+    /// server cuts generation off. Text that might begin a stop sequence is
+    /// held back until the following chunks settle it, so a sequence split
+    /// across chunks is still caught and never shown. On a match the text
+    /// is cut just before the sequence, that chunk gets the finish reason
+    /// `stop` and the stream ends, dropping the connection.
+    fn stop_at<I>(self, stops: I) -> impl Stream<Item = Result<ChatCompletionChunk>>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        struct State<S> {
+            chunks: std::pin::Pin<Box<S>>,
+            matcher: StopMatcher,
+            last: Option<ChatCompletionChunk>,
+            ended: bool,
+        }
+
+        let state = State {
+            chunks: Box::pin(self),
+            matcher: StopMatcher::new(stops.into_iter().map(Into::into).collect()),
+            last: None,
+            ended: false,
+        };
+        stream::unfold(state, |mut state| async move {
+            if state.ended {
+                return None;
+            }
+            match state.chunks.next().await {
+                Some(Ok(mut chunk)) => {
+                    if let Some(choice) = chunk.choices.iter_mut().find(|choice| choice.index == 0)
+                    {
+                        let finished = choice.finish_reason.is_some();
+                        if let Some(content) = &mut choice.delta.content {
+                            let (mut text, stopped) = state.matcher.push(content);
+                            if stopped {
+                                state.ended = true;
+                                choice.finish_reason = Some(FinishReason::Stop);
+                            } else if finished {
+                                text.push_str(&state.matcher.flush());
+                            }
+                            *content = text;
+                        } else if finished {
+                            let held = state.matcher.flush();
+                            if !held.is_empty() {
+                                choice.delta.content = Some(held);
+                            }
+                        }
+                    }
+                    state.last = Some(chunk.clone());
+                    Some((Ok(chunk), state))
+                }
+                Some(Err(err)) => {
+                    state.ended = true;
+                    Some((Err(err), state))
+                }
+                None => {
+                    state.ended = true;
+                    let held = state.matcher.flush();
+                    let last = state.last.take().filter(|_| !held.is_empty())?;
+                    let chunk = ChatCompletionChunk {
+                        choices: vec![ChatChoiceDelta {
+                            index: 0,
+                            delta: Delta {
+                                content: Some(held),
+                                ..Delta::default()
+                            },
+                            finish_reason: None,
+                            native_finish_reason: None,
+                            extra: Default::default(),
+                        }],
+                        usage: None,
+                        ..last
+                    };
+                    Some((Ok(chunk), state))
+                }
+            }
+        })
+    }
+
+    /// Just the text of the first choice, one piece per chunk that has any
+    ///
+    /// Role-only chunks, empty deltas, reasoning, tool calls and usage are
+    /// skipped; errors are passed through.
+    fn content_stream(self) -> impl Stream<Item = Result<String>> {
+        self.filter_map(|chunk| async move {
+            match chunk {
+                Ok(chunk) => chunk
+                    .choices
+                    .into_iter()
+                    .find(|choice| choice.index == 0)
+                    .and_then(|choice| choice.delta.content)
+                    .filter(|text| !text.is_empty())
+                    .map(Ok),
+                Err(err) => Some(Err(err)),
+            }
+        })
+    }
+
+    /// Time the stream, making [`StreamStats`] available from
+    /// [`StatsStream::stats`] once it has ended
+    ///
+    /// The chunks pass through unchanged.
+    fn with_stats(self) -> StatsStream<Self> {
+        StatsStream {
+            chunks: Box::pin(self),
+            start: Instant::now(),
+            first: None,
+            last: None,
+            tokens: 0,
+            gaps: Duration::ZERO,
+            max_gap: None,
+            completion_tokens: None,
+            stats: None,
+        }
+    }
+
+    /// Read the stream on a spawned task into a buffer of at most
+    /// `capacity` chunks, so a slow consumer holds a known amount of memory
+    ///
+    /// When the buffer is full, [`Overflow`] decides whether reading waits
+    /// for the consumer or makes room by dropping a chunk that only reports
+    /// progress. Dropping the returned stream cancels the task and the
+    /// request. Must be called within a Tokio runtime.
+    #[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+    fn bounded(self, capacity: usize, overflow: Overflow) -> BoundedStream
+    where
+        Self: Send + 'static,
+    {
+        BoundedStream::spawn(self, capacity, overflow)
+    }
+}
+
+impl<S> ChatStreamExt for S where S: Stream<Item = Result<ChatCompletionChunk>> {}
+
+// ============================================================================
+// Callbacks
+// ============================================================================
+
+impl LlamaCppClient {
+    /// Stream a chat completion, calling back as it arrives instead of
+    /// handing out a stream to poll
+    ///
+    /// `on_token` gets each piece of the reply's text, `on_tool_call` each
+    /// tool call once its arguments are complete, and `on_done` the finish
+    /// reason when the reply has ended. An error ends the call without
+    /// `on_done`. The request is sent with `stream` set.
+    pub async fn chat_completion_with_callbacks<T, C, D>(
+        &self,
+        request: ChatCompletionRequest,
+        mut on_token: T,
+        mut on_tool_call: C,
+        on_done: D,
+    ) -> Result<()>
+    where
+        T: FnMut(&str),
+        C: FnMut(&ToolCall),
+        D: FnOnce(Option<FinishReason>),
+    {
+        let stream = self.chat_completion_stream(request.stream(true)).await?;
+        let mut events = std::pin::pin!(stream.events());
+        while let Some(event) = events.next().await {
+            match event? {
+                ChatEvent::ContentDelta(text) => on_token(&text),
+                ChatEvent::ToolCall(call) => on_tool_call(&call),
+                ChatEvent::Done(reason) => {
+                    on_done(reason);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Channels
+// ============================================================================
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl LlamaCppClient {
+    /// Stream a chat completion on a spawned task that sends its
+    /// [`ChatEvent`]s down a channel
+    ///
+    /// Handy for actor systems and UI threads that already wait on
+    /// channels. At most `buffer` events queue up before the task waits for
+    /// the receiver. The task ends with the stream: its handle yields the
+    /// error if the request or stream failed, and `Ok` otherwise, including
+    /// when the receiver was dropped early, which cancels the request.
+    /// Must be called within a Tokio runtime.
+    pub fn chat_completion_channel(
+        &self,
+        request: ChatCompletionRequest,
+        buffer: usize,
+    ) -> (
+        tokio::sync::mpsc::Receiver<ChatEvent>,
+        tokio::task::JoinHandle<Result<()>>,
+    ) {
+        let (sender, receiver) = tokio::sync::mpsc::channel(buffer.max(1));
+        let client = self.clone();
+        let task = tokio::spawn(async move {
+            let stream = client.chat_completion_stream(request.stream(true)).await?;
+            let mut events = std::pin::pin!(stream.events());
+            while let Some(event) = events.next().await {
+                if sender.send(event?).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        (receiver, task)
+    }
+}
+
+// ============================================================================
+// Bounded Buffering
+// ============================================================================
+
+/// What [`ChatStreamExt::bounded`] does with a chunk that arrives when its
+/// buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Stop reading until the consumer takes a chunk, leaving the server to
+    /// wait on the connection
+    #[default]
+    Block,
+    /// Drop the oldest buffered chunk that carries nothing but progress,
+    /// such as timings or prompt processing updates; chunks with output, a
+    /// role, a finish reason or usage are never dropped, so a buffer full
+    /// of those blocks as with [`Overflow::Block`]
+    DropProgress,
+}
+
+/// Whether `chunk` only reports progress and can be dropped
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+fn progress_only(chunk: &ChatCompletionChunk) -> bool {
+    chunk.usage.is_none()
+        && chunk.choices.iter().all(|choice| {
+            let delta = &choice.delta;
+            choice.finish_reason.is_none()
+                && delta.role.is_none()
+                && delta.content.as_ref().is_none_or(String::is_empty)
+                && delta
+                    .reasoning_content
+                    .as_ref()
+                    .is_none_or(String::is_empty)
+                && delta.tool_calls.as_ref().is_none_or(Vec::is_empty)
+        })
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+#[derive(Default)]
+struct BufferState {
+    chunks: VecDeque<Result<ChatCompletionChunk>>,
+    ended: bool,
+    dropped: u64,
+    consumer: Option<std::task::Waker>,
+}
+
+/// A chunk stream read ahead into a bounded buffer; see
+/// [`ChatStreamExt::bounded`]
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+pub struct BoundedStream {
+    state: std::sync::Arc<std::sync::Mutex<BufferState>>,
+    space: std::sync::Arc<tokio::sync::Notify>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl BoundedStream {
+    fn spawn<S>(chunks: S, capacity: usize, overflow: Overflow) -> Self
+    where
+        S: Stream<Item = Result<ChatCompletionChunk>> + Send + 'static,
+    {
+        let capacity = capacity.max(1);
+        let state = std::sync::Arc::new(std::sync::Mutex::new(BufferState::default()));
+        let space = std::sync::Arc::new(tokio::sync::Notify::new());
+
+        let (shared, notified) = (state.clone(), space.clone());
+        let task = tokio::spawn(async move {
+            let mut chunks = std::pin::pin!(chunks);
+            while let Some(chunk) = chunks.next().await {
+                let mut chunk = Some(chunk);
+                while let Some(next) = chunk.take() {
+                    {
+                        let mut state = lock(&shared);
+                        let room = state.chunks.len() < capacity
+                            || (overflow == Overflow::DropProgress
+                                && match state
+                                    .chunks
+                                    .iter()
+                                    .position(|c| c.as_ref().is_ok_and(progress_only))
+                                {
+                                    Some(index) => {
+                                        state.chunks.remove(index);
+                                        state.dropped += 1;
+                                        true
+                                    }
+                                    None => false,
+                                });
+                        if room {
+                            state.chunks.push_back(next);
+                            if let Some(waker) = state.consumer.take() {
+                                waker.wake();
+                            }
+                            continue;
+                        }
+                        chunk = Some(next);
+                    }
+                    // The consumer leaves a permit when it takes a chunk, so
+                    // one taken since the check is not missed
+                    notified.notified().await;
+                }
+            }
+            let mut state = lock(&shared);
+            state.ended = true;
+            if let Some(waker) = state.consumer.take() {
+                waker.wake();
+            }
+        });
+
+        Self { state, space, task }
+    }
+
+    /// Chunks read but not yet taken
+    pub fn queued(&self) -> usize {
+        lock(&self.state).chunks.len()
+    }
+
+    /// Progress-only chunks dropped to make room so far
+    pub fn dropped(&self) -> u64 {
+        lock(&self.state).dropped
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+fn lock(state: &std::sync::Mutex<BufferState>) -> std::sync::MutexGuard<'_, BufferState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Stream for BoundedStream {
+    type Item = Result<ChatCompletionChunk>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = lock(&self.state);
+        if let Some(chunk) = state.chunks.pop_front() {
+            self.space.notify_one();
+            return Poll::Ready(Some(chunk));
+        }
+        if state.ended {
+            return Poll::Ready(None);
+        }
+        state.consumer = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl Drop for BoundedStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl std::fmt::Debug for BoundedStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = lock(&self.state);
+        f.debug_struct("BoundedStream")
+            .field("buffered", &state.chunks.len())
+            .field("ended", &state.ended)
+            .field("dropped", &state.dropped)
+            .finish()
+    }
+}
+
+// ============================================================================
+// Writers
+// ============================================================================
+
+/// What is left of a chat completion streamed with
+/// [`LlamaCppClient::stream_to`] once its text has been written out
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamSummary {
+    pub id: String,
+    pub model: String,
+    pub finish_reason: Option<FinishReason>,
+    /// Token usage, if the server reported it
+    pub usage: Option<Usage>,
+    /// Tool calls the model made instead of, or as well as, replying
+    pub tool_calls: Vec<ToolCall>,
+}
+
+#[cfg(all(feature = "runtime-tokio", not(target_arch = "wasm32")))]
+impl LlamaCppClient {
+    /// Stream a chat completion's text into `writer`, flushing it once the
+    /// reply has ended
+    ///
+    /// Any [`AsyncWrite`](tokio::io::AsyncWrite) works: stdout, a socket, a
+    /// file. Only the first choice's content is written; the rest of the
+    /// response comes back as a [`StreamSummary`]. The request is sent with
+    /// `stream` set.
+    pub async fn stream_to<W>(
+        &self,
+        request: ChatCompletionRequest,
+        writer: &mut W,
+    ) -> Result<StreamSummary>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        self.write_stream(request, writer, false).await
+    }
+
+    /// Like [`LlamaCppClient::stream_to`], flushing after every token so
+    /// each one shows up as soon as it arrives
+    pub async fn stream_to_flushing<W>(
+        &self,
+        request: ChatCompletionRequest,
+        writer: &mut W,
+    ) -> Result<StreamSummary>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        self.write_stream(request, writer, true).await
+    }
+
+    async fn write_stream<W>(
+        &self,
+        request: ChatCompletionRequest,
+        writer: &mut W,
+        flush_each: bool,
+    ) -> Result<StreamSummary>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let stream = self.chat_completion_stream(request.stream(true)).await?;
+        let mut stream = std::pin::pin!(stream);
+        let mut summary = StreamSummary::default();
+        let mut tool_calls = ToolCallAccumulator::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if summary.id.is_empty() {
+                summary.id = chunk.id;
+                summary.model = chunk.model;
+            }
+            if let Some(usage) = chunk.usage {
+                summary.usage = Some(usage);
+            }
+            let Some(choice) = chunk.choices.into_iter().find(|choice| choice.index == 0) else {
+                continue;
+            };
+            for call in choice.delta.tool_calls.iter().flatten() {
+                tool_calls.push(call);
+            }
+            if choice.finish_reason.is_some() {
+                summary.finish_reason = choice.finish_reason;
+            }
+            if let Some(content) = choice.delta.content.filter(|text| !text.is_empty()) {
+                writer.write_all(content.as_bytes()).await?;
+                if flush_each {
+                    writer.flush().await?;
+                }
+            }
+        }
+        writer.flush().await?;
+
+        summary.tool_calls = tool_calls.calls();
+        Ok(summary)
+    }
+}
+
+// ============================================================================
+// Resuming
+// ============================================================================
+
+struct Resume {
+    client: LlamaCppClient,
+    request: ChatCompletionRequest,
+    chunks: BoxStream<'static, Result<ChatCompletionChunk>>,
+    /// The reply's text so far, across connections
+    partial: String,
+    /// Chunks of text received, which llama.cpp sends one token at a time
+    generated: u32,
+    resumes_left: u32,
+    resumed: bool,
+    finished: bool,
+    called_tools: bool,
+    ended: bool,
+}
+
+impl Resume {
+    /// Whether the stream can be picked up again after `err`: the
+    /// connection broke, rather than the server reporting an error or
+    /// sending something unreadable
+    fn can_resume(&self, err: &anyhow::Error) -> bool {
+        self.resumes_left > 0
+            && !self.called_tools
+            && err.downcast_ref::<ApiError>().is_none()
+            && err.downcast_ref::<serde_json::Error>().is_none()
+    }
+
+    /// Ask for the rest of the reply, with what has arrived so far as the
+    /// start of the assistant message for the model to continue
+    async fn reconnect(&mut self) -> Result<()> {
+        self.resumes_left -= 1;
+        let mut request = self.request.clone();
+        if !self.partial.is_empty() {
+            request
+                .messages
+                .push(Message::assistant(self.partial.clone()));
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            request.max_tokens = Some(max_tokens.saturating_sub(self.generated).max(1));
+        }
+        let chunks = self.client.chat_completion_stream(request).await?;
+        self.chunks = compat::boxed(chunks);
+        self.resumed = true;
+        Ok(())
+    }
+
+    fn record(&mut self, chunk: &mut ChatCompletionChunk) {
+        let Some(choice) = chunk.choices.iter_mut().find(|choice| choice.index == 0) else {
+            return;
+        };
+        if self.resumed {
+            // The reply carries on, so a repeated role would look like a
+            // new message
+            choice.delta.role = None;
+        }
+        if let Some(content) = choice
+            .delta
+            .content
+            .as_deref()
+            .filter(|text| !text.is_empty())
+        {
+            self.partial.push_str(content);
+            self.generated += 1;
+        }
+        if choice.delta.tool_calls.is_some() {
+            self.called_tools = true;
+        }
+        if choice.finish_reason.is_some() {
+            self.finished = true;
+        }
+    }
+}
+
+impl LlamaCppClient {
+    /// Send a streaming chat completion that reconnects if the connection
+    /// drops before the reply is finished, up to `max_resumes` times
+    ///
+    /// The request is sent again with the text received so far appended as
+    /// an assistant message, which llama.cpp continues rather than starting
+    /// over, and with `max_tokens` reduced by what was already generated.
+    /// The stream carries on as if nothing happened. A stream that ends
+    /// without a finish reason counts as dropped too. Errors the server
+    /// reports are passed on, as is everything once the model has started
+    /// calling tools. Services that do not continue a trailing assistant
+    /// message, such as OpenAI, would repeat text, so use this with
+    /// llama.cpp.
+    pub async fn chat_completion_stream_resuming(
+        &self,
+        request: ChatCompletionRequest,
+        max_resumes: u32,
+    ) -> Result<BoxStream<'static, Result<ChatCompletionChunk>>> {
+        let request = request.stream(true);
+        let chunks = self.chat_completion_stream(request.clone()).await?;
+        let state = Resume {
+            client: self.clone(),
+            request,
+            chunks: compat::boxed(chunks),
+            partial: String::new(),
+            generated: 0,
+            resumes_left: max_resumes,
+            resumed: false,
+            finished: false,
+            called_tools: false,
+            ended: false,
+        };
+
+        Ok(compat::boxed(stream::unfold(
+            state,
+            |mut state| async move {
+                if state.ended {
+                    return None;
+                }
+                loop {
+                    match state.chunks.next().await {
+                        Some(Ok(mut chunk)) => {
+                            state.record(&mut chunk);
+                            return Some((Ok(chunk), state));
+                        }
+                        Some(Err(err)) if !state.can_resume(&err) => {
+                            state.ended = true;
+                            return Some((Err(err), state));
+                        }
+                        None if state.finished || state.called_tools || state.resumes_left == 0 => {
+                            return None;
+                        }
+                        Some(Err(_)) | None => {
+                            if let Err(err) = state.reconnect().await {
+                                state.ended = true;
+                                return Some((Err(err), state));
+                            }
+                        }
+                    }
+                }
+            },
+        )))
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+
+use crate::{ChatCompletionRequest, LlamaCppClient, Message, ResponseFormat};
+
+// ============================================================================
+// Structured Generation
+// ============================================================================
+
+/// Retries after the first attempt used by [`LlamaCppClient::generate`]
+const DEFAULT_RETRIES: usize = 2;
+
+/// The system prompt hint added by [`ChatCompletionRequest::json_mode_with_hint`]
+pub const JSON_MODE_HINT: &str = "Respond with JSON only.";
+
+/// A type the model can be asked to produce as JSON
+///
+/// Implement this by returning the JSON schema your `Deserialize` impl
+/// accepts. With the `schemars` feature, types deriving `JsonSchema` can skip
+/// it and use [`LlamaCppClient::generate_typed`] instead.
+pub trait OutputSchema: DeserializeOwned {
+    /// Name of the schema, sent to servers that require one
+    fn schema_name() -> String {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+
+    /// The JSON schema of the type
+    fn json_schema() -> serde_json::Value;
+}
+
+/// Parse a model reply as `T`, tolerating a surrounding Markdown code fence
+pub fn parse_output<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let trimmed = content.trim();
+    let json = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(trimmed);
+    serde_json::from_str(json.trim()).context("Reply is not valid JSON for the expected type")
+}
+
+impl LlamaCppClient {
+    /// Ask `model` for a `T` and parse the reply
+    ///
+    /// The request constrains the output to `T`'s JSON schema. If the reply
+    /// still fails to parse, the error is sent back to the model and the
+    /// request is retried up to two more times.
+    pub async fn generate<T: OutputSchema>(
+        &self,
+        model: impl Into<String>,
+        prompt: impl Into<String>,
+    ) -> Result<T> {
+        let request = ChatCompletionRequest::new(model).message(Message::user(prompt.into()));
+        self.generate_with(request, DEFAULT_RETRIES).await
+    }
+
+    /// Send `request` in [JSON mode](ChatCompletionRequest::json_mode) and
+    /// parse the reply, tolerating a surrounding Markdown code fence
+    pub async fn chat_completion_json(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<serde_json::Value> {
+        let request = match request.response_format {
+            Some(_) => request,
+            None => request.json_mode(),
+        };
+        let response = self.chat_completion(request).await?;
+        let content = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content.text())
+            .context("Response contained no choices")?;
+        parse_output(&content)
+    }
+
+    /// Like [`LlamaCppClient::generate`], starting from a prepared request
+    /// and retrying at most `retries` times
+    pub async fn generate_with<T: OutputSchema>(
+        &self,
+        request: ChatCompletionRequest,
+        retries: usize,
+    ) -> Result<T> {
+        self.generate_as(request, retries, T::schema_name(), T::json_schema())
+            .await
+    }
+
+    /// Like [`LlamaCppClient::generate`], with the schema derived from `T`'s
+    /// `JsonSchema` impl by [`crate::schema::of`]
+    #[cfg(feature = "schemars")]
+    pub async fn generate_typed<T: DeserializeOwned + schemars::JsonSchema>(
+        &self,
+        model: impl Into<String>,
+        prompt: impl Into<String>,
+    ) -> Result<T> {
+        let request = ChatCompletionRequest::new(model).message(Message::user(prompt.into()));
+        self.generate_typed_with(request, DEFAULT_RETRIES).await
+    }
+
+    /// Like [`LlamaCppClient::generate_with`], with the schema derived from
+    /// `T`'s `JsonSchema` impl
+    #[cfg(feature = "schemars")]
+    pub async fn generate_typed_with<T: DeserializeOwned + schemars::JsonSchema>(
+        &self,
+        request: ChatCompletionRequest,
+        retries: usize,
+    ) -> Result<T> {
+        self.generate_as(
+            request,
+            retries,
+            T::schema_name().into_owned(),
+            crate::schema::of::<T>(),
+        )
+        .await
+    }
+
+    /// Constrain `request` to `schema`, then parse the reply as `T`, sending
+    /// parse errors back to the model up to `retries` times
+    async fn generate_as<T: DeserializeOwned>(
+        &self,
+        request: ChatCompletionRequest,
+        retries: usize,
+        name: String,
+        schema: serde_json::Value,
+    ) -> Result<T> {
+        let mut request =
+            request.response_format(ResponseFormat::json_schema(name, schema.clone()));
+
+        let mut attempt = 0;
+        loop {
+            let response = self.chat_completion(request.clone()).await?;
+            let content = response
+                .choices
+                .into_iter()
+                .next()
+                .map(|choice| choice.message.content.text())
+                .context("Response contained no choices")?;
+
+            let err = match parse_output::<T>(&content) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            if attempt == retries {
+                return Err(err.context(format!("Giving up after {} attempts", attempt + 1)));
+            }
+            attempt += 1;
+
+            request.messages.push(Message::assistant(content));
+            request.messages.push(Message::user(format!(
+                "That reply could not be parsed: {:#}. Reply again with only a JSON \
+                 value matching this schema:\n{}",
+                err, schema
+            )));
+        }
+    }
+}
@@ -0,0 +1,523 @@
+use anyhow::{Context, Result};
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+use crate::Message;
+
+// ============================================================================
+// Prompt Template
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+    /// `{{#if name}}then{{else}}otherwise{{/if}}`
+    Conditional {
+        name: String,
+        then: Vec<Segment>,
+        otherwise: Vec<Segment>,
+    },
+    /// `{{> partial}}`, followed by the end of its line if it stood alone
+    /// on one
+    Include {
+        segments: Vec<Segment>,
+        line_end: String,
+    },
+}
+
+/// A prompt with named `{{variable}}` placeholders
+///
+/// Besides placeholders, templates support conditionals and includes:
+///
+/// - `{{#if name}}…{{else}}…{{/if}}` keeps the first branch when `name` is
+///   set and not empty, and the optional `{{else}}` branch otherwise
+/// - `{{> partial}}` inserts another template, a file next to the including
+///   one for templates loaded with [`PromptTemplate::from_file`], or an
+///   entry of the map given to [`PromptTemplate::parse_with_partials`]
+///
+/// A conditional tag alone on its line leaves no blank line behind, and an
+/// include alone on its line is replaced by the partial, less any final
+/// newline.
+///
+/// Templates are parsed once, so malformed placeholders are reported when the
+/// template is created and missing variables are reported by name when it is
+/// rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PromptTemplate {
+    /// Parse a template, failing on unclosed or empty placeholders
+    pub fn parse(source: &str) -> Result<Self> {
+        Self::parse_in(source, &Partials::None)
+    }
+
+    /// Parse a template whose `{{> name}}` includes come from `partials`
+    pub fn parse_with_partials<K, V>(source: &str, partials: &HashMap<K, V>) -> Result<Self>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<str>,
+    {
+        Self::parse_in(source, &Partials::map(partials))
+    }
+
+    fn parse_in(source: &str, partials: &Partials) -> Result<Self> {
+        let segments = parse_segments(source, partials, &mut Vec::new())?;
+        Ok(Self { segments })
+    }
+
+    /// Parse a template and check that it uses exactly the `expected` variables
+    pub fn with_variables(source: &str, expected: &[&str]) -> Result<Self> {
+        let template = Self::parse(source)?;
+        template.expect_variables(expected)?;
+        Ok(template)
+    }
+
+    /// Load a template from a file, with includes relative to it
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let (source, partials) = Partials::read(path.as_ref())?;
+        Self::parse_in(&source, &partials)
+            .with_context(|| format!("Invalid template {}", path.as_ref().display()))
+    }
+
+    /// The names of all variables used, sorted and deduplicated, including
+    /// those only tested by conditionals
+    pub fn variables(&self) -> BTreeSet<&str> {
+        let mut names = BTreeSet::new();
+        collect_variables(&self.segments, &mut names);
+        names
+    }
+
+    /// Fail unless the template uses exactly the `expected` variables
+    pub fn expect_variables(&self, expected: &[&str]) -> Result<()> {
+        check_variables(self.variables(), expected)
+    }
+
+    /// Fill in every placeholder, failing if a variable to be inserted is
+    /// missing
+    ///
+    /// Variables only tested by conditionals, or only inserted in branches
+    /// that are not taken, may be left out.
+    pub fn render<K, V>(&self, vars: &HashMap<K, V>) -> Result<String>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<str>,
+    {
+        let mut output = String::new();
+        let mut missing = BTreeSet::new();
+        render_segments(&self.segments, vars, &mut output, &mut missing);
+        if !missing.is_empty() {
+            let missing: Vec<_> = missing.into_iter().collect();
+            anyhow::bail!("Missing template variables: {}", missing.join(", "));
+        }
+        Ok(output)
+    }
+}
+
+// ============================================================================
+// Parsing
+// ============================================================================
+
+/// Where `{{> name}}` includes come from
+enum Partials {
+    None,
+    Map(HashMap<String, String>),
+    /// Files relative to this directory
+    Dir(PathBuf),
+}
+
+impl Partials {
+    fn map<K, V>(partials: &HashMap<K, V>) -> Self
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<str>,
+    {
+        Partials::Map(
+            partials
+                .iter()
+                .map(|(name, source)| (name.borrow().to_string(), source.as_ref().to_string()))
+                .collect(),
+        )
+    }
+
+    /// Read a template file, with includes relative to it
+    fn read(path: &Path) -> Result<(String, Partials)> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok((source, Partials::Dir(dir)))
+    }
+
+    /// The source of partial `name`, and where its own includes come from
+    fn load(&self, name: &str) -> Result<(String, Option<Partials>)> {
+        match self {
+            Partials::None => anyhow::bail!(
+                "Cannot include {:?}: the template has no partials and was not loaded from a file",
+                name
+            ),
+            Partials::Map(partials) => partials
+                .get(name)
+                .map(|source| (source.clone(), None))
+                .with_context(|| format!("Unknown partial {:?}", name)),
+            Partials::Dir(dir) => {
+                let (source, partials) = Partials::read(&dir.join(name))?;
+                Ok((source, Some(partials)))
+            }
+        }
+    }
+}
+
+/// An `{{#if}}` whose `{{/if}}` has not been reached yet
+struct Block {
+    name: String,
+    then: Vec<Segment>,
+    otherwise: Option<Vec<Segment>>,
+}
+
+impl Block {
+    fn segments(&mut self) -> &mut Vec<Segment> {
+        match &mut self.otherwise {
+            Some(otherwise) => otherwise,
+            None => &mut self.then,
+        }
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn variable_name(name: &str) -> Result<String> {
+    if !is_variable_name(name) {
+        anyhow::bail!("Invalid placeholder name {:?}", name);
+    }
+    Ok(name.to_string())
+}
+
+/// If the tag from `start` to `end` is alone on its line, the end of that
+/// line, newline included
+fn standalone(source: &str, start: usize, end: usize) -> Option<usize> {
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    if !source[line_start..start]
+        .chars()
+        .all(|c| c == ' ' || c == '\t')
+    {
+        return None;
+    }
+    let rest = &source[end..];
+    let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+    rest[..line_end].trim().is_empty().then_some(end + line_end)
+}
+
+/// The newline, if any, that ends `line`
+fn line_ending(line: &str) -> &str {
+    ["\r\n", "\n"]
+        .into_iter()
+        .find(|ending| line.ends_with(ending))
+        .unwrap_or("")
+}
+
+/// Parse `source`, inlining includes; `including` holds the partials being
+/// included, to catch cycles
+fn parse_segments(
+    source: &str,
+    partials: &Partials,
+    including: &mut Vec<String>,
+) -> Result<Vec<Segment>> {
+    let mut root = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut literal = String::new();
+    let mut pos = 0;
+
+    while let Some(found) = source[pos..].find("{{") {
+        let start = pos + found;
+        let end = source[start + 2..]
+            .find("}}")
+            .map(|end| start + 2 + end)
+            .with_context(|| format!("Unclosed placeholder at byte {}", start))?;
+        let tag = source[start + 2..end].trim();
+        literal.push_str(&source[pos..start]);
+        pos = end + 2;
+
+        let mut line_end = "";
+        let is_block = tag.starts_with("#if ") || tag == "else" || tag == "/if";
+        if (is_block || tag.starts_with('>'))
+            && let Some(end) = standalone(source, start, pos)
+        {
+            literal.truncate(literal.trim_end_matches([' ', '\t']).len());
+            line_end = line_ending(&source[pos..end]);
+            pos = end;
+        }
+
+        let segments = blocks.last_mut().map_or(&mut root, Block::segments);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(std::mem::take(&mut literal)));
+        }
+
+        if let Some(name) = tag.strip_prefix("#if ") {
+            blocks.push(Block {
+                name: variable_name(name.trim())?,
+                then: Vec::new(),
+                otherwise: None,
+            });
+        } else if tag == "else" {
+            let block = blocks.last_mut().context("{{else}} outside {{#if}}")?;
+            if block.otherwise.is_some() {
+                anyhow::bail!("Second {{{{else}}}} in {{{{#if {}}}}}", block.name);
+            }
+            block.otherwise = Some(Vec::new());
+        } else if tag == "/if" {
+            let block = blocks.pop().context("{{/if}} without {{#if}}")?;
+            let conditional = Segment::Conditional {
+                name: block.name,
+                then: block.then,
+                otherwise: block.otherwise.unwrap_or_default(),
+            };
+            blocks
+                .last_mut()
+                .map_or(&mut root, Block::segments)
+                .push(conditional);
+        } else if let Some(name) = tag.strip_prefix('>') {
+            let name = name.trim();
+            if name.is_empty() {
+                anyhow::bail!("Include without a partial name");
+            }
+            if including.iter().any(|included| included == name) {
+                anyhow::bail!(
+                    "Template include cycle: {} -> {}",
+                    including.join(" -> "),
+                    name
+                );
+            }
+            let (partial, nested) = partials.load(name)?;
+            including.push(name.to_string());
+            let included = parse_segments(&partial, nested.as_ref().unwrap_or(partials), including)
+                .with_context(|| format!("In partial {:?}", name))?;
+            including.pop();
+            segments.push(Segment::Include {
+                segments: included,
+                line_end: line_end.to_string(),
+            });
+        } else {
+            segments.push(Segment::Variable(variable_name(tag)?));
+        }
+    }
+
+    literal.push_str(&source[pos..]);
+    if let Some(block) = blocks.last() {
+        anyhow::bail!("Unclosed {{{{#if {}}}}}", block.name);
+    }
+    if !literal.is_empty() {
+        root.push(Segment::Literal(literal));
+    }
+    Ok(root)
+}
+
+// ============================================================================
+// Rendering
+// ============================================================================
+
+fn collect_variables<'a>(segments: &'a [Segment], names: &mut BTreeSet<&'a str>) {
+    for segment in segments {
+        match segment {
+            Segment::Literal(_) => {}
+            Segment::Variable(name) => {
+                names.insert(name);
+            }
+            Segment::Conditional {
+                name,
+                then,
+                otherwise,
+            } => {
+                names.insert(name);
+                collect_variables(then, names);
+                collect_variables(otherwise, names);
+            }
+            Segment::Include { segments, .. } => collect_variables(segments, names),
+        }
+    }
+}
+
+fn render_segments<'a, K, V>(
+    segments: &'a [Segment],
+    vars: &HashMap<K, V>,
+    output: &mut String,
+    missing: &mut BTreeSet<&'a str>,
+) where
+    K: Borrow<str> + Hash + Eq,
+    V: AsRef<str>,
+{
+    for segment in segments {
+        match segment {
+            Segment::Literal(text) => output.push_str(text),
+            Segment::Variable(name) => match vars.get(name.as_str()) {
+                Some(value) => output.push_str(value.as_ref()),
+                None => {
+                    missing.insert(name);
+                }
+            },
+            Segment::Conditional {
+                name,
+                then,
+                otherwise,
+            } => {
+                let set = vars
+                    .get(name.as_str())
+                    .is_some_and(|value| !value.as_ref().is_empty());
+                let branch = if set { then } else { otherwise };
+                render_segments(branch, vars, output, missing);
+            }
+            Segment::Include { segments, line_end } => {
+                let mut partial = String::new();
+                render_segments(segments, vars, &mut partial, missing);
+                output.push_str(
+                    partial
+                        .strip_suffix(line_ending(&partial))
+                        .unwrap_or(&partial),
+                );
+                output.push_str(line_end);
+            }
+        }
+    }
+}
+
+fn check_variables(used: BTreeSet<&str>, expected: &[&str]) -> Result<()> {
+    let expected: BTreeSet<&str> = expected.iter().copied().collect();
+
+    let missing: Vec<_> = expected.difference(&used).collect();
+    let unknown: Vec<_> = used.difference(&expected).collect();
+    if !missing.is_empty() || !unknown.is_empty() {
+        anyhow::bail!(
+            "Template variables do not match: missing {:?}, unexpected {:?}",
+            missing,
+            unknown
+        );
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Chat Template
+// ============================================================================
+
+/// A list of message templates that renders into a conversation
+///
+/// In files, each message starts with a `### system`, `### user` or
+/// `### assistant` header line. A file without headers is a single user
+/// message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatTemplate {
+    messages: Vec<(String, PromptTemplate)>,
+}
+
+impl ChatTemplate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a message template with the given role
+    pub fn message(mut self, role: impl Into<String>, source: &str) -> Result<Self> {
+        self.messages
+            .push((role.into(), PromptTemplate::parse(source)?));
+        Ok(self)
+    }
+
+    pub fn system(self, source: &str) -> Result<Self> {
+        self.message("system", source)
+    }
+
+    pub fn user(self, source: &str) -> Result<Self> {
+        self.message("user", source)
+    }
+
+    pub fn assistant(self, source: &str) -> Result<Self> {
+        self.message("assistant", source)
+    }
+
+    /// Parse the `### role` sectioned format described above
+    pub fn parse(source: &str) -> Result<Self> {
+        Self::parse_in(source, &Partials::None)
+    }
+
+    /// Parse the `### role` sectioned format, with `{{> name}}` includes
+    /// from `partials`
+    pub fn parse_with_partials<K, V>(source: &str, partials: &HashMap<K, V>) -> Result<Self>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<str>,
+    {
+        Self::parse_in(source, &Partials::map(partials))
+    }
+
+    fn parse_in(source: &str, partials: &Partials) -> Result<Self> {
+        let mut template = Self::new();
+        let mut role: Option<&str> = None;
+        let mut body = String::new();
+
+        for line in source.split_inclusive('\n') {
+            let header = line
+                .trim_end()
+                .strip_prefix("### ")
+                .map(str::trim)
+                .filter(|name| matches!(*name, "system" | "user" | "assistant"));
+            match header {
+                Some(name) => {
+                    if let Some(role) = role {
+                        template = template.push(role, body.trim(), partials)?;
+                    } else if !body.trim().is_empty() {
+                        anyhow::bail!("Template text before the first ### header");
+                    }
+                    role = Some(name);
+                    body.clear();
+                }
+                None => body.push_str(line),
+            }
+        }
+
+        template.push(role.unwrap_or("user"), body.trim(), partials)
+    }
+
+    fn push(mut self, role: &str, source: &str, partials: &Partials) -> Result<Self> {
+        self.messages.push((
+            role.to_string(),
+            PromptTemplate::parse_in(source, partials)?,
+        ));
+        Ok(self)
+    }
+
+    /// Load a chat template from a file, with includes relative to it
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let (source, partials) = Partials::read(path.as_ref())?;
+        Self::parse_in(&source, &partials)
+            .with_context(|| format!("Invalid template {}", path.as_ref().display()))
+    }
+
+    /// The names of all variables used across every message
+    pub fn variables(&self) -> BTreeSet<&str> {
+        self.messages
+            .iter()
+            .flat_map(|(_, template)| template.variables())
+            .collect()
+    }
+
+    /// Fail unless the templates use exactly the `expected` variables
+    pub fn expect_variables(&self, expected: &[&str]) -> Result<()> {
+        check_variables(self.variables(), expected)
+    }
+
+    /// Render every message, failing if a variable to be inserted is missing
+    pub fn render<K, V>(&self, vars: &HashMap<K, V>) -> Result<Vec<Message>>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<str>,
+    {
+        self.messages
+            .iter()
+            .map(|(role, template)| Ok(Message::new(role.clone(), template.render(vars)?)))
+            .collect()
+    }
+}
@@ -0,0 +1,292 @@
+//! Transcripts of requests and replies as JSON lines.
+//!
+//! [`LlamaCppClient::with_transcript`] appends one line per event to a
+//! [`TranscriptWriter`]: each request body, each delta of a streamed reply as
+//! it arrives, and the final response, all timestamped and tied together by
+//! the request's `X-Request-Id`. Unlike [request logging](crate::logging),
+//! headers are never written, and bodies are kept as JSON for analysis or for
+//! collecting fine-tuning data:
+//!
+//! ```no_run
+//! use lancor::LlamaCppClient;
+//! use lancor::transcript::TranscriptWriter;
+//!
+//! # fn example() -> anyhow::Result<()> {
+//! let client = LlamaCppClient::new("http://localhost:8080")?
+//!     .with_transcript(TranscriptWriter::file("transcript.jsonl")?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Lines look like this, with `ts` in seconds since the Unix epoch:
+//!
+//! ```text
+//! {"ts":1760000000.123,"request_id":"…","event":"request","method":"POST","url":"http://localhost:8080/v1/chat/completions","body":{…}}
+//! {"ts":1760000000.301,"request_id":"…","event":"delta","data":{…}}
+//! {"ts":1760000000.950,"request_id":"…","event":"response","status":200,"elapsed_ms":827,"streamed":true,"content":"…","usage":{…}}
+//! ```
+//!
+//! Non-streamed responses carry the whole `body` instead of `content`, and a
+//! request that gets no response ends with an `error` event.
+
+use anyhow::Result;
+use futures::stream::StreamExt;
+use serde_json::{Map, Value, json};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::LlamaCppClient;
+use crate::compat::{self, BoxFuture, Instant};
+use crate::transport::{HttpRequest, HttpResponse, Transport};
+
+// ============================================================================
+// Writer
+// ============================================================================
+
+/// Where transcript lines are appended
+pub struct TranscriptWriter {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl TranscriptWriter {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Append to the file at `path`, creating it if needed
+    pub fn file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open transcript {}: {}", path.display(), e))?;
+        Ok(Self::new(file))
+    }
+
+    /// Append an `event` line for `request_id` with `fields`
+    fn record(&self, request_id: &str, event: &str, fields: Value) {
+        let mut line = Map::new();
+        line.insert(
+            "ts".into(),
+            json!(compat::unix_time().as_millis() as f64 / 1000.0),
+        );
+        line.insert("request_id".into(), json!(request_id));
+        line.insert("event".into(), json!(event));
+        if let Value::Object(fields) = fields {
+            line.extend(fields);
+        }
+        let Ok(mut line) = serde_json::to_string(&line) else {
+            return;
+        };
+        line.push('\n');
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        // A transcript must never make a request fail
+        let _ = writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.flush());
+    }
+}
+
+impl std::fmt::Debug for TranscriptWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TranscriptWriter").finish_non_exhaustive()
+    }
+}
+
+// ============================================================================
+// Transcript Transport
+// ============================================================================
+
+/// A transport that writes a transcript of every exchange of the transport
+/// it wraps
+#[derive(Debug, Clone)]
+pub(crate) struct TranscriptTransport {
+    inner: Arc<dyn Transport>,
+    writer: Arc<TranscriptWriter>,
+}
+
+/// A body as JSON if it is JSON, as text otherwise, or `null` if empty
+fn body_value(body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+/// A response being read; its `response` event is written when dropped
+struct PendingResponse {
+    writer: Arc<TranscriptWriter>,
+    request_id: String,
+    status: u16,
+    started: Instant,
+    streamed: bool,
+    /// The unread part of a streamed body, or the whole of any other
+    buffer: Vec<u8>,
+    /// `data:` lines of the event being read
+    data: Option<String>,
+    content: String,
+    usage: Option<Value>,
+    error: Option<String>,
+}
+
+impl PendingResponse {
+    /// Record each complete SSE event in the buffer as a `delta`
+    fn read_events(&mut self) {
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if let Some(data) = self.data.take() {
+                    self.delta(&data);
+                }
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("data:") {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match &mut self.data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => self.data = Some(value.to_string()),
+                }
+            }
+        }
+    }
+
+    fn delta(&mut self, data: &str) {
+        if data.trim().is_empty() || data == "[DONE]" {
+            return;
+        }
+        let data = body_value(data.as_bytes());
+        // Chat chunks carry text in a delta, completion chunks in `content`
+        let text = data["choices"][0]["delta"]["content"]
+            .as_str()
+            .or_else(|| data["content"].as_str());
+        if let Some(text) = text {
+            self.content.push_str(text);
+        }
+        if !data["usage"].is_null() {
+            self.usage = Some(data["usage"].clone());
+        }
+        self.writer
+            .record(&self.request_id, "delta", json!({ "data": data }));
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        let mut fields = json!({
+            "status": self.status,
+            "elapsed_ms": self.started.elapsed().as_millis() as u64,
+        });
+        if self.streamed {
+            if let Some(data) = self.data.take() {
+                self.delta(&data);
+            }
+            fields["streamed"] = json!(true);
+            fields["content"] = json!(self.content);
+            if let Some(usage) = self.usage.take() {
+                fields["usage"] = usage;
+            }
+        } else {
+            fields["body"] = body_value(&self.buffer);
+        }
+        if let Some(error) = &self.error {
+            fields["error"] = json!(error);
+        }
+        self.writer.record(&self.request_id, "response", fields);
+    }
+}
+
+impl Transport for TranscriptTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        let request_id = request
+            .header_value("X-Request-Id")
+            .unwrap_or_default()
+            .to_string();
+        // The query string may hold an API key
+        let url = request.url.split('?').next().unwrap_or_default();
+        self.writer.record(
+            &request_id,
+            "request",
+            json!({
+                "method": request.method,
+                "url": url,
+                "body": body_value(&request.body),
+            }),
+        );
+        let started = Instant::now();
+
+        compat::boxed_future(async move {
+            let response = match self.inner.send(request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    self.writer.record(
+                        &request_id,
+                        "error",
+                        json!({
+                            "elapsed_ms": started.elapsed().as_millis() as u64,
+                            "error": format!("{:#}", err),
+                        }),
+                    );
+                    return Err(err);
+                }
+            };
+
+            let streamed = response
+                .header_value("Content-Type")
+                .is_some_and(|kind| kind.contains("text/event-stream"));
+            let mut pending = PendingResponse {
+                writer: self.writer.clone(),
+                request_id,
+                status: response.status,
+                started,
+                streamed,
+                buffer: Vec::new(),
+                data: None,
+                content: String::new(),
+                usage: None,
+                error: None,
+            };
+            let body = response.body.map(move |chunk| {
+                match &chunk {
+                    Ok(bytes) => {
+                        pending.buffer.extend_from_slice(bytes);
+                        if pending.streamed {
+                            pending.read_events();
+                        }
+                    }
+                    Err(err) => pending.error = Some(format!("{:#}", err)),
+                }
+                chunk
+            });
+
+            Ok(HttpResponse {
+                status: response.status,
+                headers: response.headers,
+                body: compat::boxed(body),
+            })
+        })
+    }
+}
+
+impl LlamaCppClient {
+    /// Append a transcript of every request and reply to `writer`; see
+    /// [`transcript`](crate::transcript)
+    ///
+    /// The transcript wraps the transport installed at that point, so call
+    /// [`LlamaCppClient::with_transport`] first.
+    pub fn with_transcript(mut self, writer: TranscriptWriter) -> Self {
+        self.transport = Arc::new(TranscriptTransport {
+            inner: self.transport,
+            writer: Arc::new(writer),
+        });
+        self
+    }
+}
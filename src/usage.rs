@@ -0,0 +1,234 @@
+//! Token usage accounting and cost tracking.
+//!
+//! A [`UsageTracker`] collects the prompt and completion tokens the server
+//! reports, per model. Give it per-1k-token prices to follow the running
+//! cost, and a budget to make the client refuse requests once it is spent.
+//! Install it with [`crate::LlamaCppClient::with_usage_tracker`]; clones share
+//! their counters.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::metrics::{MetricsObserver, RequestInfo};
+
+// ============================================================================
+// Pricing
+// ============================================================================
+
+/// The price of a model's tokens, per 1000 tokens
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pricing {
+    pub prompt_per_1k: f64,
+    pub completion_per_1k: f64,
+}
+
+impl Pricing {
+    pub fn new(prompt_per_1k: f64, completion_per_1k: f64) -> Self {
+        Self {
+            prompt_per_1k,
+            completion_per_1k,
+        }
+    }
+
+    /// The cost of `prompt_tokens` and `completion_tokens` at this price
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.prompt_per_1k
+            + completion_tokens as f64 * self.completion_per_1k)
+            / 1000.0
+    }
+}
+
+// ============================================================================
+// Usage
+// ============================================================================
+
+/// Tokens and cost accumulated for one model, or for all of them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ModelUsage {
+    /// Requests that reported usage
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Cost at the prices known when each request finished; zero for
+    /// unpriced models
+    pub cost: f64,
+}
+
+impl ModelUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn add(&mut self, other: &ModelUsage) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+    }
+}
+
+/// Usage counters at one point in time
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UsageSnapshot {
+    pub models: BTreeMap<String, ModelUsage>,
+}
+
+impl UsageSnapshot {
+    /// Usage summed over all models
+    pub fn total(&self) -> ModelUsage {
+        let mut total = ModelUsage::default();
+        for usage in self.models.values() {
+            total.add(usage);
+        }
+        total
+    }
+
+    pub fn model(&self, model: &str) -> ModelUsage {
+        self.models.get(model).copied().unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for UsageSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (model, usage) in &self.models {
+            writeln!(
+                f,
+                "{}: {} requests, {} prompt + {} completion tokens, cost {:.4}",
+                model, usage.requests, usage.prompt_tokens, usage.completion_tokens, usage.cost
+            )?;
+        }
+        let total = self.total();
+        write!(
+            f,
+            "total: {} requests, {} tokens, cost {:.4}",
+            total.requests,
+            total.total_tokens(),
+            total.cost
+        )
+    }
+}
+
+// ============================================================================
+// Tracker
+// ============================================================================
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    usage: UsageSnapshot,
+    prices: HashMap<String, Pricing>,
+    default_price: Option<Pricing>,
+    cost_budget: Option<f64>,
+    token_budget: Option<u64>,
+}
+
+/// Accumulates token usage and cost per model, optionally within a budget
+#[derive(Debug, Clone, Default)]
+pub struct UsageTracker {
+    state: Arc<Mutex<TrackerState>>,
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Charge `pricing` for requests to `model`
+    pub fn price(self, model: impl Into<String>, pricing: Pricing) -> Self {
+        self.lock().prices.insert(model.into(), pricing);
+        self
+    }
+
+    /// Charge `pricing` for models without a price of their own
+    pub fn default_price(self, pricing: Pricing) -> Self {
+        self.lock().default_price = Some(pricing);
+        self
+    }
+
+    /// Refuse requests once the total cost reaches `max_cost`
+    pub fn cost_budget(self, max_cost: f64) -> Self {
+        self.lock().cost_budget = Some(max_cost);
+        self
+    }
+
+    /// Refuse requests once the total number of tokens reaches `max_tokens`
+    pub fn token_budget(self, max_tokens: u64) -> Self {
+        self.lock().token_budget = Some(max_tokens);
+        self
+    }
+
+    /// Add the usage of one request to `model`
+    pub fn record(&self, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+        let mut state = self.lock();
+        let (prompt_tokens, completion_tokens) =
+            (u64::from(prompt_tokens), u64::from(completion_tokens));
+        let cost = state
+            .prices
+            .get(model)
+            .or(state.default_price.as_ref())
+            .map_or(0.0, |price| price.cost(prompt_tokens, completion_tokens));
+
+        state
+            .usage
+            .models
+            .entry(model.to_string())
+            .or_default()
+            .add(&ModelUsage {
+                requests: 1,
+                prompt_tokens,
+                completion_tokens,
+                cost,
+            });
+    }
+
+    /// The counters as they are now
+    pub fn snapshot(&self) -> UsageSnapshot {
+        self.lock().usage.clone()
+    }
+
+    /// Return the counters and start again from zero; prices and budgets are
+    /// kept
+    pub fn reset(&self) -> UsageSnapshot {
+        std::mem::take(&mut self.lock().usage)
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        self.lock().usage.total().cost
+    }
+
+    /// Fail if the cost or token budget has been used up
+    pub fn check_budget(&self) -> Result<()> {
+        let state = self.lock();
+        let total = state.usage.total();
+
+        if let Some(max_cost) = state.cost_budget
+            && total.cost >= max_cost
+        {
+            anyhow::bail!(
+                "Cost budget exhausted: spent {:.4} of {:.4}",
+                total.cost,
+                max_cost
+            );
+        }
+        if let Some(max_tokens) = state.token_budget
+            && total.total_tokens() >= max_tokens
+        {
+            anyhow::bail!(
+                "Token budget exhausted: used {} of {} tokens",
+                total.total_tokens(),
+                max_tokens
+            );
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TrackerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl MetricsObserver for UsageTracker {
+    fn on_tokens(&self, request: &RequestInfo, prompt_tokens: u32, completion_tokens: u32) {
+        self.record(&request.model, prompt_tokens, completion_tokens);
+    }
+}
@@ -0,0 +1,218 @@
+//! Running tools for the model with `run_agent`.
+
+#![cfg(feature = "agent")]
+
+mod common;
+
+use common::reply;
+use lancor::transport::MockTransport;
+use lancor::{ChatSession, InvalidArguments, LlamaCppClient, Message, ToolRegistry, run_agent};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn call(id: &str, name: &str, arguments: Value) -> Value {
+    json!({
+        "id": id,
+        "type": "function",
+        "function": { "name": name, "arguments": arguments.to_string() }
+    })
+}
+
+#[tokio::test]
+async fn calls_in_one_turn_run_concurrently_and_answer_in_order() {
+    let mock = MockTransport::new()
+        .json(
+            "/v1/chat/completions",
+            reply(json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                    call("call_0", "wait", json!({ "ms": 60, "label": "slow" })),
+                    call("call_1", "wait", json!({ "ms": 0, "label": "quick" })),
+                    call("call_2", "missing", json!({})),
+                    call("call_3", "wait", json!({ "ms": 30, "label": "medium" }))
+                ]
+            })),
+        )
+        .json(
+            "/v1/chat/completions",
+            reply(json!({ "role": "assistant", "content": "All done" })),
+        );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let (running, most) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let mut tools = ToolRegistry::new();
+    let (r, m) = (running.clone(), most.clone());
+    tools
+        .register("wait", "Wait a while", json!({}), move |args| {
+            let (running, most) = (r.clone(), m.clone());
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(args["ms"].as_u64().unwrap())).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(args["label"].as_str().unwrap().to_string())
+            }
+        })
+        .unwrap()
+        .max_concurrent(2);
+
+    let mut session = ChatSession::new(client, "test-model");
+    session.push(Message::user("Go"));
+    let answer = run_agent(&mut session, &tools, 3).await.unwrap();
+    assert_eq!(answer, "All done");
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+
+    let results: Vec<(String, String)> = session
+        .history()
+        .iter()
+        .filter(|message| message.role == "tool")
+        .map(|message| {
+            (
+                message.tool_call_id.clone().unwrap(),
+                message.content.text(),
+            )
+        })
+        .collect();
+    assert_eq!(results[0], ("call_0".to_string(), "slow".to_string()));
+    assert_eq!(results[1], ("call_1".to_string(), "quick".to_string()));
+    assert_eq!(results[2].0, "call_2");
+    assert!(results[2].1.contains("Unknown tool"), "{}", results[2].1);
+    assert_eq!(results[3], ("call_3".to_string(), "medium".to_string()));
+
+    // The second turn sends the results back in the same order
+    let body: Value = mock.requests()[1].json().unwrap();
+    let ids: Vec<&str> = body["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|message| message["tool_call_id"].as_str())
+        .collect();
+    assert_eq!(ids, ["call_0", "call_1", "call_2", "call_3"]);
+}
+
+#[test]
+#[cfg(not(feature = "jsonschema"))]
+fn schemas_that_cannot_be_checked_fail_to_register() {
+    let mut tools = ToolRegistry::new();
+    let err = tools
+        .register(
+            "book",
+            "Book a table",
+            json!({
+                "type": "object",
+                "properties": { "when": { "type": "string", "format": "date-time" } }
+            }),
+            |_| async move { Ok("Booked".to_string()) },
+        )
+        .unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        "Cannot check arguments for tool book: Invalid schema: schema keyword \
+         #/properties/when/format can only be checked with the jsonschema feature"
+    );
+    assert!(tools.is_empty());
+}
+
+fn weather_tools() -> ToolRegistry {
+    let mut tools = ToolRegistry::new();
+    tools
+        .register(
+            "get_weather",
+            "Current weather for a city",
+            json!({
+                "type": "object",
+                "properties": {
+                    "city": { "type": "string" },
+                    "unit": { "type": "string", "enum": ["c", "f"] }
+                },
+                "required": ["city"]
+            }),
+            |args| async move { Ok(format!("Sunny in {}", args["city"].as_str().unwrap())) },
+        )
+        .unwrap();
+    tools
+}
+
+fn weather_call(arguments: &str) -> Value {
+    reply(json!({
+        "role": "assistant",
+        "content": null,
+        "tool_calls": [{
+            "id": "call_0",
+            "type": "function",
+            "function": { "name": "get_weather", "arguments": arguments }
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn invalid_arguments_are_sent_back_for_another_try() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", weather_call(r#"{"city": 7}"#))
+        .json(
+            "/v1/chat/completions",
+            weather_call(r#"{"city": "Lisbon", "unit": null}"#),
+        )
+        .json(
+            "/v1/chat/completions",
+            reply(json!({ "role": "assistant", "content": "It is sunny." })),
+        );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let mut session = ChatSession::new(client, "test-model");
+    session.push(Message::user("Weather in Lisbon?"));
+
+    let answer = run_agent(&mut session, &weather_tools(), 5).await.unwrap();
+    assert_eq!(answer, "It is sunny.");
+    let outputs: Vec<String> = session
+        .history()
+        .iter()
+        .filter(|message| message.role == "tool")
+        .map(|message| message.content.text())
+        .collect();
+    // The jsonschema feature words violations its own way
+    let problem = if cfg!(feature = "jsonschema") {
+        "/city: 7 is not of type \"string\""
+    } else {
+        "/city: expected string, got 7"
+    };
+    assert!(
+        outputs[0].starts_with(&format!(
+            "Error: Invalid arguments for get_weather: {}. \
+             Call get_weather again with arguments matching this JSON schema: {{",
+            problem
+        )),
+        "{}",
+        outputs[0]
+    );
+    // A null optional argument counts as left out
+    assert_eq!(outputs[1], "Sunny in Lisbon");
+}
+
+#[tokio::test]
+async fn gives_up_after_the_argument_retries() {
+    let mut mock = MockTransport::new();
+    for _ in 0..3 {
+        mock = mock.json("/v1/chat/completions", weather_call("{not json"));
+    }
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let mut session = ChatSession::new(client, "test-model");
+    session.push(Message::user("Weather in Lisbon?"));
+    let mut tools = weather_tools();
+    tools.max_argument_retries(1);
+
+    let err = run_agent(&mut session, &tools, 5).await.unwrap_err();
+    assert!(err.to_string().contains("after 1 retries"), "{}", err);
+    let invalid = err.downcast_ref::<InvalidArguments>().unwrap();
+    assert_eq!(invalid.tool, "get_weather");
+    assert!(invalid.problems[0].starts_with("not valid JSON"));
+    assert_eq!(mock.requests().len(), 2);
+}
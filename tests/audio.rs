@@ -0,0 +1,111 @@
+//! Transcription through whisper.cpp's OpenAI-compatible endpoint.
+
+use lancor::LlamaCppClient;
+use lancor::audio::TranscriptionOptions;
+use lancor::transport::MockTransport;
+use serde_json::json;
+
+fn whisper_response() -> serde_json::Value {
+    json!({
+        "task": "transcribe",
+        "language": "english",
+        "duration": 4.2,
+        "text": " Hello there. General Kenobi.",
+        "segments": [
+            {
+                "id": 0, "start": 0.0, "end": 1.6, "text": " Hello there.",
+                "tokens": [50364, 2425, 456, 13], "temperature": 0.0,
+                "avg_logprob": -0.21, "no_speech_prob": 0.01,
+                "words": [
+                    { "word": " Hello", "start": 0.0, "end": 0.7, "probability": 0.98 },
+                    { "word": " there.", "start": 0.7, "end": 1.6, "probability": 0.95 }
+                ]
+            },
+            { "id": 1, "start": 1.9, "end": 4.2, "text": " General Kenobi." }
+        ]
+    })
+}
+
+#[tokio::test]
+async fn audio_is_uploaded_as_multipart_and_segments_parsed() {
+    let mock = MockTransport::new().json("/v1/audio/transcriptions", whisper_response());
+    let client = LlamaCppClient::new("http://localhost:8081")
+        .unwrap()
+        .with_transport(mock.clone());
+    let audio = b"RIFF\x24\0\0\0WAVEfmt \x00\x01\x02";
+
+    let transcript = client
+        .transcribe_bytes(
+            "greeting.wav",
+            audio,
+            TranscriptionOptions::new()
+                .language("en")
+                .prompt("Star Wars")
+                .word_timestamps(true),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(transcript.text, " Hello there. General Kenobi.");
+    assert_eq!(transcript.duration, Some(4.2));
+    assert_eq!(transcript.segments.len(), 2);
+    assert_eq!(transcript.segments[1].start, 1.9);
+    assert_eq!(transcript.segments[1].text, " General Kenobi.");
+    assert_eq!(transcript.segments[0].words[1].word, " there.");
+    assert!(transcript.segments[1].words.is_empty());
+
+    let sent = &mock.requests()[0];
+    let content_type = sent.header_value("content-type").unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/form-data; boundary=")
+        .unwrap();
+    let body = String::from_utf8_lossy(&sent.body);
+    let field = |name: &str, value: &str| {
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            boundary, name, value
+        )
+    };
+    assert!(body.contains(&field("response_format", "verbose_json")));
+    assert!(body.contains(&field("language", "en")));
+    assert!(body.contains(&field("prompt", "Star Wars")));
+    assert!(body.contains(&field("timestamp_granularities[]", "word")));
+    assert!(!body.contains("name=\"model\""));
+    assert!(
+        body.contains(
+            "name=\"file\"; filename=\"greeting.wav\"\r\nContent-Type: audio/wav\r\n\r\n"
+        )
+    );
+    assert!(sent.body.windows(audio.len()).any(|window| window == audio));
+    assert!(body.ends_with(&format!("\r\n--{}--\r\n", boundary)));
+}
+
+#[tokio::test]
+async fn files_are_read_from_disk() {
+    let path = std::env::temp_dir().join(format!("lancor-whisper-{}.mp3", std::process::id()));
+    std::fs::write(&path, b"ID3 fake").unwrap();
+    let mock = MockTransport::new().json(
+        "/v1/audio/transcriptions",
+        json!({ "text": "fake", "segments": [] }),
+    );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let transcript = client
+        .transcribe(&path, TranscriptionOptions::new().model("whisper-1"))
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(transcript.text, "fake");
+    let body = String::from_utf8_lossy(&mock.requests()[0].body).into_owned();
+    assert!(body.contains("Content-Type: audio/mpeg"), "{}", body);
+    assert!(body.contains("whisper-1"), "{}", body);
+
+    let missing = client
+        .transcribe("/nonexistent/clip.wav", TranscriptionOptions::new())
+        .await
+        .unwrap_err();
+    assert!(missing.to_string().contains("Failed to read audio"));
+}
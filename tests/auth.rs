@@ -0,0 +1,139 @@
+//! Sending the API key with different auth schemes.
+
+mod common;
+
+use common::chat_response;
+use lancor::logging::{LogEntry, REDACTED, Redactor};
+use lancor::transport::MockTransport;
+use lancor::{AuthScheme, ChatCompletionRequest, LancorConfig, LlamaCppClient, Message};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// The request a client with `config` sends
+async fn sent(config: LancorConfig) -> lancor::transport::HttpRequest {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("Hi"))
+        .json(
+            "/openai/deployments/gpt/chat/completions",
+            chat_response("Hi"),
+        );
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+    let request = ChatCompletionRequest::new("m").message(Message::user("Hello"));
+    client.chat_completion(request).await.unwrap();
+    mock.requests().remove(0)
+}
+
+#[tokio::test]
+async fn keys_are_bearer_tokens_by_default() {
+    let request = sent(LancorConfig::new("http://proxy").api_key("sk-1")).await;
+    assert_eq!(request.header_value("authorization"), Some("Bearer sk-1"));
+
+    let azure = LancorConfig::azure("https://res.openai.azure.com", "az-1").deployment("gpt");
+    let request = sent(azure).await;
+    assert_eq!(request.header_value("api-key"), Some("az-1"));
+    assert_eq!(request.header_value("authorization"), None);
+}
+
+#[tokio::test]
+async fn keys_can_go_in_a_header_of_their_own() {
+    let config = LancorConfig::new("http://proxy")
+        .api_key("sk-1")
+        .auth(AuthScheme::Header("x-api-key".to_string()));
+    let request = sent(config).await;
+    assert_eq!(request.header_value("x-api-key"), Some("sk-1"));
+    assert_eq!(request.header_value("authorization"), None);
+}
+
+#[tokio::test]
+async fn keys_can_go_in_the_query() {
+    let config = LancorConfig::new("http://proxy")
+        .api_key("a b&c")
+        .auth(AuthScheme::Query("key".to_string()));
+    let request = sent(config).await;
+    assert_eq!(
+        request.url,
+        "http://proxy/v1/chat/completions?key=a%20b%26c"
+    );
+    assert_eq!(request.header_value("authorization"), None);
+
+    // Joined to a query the route already has
+    let azure = LancorConfig::azure("https://res.openai.azure.com", "az-1")
+        .deployment("gpt")
+        .auth(AuthScheme::Query("api-key".to_string()));
+    let request = sent(azure).await;
+    assert!(
+        request
+            .url
+            .ends_with("?api-version=2024-10-21&api-key=az-1"),
+        "{}",
+        request.url
+    );
+}
+
+#[tokio::test]
+async fn keys_can_be_basic_auth_passwords() {
+    let config = LancorConfig::new("http://proxy")
+        .api_key("secret")
+        .auth(AuthScheme::Basic {
+            username: "lancor".to_string(),
+        });
+    let request = sent(config).await;
+    // base64("lancor:secret")
+    assert_eq!(
+        request.header_value("authorization"),
+        Some("Basic bGFuY29yOnNlY3JldA==")
+    );
+}
+
+#[test]
+fn schemes_are_read_from_config_files() {
+    let config: LancorConfig = serde_json::from_value(json!({
+        "base_url": "http://proxy",
+        "api_key": "sk-1",
+        "auth": { "basic": { "username": "admin" } }
+    }))
+    .unwrap();
+    assert_eq!(
+        config.auth,
+        Some(AuthScheme::Basic {
+            username: "admin".to_string()
+        })
+    );
+
+    let config: LancorConfig =
+        serde_json::from_value(json!({ "auth": { "header": "x-api-key" } })).unwrap();
+    assert_eq!(
+        config.auth,
+        Some(AuthScheme::Header("x-api-key".to_string()))
+    );
+    let config: LancorConfig = serde_json::from_value(json!({ "auth": "bearer" })).unwrap();
+    assert_eq!(config.auth, Some(AuthScheme::Bearer));
+}
+
+#[tokio::test]
+async fn query_keys_are_not_logged() {
+    let entries = Arc::new(Mutex::new(Vec::<LogEntry>::new()));
+    let sink = {
+        let entries = entries.clone();
+        move |entry: &LogEntry| entries.lock().unwrap().push(entry.clone())
+    };
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let config = LancorConfig::new("http://proxy")
+        .api_key("sk-query")
+        .auth(AuthScheme::Query("key".to_string()));
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock)
+        .with_logging(sink, Redactor::new());
+
+    let request = ChatCompletionRequest::new("m").message(Message::user("Hello"));
+    client.chat_completion(request).await.unwrap();
+
+    let entries = entries.lock().unwrap();
+    assert_eq!(
+        entries[0].url,
+        format!("http://proxy/v1/chat/completions?key={}", REDACTED)
+    );
+}
@@ -0,0 +1,121 @@
+//! Mapping over many requests with bounded concurrency.
+
+#![cfg(feature = "batch")]
+
+mod common;
+
+use common::{chat_response_with_usage, client};
+use lancor::transport::{HttpRequest, HttpResponse, Transport};
+use lancor::{BatchProgress, ChatCompletionRequest, EmbeddingRequest, Message};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Echoes each prompt back after a delay that is longer for earlier prompts,
+/// so requests finish out of order; prompts containing "fail" get a 400
+#[derive(Debug, Clone, Default)]
+struct EchoTransport {
+    current: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+impl Transport for EchoTransport {
+    fn send(&self, request: HttpRequest) -> lancor::BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        Box::pin(async move {
+            let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+
+            let body: Value = request.json()?;
+            let text = body["messages"][0]["content"]
+                .as_str()
+                .or(body["input"].as_str())
+                .unwrap_or_default()
+                .to_string();
+            let number: u64 = text
+                .trim_start_matches(|c: char| !c.is_ascii_digit())
+                .parse()?;
+            tokio::time::sleep(Duration::from_millis(60 - 10 * number)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+
+            if text.contains("fail") {
+                return Ok(HttpResponse::new(400, r#"{"error":"bad prompt"}"#));
+            }
+            let reply = if request.path() == "/v1/embeddings" {
+                json!({
+                    "object": "list",
+                    "data": [{ "object": "embedding", "embedding": [number as f32], "index": 0 }],
+                    "model": "nomic",
+                    "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+                })
+            } else {
+                chat_response_with_usage(&text, 1, 1)
+            };
+            Ok(HttpResponse::new(200, reply.to_string()))
+        })
+    }
+}
+
+#[tokio::test]
+async fn chat_results_keep_input_order() {
+    let transport = EchoTransport::default();
+    let requests = ["prompt 0", "prompt 1", "fail 2", "prompt 3", "prompt 4"]
+        .map(|prompt| ChatCompletionRequest::new("qwen").message(Message::user(prompt)));
+
+    let mut reports: Vec<BatchProgress> = Vec::new();
+    let results = client(&transport)
+        .map_chat_with_progress(requests, 2, |progress| reports.push(*progress))
+        .await;
+
+    let replies: Vec<String> = results
+        .iter()
+        .map(|result| match result {
+            Ok(response) => response.choices[0].message.content.text(),
+            Err(err) => format!("error: {}", err),
+        })
+        .collect();
+    assert_eq!(
+        replies,
+        [
+            "prompt 0",
+            "prompt 1",
+            "error: API error (400): bad prompt",
+            "prompt 3",
+            "prompt 4"
+        ]
+    );
+    assert_eq!(transport.peak.load(Ordering::SeqCst), 2);
+
+    assert_eq!(reports.len(), 5);
+    assert_eq!(
+        reports.iter().map(|r| r.completed).collect::<Vec<_>>(),
+        [1, 2, 3, 4, 5]
+    );
+    assert!(reports.iter().all(|r| r.total == 5));
+    assert_eq!(reports.last().unwrap().failed, 1);
+    // Requests 0 and 1 start together and the later one is quicker
+    assert_eq!(reports[0].index, 1);
+}
+
+#[tokio::test]
+async fn embeddings_map_in_order() {
+    let transport = EchoTransport::default();
+    let requests = (0..5).map(|i| EmbeddingRequest::new("nomic", format!("text {}", i)));
+
+    let results = client(&transport).map_embeddings(requests, 3).await;
+
+    let firsts: Vec<f32> = results
+        .into_iter()
+        .map(|result| result.unwrap().data[0].embedding[0])
+        .collect();
+    assert_eq!(firsts, [0.0, 1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(transport.peak.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn an_empty_batch_sends_nothing() {
+    let transport = EchoTransport::default();
+    let results = client(&transport).map_chat(Vec::new(), 4).await;
+    assert!(results.is_empty());
+    assert_eq!(transport.peak.load(Ordering::SeqCst), 0);
+}
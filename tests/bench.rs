@@ -0,0 +1,140 @@
+//! Benchmarking a server programmatically with `Benchmark`.
+
+#![cfg(feature = "bench")]
+
+mod common;
+
+use common::client;
+use lancor::bench::{self, Benchmark, Percentiles};
+use lancor::transport::{HttpRequest, HttpResponse, MockTransport, Transport};
+use lancor::{ChatCompletionRequest, CompletionRequest, LlamaCppClient, Message};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A server with two slots that takes 30ms per request
+#[derive(Debug, Clone)]
+struct TwoSlotServer {
+    inner: MockTransport,
+    slots: Arc<tokio::sync::Semaphore>,
+}
+
+impl Transport for TwoSlotServer {
+    fn send(&self, request: HttpRequest) -> lancor::BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        Box::pin(async move {
+            let _slot = self.slots.acquire().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            self.inner.send(request).await
+        })
+    }
+}
+
+#[tokio::test]
+async fn chat_benchmarks_measure_every_request() {
+    let chunk = |content: &str| {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "test-model",
+            "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+        })
+    };
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![chunk("Once"), chunk(" upon"), chunk(" a time")],
+    );
+
+    let report = Benchmark::new(client(&mock))
+        .chat(ChatCompletionRequest::new("test-model").message(Message::user("Hi")))
+        .requests(10)
+        .concurrency(3)
+        .run()
+        .await;
+
+    assert_eq!(report.requests, 10);
+    assert_eq!(report.succeeded(), 10);
+    assert_eq!(report.error_rate(), 0.0);
+    assert_eq!(report.tokens, 30);
+    assert!(
+        report
+            .samples
+            .iter()
+            .all(|s| s.tokens == 3 && s.ttft.is_some())
+    );
+    assert!(report.ttft.is_some());
+    let latency = report.latency.unwrap();
+    assert!(latency.p50 <= latency.p99 && latency.p99 <= latency.max);
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 10);
+    let body: serde_json::Value = requests[0].json().unwrap();
+    assert_eq!(body["stream"], true);
+}
+
+#[tokio::test]
+async fn completion_benchmarks_report_failures() {
+    let mock = MockTransport::new()
+        .json(
+            "/v1/completions",
+            json!({ "content": "world", "tokens_predicted": 5 }),
+        )
+        .respond("/v1/completions", 500, "out of memory");
+
+    let report = Benchmark::new(client(&mock))
+        .completion(CompletionRequest::new("test-model", "Hello"))
+        .requests(4)
+        .concurrency(1)
+        .run()
+        .await;
+
+    assert_eq!(report.succeeded(), 1);
+    assert_eq!(report.errors.len(), 3);
+    assert_eq!(report.error_rate(), 0.75);
+    assert_eq!(report.tokens, 5);
+    assert!(report.ttft.is_none());
+    assert!(
+        report.errors[0].contains("out of memory"),
+        "{}",
+        report.errors[0]
+    );
+}
+
+#[test]
+fn percentiles_use_the_nearest_rank() {
+    let values = (1..=100).map(Duration::from_millis);
+    let percentiles = Percentiles::of(values).unwrap();
+    assert_eq!(percentiles.p50, Duration::from_millis(50));
+    assert_eq!(percentiles.p99, Duration::from_millis(99));
+    assert_eq!(percentiles.max, Duration::from_millis(100));
+    assert_eq!(percentiles.mean, Duration::from_micros(50_500));
+    assert!(Percentiles::of([]).is_none());
+}
+
+#[tokio::test]
+async fn sweeps_find_where_throughput_stops_growing() {
+    assert_eq!(bench::sweep_levels(8), [1, 2, 4, 8]);
+    assert_eq!(bench::sweep_levels(6), [1, 2, 4, 6]);
+    assert_eq!(bench::sweep_levels(1), [1]);
+
+    let server = TwoSlotServer {
+        inner: MockTransport::new().json(
+            "/v1/completions",
+            json!({ "content": "world", "tokens_predicted": 5 }),
+        ),
+        slots: Arc::new(tokio::sync::Semaphore::new(2)),
+    };
+    let client = LlamaCppClient::default().unwrap().with_transport(server);
+
+    let sweep = Benchmark::new(client)
+        .completion(CompletionRequest::new("test-model", "Hello"))
+        .requests(8)
+        .sweep(4)
+        .await;
+
+    let levels: Vec<_> = sweep.levels.iter().map(|r| r.concurrency).collect();
+    assert_eq!(levels, [1, 2, 4]);
+    assert_eq!(sweep.levels[2].requests, 16);
+    assert_eq!(sweep.saturation, Some(2));
+    assert_eq!(sweep.saturated().unwrap().concurrency, 2);
+}
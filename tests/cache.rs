@@ -0,0 +1,218 @@
+//! Answering repeated deterministic requests from a `ResponseCache`.
+
+mod common;
+
+use common::chat_response;
+use lancor::transport::MockTransport;
+use lancor::{
+    ChatCompletionRequest, CompletionRequest, EmbeddingRequest, LlamaCppClient, Message,
+    ResponseCache,
+};
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lancor-cache-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn client(mock: &MockTransport, cache: &ResponseCache) -> LlamaCppClient {
+    common::client(mock).with_cache(cache.clone())
+}
+
+fn ask(question: &str) -> ChatCompletionRequest {
+    ChatCompletionRequest::new("test-model")
+        .message(Message::user(question))
+        .temperature(0.0)
+}
+
+async fn answer(client: &LlamaCppClient, request: ChatCompletionRequest) -> String {
+    let response = client.chat_completion(request).await.unwrap();
+    response.choices[0].message.content.text()
+}
+
+#[tokio::test]
+async fn repeats_are_served_from_cache() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"));
+    let cache = ResponseCache::new();
+    let client = client(&mock, &cache);
+
+    assert_eq!(answer(&client, ask("Hi")).await, "first");
+    assert_eq!(answer(&client, ask("Hi")).await, "first");
+    assert_eq!(answer(&client, ask("Bye")).await, "second");
+
+    assert_eq!(mock.requests().len(), 2);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 2));
+}
+
+#[tokio::test]
+async fn sampled_requests_are_not_cached() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"));
+    let cache = ResponseCache::new();
+    let client = client(&mock, &cache);
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("Hi"))
+        .temperature(0.8);
+    assert_eq!(answer(&client, request.clone()).await, "first");
+    assert_eq!(answer(&client, request).await, "second");
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn seeded_requests_are_cached() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"))
+        .json(
+            "/v1/completions",
+            json!({
+                "id": "cmpl-1", "object": "text_completion", "created": 0, "model": "test-model",
+                "content": "world"
+            }),
+        );
+    let cache = ResponseCache::new();
+    let client = client(&mock, &cache);
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("Hi"))
+        .temperature(0.8)
+        .seed(7);
+    assert_eq!(answer(&client, request.clone()).await, "first");
+    assert_eq!(answer(&client, request.clone()).await, "first");
+    // Another seed samples differently
+    assert_eq!(answer(&client, request.seed(8)).await, "second");
+
+    let request = CompletionRequest::new("test-model", "Hello")
+        .temperature(0.8)
+        .seed(7);
+    client.completion(request.clone()).await.unwrap();
+    client.completion(request).await.unwrap();
+    assert_eq!(mock.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn parameters_are_part_of_the_key() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("ok"));
+    let cache = ResponseCache::new();
+    let client = client(&mock, &cache);
+
+    answer(&client, ask("Hi")).await;
+    answer(&client, ask("Hi").max_tokens(10)).await;
+    answer(&client, ask("Hi").max_tokens(10)).await;
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn expired_entries_are_refetched() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"));
+    let cache = ResponseCache::new().ttl(Duration::from_millis(30));
+    let client = client(&mock, &cache);
+
+    assert_eq!(answer(&client, ask("Hi")).await, "first");
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    assert_eq!(answer(&client, ask("Hi")).await, "second");
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("ok"));
+    let cache = ResponseCache::new().max_entries(2);
+    let client = client(&mock, &cache);
+
+    answer(&client, ask("a")).await;
+    answer(&client, ask("b")).await;
+    answer(&client, ask("a")).await;
+    answer(&client, ask("c")).await;
+    assert_eq!(cache.len(), 2);
+    assert_eq!(mock.requests().len(), 3);
+
+    // "b" was evicted, "a" was kept
+    answer(&client, ask("a")).await;
+    answer(&client, ask("b")).await;
+    assert_eq!(mock.requests().len(), 4);
+}
+
+#[tokio::test]
+async fn caches_text_completions() {
+    let mock = MockTransport::new().json(
+        "/v1/completions",
+        json!({
+            "id": "cmpl-1", "object": "text_completion", "created": 0, "model": "test-model",
+            "content": "world"
+        }),
+    );
+    let cache = ResponseCache::new();
+    let client = client(&mock, &cache);
+
+    let request = CompletionRequest::new("test-model", "Hello").temperature(0.0);
+    client.completion(request.clone()).await.unwrap();
+    client.completion(request).await.unwrap();
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn caches_embeddings() {
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        json!({
+            "object": "list", "model": "embed",
+            "data": [{ "object": "embedding", "index": 0, "embedding": [0.5, 0.5] }],
+            "usage": { "prompt_tokens": 2, "total_tokens": 2 }
+        }),
+    );
+    let cache = ResponseCache::new();
+    let client = client(&mock, &cache);
+
+    let request = EmbeddingRequest::new("embed", "hello");
+    let first = client.embedding(request.clone()).await.unwrap();
+    let second = client.embedding(request).await.unwrap();
+    assert_eq!(first.data[0].embedding, second.data[0].embedding);
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn disk_cache_survives_restart() {
+    let dir = cache_dir("restart");
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"));
+
+    let cache = ResponseCache::new().directory(&dir);
+    assert_eq!(answer(&client(&mock, &cache), ask("Hi")).await, "first");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // A fresh cache over the same directory, as after a restart
+    let cache = ResponseCache::new().directory(&dir);
+    assert_eq!(answer(&client(&mock, &cache), ask("Hi")).await, "first");
+    assert_eq!(mock.requests().len(), 1);
+
+    cache.clear();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn disk_cache_respects_ttl() {
+    let dir = cache_dir("ttl");
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"));
+
+    let cache = ResponseCache::new().directory(&dir);
+    answer(&client(&mock, &cache), ask("Hi")).await;
+
+    let cache = ResponseCache::new().directory(&dir).ttl(Duration::ZERO);
+    assert_eq!(answer(&client(&mock, &cache), ask("Hi")).await, "second");
+    assert_eq!(mock.requests().len(), 2);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,99 @@
+//! Splitting text with `lancor::chunking`.
+
+#![cfg(feature = "chunking")]
+
+use lancor::chunking;
+use lancor::transport::MockTransport;
+use lancor::{LlamaCppClient, TokenCounter};
+use serde_json::json;
+
+const TEXT: &str = "The server starts. It loads the model! Does it work? Yes.\n\nA new paragraph";
+
+#[test]
+fn splits_sentences() {
+    assert_eq!(
+        chunking::split_sentences(TEXT),
+        [
+            "The server starts.",
+            "It loads the model!",
+            "Does it work?",
+            "Yes.",
+            "A new paragraph"
+        ]
+    );
+    assert_eq!(
+        chunking::split_sentences("Pi is 3.14, \"roughly.\" Done"),
+        ["Pi is 3.14, \"roughly.\"", "Done"]
+    );
+}
+
+#[test]
+fn characters_respect_size_and_overlap() {
+    let text = "one two three four five six seven eight nine ten";
+    let chunks = chunking::by_characters(text, 15, 5);
+    assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 15));
+    assert_eq!(chunks.first().unwrap(), "one two three");
+    assert!(chunks.last().unwrap().ends_with("ten"));
+}
+
+#[test]
+fn sentences_are_packed_whole() {
+    let chunks = chunking::by_sentences(TEXT, 40, 0);
+    assert_eq!(
+        chunks,
+        [
+            "The server starts. It loads the model!",
+            "Does it work? Yes.\n\nA new paragraph"
+        ]
+    );
+}
+
+#[test]
+fn sentence_overlap_repeats_trailing_sentences() {
+    let chunks = chunking::by_sentences(TEXT, 40, 20);
+    assert_eq!(chunks[0], "The server starts. It loads the model!");
+    assert!(chunks[1].starts_with("It loads the model!"));
+}
+
+#[test]
+fn long_sentences_fall_back_to_words() {
+    let text = "aaaa bbbb cccc dddd eeee ffff";
+    let chunks = chunking::by_sentences(text, 10, 0);
+    assert_eq!(chunks, ["aaaa bbbb", "cccc dddd", "eeee ffff"]);
+}
+
+#[test]
+fn estimated_tokens() {
+    // 4, 5, 4, 2 and 3 tokens by token_estimate
+    let chunks = chunking::by_estimated_tokens(TEXT, 10, 0);
+    assert_eq!(
+        chunks,
+        [
+            "The server starts. It loads the model!",
+            "Does it work? Yes.\n\nA new paragraph"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn server_token_counts() {
+    // Every sentence counts as 3 tokens
+    let mock = MockTransport::new().json("/tokenize", json!({ "tokens": [1, 2, 3] }));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let chunks = chunking::by_tokens(&client, TokenCounter::Server, TEXT, 6, 3)
+        .await
+        .unwrap();
+    assert_eq!(
+        chunks,
+        [
+            "The server starts. It loads the model!",
+            "It loads the model! Does it work?",
+            "Does it work? Yes.",
+            "Yes.\n\nA new paragraph"
+        ]
+    );
+    assert_eq!(mock.requests().len(), 5);
+}
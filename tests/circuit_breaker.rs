@@ -0,0 +1,110 @@
+//! Failing fast on servers whose circuit is open.
+
+mod common;
+
+use common::chat_response;
+use lancor::transport::MockTransport;
+use lancor::{
+    ChatCompletionRequest, CircuitBreaker, CircuitState, LancorConfig, LlamaCppClient, Message,
+};
+use std::time::Duration;
+
+const PRIMARY: &str = "http://gpu:8080";
+const FALLBACK: &str = "http://cpu:8080";
+
+async fn ask(client: &LlamaCppClient) -> anyhow::Result<String> {
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+    let response = client.chat_completion(request).await?;
+    Ok(response.choices[0].message.content.text())
+}
+
+fn circuit(client: &LlamaCppClient) -> CircuitState {
+    client.endpoint_status()[0].circuit
+}
+
+#[tokio::test]
+async fn opens_after_consecutive_failures() {
+    let mock = MockTransport::new().respond("/v1/chat/completions", 503, "overloaded");
+    let client = LlamaCppClient::new(PRIMARY)
+        .unwrap()
+        .with_transport(mock.clone())
+        .with_circuit_breaker(CircuitBreaker::new(2, Duration::from_secs(60)));
+
+    assert!(ask(&client).await.is_err());
+    assert_eq!(circuit(&client), CircuitState::Closed);
+    assert!(ask(&client).await.is_err());
+    assert_eq!(circuit(&client), CircuitState::Open);
+
+    let err = ask(&client).await.unwrap_err();
+    assert!(err.to_string().contains("Circuit breaker open"), "{}", err);
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn closes_after_successful_probe() {
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 500, "crashed")
+        .json("/v1/chat/completions", chat_response("back"));
+    let client = LlamaCppClient::new(PRIMARY)
+        .unwrap()
+        .with_transport(mock.clone())
+        .with_circuit_breaker(CircuitBreaker::new(1, Duration::ZERO));
+
+    assert!(ask(&client).await.is_err());
+    assert_eq!(circuit(&client), CircuitState::HalfOpen);
+    assert_eq!(ask(&client).await.unwrap(), "back");
+    assert_eq!(circuit(&client), CircuitState::Closed);
+}
+
+#[tokio::test]
+async fn failed_probe_reopens() {
+    let mock = MockTransport::new().respond("/v1/chat/completions", 502, "bad gateway");
+    let client = LlamaCppClient::new(PRIMARY)
+        .unwrap()
+        .with_transport(mock.clone())
+        .with_circuit_breaker(CircuitBreaker::new(1, Duration::from_millis(50)));
+
+    assert!(ask(&client).await.is_err());
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(circuit(&client), CircuitState::HalfOpen);
+
+    assert!(ask(&client).await.is_err());
+    assert_eq!(circuit(&client), CircuitState::Open);
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn skips_open_server_for_fallback() {
+    let mock = MockTransport::new()
+        .respond(format!("{}/v1/chat/completions", PRIMARY), 503, "busy")
+        .json(
+            format!("{}/v1/chat/completions", FALLBACK),
+            chat_response("from cpu"),
+        );
+    let config = LancorConfig::new(PRIMARY)
+        .fallback_url(FALLBACK)
+        .failover_cooldown(Duration::ZERO);
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone())
+        .with_circuit_breaker(CircuitBreaker::new(1, Duration::from_secs(60)));
+
+    assert_eq!(ask(&client).await.unwrap(), "from cpu");
+    assert_eq!(ask(&client).await.unwrap(), "from cpu");
+    // The cooldown has passed, but the open circuit keeps the primary out
+    assert_eq!(mock.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn client_errors_do_not_count() {
+    let mock = MockTransport::new().respond("/v1/chat/completions", 400, "bad request");
+    let client = LlamaCppClient::new(PRIMARY)
+        .unwrap()
+        .with_transport(mock.clone())
+        .with_circuit_breaker(CircuitBreaker::new(1, Duration::from_secs(60)));
+
+    assert!(ask(&client).await.is_err());
+    assert!(ask(&client).await.is_err());
+    assert_eq!(circuit(&client), CircuitState::Closed);
+    assert_eq!(mock.requests().len(), 2);
+}
@@ -0,0 +1,61 @@
+//! The `lancor` command line tool.
+
+#![cfg(feature = "cli")]
+
+use std::process::{Command, Stdio};
+
+#[test]
+fn api_key_option_overrides_the_profile_variable() {
+    let path = std::env::temp_dir().join(format!("lancor-cli-{}.toml", std::process::id()));
+    // Nothing listens on port 9, so the request fails after the client is
+    // built
+    std::fs::write(
+        &path,
+        "[profiles.work]\nbase_url = \"http://127.0.0.1:9\"\napi_key_env = \"LANCOR_TEST_SURELY_UNSET_KEY\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lancor"))
+        .args(["--profile", "work", "--api-key", "cli-key", "ask", "Hello"])
+        .env("LANCOR_CONFIG", &path)
+        .env_remove("LANCOR_TEST_SURELY_UNSET_KEY")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(!stderr.contains("is not set"), "{}", stderr);
+}
+
+#[test]
+fn a_broken_config_only_fails_commands_that_name_a_profile() {
+    let path = std::env::temp_dir().join(format!("lancor-cli-broken-{}.toml", std::process::id()));
+    std::fs::write(&path, "[profiles.work\nbase_url = ").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lancor"))
+            .args(args)
+            .env("LANCOR_CONFIG", &path)
+            .env_remove("LANCOR_PROFILE")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--help"]);
+    assert!(output.status.success());
+
+    // Nothing listens on port 9, so the command gets as far as the request
+    let output = run(&["--url", "http://127.0.0.1:9", "ask", "Hello"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning: ignoring profiles"), "{}", stderr);
+    assert!(stderr.contains("127.0.0.1:9"), "{}", stderr);
+
+    let output = run(&["--profile", "work", "ask", "Hello"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+    assert!(stderr.contains("Invalid config"), "{}", stderr);
+    std::fs::remove_file(&path).unwrap();
+}
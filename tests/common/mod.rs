@@ -0,0 +1,54 @@
+//! Fixtures shared by the integration tests.
+//!
+//! Each test file is its own crate and uses only some of these, hence the
+//! `dead_code` allowance.
+
+#![allow(dead_code)]
+
+use lancor::transport::Transport;
+use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+use serde_json::{Value, json};
+
+/// A chat completion from `test-model` whose only choice is `message`
+pub fn reply(message: Value) -> Value {
+    json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }]
+    })
+}
+
+/// A chat completion answering `content`, using 3 prompt and 1 completion
+/// tokens
+pub fn chat_response(content: &str) -> Value {
+    chat_response_with_usage(content, 3, 1)
+}
+
+/// A chat completion answering `content` with the given token usage
+pub fn chat_response_with_usage(
+    content: &str,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+) -> Value {
+    let mut response = reply(json!({ "role": "assistant", "content": content }));
+    response["usage"] = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens
+    });
+    response
+}
+
+/// A client for the default base URL that sends through `transport`
+pub fn client(transport: &(impl Transport + Clone + 'static)) -> LlamaCppClient {
+    LlamaCppClient::default()
+        .unwrap()
+        .with_transport(transport.clone())
+}
+
+/// "Hi" to `test-model`
+pub fn request() -> ChatCompletionRequest {
+    ChatCompletionRequest::new("test-model").message(Message::user("Hi"))
+}
@@ -0,0 +1,186 @@
+//! Decoding compressed responses, against a local HTTP server.
+
+#![cfg(all(feature = "gzip", feature = "deflate", feature = "zstd"))]
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::StreamExt;
+use lancor::{ChatCompletionRequest, EmbeddingRequest, LlamaCppClient, Message, TokenizeRequest};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+/// A 384-dimension embedding response, gzip-compressed
+const EMBEDDING_GZIP: &str = concat!(
+    "H4sIAAAAAAACA+1Xy26DMBD8FcQ5jvbFq79SVRUJqKINIWqoVCnKvxfvYvC1d19ieccZ1rMzBz/y",
+    "6fTZn+f8Jcsvw33OD1netXO77F8fEdaPp77rhuuHP7BvllMOjsUhW36RuTpkcCSmSgsCqCuwKMCM",
+    "BjCQ30Mp4ldhsDoB+z2WLEZQ+YKnBj1IJRnAFRoAUChziQZQRUoBjaBSl2AAVmzcDVtTIlAaRSWK",
+    "UEOGsGCpHCS1kjdoAAkpgMQKSLNeEIUVIKLayBEaIxdRhAkNYcRGyQvRzoXAAEJSAAs2OaX2Knl2",
+    "ZEWoIEO4RkMARREu0BCqyZStRVcpYJ1NzVqw1UVIOGp/dYEsIg9fs+/v/ewNasvhCi66VLil3doF",
+    "HXZdNqFMuiCli8Vd1Tb1XTSPbUA6MBdGuI90m7FNPbjARb4IRjHjuGCl3VnBaea9zYq7NdWsq3dd",
+    "ZObgbl5VMvfvaQjpsLiE+KQ0pTSlNKU0pTSlNKU0pTSlNP0/TW/LE2W4dv3v8jyBp9+NU9df/DPm",
+    "Oo3D2T9hfu7tR79UHvntexpv8/s8ffXX+1JZXJTP09xeotLz+QeEkdCsKA0AAA==",
+);
+
+/// A tokenize response, zlib-compressed
+const TOKENS_DEFLATE: &str = "eNqrVirJz07NK1ayUog21FEw0lEw1lEw0VEw1VEw01Ew11GwiK0FALBjCJs=";
+
+/// A tokenize response, zstd-compressed
+const TOKENS_ZSTD: &str = "KLUv/QRYmQAAeyJ0b2tlbnMiOlsxLDIsM119Crop+pA=";
+
+/// The first event of a gzip-compressed stream, flushed
+const STREAM_FIRST_GZIP: &str = concat!(
+    "H4sIAAAAAAACAyyNQQoCMQxF93OKkrUMrucE3kFEYhpptU3EZkCQ3t20uPy89/+PaLiFL+QIWwCC",
+    "QwC9PZhsxoS2ktZXYcsqK6VdnkOhN6PxqBw9VY1chl8nS5qJm+ezz0rkz19zyRDGGakYy7w4ebM7",
+    "vGfJLV19t6k4kL2UfunL8gMAAP//",
+);
+
+/// The rest of the stream
+const STREAM_REST_GZIP: &str =
+    "ShlgB+bkA2XwuhAAAAD//xpoF5bnF+Wk4HciAAAA//+DODHaxd/PNZaLCwCyayjy9AEAAA==";
+
+fn decode(base64: &str) -> Vec<u8> {
+    BASE64.decode(base64).unwrap()
+}
+
+/// Serve one request with `encoding` and `body`, recording the request head
+async fn serve(encoding: &'static str, body: Vec<u8>) -> (String, Arc<Mutex<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let head = Arc::new(Mutex::new(String::new()));
+    let recorded = head.clone();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        *recorded.lock().unwrap() = read_head(&mut socket).await;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Encoding: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            encoding,
+            body.len()
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        socket.write_all(&body).await.unwrap();
+    });
+    (url, head)
+}
+
+async fn read_head(socket: &mut tokio::net::TcpStream) -> String {
+    let mut buf = vec![0u8; 8192];
+    let n = socket.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+#[tokio::test]
+async fn gzip_responses_are_decoded() {
+    let (url, head) = serve("gzip", decode(EMBEDDING_GZIP)).await;
+    let client = LlamaCppClient::new(url).unwrap();
+
+    let response = client
+        .embedding(EmbeddingRequest::new("nomic", "text"))
+        .await
+        .unwrap();
+    assert_eq!(response.data[0].embedding.len(), 384);
+    assert_eq!(response.data[0].embedding[1], -0.1337);
+
+    let head = head.lock().unwrap().to_ascii_lowercase();
+    let accepted = head
+        .lines()
+        .find_map(|line| line.strip_prefix("accept-encoding: "))
+        .unwrap();
+    for encoding in ["gzip", "deflate", "zstd"] {
+        assert!(accepted.contains(encoding), "{}", head);
+    }
+}
+
+#[tokio::test]
+async fn deflate_responses_are_decoded() {
+    let (url, _) = serve("deflate", decode(TOKENS_DEFLATE)).await;
+    let client = LlamaCppClient::new(url).unwrap();
+
+    let response = client.tokenize(TokenizeRequest::new("text")).await.unwrap();
+    assert_eq!(response.tokens, [1, 2, 3, 4, 5, 6, 7, 8]);
+}
+
+#[tokio::test]
+async fn zstd_responses_are_decoded() {
+    let (url, _) = serve("zstd", decode(TOKENS_ZSTD)).await;
+    let client = LlamaCppClient::new(url).unwrap();
+
+    let response = client.tokenize(TokenizeRequest::new("text")).await.unwrap();
+    assert_eq!(response.tokens, [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn other_encodings_pass_through() {
+    let (url, _) = serve("identity", br#"{"tokens":[4,5]}"#.to_vec()).await;
+    let client = LlamaCppClient::new(url).unwrap();
+
+    let response = client.tokenize(TokenizeRequest::new("text")).await.unwrap();
+    assert_eq!(response.tokens, [4, 5]);
+}
+
+#[tokio::test]
+async fn corrupt_responses_are_errors() {
+    let mut body = decode(EMBEDDING_GZIP);
+    let crc = body.len() - 8;
+    body[crc] ^= 0xff;
+    let (url, _) = serve("gzip", body).await;
+    let client = LlamaCppClient::new(url).unwrap();
+
+    let err = client
+        .embedding(EmbeddingRequest::new("nomic", "text"))
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("decoding response body"),
+        "{:#}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn compressed_streams_arrive_event_by_event() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (resume, resumed) = oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        read_head(&mut socket).await;
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n";
+        socket.write_all(head.as_bytes()).await.unwrap();
+        write_chunk(&mut socket, &decode(STREAM_FIRST_GZIP)).await;
+        // Hold the rest back until the first event has been read
+        let _ = resumed.await;
+        write_chunk(&mut socket, &decode(STREAM_REST_GZIP)).await;
+        socket.write_all(b"0\r\n\r\n").await.unwrap();
+    });
+
+    let client = LlamaCppClient::new(url).unwrap();
+    let request = ChatCompletionRequest::new("m").message(Message::user("Hi"));
+    let mut stream = Box::pin(client.chat_completion_stream(request).await.unwrap());
+
+    let first = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.choices[0].delta.content.as_deref(), Some("Hel"));
+    resume.send(()).unwrap();
+
+    let mut text = String::from("Hel");
+    while let Some(chunk) = stream.next().await {
+        text.push_str(
+            chunk.unwrap().choices[0]
+                .delta
+                .content
+                .as_deref()
+                .unwrap_or(""),
+        );
+    }
+    assert_eq!(text, "Hello, world");
+}
+
+async fn write_chunk(socket: &mut tokio::net::TcpStream, data: &[u8]) {
+    socket
+        .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+        .await
+        .unwrap();
+    socket.write_all(data).await.unwrap();
+    socket.write_all(b"\r\n").await.unwrap();
+}
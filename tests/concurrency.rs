@@ -0,0 +1,105 @@
+//! Queueing requests client-side with `max_concurrent_requests`.
+
+mod common;
+
+use common::{chat_response, request};
+use lancor::LlamaCppClient;
+use lancor::transport::{HttpRequest, HttpResponse, MockTransport, Transport};
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Answers after a delay and remembers the highest number of requests it
+/// saw at once
+#[derive(Debug, Clone)]
+struct SlowTransport {
+    inner: MockTransport,
+    current: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+impl Transport for SlowTransport {
+    fn send(&self, request: HttpRequest) -> lancor::BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        Box::pin(async move {
+            let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            self.inner.send(request).await
+        })
+    }
+}
+
+#[tokio::test]
+async fn limits_requests_in_progress() {
+    let transport = SlowTransport {
+        inner: MockTransport::new().json("/v1/chat/completions", chat_response("Hi")),
+        current: Arc::default(),
+        peak: Arc::default(),
+    };
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(transport.clone())
+        .max_concurrent_requests(2);
+
+    let requests = (0..6).map(|_| client.chat_completion(request()));
+    for result in futures::future::join_all(requests).await {
+        result.unwrap();
+    }
+    assert_eq!(transport.peak.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn a_limit_of_zero_allows_one_request_at_a_time() {
+    let transport = SlowTransport {
+        inner: MockTransport::new().json("/v1/chat/completions", chat_response("Hi")),
+        current: Arc::default(),
+        peak: Arc::default(),
+    };
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(transport.clone())
+        .max_concurrent_requests(0);
+
+    let requests = (0..3).map(|_| client.chat_completion(request()));
+    let results = tokio::time::timeout(Duration::from_secs(5), futures::future::join_all(requests))
+        .await
+        .unwrap();
+    for result in results {
+        result.unwrap();
+    }
+    assert_eq!(transport.peak.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn streams_hold_their_slot_and_queue_times_out() {
+    use futures::stream::StreamExt;
+
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }]
+    });
+    let mock = MockTransport::new()
+        .sse("/v1/chat/completions", vec![chunk])
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock)
+        .max_concurrent_requests(1)
+        .queue_timeout(Duration::from_millis(50));
+
+    let stream = client
+        .chat_completion_stream(request().stream(true))
+        .await
+        .unwrap();
+    let err = client.chat_completion(request()).await.unwrap_err();
+    assert!(err.to_string().contains("Timed out"), "{}", err);
+
+    stream.collect::<Vec<_>>().await;
+    client.chat_completion(request()).await.unwrap();
+}
@@ -0,0 +1,123 @@
+//! Reusing embedding vectors across requests and runs.
+
+#![cfg(feature = "embedding-cache")]
+
+mod common;
+
+use lancor::transport::MockTransport;
+use lancor::{EmbeddingCache, EmbeddingRequest, LlamaCppClient};
+use serde_json::json;
+
+fn embedding_response(vector: &[f32]) -> serde_json::Value {
+    json!({
+        "object": "list",
+        "model": "embed",
+        "data": [{ "object": "embedding", "index": 0, "embedding": vector }],
+        "usage": { "prompt_tokens": 2, "total_tokens": 2 }
+    })
+}
+
+fn client(mock: &MockTransport, cache: &EmbeddingCache) -> LlamaCppClient {
+    common::client(mock).with_embedding_cache(cache.clone())
+}
+
+async fn embed(client: &LlamaCppClient, model: &str, text: &str) -> Vec<f32> {
+    let response = client
+        .embedding(EmbeddingRequest::new(model, text))
+        .await
+        .unwrap();
+    response.data[0].embedding.clone()
+}
+
+#[tokio::test]
+async fn unchanged_texts_are_not_re_embedded() {
+    let mock = MockTransport::new()
+        .json("/v1/embeddings", embedding_response(&[1.0, 0.0]))
+        .json("/v1/embeddings", embedding_response(&[0.0, 1.0]));
+    let cache = EmbeddingCache::new();
+    let client = client(&mock, &cache);
+
+    assert_eq!(embed(&client, "embed", "a").await, [1.0, 0.0]);
+    assert_eq!(embed(&client, "embed", "b").await, [0.0, 1.0]);
+    assert_eq!(embed(&client, "embed", "a").await, [1.0, 0.0]);
+    assert_eq!(mock.requests().len(), 2);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 2));
+}
+
+#[tokio::test]
+async fn models_are_kept_apart() {
+    let mock = MockTransport::new().json("/v1/embeddings", embedding_response(&[1.0]));
+    let cache = EmbeddingCache::new();
+    let client = client(&mock, &cache);
+
+    embed(&client, "small", "a").await;
+    embed(&client, "large", "a").await;
+    assert_eq!(mock.requests().len(), 2);
+    assert_eq!(cache.get("small", "a"), Some(vec![1.0]));
+    assert_eq!(cache.get("small", "b"), None);
+
+    // Where the model ends and the text begins is part of the key
+    cache.insert("ab", "c", vec![2.0]);
+    assert_eq!(cache.get("a", "bc"), None);
+}
+
+#[tokio::test]
+async fn file_cache_survives_restart() {
+    let path = std::env::temp_dir().join(format!(
+        "lancor-embedding-cache-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let mock = MockTransport::new().json("/v1/embeddings", embedding_response(&[0.25, 0.75]));
+
+    let cache = EmbeddingCache::open(&path).unwrap();
+    embed(&client(&mock, &cache), "embed", "hello").await;
+
+    let cache = EmbeddingCache::open(&path).unwrap();
+    assert_eq!(cache.len(), 1);
+    assert_eq!(
+        embed(&client(&mock, &cache), "embed", "hello").await,
+        [0.25, 0.75]
+    );
+    assert_eq!(mock.requests().len(), 1);
+
+    // The file holds digests, not the text
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(!text.contains("hello"));
+    let line: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+    assert_eq!(line["key"].as_str().unwrap().len(), 64);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn batches_send_only_uncached_texts() {
+    let mock = MockTransport::new()
+        .json("/v1/embeddings", embedding_response(&[1.0, 0.0]))
+        .json(
+            "/v1/embeddings",
+            json!({
+                "object": "list",
+                "model": "embed",
+                "data": [
+                    { "object": "embedding", "index": 1, "embedding": [0.0, 3.0] },
+                    { "object": "embedding", "index": 0, "embedding": [0.0, 2.0] }
+                ],
+                "usage": { "prompt_tokens": 4, "total_tokens": 4 }
+            }),
+        );
+    let cache = EmbeddingCache::new();
+    let client = client(&mock, &cache);
+
+    embed(&client, "embed", "b").await;
+    let vectors = client
+        .embedding_batch("embed", &["a", "b", "c"])
+        .await
+        .unwrap();
+    assert_eq!(vectors, [vec![0.0, 2.0], vec![1.0, 0.0], vec![0.0, 3.0]]);
+
+    let sent: serde_json::Value = mock.requests()[1].json().unwrap();
+    assert_eq!(sent["input"], json!(["a", "c"]));
+    assert_eq!(cache.get("embed", "c"), Some(vec![0.0, 3.0]));
+}
@@ -0,0 +1,128 @@
+//! Vector math in `lancor::embeddings::math`.
+
+use lancor::EmbeddingData;
+use lancor::embeddings::math;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+#[test]
+fn dot_norm_and_cosine() {
+    assert_eq!(math::dot(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]), 32.0);
+    assert_eq!(math::l2_norm(&[3.0, 4.0]), 5.0);
+    assert!(close(
+        math::cosine_similarity(&[1.0, 0.0], &[1.0, 1.0]),
+        0.5f32.sqrt()
+    ));
+    assert_eq!(math::cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+}
+
+#[test]
+fn normalizes_to_unit_length() {
+    let mut v = vec![3.0, 4.0];
+    math::normalize(&mut v);
+    assert_eq!(v, [0.6, 0.8]);
+
+    let mut zero = vec![0.0, 0.0];
+    math::normalize(&mut zero);
+    assert_eq!(zero, [0.0, 0.0]);
+    assert!(close(
+        math::l2_norm(&math::normalized(&[1.0, 1.0, 1.0])),
+        1.0
+    ));
+}
+
+#[test]
+fn top_k_ranks_best_first() {
+    let candidates = vec![
+        vec![0.0, 1.0],
+        vec![1.0, 0.1],
+        vec![1.0, 0.0],
+        vec![-1.0, 0.0],
+    ];
+    let hits = math::top_k(&[1.0, 0.0], &candidates, 2);
+    assert_eq!(hits.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [2, 1]);
+    assert!(close(hits[0].1, 1.0));
+
+    assert_eq!(math::top_k(&[1.0, 0.0], &candidates, 10).len(), 4);
+}
+
+#[test]
+fn pairwise_and_matrix() {
+    let vectors = [vec![1.0, 0.0], vec![0.0, 1.0]];
+    let results = math::pairwise_top_k(&vectors, &vectors, 1);
+    assert_eq!(results, [vec![(0, 1.0)], vec![(1, 1.0)]]);
+
+    let matrix = math::similarity_matrix(&vectors);
+    assert_eq!(matrix, [vec![1.0, 0.0], vec![0.0, 1.0]]);
+}
+
+#[test]
+fn works_on_embedding_data() {
+    let data: Vec<EmbeddingData> = serde_json::from_value(serde_json::json!([
+        { "object": "embedding", "index": 0, "embedding": [1.0, 0.0] },
+        { "object": "embedding", "index": 1, "embedding": [0.0, 1.0] }
+    ]))
+    .unwrap();
+    let hits = math::top_k(data[1].as_ref(), &data, 1);
+    assert_eq!(hits[0].0, 1);
+}
+
+/// Deterministic pseudo-random vectors, odd-length so the tail is exercised
+fn vectors(count: usize, dims: usize) -> Vec<Vec<f32>> {
+    let mut state = 0x2545_f491_u32;
+    (0..count)
+        .map(|_| {
+            (0..dims)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (state % 2000) as f32 / 1000.0 - 1.0
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn long_vectors_match_a_plain_sum() {
+    let v = vectors(2, 389);
+    let naive_dot: f64 = v[0].iter().zip(&v[1]).map(|(x, y)| (x * y) as f64).sum();
+    let naive_norm = |v: &[f32]| v.iter().map(|x| (x * x) as f64).sum::<f64>().sqrt();
+    let naive_cosine = naive_dot / (naive_norm(&v[0]) * naive_norm(&v[1]));
+
+    assert!((math::dot(&v[0], &v[1]) as f64 - naive_dot).abs() < 1e-3);
+    assert!((math::cosine_similarity(&v[0], &v[1]) as f64 - naive_cosine).abs() < 1e-5);
+    // Still the common prefix when lengths differ
+    assert!(close(
+        math::cosine_similarity(&v[0][..100], &v[1]),
+        math::cosine_similarity(&v[0][..100], &v[1][..100]) * math::l2_norm(&v[1][..100])
+            / math::l2_norm(&v[1])
+    ));
+}
+
+#[test]
+fn top_k_agrees_with_a_full_sort() {
+    let candidates = vectors(500, 37);
+    let query = &vectors(1, 37)[0];
+    let mut all: Vec<(usize, f32)> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i, math::cosine_similarity(query, c)))
+        .collect();
+    all.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    assert_eq!(math::top_k(query, &candidates, 10), all[..10]);
+    assert_eq!(math::top_k(query, &candidates, 1000).len(), 500);
+    assert!(math::top_k(query, &candidates, 0).is_empty());
+
+    // Ties keep candidate order
+    let same = vec![vec![1.0, 0.0]; 4];
+    let hits: Vec<usize> = math::top_k(&[1.0, 0.0], &same, 2)
+        .into_iter()
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(hits, [0, 1]);
+}
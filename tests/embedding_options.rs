@@ -0,0 +1,111 @@
+//! Truncation, normalization and base64 encoding of embeddings.
+
+mod common;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use common::client;
+#[cfg(feature = "embedding-cache")]
+use lancor::EmbeddingCache;
+use lancor::transport::MockTransport;
+use lancor::{EmbeddingRequest, EncodingFormat};
+use serde_json::{Value, json};
+
+fn embedding_response(embedding: Value) -> Value {
+    json!({
+        "object": "list",
+        "model": "embed",
+        "data": [{ "object": "embedding", "index": 0, "embedding": embedding }],
+        "usage": { "prompt_tokens": 2, "total_tokens": 2 }
+    })
+}
+
+#[tokio::test]
+async fn base64_vectors_are_decoded() {
+    let vector = [0.25f32, -1.5, 3.0e-7, 42.0];
+    let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        embedding_response(json!(BASE64.encode(bytes))),
+    );
+
+    let request = EmbeddingRequest::new("embed", "hello").encoding_format(EncodingFormat::Base64);
+    let response = client(&mock).embedding(request).await.unwrap();
+    assert_eq!(response.data[0].embedding, vector);
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["encoding_format"], "base64");
+
+    // Neither option is sent unless set
+    let mock = MockTransport::new().json("/v1/embeddings", embedding_response(json!([1.0])));
+    let request = EmbeddingRequest::new("embed", "hello")
+        .truncate(1)
+        .normalize(true);
+    client(&mock).embedding(request).await.unwrap();
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body, json!({ "model": "embed", "input": "hello" }));
+}
+
+#[tokio::test]
+async fn malformed_base64_is_an_error() {
+    let mock = MockTransport::new()
+        .json("/v1/embeddings", embedding_response(json!("AAAA")))
+        .json("/v1/embeddings", embedding_response(json!("not base64!")));
+    let client = client(&mock);
+    for _ in 0..2 {
+        let err = client
+            .embedding(EmbeddingRequest::new("embed", "hello"))
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("embedding response"),
+            "{:#}",
+            err
+        );
+    }
+}
+
+#[tokio::test]
+async fn truncates_then_normalizes() {
+    let mock = MockTransport::new()
+        .json(
+            "/v1/embeddings",
+            embedding_response(json!([3.0, 4.0, 12.0])),
+        )
+        .json(
+            "/v1/embeddings",
+            embedding_response(json!([3.0, 4.0, 12.0])),
+        );
+    let client = client(&mock);
+
+    let request = EmbeddingRequest::new("embed", "a").truncate(2);
+    let response = client.embedding(request).await.unwrap();
+    assert_eq!(response.data[0].embedding, [3.0, 4.0]);
+
+    let request = EmbeddingRequest::new("embed", "b")
+        .truncate(2)
+        .normalize(true);
+    let response = client.embedding(request).await.unwrap();
+    assert_eq!(response.data[0].embedding, [0.6, 0.8]);
+}
+
+#[tokio::test]
+#[cfg(feature = "embedding-cache")]
+async fn cached_vectors_are_kept_whole() {
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        embedding_response(json!([3.0, 4.0, 12.0])),
+    );
+    let client = client(&mock).with_embedding_cache(EmbeddingCache::new());
+
+    let request = EmbeddingRequest::new("embed", "a").truncate(1);
+    let response = client.embedding(request).await.unwrap();
+    assert_eq!(response.data[0].embedding, [3.0]);
+
+    let request = EmbeddingRequest::new("embed", "a").normalize(true);
+    let response = client.embedding(request).await.unwrap();
+    assert_eq!(
+        response.data[0].embedding,
+        [3.0 / 13.0, 4.0 / 13.0, 12.0 / 13.0]
+    );
+    assert_eq!(mock.requests().len(), 1);
+}
@@ -0,0 +1,97 @@
+//! Failing over between the servers of a [`LancorConfig`].
+
+mod common;
+
+use common::chat_response;
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, LancorConfig, LlamaCppClient, Message};
+use std::time::Duration;
+
+const PRIMARY: &str = "http://gpu:8080";
+const FALLBACK: &str = "http://cpu:8080";
+
+fn client(mock: &MockTransport, cooldown: Duration) -> LlamaCppClient {
+    let config = LancorConfig::new(PRIMARY)
+        .fallback_url(FALLBACK)
+        .failover_cooldown(cooldown);
+    LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone())
+}
+
+async fn ask(client: &LlamaCppClient) -> anyhow::Result<String> {
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+    let response = client.chat_completion(request).await?;
+    Ok(response.choices[0].message.content.text())
+}
+
+fn hosts(mock: &MockTransport) -> Vec<String> {
+    mock.requests()
+        .iter()
+        .map(|request| request.url.split('/').nth(2).unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn fails_over_on_server_errors_and_cools_down() {
+    let mock = MockTransport::new()
+        .respond(format!("{}/v1/chat/completions", PRIMARY), 503, "busy")
+        .json(
+            format!("{}/v1/chat/completions", FALLBACK),
+            chat_response("from cpu"),
+        );
+    let client = client(&mock, Duration::from_secs(60));
+
+    assert_eq!(ask(&client).await.unwrap(), "from cpu");
+    // The primary is cooling down, so it is not tried again
+    assert_eq!(ask(&client).await.unwrap(), "from cpu");
+    assert_eq!(hosts(&mock), ["gpu:8080", "cpu:8080", "cpu:8080"]);
+}
+
+#[tokio::test]
+async fn retries_primary_after_cooldown() {
+    let mock = MockTransport::new()
+        .respond(format!("{}/v1/chat/completions", PRIMARY), 500, "oops")
+        .json(
+            format!("{}/v1/chat/completions", PRIMARY),
+            chat_response("from gpu"),
+        )
+        .json(
+            format!("{}/v1/chat/completions", FALLBACK),
+            chat_response("from cpu"),
+        );
+    let client = client(&mock, Duration::ZERO);
+
+    assert_eq!(ask(&client).await.unwrap(), "from cpu");
+    assert_eq!(ask(&client).await.unwrap(), "from gpu");
+    assert_eq!(hosts(&mock), ["gpu:8080", "cpu:8080", "gpu:8080"]);
+}
+
+#[tokio::test]
+async fn client_errors_do_not_fail_over() {
+    let mock = MockTransport::new()
+        .respond(
+            format!("{}/v1/chat/completions", PRIMARY),
+            400,
+            "bad request",
+        )
+        .json(
+            format!("{}/v1/chat/completions", FALLBACK),
+            chat_response("from cpu"),
+        );
+    let client = client(&mock, Duration::from_secs(60));
+
+    let err = ask(&client).await.unwrap_err();
+    assert!(err.to_string().contains("400"), "{}", err);
+    assert_eq!(hosts(&mock), ["gpu:8080"]);
+}
+
+#[tokio::test]
+async fn reports_last_error_when_all_fail() {
+    let mock = MockTransport::new().respond("/v1/chat/completions", 502, "bad gateway");
+    let client = client(&mock, Duration::from_secs(60));
+
+    let err = ask(&client).await.unwrap_err();
+    assert!(err.to_string().contains("502"), "{}", err);
+    assert_eq!(hosts(&mock), ["gpu:8080", "cpu:8080"]);
+}
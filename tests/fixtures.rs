@@ -0,0 +1,116 @@
+//! Recording traffic with [`FixtureTransport`] and replaying it.
+
+mod common;
+
+use common::chat_response;
+use futures::stream::StreamExt;
+use lancor::fixtures::{FixtureMode, FixtureTransport};
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+use serde_json::json;
+use std::path::PathBuf;
+
+fn fixture_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lancor-fixtures-{}", std::process::id()));
+    let path = dir.join(format!("{}.json", name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn request(text: &str) -> ChatCompletionRequest {
+    ChatCompletionRequest::new("test-model").message(Message::user(text))
+}
+
+#[tokio::test]
+async fn replays_recorded_responses() {
+    let path = fixture_path("replay");
+    let server = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("one"))
+        .json("/v1/chat/completions", chat_response("two"));
+
+    let recorder = LlamaCppClient::with_api_key("http://gpu-box:8080", "secret")
+        .unwrap()
+        .with_transport(server.clone())
+        .with_fixtures(&path, FixtureMode::Auto)
+        .unwrap();
+    recorder.chat_completion(request("a")).await.unwrap();
+    recorder.chat_completion(request("b")).await.unwrap();
+    assert_eq!(server.requests().len(), 2);
+
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(!saved.contains("secret"));
+
+    // The fixture now exists, so Auto replays without reaching the server
+    let offline = MockTransport::new();
+    let replayer = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(offline.clone())
+        .with_fixtures(&path, FixtureMode::Auto)
+        .unwrap();
+    let b = replayer.chat_completion(request("b")).await.unwrap();
+    let a = replayer.chat_completion(request("a")).await.unwrap();
+    assert_eq!(a.choices[0].message.content.text(), "one");
+    assert_eq!(b.choices[0].message.content.text(), "two");
+    assert!(offline.requests().is_empty());
+
+    let err = replayer.chat_completion(request("c")).await.unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("No recorded response"),
+        "{:#}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn replays_streams() {
+    let path = fixture_path("stream");
+    let chunk = |content: &str| {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "test-model",
+            "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+        })
+    };
+    let server = MockTransport::new().sse("/v1/chat/completions", vec![chunk("Hi"), chunk("!")]);
+
+    let transport = FixtureTransport::new(server, &path, FixtureMode::Record).unwrap();
+    assert!(transport.is_recording());
+    let recorder = LlamaCppClient::default().unwrap().with_transport(transport);
+    let replayer = LlamaCppClient::default()
+        .unwrap()
+        .with_fixtures(&path, FixtureMode::Replay);
+    assert!(replayer.is_err(), "fixture does not exist yet");
+
+    let collect = |client: LlamaCppClient| async move {
+        let stream = client
+            .chat_completion_stream(request("hello").stream(true))
+            .await
+            .unwrap();
+        stream
+            .map(|chunk| chunk.unwrap().choices[0].delta.content.clone().unwrap())
+            .collect::<String>()
+            .await
+    };
+
+    assert_eq!(collect(recorder).await, "Hi!");
+    let replayer = LlamaCppClient::default()
+        .unwrap()
+        .with_fixtures(&path, FixtureMode::Replay)
+        .unwrap();
+    assert_eq!(collect(replayer).await, "Hi!");
+}
+
+#[test]
+fn parses_modes() {
+    assert_eq!(
+        "record".parse::<FixtureMode>().unwrap(),
+        FixtureMode::Record
+    );
+    assert_eq!(
+        "REPLAY".parse::<FixtureMode>().unwrap(),
+        FixtureMode::Replay
+    );
+    assert!("sometimes".parse::<FixtureMode>().is_err());
+}
@@ -0,0 +1,106 @@
+//! Gateways such as OpenRouter: extra headers and extended response fields.
+
+use futures::StreamExt;
+use lancor::transport::MockTransport;
+use lancor::{ApiError, ChatCompletionRequest, LancorConfig, LlamaCppClient, Message};
+use serde_json::json;
+
+fn client(mock: &MockTransport, config: LancorConfig) -> LlamaCppClient {
+    LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone())
+}
+
+fn request() -> ChatCompletionRequest {
+    ChatCompletionRequest::new("anthropic/claude-3.5-haiku").message(Message::user("Hi"))
+}
+
+#[tokio::test]
+async fn openrouter_sends_attribution_and_static_headers() {
+    let mock = MockTransport::new().json(
+        "/api/v1/chat/completions",
+        json!({
+            "id": "gen-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "anthropic/claude-3.5-haiku",
+            "provider": "Anthropic",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hello" },
+                "finish_reason": "stop",
+                "native_finish_reason": "end_turn"
+            }],
+            "usage": { "prompt_tokens": 8, "completion_tokens": 2, "total_tokens": 10, "cost": 0.00012 }
+        }),
+    );
+    let config = LancorConfig::openrouter("sk-or-test")
+        .referer("https://example.com")
+        .app_title("Example App")
+        .header("X-Team", "search");
+
+    let response = client(&mock, config)
+        .chat_completion(request())
+        .await
+        .unwrap();
+    assert_eq!(response.provider.as_deref(), Some("Anthropic"));
+    assert_eq!(
+        response.choices[0].native_finish_reason.as_deref(),
+        Some("end_turn")
+    );
+    assert_eq!(response.usage.cost, Some(0.00012));
+
+    let sent = &mock.requests()[0];
+    assert_eq!(sent.url, "https://openrouter.ai/api/v1/chat/completions");
+    assert_eq!(
+        sent.header_value("authorization"),
+        Some("Bearer sk-or-test")
+    );
+    assert_eq!(
+        sent.header_value("http-referer"),
+        Some("https://example.com")
+    );
+    assert_eq!(sent.header_value("x-title"), Some("Example App"));
+    assert_eq!(sent.header_value("x-team"), Some("search"));
+}
+
+#[tokio::test]
+async fn mid_stream_errors_become_api_errors() {
+    let body = concat!(
+        ": OPENROUTER PROCESSING\n\n",
+        "data: {\"id\":\"gen-1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"provider\":\"Groq\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"error\":{\"code\":502,\"message\":\"Provider returned error\"}}\n\n",
+    );
+    let mock = MockTransport::new().respond("/v1/chat/completions", 200, body);
+    let client = client(&mock, LancorConfig::default());
+
+    let results: Vec<_> = client
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .collect()
+        .await;
+
+    assert_eq!(results.len(), 2);
+    let first = results[0].as_ref().unwrap();
+    assert_eq!(first.provider.as_deref(), Some("Groq"));
+    assert_eq!(first.choices[0].delta.content.as_deref(), Some("Hel"));
+
+    let err = results[1].as_ref().unwrap_err();
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(api_error.status, 502);
+    assert_eq!(api_error.message, "Provider returned error");
+}
+
+#[test]
+fn static_headers_round_trip_through_config_files() {
+    let config: LancorConfig = serde_json::from_value(json!({
+        "base_url": "https://gateway.internal",
+        "headers": { "X-Tenant": "acme" }
+    }))
+    .unwrap();
+    assert_eq!(config.headers["X-Tenant"], "acme");
+
+    let serialized = serde_json::to_value(LancorConfig::default()).unwrap();
+    assert!(serialized.get("headers").is_none(), "{}", serialized);
+}
@@ -0,0 +1,97 @@
+//! Trimming history to a message, turn or token budget.
+
+use lancor::transport::MockTransport;
+use lancor::{HistoryPolicy, LlamaCppClient, Message, TokenCounter, token_estimate};
+use serde_json::json;
+
+#[test]
+fn token_estimate_follows_tokenizer_splits() {
+    assert_eq!(token_estimate(""), 0);
+    // Short words with their leading spaces and the full stop
+    assert_eq!(token_estimate("The server starts."), 4);
+    // Long words take more than one token
+    assert_eq!(token_estimate("internationalization"), 4);
+    // Digits go in groups of three
+    assert_eq!(token_estimate("1234567"), 3);
+    // Indentation and line breaks count, single spaces do not
+    assert_eq!(token_estimate("fn main() {\n    x\n}"), 10);
+    // CJK is about a token per character
+    assert_eq!(token_estimate("你好世界"), 4);
+}
+
+#[tokio::test]
+async fn token_budget_falls_back_to_estimates_without_tokenize() {
+    let mock = MockTransport::new().respond("/tokenize", 404, "Not Found");
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let system = Message::system("Be brief.");
+    let history = vec![
+        Message::user("first question about the weather in the mountains"),
+        Message::assistant("first answer"),
+        Message::user("second question"),
+    ];
+
+    let policy = HistoryPolicy::token_budget(20, TokenCounter::Server);
+    let messages = policy
+        .apply(&client, Some(&system), &history)
+        .await
+        .unwrap();
+
+    let contents: Vec<_> = messages.iter().map(|m| m.content.text()).collect();
+    assert_eq!(
+        contents,
+        ["Be brief.", "first answer", "second question"].map(String::from)
+    );
+    // Only the first count went to the server
+    assert_eq!(mock.requests().len(), 1);
+    assert_eq!(
+        mock.requests()[0].json::<serde_json::Value>().unwrap()["content"],
+        json!("Be brief.")
+    );
+}
+
+fn tool_exchange() -> Vec<Message> {
+    let call: Message = serde_json::from_value(json!({
+        "role": "assistant",
+        "content": null,
+        "tool_calls": [
+            { "id": "call_0", "type": "function", "function": { "name": "a", "arguments": "{}" } },
+            { "id": "call_1", "type": "function", "function": { "name": "b", "arguments": "{}" } }
+        ]
+    }))
+    .unwrap();
+    vec![
+        Message::user("Check a and b"),
+        call,
+        Message::tool("call_0", "a is fine"),
+        Message::tool("call_1", "b is fine"),
+    ]
+}
+
+fn roles(messages: &[Message]) -> Vec<&str> {
+    messages.iter().map(|m| m.role.as_str()).collect()
+}
+
+#[tokio::test]
+async fn windows_never_start_with_orphaned_tool_results() {
+    let client = LlamaCppClient::default().unwrap();
+    let system = Message::system("Be brief.");
+    let mut history = tool_exchange();
+    history.push(Message::assistant("Both are fine"));
+    history.push(Message::user("Thanks"));
+
+    // The last 4 messages would start at the second tool result
+    let messages = HistoryPolicy::LastMessages(4)
+        .apply(&client, Some(&system), &history)
+        .await
+        .unwrap();
+    assert_eq!(roles(&messages), ["system", "assistant", "user"]);
+
+    // With only tool results left after the cut, their call is kept
+    let messages = HistoryPolicy::LastMessages(1)
+        .apply(&client, Some(&system), &tool_exchange())
+        .await
+        .unwrap();
+    assert_eq!(roles(&messages), ["system", "assistant", "tool", "tool"]);
+}
@@ -0,0 +1,208 @@
+//! End-to-end tests against a real llama.cpp server.
+//!
+//! These only build with `--features integration`. Point them at a running
+//! server with `LANCOR_TEST_URL`, or set `LANCOR_TEST_DOCKER=1` to start the
+//! official llama.cpp server image with a small quantized model:
+//!
+//! ```bash
+//! LANCOR_TEST_DOCKER=1 cargo test --features integration --test integration
+//! ```
+//!
+//! The container is named `lancor-it` and is left running so later runs can
+//! reuse it; remove it with `docker rm -f lancor-it`. Without either variable
+//! every test is skipped.
+#![cfg(feature = "integration")]
+
+use futures::stream::StreamExt;
+use lancor::{
+    ChatCompletionRequest, ChatSession, CompletionRequest, EmbeddingRequest, LlamaCppClient,
+    Message, TokenizeRequest,
+};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+const CONTAINER: &str = "lancor-it";
+const DEFAULT_IMAGE: &str = "ghcr.io/ggml-org/llama.cpp:server";
+const DEFAULT_HF_REPO: &str = "ggml-org/SmolLM2-135M-Instruct-GGUF";
+const DEFAULT_PORT: u16 = 18181;
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Start (or reuse) the test container and return its base URL
+fn start_container() -> String {
+    let port = env("LANCOR_TEST_PORT")
+        .map(|p| p.parse().expect("LANCOR_TEST_PORT must be a port number"))
+        .unwrap_or(DEFAULT_PORT);
+
+    let running = Command::new("docker")
+        .args(["ps", "-q", "-f", &format!("name=^{}$", CONTAINER)])
+        .output()
+        .expect("failed to run docker");
+    if running.stdout.is_empty() {
+        let image = env("LANCOR_TEST_IMAGE").unwrap_or_else(|| DEFAULT_IMAGE.into());
+        let repo = env("LANCOR_TEST_HF_REPO").unwrap_or_else(|| DEFAULT_HF_REPO.into());
+        let status = Command::new("docker")
+            .args(["run", "-d", "--rm", "--name", CONTAINER])
+            .args(["-p", &format!("{}:8080", port)])
+            .arg(image)
+            .args(["-hf", &repo, "--host", "0.0.0.0", "--port", "8080"])
+            .args(["--embeddings", "--jinja", "-c", "2048"])
+            .status()
+            .expect("failed to run docker");
+        assert!(status.success(), "failed to start {}", CONTAINER);
+    }
+
+    format!("http://127.0.0.1:{}", port)
+}
+
+/// Block until `/health` reports ready; the model download can take a while
+///
+/// Runs on its own thread because the tests call it from inside a runtime.
+fn wait_until_healthy(base_url: &str) {
+    let url = format!("{}/health", base_url);
+    let healthy = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(600);
+        while Instant::now() < deadline {
+            let ready = runtime.block_on(async {
+                matches!(reqwest::get(&url).await, Ok(r) if r.status().is_success())
+            });
+            if ready {
+                return true;
+            }
+            std::thread::sleep(Duration::from_secs(2));
+        }
+        false
+    })
+    .join()
+    .unwrap();
+
+    assert!(healthy, "server at {} did not become healthy", base_url);
+}
+
+fn base_url() -> Option<&'static str> {
+    static URL: OnceLock<Option<String>> = OnceLock::new();
+    URL.get_or_init(|| {
+        let url = match env("LANCOR_TEST_URL") {
+            Some(url) => url,
+            None if env("LANCOR_TEST_DOCKER").is_some() => start_container(),
+            None => return None,
+        };
+        wait_until_healthy(&url);
+        Some(url)
+    })
+    .as_deref()
+}
+
+/// The client and model under test, or `None` to skip
+fn setup() -> Option<(LlamaCppClient, String)> {
+    let Some(url) = base_url() else {
+        eprintln!("skipping: set LANCOR_TEST_URL or LANCOR_TEST_DOCKER=1");
+        return None;
+    };
+    let client = match env("LANCOR_TEST_API_KEY") {
+        Some(key) => LlamaCppClient::with_api_key(url, key),
+        None => LlamaCppClient::new(url),
+    }
+    .unwrap();
+    let model = env("LANCOR_TEST_MODEL").unwrap_or_else(|| "default".into());
+    Some((client, model))
+}
+
+#[tokio::test]
+async fn chat_completion() {
+    let Some((client, model)) = setup() else {
+        return;
+    };
+
+    let request = ChatCompletionRequest::new(model)
+        .message(Message::user("Say hello."))
+        .max_tokens(16)
+        .temperature(0.0);
+    let response = client.chat_completion(request).await.unwrap();
+
+    assert_eq!(response.choices.len(), 1);
+    assert_eq!(response.choices[0].message.role, "assistant");
+    assert!(response.usage.total_tokens > 0);
+}
+
+#[tokio::test]
+async fn chat_completion_stream() {
+    let Some((client, model)) = setup() else {
+        return;
+    };
+
+    let request = ChatCompletionRequest::new(model)
+        .message(Message::user("Count from 1 to 5."))
+        .max_tokens(24)
+        .stream(true);
+    let mut stream = client.chat_completion_stream(request).await.unwrap();
+
+    let mut content = String::new();
+    let mut chunks = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.unwrap();
+        chunks += 1;
+        if let Some(delta) = chunk.choices.first().and_then(|c| c.delta.content.clone()) {
+            content.push_str(&delta);
+        }
+    }
+
+    assert!(chunks > 1);
+    assert!(!content.is_empty());
+}
+
+#[tokio::test]
+async fn chat_session_keeps_history() {
+    let Some((client, model)) = setup() else {
+        return;
+    };
+
+    let mut session = ChatSession::new(client, model)
+        .system_prompt("You are terse.")
+        .max_tokens(16);
+    session.send("Hi.").await.unwrap();
+    session.send("Again.").await.unwrap();
+
+    assert_eq!(session.history().len(), 4);
+}
+
+#[tokio::test]
+async fn completion() {
+    let Some((client, model)) = setup() else {
+        return;
+    };
+
+    let request = CompletionRequest::new(model, "The capital of France is").max_tokens(8);
+    let response = client.completion(request).await.unwrap();
+
+    assert!(!response.content.is_empty());
+}
+
+#[tokio::test]
+async fn embedding() {
+    let Some((client, model)) = setup() else {
+        return;
+    };
+
+    let request = EmbeddingRequest::new(model, "Hello, world!");
+    let response = client.embedding(request).await.unwrap();
+
+    assert_eq!(response.data.len(), 1);
+    assert!(!response.data[0].embedding.is_empty());
+}
+
+#[tokio::test]
+async fn tokenize() {
+    let Some((client, _)) = setup() else { return };
+
+    let response = client
+        .tokenize(TokenizeRequest::new("Hello, world!"))
+        .await
+        .unwrap();
+
+    assert!(!response.tokens.is_empty());
+}
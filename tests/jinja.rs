@@ -0,0 +1,147 @@
+//! Rendering chat templates on the client with `JinjaTemplate`.
+
+#![cfg(feature = "jinja")]
+
+use lancor::jinja::JinjaTemplate;
+use lancor::transport::MockTransport;
+use lancor::{FunctionCall, LlamaCppClient, Message, Tool, ToolCall};
+use serde_json::json;
+
+const CHATML: &str = "{% for message in messages %}\
+{{ '<|im_start|>' + message['role'] + '\n' + message['content'] | trim + '<|im_end|>' + '\n' }}\
+{% endfor %}\
+{% if add_generation_prompt %}{{ '<|im_start|>assistant\n' }}{% endif %}";
+
+/// A GGUF file holding only the metadata `JinjaTemplate::from_gguf` reads,
+/// plus a float it has to skip
+fn gguf(template: &str) -> Vec<u8> {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend((s.len() as u64).to_le_bytes());
+        out.extend(s.as_bytes());
+    }
+    let mut out = b"GGUF".to_vec();
+    out.extend(3u32.to_le_bytes());
+    out.extend(0u64.to_le_bytes());
+    out.extend(5u64.to_le_bytes());
+
+    string(&mut out, "general.temperature");
+    out.extend(6u32.to_le_bytes());
+    out.extend(0.5f32.to_le_bytes());
+    string(&mut out, "tokenizer.ggml.bos_token_id");
+    out.extend(4u32.to_le_bytes());
+    out.extend(1u32.to_le_bytes());
+    string(&mut out, "tokenizer.ggml.tokens");
+    out.extend(9u32.to_le_bytes());
+    out.extend(8u32.to_le_bytes());
+    out.extend(3u64.to_le_bytes());
+    for token in ["<unk>", "<s>", "</s>"] {
+        string(&mut out, token);
+    }
+    string(&mut out, "tokenizer.ggml.eos_token_id");
+    out.extend(4u32.to_le_bytes());
+    out.extend(2u32.to_le_bytes());
+    string(&mut out, "tokenizer.chat_template");
+    out.extend(8u32.to_le_bytes());
+    string(&mut out, template);
+    out
+}
+
+#[test]
+fn messages_render_into_a_prompt() {
+    let template = JinjaTemplate::new(CHATML).unwrap();
+    let messages = [Message::system("Be terse. "), Message::user("Hi")];
+
+    let prompt = template.render(&messages).unwrap();
+    assert_eq!(
+        prompt,
+        "<|im_start|>system\nBe terse.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+    );
+
+    let prompt = template
+        .add_generation_prompt(false)
+        .render(&messages)
+        .unwrap();
+    assert!(prompt.ends_with("Hi<|im_end|>\n"), "{}", prompt);
+}
+
+#[test]
+fn templates_see_tools_and_tool_call_arguments_as_objects() {
+    let template = JinjaTemplate::new(
+        "{% for tool in tools %}[{{ tool.function.name }}]{% endfor %}\
+         {% for message in messages %}{% if message.tool_calls %}\
+         {% for call in message.tool_calls %}{{ call.function.name }}({{ call.function.arguments.city }}){% endfor %}\
+         {% else %}{{ message.content.strip() }}{% endif %}{% endfor %}",
+    )
+    .unwrap();
+    let mut call = Message::assistant("");
+    call.tool_calls = Some(vec![ToolCall {
+        id: "call_1".into(),
+        kind: "function".into(),
+        function: FunctionCall {
+            name: "weather".into(),
+            arguments: r#"{"city": "Oslo"}"#.into(),
+        },
+    }]);
+    let tool = Tool::function("weather", "Current weather", json!({ "type": "object" }));
+
+    let prompt = template
+        .render_with_tools(&[Message::user("  Weather? "), call], &[tool])
+        .unwrap();
+    assert_eq!(prompt, "[weather]Weather?weather(Oslo)");
+}
+
+#[test]
+fn raised_exceptions_fail_rendering() {
+    let template = JinjaTemplate::new(
+        "{% if messages[0].role != 'user' %}{{ raise_exception('Conversations must start with a user message') }}{% endif %}",
+    )
+    .unwrap();
+    let err = template.render(&[Message::assistant("Hi")]).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("must start with a user message"),
+        "{:#}",
+        err
+    );
+    assert!(JinjaTemplate::new("{% for %}").is_err());
+
+    let date = JinjaTemplate::new("{{ strftime_now('%d %b %Y') }}")
+        .unwrap()
+        .render(&[])
+        .unwrap();
+    assert_eq!(date.len(), 11, "{}", date);
+}
+
+#[test]
+fn gguf_files_provide_the_template_and_tokens() {
+    let path = std::env::temp_dir().join(format!("lancor-template-{}.gguf", std::process::id()));
+    std::fs::write(
+        &path,
+        gguf("{{ bos_token }}{% for m in messages %}{{ m.content }}{{ eos_token }}{% endfor %}"),
+    )
+    .unwrap();
+
+    let template = JinjaTemplate::from_gguf(&path).unwrap();
+    // The leading BOS is left for llama.cpp to add
+    assert_eq!(template.render(&[Message::user("Hi")]).unwrap(), "Hi</s>");
+
+    std::fs::write(&path, b"not a model").unwrap();
+    assert!(JinjaTemplate::from_gguf(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn the_servers_template_comes_from_props() {
+    let mock = MockTransport::new().json(
+        "/props",
+        json!({
+            "chat_template": CHATML,
+            "bos_token": "<s>",
+            "eos_token": "<|im_end|>"
+        }),
+    );
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let template = client.jinja_template().await.unwrap();
+    let prompt = template.render(&[Message::user("Hi")]).unwrap();
+    assert!(prompt.starts_with("<|im_start|>user\nHi"), "{}", prompt);
+}
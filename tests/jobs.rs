@@ -0,0 +1,140 @@
+//! The persistent job queue.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{chat_response, client};
+use lancor::jobs::{JobQueue, JobStatus};
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, CompletionRequest, Message};
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::PathBuf;
+
+fn chat(prompt: &str) -> ChatCompletionRequest {
+    ChatCompletionRequest::new("test-model").message(Message::user(prompt))
+}
+
+fn queue_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("lancor-jobs-{}-{}.jsonl", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn jobs_run_by_priority_and_outlive_the_queue() {
+    let path = queue_path("priority");
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("one"))
+        .json("/v1/chat/completions", chat_response("two"))
+        .json("/v1/chat/completions", chat_response("three"));
+
+    let queue = JobQueue::open(&path).unwrap();
+    let first = queue.submit(chat("first")).unwrap();
+    let second = queue.submit(chat("second")).unwrap();
+    let urgent = queue.submit_with_priority(chat("urgent"), 5).unwrap();
+    assert_eq!(queue.counts().queued, 3);
+
+    let counts = queue.run(&client(&mock), 1).await.unwrap();
+    assert_eq!(counts.succeeded, 3);
+    let prompts: Vec<String> = mock
+        .requests()
+        .iter()
+        .map(|request| {
+            let body: Value = request.json().unwrap();
+            body["messages"][0]["content"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(prompts, ["urgent", "first", "second"]);
+
+    drop(queue);
+    let reopened = JobQueue::open(&path).unwrap();
+    let text = |id: &str| reopened.get(id).unwrap().output.unwrap().text();
+    assert_eq!(text(&urgent), "one");
+    assert_eq!(text(&first), "two");
+    assert_eq!(text(&second), "three");
+    let jobs = reopened.jobs();
+    assert_eq!(jobs[0].id, first);
+    assert!(
+        jobs.iter()
+            .all(|job| job.attempts == 1 && job.finished_at.is_some())
+    );
+    // Reopening keeps only the latest record of each job
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn failed_jobs_can_be_retried_and_cancelled_ones_never_run() {
+    let path = queue_path("retry");
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 500, "boom")
+        .json("/v1/chat/completions", chat_response("fine"));
+    let queue = JobQueue::open(&path).unwrap();
+    let flaky = queue.submit(chat("flaky")).unwrap();
+    let dropped = queue.submit(chat("dropped")).unwrap();
+
+    assert!(queue.cancel(&dropped).unwrap());
+    assert!(!queue.cancel(&dropped).unwrap());
+    assert!(!queue.cancel("job_missing").unwrap());
+
+    let counts = queue.run(&client(&mock), 2).await.unwrap();
+    assert_eq!((counts.failed, counts.cancelled), (1, 1));
+    assert_eq!(mock.requests().len(), 1);
+    let job = queue.get(&flaky).unwrap();
+    assert_eq!(job.status, JobStatus::Failed);
+    assert!(job.error.unwrap().contains("500"));
+
+    assert_eq!(queue.retry_failed().unwrap(), 1);
+    queue.run(&client(&mock), 2).await.unwrap();
+    let job = queue.get(&flaky).unwrap();
+    assert_eq!(job.status, JobStatus::Succeeded);
+    assert_eq!(job.attempts, 2);
+    assert!(job.error.is_none());
+    assert_eq!(queue.get(&dropped).unwrap().status, JobStatus::Cancelled);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn jobs_interrupted_by_a_crash_run_again() {
+    let path = queue_path("crash");
+    let queue = JobQueue::open(&path).unwrap();
+    let id = queue
+        .submit(CompletionRequest::new("test-model", "Once upon a time"))
+        .unwrap();
+    drop(queue);
+
+    // A crash leaves the job running and its next record half written
+    let mut record: Value =
+        serde_json::from_str(std::fs::read_to_string(&path).unwrap().trim()).unwrap();
+    record["status"] = json!("running");
+    record["attempts"] = json!(1);
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    writeln!(file, "{}", record).unwrap();
+    write!(file, "{{\"id\":\"{}\",\"prio", id).unwrap();
+    drop(file);
+
+    let queue = JobQueue::open(&path).unwrap();
+    assert_eq!(queue.get(&id).unwrap().status, JobStatus::Queued);
+
+    let mock = MockTransport::new().json(
+        "/v1/completions",
+        json!({ "content": " there was a llama.", "stop": true }),
+    );
+    queue.run(&client(&mock), 1).await.unwrap();
+    let job = queue.get(&id).unwrap();
+    assert_eq!(job.status, JobStatus::Succeeded);
+    assert_eq!(job.attempts, 2);
+    assert_eq!(job.output.unwrap().text(), " there was a llama.");
+
+    std::fs::write(&path, "not json\n").unwrap();
+    assert!(JobQueue::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,276 @@
+//! llama.cpp-specific request parameters.
+
+mod common;
+
+use common::{chat_response, client};
+use futures::StreamExt;
+use lancor::transport::MockTransport;
+use lancor::{
+    ChatCompletionRequest, ChatSession, CompletionRequest, Dialect, LancorConfig, LlamaCppClient,
+    Message, SlotPinnedSession, Speculative,
+};
+use serde_json::{Value, json};
+
+fn completion_response() -> Value {
+    json!({
+        "content": "fn main() {}",
+        "model": "qwen2.5-coder",
+        "stop": true,
+        "tokens_predicted": 5,
+        "tokens_evaluated": 3
+    })
+}
+
+#[tokio::test]
+async fn speculative_settings_use_dotted_keys() {
+    let mock = MockTransport::new().json("/v1/completions", completion_response());
+    let request = CompletionRequest::new("qwen2.5-coder", "fn main")
+        .speculative(Speculative::new().n_max(16).n_min(2).p_min(0.75));
+
+    client(&mock).completion(request).await.unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["speculative.n_max"], 16);
+    assert_eq!(body["speculative.n_min"], 2);
+    assert_eq!(body["speculative.p_min"], 0.75);
+}
+
+#[tokio::test]
+async fn unset_speculative_settings_are_left_out() {
+    let mock = MockTransport::new().json("/v1/completions", completion_response());
+    let request =
+        CompletionRequest::new("qwen2.5-coder", "fn main").speculative(Speculative::new().n_max(8));
+
+    client(&mock).completion(request).await.unwrap();
+    client(&mock)
+        .completion(CompletionRequest::new("qwen2.5-coder", "fn main"))
+        .await
+        .unwrap();
+
+    let requests = mock.requests();
+    let with: Value = requests[0].json().unwrap();
+    assert_eq!(with["speculative.n_max"], 8);
+    assert!(with.get("speculative.p_min").is_none(), "{}", with);
+    let without: Value = requests[1].json().unwrap();
+    assert!(
+        without
+            .as_object()
+            .unwrap()
+            .keys()
+            .all(|key| !key.starts_with("speculative")),
+        "{}",
+        without
+    );
+}
+
+#[tokio::test]
+async fn slot_and_cache_settings_are_sent() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("Hi"))
+        .json("/v1/completions", completion_response());
+    let client = client(&mock);
+
+    client
+        .chat_completion(
+            ChatCompletionRequest::new("qwen")
+                .message(Message::user("Hi"))
+                .cache_prompt(true)
+                .id_slot(2),
+        )
+        .await
+        .unwrap();
+    client
+        .completion(CompletionRequest::new("qwen2.5-coder", "fn main").cache_prompt(false))
+        .await
+        .unwrap();
+
+    let requests = mock.requests();
+    let chat: Value = requests[0].json().unwrap();
+    assert_eq!(chat["cache_prompt"], true);
+    assert_eq!(chat["id_slot"], 2);
+    let completion: Value = requests[1].json().unwrap();
+    assert_eq!(completion["cache_prompt"], false);
+    assert!(completion.get("id_slot").is_none(), "{}", completion);
+}
+
+#[tokio::test]
+async fn slot_settings_are_dropped_for_openai() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let config = LancorConfig::default().dialect(Dialect::OpenAi);
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("gpt-4o-mini")
+        .message(Message::user("Hi"))
+        .cache_prompt(true)
+        .id_slot(0);
+    client.chat_completion(request).await.unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert!(body.get("cache_prompt").is_none(), "{}", body);
+    assert!(body.get("id_slot").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn pinned_sessions_send_every_turn_to_their_slot() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let session = ChatSession::new(client(&mock), "qwen")
+        .system_prompt("You are a terse assistant.")
+        .temperature(0.5);
+    let mut session = SlotPinnedSession::new(session, 3);
+
+    session.send("Hi").await.unwrap();
+    session.send("And again").await.unwrap();
+
+    assert_eq!(session.slot(), 3);
+    assert_eq!(session.history().len(), 4);
+    for request in mock.requests() {
+        let body: Value = request.json().unwrap();
+        assert_eq!(body["id_slot"], 3);
+        assert_eq!(body["cache_prompt"], true);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["messages"][0]["content"], "You are a terse assistant.");
+    }
+}
+
+#[tokio::test]
+async fn token_probabilities_are_parsed() {
+    let mut response = completion_response();
+    response["content"] = json!("fn");
+    response["completion_probabilities"] = json!([{
+        "id": 8822,
+        "token": "fn",
+        "bytes": [102, 110],
+        "logprob": -0.105,
+        "top_logprobs": [
+            { "id": 8822, "token": "fn", "bytes": [102, 110], "logprob": -0.105 },
+            { "id": 1342, "token": "pub", "bytes": [112, 117, 98], "logprob": -2.4 }
+        ]
+    }]);
+    let mock = MockTransport::new().json("/v1/completions", response);
+    let request = CompletionRequest::new("qwen2.5-coder", "// entry point\n").n_probs(2);
+
+    let response = client(&mock).completion(request).await.unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["n_probs"], 2);
+    let probs = response.completion_probabilities.unwrap();
+    assert_eq!(probs[0].token, "fn");
+    assert_eq!(probs[0].top_logprobs[1].token, "pub");
+    assert!((probs[0].probability().unwrap() - 0.9).abs() < 0.001);
+    assert!((probs[0].top_logprobs[1].probability().unwrap() - 0.0907).abs() < 0.001);
+}
+
+#[tokio::test]
+async fn streamed_chunks_carry_their_probabilities() {
+    let mock = MockTransport::new().sse(
+        "/v1/completions",
+        vec![
+            // The older shape, with plain probabilities
+            json!({
+                "content": "fn",
+                "stop": false,
+                "completion_probabilities": [{
+                    "content": "fn",
+                    "probs": [{ "tok_str": "fn", "prob": 0.8 }, { "tok_str": "pub", "prob": 0.15 }]
+                }]
+            }),
+            json!({
+                "content": " main",
+                "stop": false,
+                "completion_probabilities": [{
+                    "id": 1925,
+                    "token": " main",
+                    "prob": 0.97,
+                    "top_probs": [{ "id": 1925, "token": " main", "prob": 0.97 }]
+                }]
+            }),
+            json!({ "content": "", "stop": true, "tokens_predicted": 2, "tokens_evaluated": 4 }),
+        ],
+    );
+
+    let chunks: Vec<_> = client(&mock)
+        .completion_stream(CompletionRequest::new("qwen2.5-coder", "// entry point\n").n_probs(2))
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["stream"], true);
+    assert_eq!(chunks.len(), 3);
+    let first = &chunks[0].completion_probabilities.as_ref().unwrap()[0];
+    assert_eq!(first.token, "fn");
+    assert_eq!(first.probability(), Some(0.8));
+    assert_eq!(first.top_logprobs[1].token, "pub");
+    let second = &chunks[1].completion_probabilities.as_ref().unwrap()[0];
+    assert_eq!(second.probability(), Some(0.97));
+    assert_eq!(chunks[2].stop, Some(true));
+    assert!(chunks[2].completion_probabilities.is_none());
+}
+
+#[tokio::test]
+async fn timings_are_parsed_from_responses_and_chunks() {
+    let timings = json!({
+        "prompt_n": 12,
+        "prompt_ms": 48.5,
+        "prompt_per_token_ms": 4.04,
+        "prompt_per_second": 247.4,
+        "predicted_n": 5,
+        "predicted_ms": 250.0,
+        "predicted_per_token_ms": 50.0,
+        "predicted_per_second": 20.0,
+        "cache_n": 30,
+        "draft_n": 8,
+        "draft_n_accepted": 6
+    });
+    let mut chat = chat_response("Hi");
+    chat["timings"] = timings.clone();
+    let mut completion = completion_response();
+    completion["timings"] = json!({ "prompt_n": 3, "predicted_n": 5, "predicted_ms": 100.0 });
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "qwen",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }],
+        "timings": { "prompt_n": 12, "predicted_n": 1, "predicted_per_second": 18.5 }
+    });
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat)
+        .sse("/v1/chat/completions", vec![chunk])
+        .json("/v1/completions", completion);
+    let client = client(&mock);
+
+    let request = ChatCompletionRequest::new("qwen").message(Message::user("Hi"));
+    let response = client.chat_completion(request.clone()).await.unwrap();
+    let timings = response.timings.unwrap();
+    assert_eq!(timings.prompt_n, 12);
+    assert_eq!(timings.cache_n, Some(30));
+    assert_eq!(
+        timings.predicted_time(),
+        std::time::Duration::from_millis(250)
+    );
+    assert_eq!(timings.draft_acceptance(), Some(0.75));
+
+    let chunks: Vec<_> = client
+        .chat_completion_stream(request.timings_per_token(true))
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    let timings = chunks[0].as_ref().unwrap().timings.unwrap();
+    assert_eq!(timings.predicted_per_second, 18.5);
+    let body: Value = mock.requests()[1].json().unwrap();
+    assert_eq!(body["timings_per_token"], true);
+
+    let response = client
+        .completion(CompletionRequest::new("qwen", "Hi"))
+        .await
+        .unwrap();
+    let timings = response.timings.unwrap();
+    assert_eq!((timings.prompt_n, timings.predicted_n), (3, 5));
+    assert_eq!(timings.draft_acceptance(), None);
+}
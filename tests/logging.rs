@@ -0,0 +1,104 @@
+//! Request logging through [`LoggingTransport`] and redaction.
+
+mod common;
+
+use common::chat_response;
+use futures::stream::StreamExt;
+use lancor::logging::{LogEntry, REDACTED, Redactor, redact_emails};
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+fn capture() -> (Arc<Mutex<Vec<LogEntry>>>, impl Fn(&LogEntry) + Send + Sync) {
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let sink = {
+        let entries = entries.clone();
+        move |entry: &LogEntry| entries.lock().unwrap().push(entry.clone())
+    };
+    (entries, sink)
+}
+
+#[tokio::test]
+async fn logs_redacted_exchanges() {
+    let (entries, sink) = capture();
+    let mock = MockTransport::new().json(
+        "/v1/chat/completions",
+        chat_response("Mail me at jane.doe@example.com"),
+    );
+    let client = LlamaCppClient::with_api_key("http://server:8080", "sk-12345")
+        .unwrap()
+        .with_transport(mock)
+        .with_logging(sink, Redactor::new().emails().field("model"));
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("my key is sk-12345, mail bob@corp.io"));
+    client.chat_completion(request).await.unwrap();
+
+    let entries = entries.lock().unwrap();
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry.status, Some(200));
+    assert_eq!(entry.url, "http://server:8080/v1/chat/completions");
+    assert!(
+        entry
+            .request_headers
+            .contains(&("Authorization".to_string(), REDACTED.to_string()))
+    );
+
+    let logged = serde_json::to_string(entry).unwrap();
+    for secret in [
+        "sk-12345",
+        "bob@corp.io",
+        "jane.doe@example.com",
+        "test-model",
+    ] {
+        assert!(!logged.contains(secret), "{} leaked: {}", secret, logged);
+    }
+    assert!(entry.request_body.contains("my key is [REDACTED]"));
+    assert!(entry.response_body.contains("Mail me at [REDACTED]"));
+}
+
+#[tokio::test]
+async fn logs_stream_transcripts() {
+    let (entries, sink) = capture();
+    let chunk = |content: &str| {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "test-model",
+            "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+        })
+    };
+    let mock = MockTransport::new().sse("/v1/chat/completions", vec![chunk("Hel"), chunk("lo")]);
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock)
+        .with_logging(sink, Redactor::new());
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("Hi"))
+        .stream(true);
+    let stream = client.chat_completion_stream(request).await.unwrap();
+    assert!(entries.lock().unwrap().is_empty());
+    stream.collect::<Vec<_>>().await;
+
+    let entries = entries.lock().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].response_body.contains("\"Hel\""));
+    assert!(entries[0].response_body.contains("\"lo\""));
+    assert!(entries[0].response_body.ends_with("data: [DONE]\n\n"));
+}
+
+#[test]
+fn redacts_emails() {
+    assert_eq!(
+        redact_emails("to: a.b+c@mail.example.org, cc x@y.co."),
+        "to: [REDACTED], cc [REDACTED]."
+    );
+    assert_eq!(
+        redact_emails("@handle and me@localhost"),
+        "@handle and me@localhost"
+    );
+}
@@ -0,0 +1,83 @@
+//! How messages serialize for the chat endpoint.
+
+mod common;
+
+use common::reply;
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, ContentPart, LlamaCppClient, Message};
+use serde_json::{Value, json};
+
+/// A reply from a named assistant
+fn chat_response() -> Value {
+    reply(json!({ "role": "assistant", "name": "planner", "content": "Done" }))
+}
+
+#[tokio::test]
+async fn names_and_tool_results_are_sent() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response());
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let request = ChatCompletionRequest::new("qwen")
+        .message(Message::named_user("alice", "Book a table for two"))
+        .message(Message::assistant("Checking availability").name("planner"))
+        .message(Message::tool("call_7", r#"{"available":true}"#))
+        .message(Message::user("Thanks"));
+
+    let response = client.chat_completion(request).await.unwrap();
+    assert_eq!(response.choices[0].message.name.as_deref(), Some("planner"));
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(
+        body["messages"],
+        json!([
+            { "role": "user", "name": "alice", "content": "Book a table for two" },
+            { "role": "assistant", "name": "planner", "content": "Checking availability" },
+            { "role": "tool", "content": "{\"available\":true}", "tool_call_id": "call_7" },
+            { "role": "user", "content": "Thanks" }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn audio_files_become_input_audio_parts() {
+    let dir = std::env::temp_dir().join(format!("lancor-audio-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut wav = b"RIFF\x24\0\0\0WAVEfmt ".to_vec();
+    wav.extend_from_slice(&[0; 8]);
+    std::fs::write(dir.join("clip.bin"), &wav).unwrap();
+    std::fs::write(dir.join("voice.mp3"), b"not a real frame").unwrap();
+    std::fs::write(dir.join("notes.txt"), b"hello").unwrap();
+
+    let clip = ContentPart::audio_file(dir.join("clip.bin")).unwrap();
+    let voice = ContentPart::audio_file(dir.join("voice.mp3")).unwrap();
+    let err = ContentPart::audio_file(dir.join("notes.txt")).unwrap_err();
+    assert!(err.to_string().contains("not a WAV or MP3"), "{}", err);
+
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response());
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let message = Message::user(vec![ContentPart::text("Transcribe this"), clip, voice]);
+    assert_eq!(message.content.audio()[0].bytes().unwrap(), wav);
+    client
+        .chat_completion(ChatCompletionRequest::new("qwen2.5-omni").message(message))
+        .await
+        .unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    let parts = &body["messages"][0]["content"];
+    assert_eq!(
+        parts[0],
+        json!({ "type": "text", "text": "Transcribe this" })
+    );
+    assert_eq!(parts[1]["type"], "input_audio");
+    assert_eq!(parts[1]["input_audio"]["format"], "wav");
+    assert_eq!(
+        parts[1]["input_audio"]["data"],
+        "UklGRiQAAABXQVZFZm10IAAAAAAAAAAA"
+    );
+    assert_eq!(parts[2]["input_audio"]["format"], "mp3");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,128 @@
+//! Metrics observers see every request the client sends.
+
+mod common;
+
+use common::{chat_response_with_usage, request};
+use futures::stream::StreamExt;
+use lancor::metrics::{MetricsObserver, RequestInfo};
+use lancor::transport::MockTransport;
+use lancor::{LlamaCppClient, TokenizeRequest};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+struct Recorder {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recorder {
+    fn push(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn events(&self) -> Vec<String> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl MetricsObserver for Recorder {
+    fn on_request_start(&self, request: &RequestInfo) {
+        self.push(format!("start {} {}", request.endpoint, request.model));
+    }
+
+    fn on_request_end(&self, request: &RequestInfo, _elapsed: Duration) {
+        self.push(format!("end {}", request.endpoint));
+    }
+
+    fn on_tokens(&self, request: &RequestInfo, prompt_tokens: u32, completion_tokens: u32) {
+        self.push(format!(
+            "tokens {} {} {}",
+            request.endpoint, prompt_tokens, completion_tokens
+        ));
+    }
+
+    fn on_error(&self, request: &RequestInfo, _error: &anyhow::Error) {
+        self.push(format!("error {}", request.endpoint));
+    }
+}
+
+fn mock() -> MockTransport {
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": "stop" }],
+        "usage": { "prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6 }
+    });
+    MockTransport::new()
+        .json("/v1/chat/completions", chat_response_with_usage("Hi", 7, 2))
+        .sse("/v1/chat/completions", vec![chunk])
+        .respond("/tokenize", 500, "boom")
+}
+
+#[tokio::test]
+async fn reports_requests_tokens_and_errors() {
+    let recorder = Recorder::default();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock())
+        .with_metrics(recorder.clone());
+
+    client.chat_completion(request()).await.unwrap();
+    let stream = client
+        .chat_completion_stream(request().stream(true))
+        .await
+        .unwrap();
+    stream.collect::<Vec<_>>().await;
+    client
+        .tokenize(TokenizeRequest::new("Hello"))
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        recorder.events(),
+        [
+            "start chat_completion test-model",
+            "tokens chat_completion 7 2",
+            "end chat_completion",
+            "start chat_completion_stream test-model",
+            "tokens chat_completion_stream 5 1",
+            "end chat_completion_stream",
+            "start tokenize ",
+            "error tokenize",
+            "end tokenize",
+        ]
+    );
+}
+
+#[cfg(feature = "prometheus")]
+#[tokio::test]
+async fn prometheus_exports_counters() {
+    use lancor::metrics::PrometheusMetrics;
+
+    let metrics = PrometheusMetrics::new();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock())
+        .with_metrics(metrics.clone());
+
+    client.chat_completion(request()).await.unwrap();
+    client
+        .tokenize(TokenizeRequest::new("Hello"))
+        .await
+        .unwrap_err();
+
+    let text = metrics.render();
+    let labels = r#"endpoint="chat_completion",model="test-model""#;
+    assert!(text.contains(&format!("lancor_requests_total{{{}}} 1", labels)));
+    assert!(text.contains(&format!("lancor_prompt_tokens_total{{{}}} 7", labels)));
+    assert!(text.contains(&format!("lancor_completion_tokens_total{{{}}} 2", labels)));
+    assert!(text.contains(&format!("lancor_requests_in_flight{{{}}} 0", labels)));
+    assert!(text.contains(&format!(
+        "lancor_request_duration_seconds_count{{{}}} 1",
+        labels
+    )));
+    assert!(text.contains(r#"lancor_request_errors_total{endpoint="tokenize",model=""} 1"#));
+}
@@ -0,0 +1,131 @@
+//! Listing models and filling in `"auto"` models from `/v1/models`.
+
+mod common;
+
+use common::{chat_response, client};
+use lancor::transport::MockTransport;
+use lancor::{
+    AUTO_MODEL, ChatCompletionRequest, EmbeddingRequest, LancorConfig, LlamaCppClient, Message,
+};
+use serde_json::json;
+
+fn models(ids: &[&str]) -> serde_json::Value {
+    let data: Vec<_> = ids
+        .iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "object": "model",
+                "created": 1700000000,
+                "owned_by": "llamacpp",
+                "meta": { "n_ctx_train": 32768 }
+            })
+        })
+        .collect();
+    json!({ "object": "list", "data": data })
+}
+
+fn paths(mock: &MockTransport) -> Vec<String> {
+    mock.requests()
+        .iter()
+        .map(|request| format!("{} {}", request.method, request.path()))
+        .collect()
+}
+
+fn sent_model(mock: &MockTransport, index: usize) -> serde_json::Value {
+    let body: serde_json::Value = mock.requests()[index].json().unwrap();
+    body["model"].clone()
+}
+
+#[tokio::test]
+async fn lists_models() {
+    let mock = MockTransport::new().json("/v1/models", models(&["qwen2.5-7b", "phi-3"]));
+    let listed = client(&mock).list_models().await.unwrap();
+
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed[0].id, "qwen2.5-7b");
+    assert_eq!(listed[0].owned_by.as_deref(), Some("llamacpp"));
+    assert_eq!(listed[0].meta.as_ref().unwrap()["n_ctx_train"], 32768);
+    assert_eq!(paths(&mock), ["GET /v1/models"]);
+}
+
+#[tokio::test]
+async fn auto_model_is_discovered_once() {
+    let mock = MockTransport::new()
+        .json("/v1/models", models(&["qwen2.5-7b"]))
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let client = client(&mock);
+
+    for model in [AUTO_MODEL, ""] {
+        let request = ChatCompletionRequest::new(model).message(Message::user("Hello"));
+        client.chat_completion(request).await.unwrap();
+    }
+
+    assert_eq!(
+        paths(&mock),
+        [
+            "GET /v1/models",
+            "POST /v1/chat/completions",
+            "POST /v1/chat/completions"
+        ]
+    );
+    assert_eq!(sent_model(&mock, 1), "qwen2.5-7b");
+    assert_eq!(sent_model(&mock, 2), "qwen2.5-7b");
+}
+
+#[tokio::test]
+async fn named_models_are_sent_as_is() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let request = ChatCompletionRequest::new("phi-3").message(Message::user("Hello"));
+    client(&mock).chat_completion(request).await.unwrap();
+
+    assert_eq!(paths(&mock), ["POST /v1/chat/completions"]);
+    assert_eq!(sent_model(&mock, 0), "phi-3");
+}
+
+#[tokio::test]
+async fn configured_default_model_wins_over_discovery() {
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        json!({
+            "object": "list",
+            "data": [{ "object": "embedding", "embedding": [1.0], "index": 0 }],
+            "model": "nomic",
+            "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+        }),
+    );
+    let client = LlamaCppClient::from_config(LancorConfig::default().default_model("nomic"))
+        .unwrap()
+        .with_transport(mock.clone());
+
+    client
+        .embedding(EmbeddingRequest::new(AUTO_MODEL, "text"))
+        .await
+        .unwrap();
+    assert_eq!(paths(&mock), ["POST /v1/embeddings"]);
+    assert_eq!(sent_model(&mock, 0), "nomic");
+}
+
+#[tokio::test]
+async fn reloading_the_config_forgets_the_model() {
+    let mock = MockTransport::new()
+        .json("/v1/models", models(&["first"]))
+        .json("/v1/models", models(&["second"]));
+    let client = client(&mock);
+
+    assert_eq!(client.discover_model().await.unwrap(), "first");
+    assert_eq!(client.discover_model().await.unwrap(), "first");
+    client.reload_config(LancorConfig::default());
+    assert_eq!(client.discover_model().await.unwrap(), "second");
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn discovery_fails_without_models() {
+    let mock = MockTransport::new().json("/v1/models", models(&[]));
+    let request = ChatCompletionRequest::new(AUTO_MODEL).message(Message::user("Hello"));
+    let err = client(&mock).chat_completion(request).await.unwrap_err();
+
+    assert!(err.to_string().contains("no models"), "{}", err);
+    assert_eq!(paths(&mock), ["GET /v1/models"]);
+}
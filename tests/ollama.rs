@@ -0,0 +1,325 @@
+//! The Ollama client, translating to and from Ollama's native API.
+
+#![cfg(feature = "ollama")]
+
+use futures::StreamExt;
+use lancor::ollama::OllamaClient;
+use lancor::provider::Provider;
+use lancor::transport::MockTransport;
+use lancor::{
+    ChatCompletionRequest, CompletionRequest, ContentPart, EmbeddingRequest, FinishReason,
+    FunctionCall, Message, ResponseFormat, Tool, ToolCall, ToolChoice,
+};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+fn client(mock: &MockTransport) -> OllamaClient {
+    OllamaClient::default()
+        .unwrap()
+        .with_transport(mock.clone())
+}
+
+fn sent(mock: &MockTransport, index: usize) -> Value {
+    mock.requests()[index].json().unwrap()
+}
+
+fn chat_reply(content: &str) -> Value {
+    json!({
+        "model": "llama3.2",
+        "created_at": "2024-07-22T20:33:28.123648Z",
+        "message": { "role": "assistant", "content": content },
+        "done": true,
+        "done_reason": "stop",
+        "prompt_eval_count": 12,
+        "eval_count": 5
+    })
+}
+
+#[tokio::test]
+async fn chat_maps_the_request_and_response() {
+    let mock = MockTransport::new().json("/api/chat", chat_reply("Hi there"));
+    let request = ChatCompletionRequest::new("llama3.2")
+        .message(Message::system("Be brief."))
+        .message(Message::user("Hello"))
+        .temperature(0.2)
+        .max_tokens(64)
+        .stop(vec!["\n".to_string()]);
+
+    let response = client(&mock).chat_completion(request).await.unwrap();
+    assert_eq!(response.choices[0].message.content.text(), "Hi there");
+    assert_eq!(response.choices[0].finish_reason, Some(FinishReason::Stop));
+    assert_eq!(response.model, "llama3.2");
+    assert_eq!(response.usage.prompt_tokens, 12);
+    assert_eq!(response.usage.completion_tokens, Some(5));
+    assert_eq!(response.usage.total_tokens, 17);
+
+    assert_eq!(
+        sent(&mock, 0),
+        json!({
+            "model": "llama3.2",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "Hello" }
+            ],
+            "stream": false,
+            "options": { "temperature": 0.2f32, "num_predict": 64, "stop": ["\n"] }
+        })
+    );
+}
+
+#[tokio::test]
+async fn images_tools_and_formats_use_ollamas_shapes() {
+    let mock = MockTransport::new().json(
+        "/api/chat",
+        json!({
+            "model": "llava",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    { "function": { "name": "get_weather", "arguments": { "city": "Paris" } } }
+                ]
+            },
+            "done": true,
+            "done_reason": "stop"
+        }),
+    );
+    let tool = Tool::function(
+        "get_weather",
+        "Current weather",
+        json!({ "type": "object" }),
+    );
+    let mut earlier = Message::assistant("");
+    earlier.tool_calls = Some(vec![ToolCall {
+        id: "call_0".to_string(),
+        kind: "function".to_string(),
+        function: FunctionCall {
+            name: "get_weather".to_string(),
+            arguments: r#"{"city":"Rome"}"#.to_string(),
+        },
+    }]);
+    let request = ChatCompletionRequest::new("llava")
+        .message(Message::user(vec![
+            ContentPart::text("What is this?"),
+            ContentPart::image_bytes("image/png", b"png"),
+        ]))
+        .message(earlier)
+        .tool(tool)
+        .response_format(ResponseFormat::json_schema(
+            "answer",
+            json!({ "type": "object" }),
+        ));
+
+    let response = client(&mock).chat_completion(request).await.unwrap();
+    let choice = &response.choices[0];
+    assert_eq!(choice.finish_reason, Some(FinishReason::ToolCalls));
+    let calls = choice.message.tool_calls.as_ref().unwrap();
+    assert_eq!(calls[0].id, "call_0");
+    assert_eq!(calls[0].function.name, "get_weather");
+    assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+
+    let body = sent(&mock, 0);
+    assert_eq!(body["messages"][0]["content"], "What is this?");
+    assert_eq!(body["messages"][0]["images"], json!(["cG5n"]));
+    assert_eq!(
+        body["messages"][1]["tool_calls"][0]["function"]["arguments"],
+        json!({ "city": "Rome" })
+    );
+    assert_eq!(body["tools"][0]["function"]["name"], "get_weather");
+    assert_eq!(body["format"], json!({ "type": "object" }));
+}
+
+#[tokio::test]
+async fn tool_choice_narrows_the_tools_offered() {
+    let mock = MockTransport::new()
+        .json("/api/chat", chat_reply("{}"))
+        .json("/api/chat", chat_reply("Hi"));
+    let tool = |name: &str| Tool::function(name, "", json!({ "type": "object" }));
+    let request = ChatCompletionRequest::new("llama3.2")
+        .message(Message::user("Jane is 31."))
+        .tool(tool("search"))
+        .tool(tool("extract_person"));
+    let client = client(&mock);
+
+    let named = request
+        .clone()
+        .tool_choice(ToolChoice::function("extract_person"));
+    client.chat_completion(named).await.unwrap();
+    let body = sent(&mock, 0);
+    assert_eq!(body["tools"].as_array().unwrap().len(), 1);
+    assert_eq!(body["tools"][0]["function"]["name"], "extract_person");
+    assert!(body.get("tool_choice").is_none(), "{}", body);
+
+    let none = request.tool_choice(ToolChoice::None);
+    client.chat_completion(none).await.unwrap();
+    assert!(sent(&mock, 1).get("tools").is_none());
+}
+
+#[tokio::test]
+async fn remote_images_are_rejected() {
+    let mock = MockTransport::new();
+    let request =
+        ChatCompletionRequest::new("llava").message(Message::user(vec![ContentPart::image_url(
+            "https://example.com/cat.png",
+        )]));
+
+    let err = client(&mock).chat_completion(request).await.unwrap_err();
+    assert!(err.to_string().contains("inline images"), "{}", err);
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn streams_newline_delimited_chunks() {
+    let lines = [
+        json!({ "model": "llama3.2", "message": { "role": "assistant", "content": "Hel" }, "done": false }),
+        json!({ "model": "llama3.2", "message": { "role": "assistant", "content": "lo" }, "done": false }),
+        json!({
+            "model": "llama3.2",
+            "message": { "role": "assistant", "content": "" },
+            "done": true,
+            "done_reason": "length",
+            "prompt_eval_count": 4,
+            "eval_count": 2
+        }),
+    ];
+    let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    let mock = MockTransport::new().respond("/api/chat", 200, body);
+    let request = ChatCompletionRequest::new("llama3.2").message(Message::user("Hi"));
+
+    let chunks: Vec<_> = client(&mock)
+        .chat_completion_stream(request)
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+        .collect();
+    assert_eq!(text, "Hello");
+    assert_eq!(chunks.len(), 3);
+    let last = chunks.last().unwrap();
+    assert_eq!(last.choices[0].finish_reason, Some(FinishReason::Length));
+    assert_eq!(last.usage.as_ref().unwrap().total_tokens, 6);
+    assert_eq!(sent(&mock, 0)["stream"], true);
+}
+
+#[tokio::test]
+async fn keep_alive_is_sent_with_every_request() {
+    let mock = MockTransport::new()
+        .json("/api/chat", chat_reply("Hi"))
+        .json(
+            "/api/embed",
+            json!({ "model": "nomic", "embeddings": [[0.3, 0.4]], "prompt_eval_count": 6 }),
+        );
+    let client = client(&mock).keep_alive(Duration::from_secs(600));
+
+    let request = ChatCompletionRequest::new("llama3.2").message(Message::user("Hi"));
+    client.chat_completion(request).await.unwrap();
+    let embeddings = client
+        .embedding(EmbeddingRequest::new("nomic", "text"))
+        .await
+        .unwrap();
+
+    assert_eq!(sent(&mock, 0)["keep_alive"], "600s");
+    assert_eq!(
+        sent(&mock, 1),
+        json!({ "model": "nomic", "input": "text", "keep_alive": "600s" })
+    );
+    assert_eq!(embeddings.data[0].embedding, vec![0.3, 0.4]);
+    assert_eq!(embeddings.usage.prompt_tokens, 6);
+}
+
+#[tokio::test]
+async fn unload_sets_keep_alive_to_zero() {
+    let mock = MockTransport::new().json(
+        "/api/generate",
+        json!({ "model": "llama3.2", "response": "", "done": true, "done_reason": "unload" }),
+    );
+    client(&mock)
+        .keep_alive_forever()
+        .unload("llama3.2")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        sent(&mock, 0),
+        json!({ "model": "llama3.2", "keep_alive": 0 })
+    );
+}
+
+#[tokio::test]
+async fn missing_models_are_pulled_when_auto_pull_is_on() {
+    let missing = json!({ "error": "model \"phi3\" not found, try pulling it first" });
+    let mock = MockTransport::new()
+        .respond("/api/generate", 404, missing.to_string())
+        .json("/api/pull", json!({ "status": "success" }))
+        .json(
+            "/api/generate",
+            json!({
+                "model": "phi3",
+                "response": "Once upon a time",
+                "done": true,
+                "prompt_eval_count": 3,
+                "eval_count": 4
+            }),
+        );
+
+    let response = client(&mock)
+        .auto_pull(true)
+        .completion(CompletionRequest::new("phi3", "Tell me a story"))
+        .await
+        .unwrap();
+    assert_eq!(response.content, "Once upon a time");
+    assert_eq!(response.tokens_predicted, Some(4));
+
+    let paths: Vec<_> = mock
+        .requests()
+        .iter()
+        .map(|r| r.path().to_string())
+        .collect();
+    assert_eq!(paths, ["/api/generate", "/api/pull", "/api/generate"]);
+    assert_eq!(sent(&mock, 1), json!({ "model": "phi3", "stream": false }));
+}
+
+#[tokio::test]
+async fn missing_models_fail_without_auto_pull() {
+    let missing = json!({ "error": "model \"phi3\" not found, try pulling it first" });
+    let mock = MockTransport::new().respond("/api/generate", 404, missing.to_string());
+
+    let err = client(&mock)
+        .completion(CompletionRequest::new("phi3", "Hi"))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("not found, try pulling"),
+        "{}",
+        err
+    );
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn lists_installed_models() {
+    let mock = MockTransport::new().json(
+        "/api/tags",
+        json!({ "models": [{ "name": "llama3.2:latest", "size": 2019393189u64, "digest": "a80c4f17" }] }),
+    );
+    let models = client(&mock).list_models().await.unwrap();
+
+    assert_eq!(models[0].name, "llama3.2:latest");
+    assert_eq!(models[0].size, Some(2019393189));
+}
+
+#[tokio::test]
+async fn works_through_the_provider_trait() {
+    let mock = MockTransport::new().json("/api/chat", chat_reply("Paris"));
+    let provider: Box<dyn Provider> = Box::new(client(&mock));
+
+    let request =
+        ChatCompletionRequest::new("llama3.2").message(Message::user("Capital of France?"));
+    let response = provider.chat_completion(request).await.unwrap();
+    assert_eq!(response.choices[0].message.content.text(), "Paris");
+}
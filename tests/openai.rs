@@ -0,0 +1,420 @@
+//! The OpenAI and Azure dialects, and parsing of error bodies.
+
+mod common;
+
+use common::chat_response;
+use lancor::transport::MockTransport;
+use lancor::{
+    ApiError, ChatCompletionRequest, CompletionRequest, Dialect, EmbeddingRequest, FinishReason,
+    LancorConfig, LlamaCppClient, Message, ResponseFormat, Speculative, Tool, ToolChoice,
+};
+use serde_json::{Value, json};
+
+fn request() -> ChatCompletionRequest {
+    ChatCompletionRequest::new("gpt-4o-mini")
+        .message(Message::user("Hello"))
+        .max_tokens(50)
+}
+
+#[tokio::test]
+async fn openai_requests_carry_account_headers_and_renamed_fields() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let config = LancorConfig::openai("sk-test")
+        .organization("org-123")
+        .project("proj_456");
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+
+    client.chat_completion(request()).await.unwrap();
+
+    let sent = &mock.requests()[0];
+    assert_eq!(sent.url, "https://api.openai.com/v1/chat/completions");
+    assert_eq!(sent.header_value("authorization"), Some("Bearer sk-test"));
+    assert_eq!(sent.header_value("openai-organization"), Some("org-123"));
+    assert_eq!(sent.header_value("openai-project"), Some("proj_456"));
+    let body: Value = sent.json().unwrap();
+    assert_eq!(body["max_completion_tokens"], 50);
+    assert!(body.get("max_tokens").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn openai_schemas_use_the_json_schema_form() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let client = LlamaCppClient::openai("sk-test")
+        .unwrap()
+        .with_transport(mock.clone());
+    let request = request().response_format(ResponseFormat::JsonObject {
+        schema: Some(json!({ "type": "object" })),
+    });
+
+    client.chat_completion(request).await.unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["response_format"]["type"], "json_schema");
+    assert_eq!(
+        body["response_format"]["json_schema"]["schema"],
+        json!({ "type": "object" })
+    );
+}
+
+#[tokio::test]
+async fn llama_cpp_requests_are_sent_unchanged() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    client.chat_completion(request()).await.unwrap();
+
+    let sent = &mock.requests()[0];
+    assert_eq!(sent.header_value("openai-organization"), None);
+    let body: Value = sent.json().unwrap();
+    assert_eq!(body["max_tokens"], 50);
+    assert!(body.get("max_completion_tokens").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn azure_requests_go_to_the_models_deployment() {
+    let mock = MockTransport::new().json(
+        "/openai/deployments/gpt-4o-mini/chat/completions",
+        chat_response("Hi"),
+    );
+    let client = LlamaCppClient::azure("https://contoso.openai.azure.com", "azure-key")
+        .unwrap()
+        .with_transport(mock.clone());
+
+    client.chat_completion(request()).await.unwrap();
+
+    let sent = &mock.requests()[0];
+    assert_eq!(
+        sent.url,
+        "https://contoso.openai.azure.com/openai/deployments/gpt-4o-mini/chat/completions?api-version=2024-10-21"
+    );
+    assert_eq!(sent.header_value("api-key"), Some("azure-key"));
+    assert_eq!(sent.header_value("authorization"), None);
+    let body: Value = sent.json().unwrap();
+    assert_eq!(body["max_completion_tokens"], 50);
+}
+
+#[tokio::test]
+async fn azure_deployment_and_version_can_be_configured() {
+    let mock = MockTransport::new()
+        .json(
+            "/openai/deployments/embed-prod/embeddings",
+            json!({
+                "object": "list",
+                "data": [{ "object": "embedding", "embedding": [1.0], "index": 0 }],
+                "model": "text-embedding-3-small",
+                "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+            }),
+        )
+        .json("/openai/models", json!({ "object": "list", "data": [] }));
+    let config = LancorConfig::azure("https://contoso.openai.azure.com", "azure-key")
+        .deployment("embed-prod")
+        .api_version("2025-01-01-preview");
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+
+    client
+        .embedding(EmbeddingRequest::new("text-embedding-3-small", "text"))
+        .await
+        .unwrap();
+    client.list_models().await.unwrap();
+
+    let urls: Vec<_> = mock.requests().into_iter().map(|r| r.url).collect();
+    assert_eq!(
+        urls,
+        [
+            "https://contoso.openai.azure.com/openai/deployments/embed-prod/embeddings?api-version=2025-01-01-preview",
+            "https://contoso.openai.azure.com/openai/models?api-version=2025-01-01-preview"
+        ]
+    );
+}
+
+#[test]
+fn dialect_is_read_from_config_files() {
+    let config: LancorConfig = serde_json::from_value(json!({
+        "base_url": "https://api.openai.com",
+        "dialect": "openai",
+        "organization": "org-123"
+    }))
+    .unwrap();
+    assert_eq!(config.dialect, Dialect::OpenAi);
+    assert_eq!(config.organization.as_deref(), Some("org-123"));
+
+    let serialized = serde_json::to_value(LancorConfig::default()).unwrap();
+    assert!(serialized.get("dialect").is_none(), "{}", serialized);
+}
+
+#[tokio::test]
+async fn error_bodies_are_parsed() {
+    let body = json!({
+        "error": {
+            "message": "Incorrect API key provided: sk-test.",
+            "type": "invalid_request_error",
+            "param": null,
+            "code": "invalid_api_key"
+        }
+    });
+    let mock = MockTransport::new().respond("/v1/chat/completions", 401, body.to_string());
+    let client = LlamaCppClient::openai("sk-test")
+        .unwrap()
+        .with_transport(mock);
+
+    let err = client.chat_completion(request()).await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "API error (401): Incorrect API key provided: sk-test. [invalid_api_key]"
+    );
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(api_error.status, 401);
+    assert_eq!(api_error.kind.as_deref(), Some("invalid_request_error"));
+    assert_eq!(api_error.code.as_deref(), Some("invalid_api_key"));
+    assert_eq!(api_error.param, None);
+}
+
+#[test]
+fn other_error_shapes_are_kept() {
+    let llama = ApiError::new(
+        400,
+        r#"{"error":{"code":400,"message":"the request exceeds the available context size","type":"exceed_context_size_error"}}"#,
+    );
+    assert_eq!(llama.code.as_deref(), Some("400"));
+    assert_eq!(
+        llama.to_string(),
+        "API error (400): the request exceeds the available context size [exceed_context_size_error]"
+    );
+
+    let plain = ApiError::new(404, r#"{"error":"model not found"}"#);
+    assert_eq!(plain.message, "model not found");
+
+    let text = ApiError::new(502, "Bad Gateway");
+    assert_eq!(text.message, "Bad Gateway");
+    assert_eq!(text.to_string(), "API error (502): Bad Gateway");
+}
+
+#[tokio::test]
+async fn finish_reasons_are_typed() {
+    let mut response = chat_response("Hi");
+    let choice = |index: u32, reason: Value| {
+        json!({
+            "index": index,
+            "message": { "role": "assistant", "content": "Hi" },
+            "finish_reason": reason
+        })
+    };
+    response["choices"] = json!([
+        choice(0, json!("length")),
+        choice(1, json!("function_call")),
+        choice(2, json!("content_filter")),
+        choice(3, json!("eos_token")),
+        choice(4, Value::Null),
+    ]);
+    let mock = MockTransport::new().json("/v1/chat/completions", response);
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let response = client.chat_completion(request()).await.unwrap();
+    let reasons: Vec<_> = response
+        .choices
+        .iter()
+        .map(|choice| choice.finish_reason.clone())
+        .collect();
+    assert_eq!(
+        reasons,
+        [
+            Some(FinishReason::Length),
+            Some(FinishReason::ToolCalls),
+            Some(FinishReason::ContentFilter),
+            Some(FinishReason::Other("eos_token".to_string())),
+            None,
+        ]
+    );
+    assert_eq!(json!(reasons[3]), json!("eos_token"));
+    assert_eq!(FinishReason::ToolCalls.to_string(), "tool_calls");
+}
+
+fn client_for(mock: &MockTransport, dialect: Dialect) -> LlamaCppClient {
+    let config = LancorConfig::new("http://localhost:8000").dialect(dialect);
+    LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone())
+}
+
+#[tokio::test]
+async fn one_request_suits_every_dialect() {
+    let schema = json!({ "type": "object", "properties": { "answer": { "type": "string" } } });
+    let request = ChatCompletionRequest::new("qwen")
+        .message(Message::user("Hello"))
+        .max_completion_tokens(64)
+        .response_format(ResponseFormat::JsonObject {
+            schema: Some(schema),
+        })
+        .cache_prompt(true);
+
+    let mut bodies = Vec::new();
+    for dialect in [Dialect::LlamaCpp, Dialect::OpenAi, Dialect::Vllm] {
+        let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+        client_for(&mock, dialect)
+            .chat_completion(request.clone())
+            .await
+            .unwrap();
+        bodies.push(mock.requests()[0].json::<Value>().unwrap());
+    }
+    let [llama, openai, vllm] = &bodies[..] else {
+        unreachable!()
+    };
+
+    assert_eq!(llama["max_tokens"], 64);
+    assert_eq!(llama["cache_prompt"], true);
+    assert_eq!(llama["response_format"]["type"], "json_object");
+
+    assert_eq!(openai["max_completion_tokens"], 64);
+    assert!(openai.get("max_tokens").is_none(), "{}", openai);
+    assert_eq!(openai["response_format"]["type"], "json_schema");
+
+    assert_eq!(vllm["max_tokens"], 64);
+    assert!(vllm.get("max_completion_tokens").is_none(), "{}", vllm);
+    assert!(vllm.get("cache_prompt").is_none(), "{}", vllm);
+    assert_eq!(vllm["response_format"]["type"], "json_schema");
+}
+
+#[tokio::test]
+async fn named_tool_choice_suits_every_dialect() {
+    let tool = |name: &str| Tool::function(name, "", json!({ "type": "object" }));
+    let request = ChatCompletionRequest::new("qwen")
+        .message(Message::user("Jane is 31."))
+        .tool(tool("search"))
+        .tool(tool("extract_person"))
+        .tool_choice(ToolChoice::function("extract_person"));
+
+    let mut bodies = Vec::new();
+    for dialect in [Dialect::LlamaCpp, Dialect::OpenAi] {
+        let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+        client_for(&mock, dialect)
+            .chat_completion(request.clone())
+            .await
+            .unwrap();
+        bodies.push(mock.requests()[0].json::<Value>().unwrap());
+    }
+
+    // llama.cpp only takes a string, so the other tools are left out instead
+    assert_eq!(bodies[0]["tool_choice"], "required");
+    assert_eq!(bodies[0]["tools"].as_array().unwrap().len(), 1);
+    assert_eq!(bodies[0]["tools"][0]["function"]["name"], "extract_person");
+    assert_eq!(
+        bodies[1]["tool_choice"],
+        json!({ "type": "function", "function": { "name": "extract_person" } })
+    );
+    assert_eq!(bodies[1]["tools"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn named_tool_choice_must_name_a_tool() {
+    let request = ChatCompletionRequest::new("qwen")
+        .message(Message::user("Jane is 31."))
+        .tool(Tool::function("search", "", json!({ "type": "object" })))
+        .tool_choice(ToolChoice::function("extract_person"));
+    let mock = MockTransport::new();
+    let err = client_for(&mock, Dialect::LlamaCpp)
+        .chat_completion(request)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("extract_person"), "{}", err);
+    assert!(mock.requests().is_empty());
+}
+
+#[test]
+fn tool_choice_round_trips() {
+    for (choice, value) in [
+        (ToolChoice::None, json!("none")),
+        (ToolChoice::Auto, json!("auto")),
+        (ToolChoice::Required, json!("required")),
+        (
+            ToolChoice::function("f"),
+            json!({ "type": "function", "function": { "name": "f" } }),
+        ),
+    ] {
+        assert_eq!(serde_json::to_value(&choice).unwrap(), value);
+        assert_eq!(serde_json::from_value::<ToolChoice>(value).unwrap(), choice);
+    }
+    assert!(serde_json::from_value::<ToolChoice>(json!("sometimes")).is_err());
+
+    let request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": "qwen",
+        "messages": [],
+        "tool_choice": "required"
+    }))
+    .unwrap();
+    assert_eq!(request.tool_choice, Some(ToolChoice::Required));
+}
+
+#[tokio::test]
+async fn completion_requests_drop_llama_cpp_fields_for_other_dialects() {
+    let response = json!({ "content": "world", "stop": true });
+    let mock = MockTransport::new().json("/v1/completions", response);
+    let request = CompletionRequest::new("qwen", "Hello")
+        .max_tokens(16)
+        .n_probs(3)
+        .speculative(Speculative::new().n_max(8));
+
+    client_for(&mock, Dialect::Vllm)
+        .completion(request)
+        .await
+        .unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["max_tokens"], 16);
+    assert!(body.get("n_probs").is_none(), "{}", body);
+    assert!(body.get("speculative.n_max").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn extra_fields_are_sent_in_every_dialect() {
+    let request = ChatCompletionRequest::new("qwen")
+        .message(Message::user("Hello"))
+        .temperature(0.5)
+        .extra("min_p", 0.05)
+        .extra("dry_multiplier", 0.8)
+        .extra("temperature", 1.0);
+
+    for dialect in [Dialect::LlamaCpp, Dialect::OpenAi, Dialect::Vllm] {
+        let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+        client_for(&mock, dialect)
+            .chat_completion(request.clone())
+            .await
+            .unwrap();
+        let body: Value = mock.requests()[0].json().unwrap();
+        assert_eq!(body["min_p"], 0.05, "{:?}", dialect);
+        assert_eq!(body["dry_multiplier"], 0.8, "{:?}", dialect);
+        assert_eq!(body["temperature"], 1.0, "{:?}", dialect);
+    }
+
+    // Even fields the dialect would otherwise drop
+    let mock = MockTransport::new().json("/v1/completions", json!({ "content": "world" }));
+    let request = CompletionRequest::new("qwen", "Hello").extra("n_probs", 2);
+    client_for(&mock, Dialect::Vllm)
+        .completion(request)
+        .await
+        .unwrap();
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["n_probs"], 2);
+
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        json!({ "data": [{ "embedding": [0.5, 0.5], "index": 0 }] }),
+    );
+    let request = EmbeddingRequest::new("nomic", "Hello").extra("dimensions", 2);
+    client_for(&mock, Dialect::LlamaCpp)
+        .embedding(request)
+        .await
+        .unwrap();
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(
+        body,
+        json!({ "model": "nomic", "input": "Hello", "dimensions": 2 })
+    );
+}
@@ -0,0 +1,148 @@
+//! Distributing requests over a [`ClientPool`].
+
+mod common;
+
+use common::{chat_response, request};
+use futures::stream::StreamExt;
+use lancor::transport::MockTransport;
+use lancor::{ClientPool, LoadBalancing};
+use serde_json::json;
+
+const NODES: [&str; 3] = [
+    "http://node1:8080",
+    "http://node2:8080",
+    "http://node3:8080",
+];
+
+fn hosts(mock: &MockTransport) -> Vec<String> {
+    mock.requests()
+        .iter()
+        .map(|request| request.url.split('/').nth(2).unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn round_robin_takes_turns() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let pool = ClientPool::new(NODES, LoadBalancing::RoundRobin)
+        .unwrap()
+        .configure(|client| client.with_transport(mock.clone()));
+
+    for _ in 0..4 {
+        pool.chat_completion(request()).await.unwrap();
+    }
+    assert_eq!(
+        hosts(&mock),
+        ["node1:8080", "node2:8080", "node3:8080", "node1:8080"]
+    );
+}
+
+#[tokio::test]
+async fn least_in_flight_avoids_busy_servers() {
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }]
+    });
+    let mock = MockTransport::new()
+        .sse("/v1/chat/completions", vec![chunk])
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let pool = ClientPool::new([NODES[0], NODES[1]], LoadBalancing::LeastInFlight)
+        .unwrap()
+        .configure(|client| client.with_transport(mock.clone()));
+
+    // An unread stream keeps node1 busy
+    let stream = pool
+        .chat_completion_stream(request().stream(true))
+        .await
+        .unwrap();
+    let in_flight = |pool: &ClientPool| {
+        pool.endpoint_status()
+            .iter()
+            .map(|status| status.in_flight)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(in_flight(&pool), [1, 0]);
+
+    pool.chat_completion(request()).await.unwrap();
+    pool.chat_completion(request()).await.unwrap();
+    assert_eq!(hosts(&mock), ["node1:8080", "node2:8080", "node2:8080"]);
+
+    stream.collect::<Vec<_>>().await;
+    assert_eq!(in_flight(&pool), [0, 0]);
+}
+
+#[tokio::test]
+async fn skips_failed_servers() {
+    let mock = MockTransport::new()
+        .respond("http://node2:8080/v1/chat/completions", 503, "down")
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let pool = ClientPool::new(NODES, LoadBalancing::RoundRobin)
+        .unwrap()
+        .configure(|client| client.with_transport(mock.clone()));
+
+    for _ in 0..4 {
+        pool.chat_completion(request()).await.unwrap();
+    }
+    assert_eq!(
+        hosts(&mock),
+        [
+            "node1:8080",
+            "node2:8080",
+            "node3:8080",
+            "node3:8080",
+            "node1:8080"
+        ]
+    );
+}
+
+#[test]
+fn needs_a_server() {
+    assert!(ClientPool::new(Vec::<String>::new(), LoadBalancing::RoundRobin).is_err());
+}
+
+#[tokio::test]
+async fn routes_to_ready_servers_with_free_slots() {
+    let idle = json!({ "id": 0, "is_processing": false });
+    let busy = json!({ "id": 0, "is_processing": true });
+    let mock = MockTransport::new()
+        .respond("http://node1:8080/health", 503, "loading model")
+        .json("http://node2:8080/slots", json!([busy]))
+        .json(
+            "http://node3:8080/slots",
+            json!([idle, { "id": 1, "state": 0 }]),
+        )
+        .json("/health", json!({ "status": "ok" }))
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let pool = ClientPool::new(NODES, LoadBalancing::RoundRobin)
+        .unwrap()
+        .configure(|client| client.with_transport(mock.clone()));
+
+    pool.probe_health().await;
+    let status = pool.endpoint_status();
+    assert_eq!(status[0].ready, Some(false));
+    assert_eq!(status[1].free_slots, Some(0));
+    assert_eq!(status[2].free_slots, Some(2));
+
+    mock.clear_requests();
+    for _ in 0..3 {
+        pool.chat_completion(request()).await.unwrap();
+    }
+    assert_eq!(hosts(&mock), ["node3:8080", "node3:8080", "node3:8080"]);
+
+    // node1 recovers and is admitted again
+    let mock = MockTransport::new()
+        .json("/health", json!({ "status": "ok" }))
+        .respond("/slots", 501, "slots endpoint disabled")
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let pool = pool.configure(|client| client.with_transport(mock.clone()));
+    pool.probe_health().await;
+    let status = pool.endpoint_status();
+    assert!(
+        status
+            .iter()
+            .all(|s| s.ready == Some(true) && s.free_slots.is_none())
+    );
+}
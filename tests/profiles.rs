@@ -0,0 +1,187 @@
+//! Named profiles loaded from a TOML config file.
+
+#![cfg(feature = "profiles")]
+
+mod common;
+
+use common::chat_response;
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, EmbeddingRequest, Message, Profiles};
+use serde_json::json;
+
+const CONFIG: &str = r#"
+# Profiles used by the tests
+default = "local"
+
+[profiles.local]
+base_url = "http://localhost:8080"
+
+[profiles.work]
+base_url = "https://llm.example.com"   # the shared server
+api_key = 'secret-key'
+model = "work-model"
+temperature = 0.25
+max_tokens = 1_024
+stop = [
+    "</s>",
+    "\n\nUser:",
+]
+"#;
+
+#[test]
+fn parses_profiles_from_toml() {
+    let profiles = Profiles::from_toml(CONFIG).unwrap();
+    assert_eq!(profiles.default.as_deref(), Some("local"));
+    assert_eq!(profiles.profiles.len(), 2);
+
+    let work = profiles.get("work").unwrap();
+    assert_eq!(work.base_url.as_deref(), Some("https://llm.example.com"));
+    assert_eq!(work.api_key.as_deref(), Some("secret-key"));
+    assert_eq!(work.model.as_deref(), Some("work-model"));
+    assert_eq!(work.temperature, Some(0.25));
+    assert_eq!(work.max_tokens, Some(1024));
+    assert_eq!(
+        work.stop,
+        Some(vec!["</s>".to_string(), "\n\nUser:".to_string()])
+    );
+
+    let local = profiles.default_profile().unwrap().unwrap();
+    assert_eq!(local.base_url.as_deref(), Some("http://localhost:8080"));
+    assert_eq!(local.model, None);
+}
+
+#[test]
+fn profile_becomes_a_client_config() {
+    let profiles = Profiles::from_toml(CONFIG).unwrap();
+    let config = profiles.get("work").unwrap().config().unwrap();
+
+    assert_eq!(config.base_url, "https://llm.example.com");
+    assert_eq!(config.api_key.as_deref(), Some("secret-key"));
+    assert_eq!(config.default_model.as_deref(), Some("work-model"));
+    assert_eq!(config.presets.defaults.temperature, Some(0.25));
+    assert_eq!(config.presets.defaults.max_tokens, Some(1024));
+}
+
+#[test]
+fn unknown_profiles_list_the_known_ones() {
+    let profiles = Profiles::from_toml(CONFIG).unwrap();
+    let err = profiles.get("home").unwrap_err().to_string();
+    assert!(err.contains("\"home\""), "{}", err);
+    assert!(err.contains("local, work"), "{}", err);
+}
+
+#[test]
+fn missing_api_key_variable_is_an_error() {
+    let profiles =
+        Profiles::from_toml("[profiles.ci]\napi_key_env = \"LANCOR_TEST_SURELY_UNSET_KEY\"\n")
+            .unwrap();
+    let err = profiles.get("ci").unwrap().config().unwrap_err();
+    assert!(
+        err.to_string().contains("LANCOR_TEST_SURELY_UNSET_KEY"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn a_given_api_key_skips_the_variable() {
+    let profiles = Profiles::from_toml(
+        "[profiles.ci]\nmodel = \"ci-model\"\napi_key_env = \"LANCOR_TEST_SURELY_UNSET_KEY\"\n",
+    )
+    .unwrap();
+    let config = profiles
+        .get("ci")
+        .unwrap()
+        .config_with_api_key(Some("cli-key".to_string()));
+    assert_eq!(config.api_key.as_deref(), Some("cli-key"));
+    assert_eq!(config.default_model.as_deref(), Some("ci-model"));
+}
+
+#[test]
+fn accepts_all_of_toml() {
+    let profiles = Profiles::from_toml(
+        r#"
+profiles.inline = { base_url = "http://gpu:8080", stop = ["</s>"] }
+
+[profiles.multiline]
+model = """
+long-model-name"""
+api_key = '''literal\key'''
+"#,
+    )
+    .unwrap();
+    let inline = profiles.get("inline").unwrap();
+    assert_eq!(inline.base_url.as_deref(), Some("http://gpu:8080"));
+    assert_eq!(inline.stop, Some(vec!["</s>".to_string()]));
+    let multiline = profiles.get("multiline").unwrap();
+    assert_eq!(multiline.model.as_deref(), Some("long-model-name"));
+    assert_eq!(multiline.api_key.as_deref(), Some("literal\\key"));
+}
+
+#[test]
+fn invalid_files_report_the_line() {
+    let err = Profiles::from_toml("[profiles.a]\nbase_url = http://x\n").unwrap_err();
+    assert!(err.to_string().contains("line 2"), "{}", err);
+
+    let err = Profiles::from_toml("[profiles.a]\nmodle = \"typo\"\n").unwrap_err();
+    assert!(err.to_string().contains("modle"), "{}", err);
+
+    let err = Profiles::from_toml("default = \"a\"\ndefault = \"b\"\n").unwrap_err();
+    assert!(err.to_string().contains("duplicate key"), "{}", err);
+}
+
+#[tokio::test]
+async fn client_uses_profile_model_and_defaults() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi"));
+    let profiles = Profiles::from_toml(CONFIG).unwrap();
+    let client = profiles
+        .client("work")
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("").message(Message::user("Hello"));
+    client.chat_completion(request).await.unwrap();
+
+    let sent = &mock.requests()[0];
+    assert_eq!(sent.url, "https://llm.example.com/v1/chat/completions");
+    assert_eq!(
+        sent.header_value("Authorization"),
+        Some("Bearer secret-key")
+    );
+    let body: serde_json::Value = sent.json().unwrap();
+    assert_eq!(body["model"], "work-model");
+    assert_eq!(body["temperature"], 0.25);
+    assert_eq!(body["max_tokens"], 1024);
+
+    // An explicit model is left alone
+    mock.clear_requests();
+    let request = ChatCompletionRequest::new("other").message(Message::user("Hello"));
+    client.chat_completion(request).await.unwrap();
+    let body: serde_json::Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["model"], "other");
+}
+
+#[tokio::test]
+async fn default_model_applies_to_embeddings() {
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        json!({
+            "object": "list",
+            "data": [{ "object": "embedding", "embedding": [1.0, 0.0], "index": 0 }],
+            "model": "work-model",
+            "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+        }),
+    );
+    let profiles = Profiles::from_toml(CONFIG).unwrap();
+    let client = profiles
+        .client("work")
+        .unwrap()
+        .with_transport(mock.clone());
+
+    client
+        .embedding(EmbeddingRequest::new("", "text"))
+        .await
+        .unwrap();
+    let body: serde_json::Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["model"], "work-model");
+}
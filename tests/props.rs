@@ -0,0 +1,147 @@
+//! Server properties and context-window checks.
+
+mod common;
+
+use common::chat_response_with_usage;
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, LancorConfig, LlamaCppClient, Message};
+use serde_json::json;
+
+fn props() -> serde_json::Value {
+    json!({
+        "default_generation_settings": {
+            "n_ctx": 64,
+            "model": "qwen2.5-7b-instruct-q4_k_m.gguf",
+            "params": { "temperature": 0.8, "top_k": 40 }
+        },
+        "total_slots": 4,
+        "model_path": "models/qwen2.5-7b-instruct-q4_k_m.gguf",
+        "chat_template": "{% for message in messages %}{{ message.content }}{% endfor %}",
+        "modalities": { "vision": true, "audio": false },
+        "bos_token": "<|im_start|>",
+        "eos_token": "<|im_end|>",
+        "build_info": "b5400-8a1d2062"
+    })
+}
+
+#[tokio::test]
+async fn server_props_are_parsed() {
+    let mock = MockTransport::new().json("/props", props());
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let props = client.server_props().await.unwrap();
+    assert_eq!(props.context_window(), Some(64));
+    assert_eq!(props.total_slots, Some(4));
+    assert!(props.modalities.vision);
+    assert!(!props.modalities.audio);
+    assert_eq!(props.build_info.as_deref(), Some("b5400-8a1d2062"));
+    assert_eq!(props.default_generation_settings.params["top_k"], json!(40));
+    assert_eq!(client.context_window().await.unwrap(), 64);
+}
+
+fn tokens(n: usize) -> serde_json::Value {
+    json!({ "tokens": vec![1; n] })
+}
+
+#[tokio::test]
+async fn fits_in_context_counts_with_the_server() {
+    let mock = MockTransport::new()
+        .json("/props", props())
+        .json("/tokenize", tokens(50))
+        .json("/tokenize", tokens(60));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let messages = vec![Message::system("Be brief."), Message::user("Hello")];
+
+    // The text plus 4 tokens per message for the template
+    assert_eq!(client.prompt_tokens(&messages).await.unwrap(), 58);
+    assert!(!client.fits_in_context(&messages).await.unwrap());
+
+    let tokenized: Vec<_> = mock
+        .requests()
+        .iter()
+        .filter(|r| r.url.ends_with("/tokenize"))
+        .map(|r| r.json::<serde_json::Value>().unwrap()["content"].clone())
+        .collect();
+    assert_eq!(tokenized[0], json!("Be brief.\nHello"));
+}
+
+#[tokio::test]
+async fn prompt_tokens_are_estimated_without_tokenize() {
+    let mock = MockTransport::new().respond("/tokenize", 404, "Not Found");
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let messages = vec![Message::user("The server starts.")];
+    assert_eq!(client.prompt_tokens(&messages).await.unwrap(), 8);
+}
+
+#[tokio::test]
+async fn max_tokens_fill_what_the_prompt_leaves() {
+    let mock = MockTransport::new()
+        .json("/props", props())
+        .json("/tokenize", tokens(20))
+        .json(
+            "/v1/chat/completions",
+            chat_response_with_usage("Hi", 24, 1),
+        );
+    let config = LancorConfig::default().auto_max_tokens(8);
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("qwen").message(Message::user("Hello"));
+    client.chat_completion(request.clone()).await.unwrap();
+    // 64 in the window, 24 for the prompt and 8 spare
+    let sent: serde_json::Value = mock.requests().last().unwrap().json().unwrap();
+    assert_eq!(sent["max_tokens"], json!(32));
+
+    // A max_tokens of the request's own is kept, and the window is
+    // only looked up once
+    client
+        .chat_completion(request.max_tokens(10))
+        .await
+        .unwrap();
+    let sent: serde_json::Value = mock.requests().last().unwrap().json().unwrap();
+    assert_eq!(sent["max_tokens"], json!(10));
+    let props = mock
+        .requests()
+        .iter()
+        .filter(|r| r.url.ends_with("/props"))
+        .count();
+    assert_eq!(props, 1);
+}
+
+#[tokio::test]
+async fn prompts_that_fill_the_window_are_rejected_before_sending() {
+    let mock = MockTransport::new()
+        .json("/props", props())
+        .json("/tokenize", tokens(60))
+        .json(
+            "/v1/chat/completions",
+            chat_response_with_usage("Hi", 24, 1),
+        );
+    let config = LancorConfig::default().auto_max_tokens(8);
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("qwen").message(Message::user("Hello"));
+    let error = client.chat_completion(request).await.unwrap_err();
+    assert!(error.to_string().contains("no room for a reply"));
+    assert!(
+        !mock
+            .requests()
+            .iter()
+            .any(|r| r.url.ends_with("/v1/chat/completions"))
+    );
+}
+
+#[tokio::test]
+async fn missing_context_size_is_an_error() {
+    let mock = MockTransport::new().json("/props", json!({ "total_slots": 1 }));
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let error = client.context_window().await.unwrap_err();
+    assert!(error.to_string().contains("context size"));
+}
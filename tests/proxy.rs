@@ -0,0 +1,194 @@
+//! Reaching the server through HTTP and SOCKS5 proxies, against local
+//! proxies that record what they were asked for.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use lancor::LlamaCppClient;
+use lancor::transport::ReqwestTransport;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const MODELS: &str = r#"{"object":"list","data":[{"id":"qwen","object":"model","created":0,"owned_by":"llamacpp"}]}"#;
+
+fn models_response() -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        MODELS.len(),
+        MODELS
+    )
+}
+
+async fn read_head(socket: &mut TcpStream) -> String {
+    let mut buf = vec![0u8; 8192];
+    let n = socket.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+/// An HTTP proxy that answers one request itself, recording its head
+async fn http_proxy() -> (String, Arc<Mutex<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let head = Arc::new(Mutex::new(String::new()));
+    let recorded = head.clone();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        *recorded.lock().unwrap() = read_head(&mut socket).await;
+        socket
+            .write_all(models_response().as_bytes())
+            .await
+            .unwrap();
+    });
+    (url, head)
+}
+
+#[cfg(feature = "socks")]
+/// A SOCKS5 proxy requiring username and password, which records the
+/// credentials and destination and then answers the tunnelled request itself
+async fn socks5_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        let mut greeting = [0u8; 2];
+        socket.read_exact(&mut greeting).await.unwrap();
+        let mut methods = vec![0u8; greeting[1] as usize];
+        socket.read_exact(&mut methods).await.unwrap();
+        assert!(methods.contains(&0x02), "{:?}", methods);
+        socket.write_all(&[0x05, 0x02]).await.unwrap();
+
+        let field = |bytes: Vec<u8>| {
+            recorded
+                .lock()
+                .unwrap()
+                .push(String::from_utf8(bytes).unwrap())
+        };
+        let mut version = [0u8; 2];
+        socket.read_exact(&mut version).await.unwrap();
+        let mut username = vec![0u8; version[1] as usize];
+        socket.read_exact(&mut username).await.unwrap();
+        let password_len = socket.read_u8().await.unwrap();
+        let mut password = vec![0u8; password_len as usize];
+        socket.read_exact(&mut password).await.unwrap();
+        field(username);
+        field(password);
+        socket.write_all(&[0x01, 0x00]).await.unwrap();
+
+        let mut connect = [0u8; 4];
+        socket.read_exact(&mut connect).await.unwrap();
+        assert_eq!(connect[3], 0x03, "expected a domain name");
+        let host_len = socket.read_u8().await.unwrap();
+        let mut host = vec![0u8; host_len as usize];
+        socket.read_exact(&mut host).await.unwrap();
+        let port = socket.read_u16().await.unwrap();
+        field(format!("{}:{}", String::from_utf8(host).unwrap(), port).into_bytes());
+        socket
+            .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+
+        let head = read_head(&mut socket).await;
+        field(head.lines().next().unwrap().as_bytes().to_vec());
+        socket
+            .write_all(models_response().as_bytes())
+            .await
+            .unwrap();
+    });
+    (format!("127.0.0.1:{}", addr.port()), seen)
+}
+
+#[tokio::test]
+async fn http_proxies_get_absolute_urls_and_credentials() {
+    let (proxy, head) = http_proxy().await;
+    let transport = ReqwestTransport::builder()
+        .proxy(proxy)
+        .proxy_auth("alice", "s3cret")
+        .build()
+        .unwrap();
+    let client = LlamaCppClient::new("http://llm.internal:8080")
+        .unwrap()
+        .with_transport(transport);
+
+    let models = client.list_models().await.unwrap();
+    assert_eq!(models[0].id, "qwen");
+
+    let head = head.lock().unwrap().to_lowercase();
+    assert!(
+        head.starts_with("get http://llm.internal:8080/v1/models http/1.1"),
+        "{}",
+        head
+    );
+    // base64("alice:s3cret")
+    assert!(
+        head.contains("proxy-authorization: basic ywxpy2u6cznjcmv0"),
+        "{}",
+        head
+    );
+}
+
+#[cfg(feature = "socks")]
+#[tokio::test]
+async fn socks5_proxies_resolve_names_remotely() {
+    let (proxy, seen) = socks5_proxy().await;
+    let transport = ReqwestTransport::builder()
+        .proxy(format!("socks5h://bastion:hunter2@{}", proxy))
+        .build()
+        .unwrap();
+    let client = LlamaCppClient::new("http://gpu-box.private:8080")
+        .unwrap()
+        .with_transport(transport);
+
+    let models = client.list_models().await.unwrap();
+    assert_eq!(models[0].id, "qwen");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "bastion",
+            "hunter2",
+            "gpu-box.private:8080",
+            "GET /v1/models HTTP/1.1"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn no_proxy_hosts_are_reached_directly() {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", server.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut socket, _) = server.accept().await.unwrap();
+        read_head(&mut socket).await;
+        socket
+            .write_all(models_response().as_bytes())
+            .await
+            .unwrap();
+    });
+    // Nothing listens on the discard port, so going through it would fail
+    let transport = ReqwestTransport::builder()
+        .proxy("http://127.0.0.1:9")
+        .no_proxy(["localhost", "127.0.0.1"])
+        .build()
+        .unwrap();
+    let client = LlamaCppClient::new(url).unwrap().with_transport(transport);
+
+    let models = client.list_models().await.unwrap();
+    assert_eq!(models[0].id, "qwen");
+}
+
+#[test]
+fn invalid_proxy_settings_are_rejected() {
+    let err = ReqwestTransport::builder()
+        .proxy("not a url")
+        .build()
+        .unwrap_err();
+    assert!(err.to_string().contains("Invalid proxy URL"), "{}", err);
+
+    let err = ReqwestTransport::builder()
+        .no_proxy(["localhost"])
+        .build()
+        .unwrap_err();
+    assert!(err.to_string().contains("need a proxy URL"), "{}", err);
+}
@@ -0,0 +1,176 @@
+//! The `Rag` pipeline from ingestion to a cited answer.
+
+#![cfg(feature = "rag")]
+
+mod common;
+
+use common::chat_response;
+use lancor::LlamaCppClient;
+use lancor::rag::{Document, Rag};
+use lancor::templates::ChatTemplate;
+use lancor::transport::MockTransport;
+use serde_json::json;
+
+fn embedding_response(vector: &[f32]) -> serde_json::Value {
+    json!({
+        "object": "list",
+        "model": "embed",
+        "data": [{ "object": "embedding", "index": 0, "embedding": vector }],
+        "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+    })
+}
+
+/// Embeds the two documents and then the question, which is closest to the
+/// first document
+fn mock() -> MockTransport {
+    MockTransport::new()
+        .json("/v1/embeddings", embedding_response(&[1.0, 0.0]))
+        .json("/v1/embeddings", embedding_response(&[0.0, 1.0]))
+        .json("/v1/embeddings", embedding_response(&[1.0, 0.2]))
+        .json("/v1/chat/completions", chat_response("Every 90 days [1]."))
+}
+
+fn documents() -> Vec<Document> {
+    vec![
+        Document::new("keys.md", "API keys rotate every 90 days."),
+        Document::new("billing.md", "Invoices are sent monthly."),
+    ]
+}
+
+fn prompt(mock: &MockTransport) -> String {
+    let request = mock.requests().pop().unwrap();
+    let body: serde_json::Value = request.json().unwrap();
+    body["messages"].to_string()
+}
+
+#[tokio::test]
+async fn answers_with_sources() {
+    let mock = mock();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let mut rag = Rag::new(client, "chat", "embed").top_k(1);
+
+    assert_eq!(rag.ingest(&documents()).await.unwrap(), 2);
+    assert_eq!(rag.index().len(), 2);
+
+    let result = rag.answer("When do keys rotate?").await.unwrap();
+    assert_eq!(result.answer, "Every 90 days [1].");
+    assert_eq!(result.sources.len(), 1);
+    assert_eq!(result.sources[0].chunk.source, "keys.md");
+    assert_eq!(
+        result.sources[0].chunk.text,
+        "API keys rotate every 90 days."
+    );
+
+    let prompt = prompt(&mock);
+    assert!(prompt.contains("[1] (keys.md)"), "{}", prompt);
+    assert!(!prompt.contains("billing.md"), "{}", prompt);
+}
+
+#[tokio::test]
+async fn min_score_drops_unrelated_chunks() {
+    let mock = mock();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let mut rag = Rag::new(client, "chat", "embed").min_score(0.5);
+    rag.ingest(&documents()).await.unwrap();
+
+    let hits = rag.retrieve("When do keys rotate?").await.unwrap();
+    assert_eq!(hits.len(), 1);
+}
+
+#[tokio::test]
+async fn renders_custom_template() {
+    let mock = mock();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let template = ChatTemplate::new()
+        .user("Context:\n{{context}}\nQ: {{question}}")
+        .unwrap();
+    let mut rag = Rag::new(client, "chat", "embed")
+        .top_k(1)
+        .template(template)
+        .unwrap();
+    rag.ingest(&documents()).await.unwrap();
+
+    rag.answer("When do keys rotate?").await.unwrap();
+    let prompt = prompt(&mock);
+    assert!(prompt.contains("Q: When do keys rotate?"), "{}", prompt);
+
+    let bad = ChatTemplate::new().user("{{question}} only").unwrap();
+    let client = LlamaCppClient::default().unwrap();
+    assert!(Rag::new(client, "chat", "embed").template(bad).is_err());
+}
+
+#[tokio::test]
+async fn reingesting_replaces_a_documents_chunks() {
+    let mock = MockTransport::new().json("/v1/embeddings", embedding_response(&[1.0, 0.0]));
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+    let mut rag = Rag::new(client, "chat", "embed").chunk_size(20, 0);
+
+    let long = Document::new("notes.md", "First sentence here. Second sentence here.");
+    assert_eq!(rag.ingest(&[long]).await.unwrap(), 2);
+    let short = Document::new("notes.md", "Only one now.");
+    assert_eq!(rag.ingest(&[short]).await.unwrap(), 1);
+    assert_eq!(rag.index().len(), 1);
+}
+
+#[tokio::test]
+async fn reranks_the_shortlist() {
+    let mock = mock().json(
+        "/v1/rerank",
+        json!({
+            "model": "rerank",
+            "object": "list",
+            "usage": { "prompt_tokens": 20, "total_tokens": 20 },
+            "results": [
+                { "index": 0, "relevance_score": -3.5 },
+                { "index": 1, "relevance_score": 2.25 }
+            ]
+        }),
+    );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let mut rag = Rag::new(client.clone(), "chat", "embed");
+    rag.ingest(&documents()).await.unwrap();
+
+    let err = rag
+        .retrieve_and_rerank("How am I billed?", 2, 1)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("reranker"), "{}", err);
+
+    let rag = rag.reranker("rerank");
+    let hits = rag
+        .retrieve_and_rerank("How am I billed?", 2, 1)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].chunk.source, "billing.md");
+    assert_eq!(hits[0].score, 2.25);
+
+    let body: serde_json::Value = mock.requests().pop().unwrap().json().unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "model": "rerank",
+            "query": "How am I billed?",
+            "documents": ["API keys rotate every 90 days.", "Invoices are sent monthly."],
+            "top_n": 1
+        })
+    );
+
+    // Results pointing past the documents are rejected
+    let mock = MockTransport::new().json(
+        "/v1/rerank",
+        json!({ "results": [{ "index": 2, "relevance_score": 1.0 }] }),
+    );
+    let client = client.with_transport(mock);
+    let request = lancor::RerankRequest::new("rerank", "query", ["a", "b"]);
+    let err = client.rerank(request).await.unwrap_err();
+    assert!(err.to_string().contains("out of range"), "{}", err);
+}
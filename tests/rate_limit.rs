@@ -0,0 +1,111 @@
+//! Client-side token-bucket rate limiting.
+
+#![cfg(feature = "runtime-tokio")]
+
+mod common;
+
+use common::{chat_response_with_usage, request};
+use lancor::transport::MockTransport;
+use lancor::{LancorConfig, LlamaCppClient, RateLimit};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn limits_requests_per_second() {
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(
+            MockTransport::new().json("/v1/chat/completions", chat_response_with_usage("Hi", 1, 1)),
+        )
+        .with_rate_limit(RateLimit::new().requests_per_second(20.0));
+
+    // A burst of 20 goes through at once; the 5 after it are spaced 50ms apart
+    let start = Instant::now();
+    for _ in 0..25 {
+        client.chat_completion(request()).await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn limits_tokens_per_minute() {
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(MockTransport::new().json(
+            "/v1/chat/completions",
+            chat_response_with_usage("Hi", 60, 1),
+        ))
+        .with_rate_limit(RateLimit::new().tokens_per_minute(60));
+
+    // The first request overdraws the bucket by one token, which takes a
+    // second to refill
+    let start = Instant::now();
+    client.chat_completion(request()).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(100));
+    client.chat_completion(request()).await.unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(3), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn limiting_a_clone_leaves_the_original_unlimited() {
+    let client = LlamaCppClient::default().unwrap().with_transport(
+        MockTransport::new().json("/v1/chat/completions", chat_response_with_usage("Hi", 1, 1)),
+    );
+    let limited = client
+        .clone()
+        .with_rate_limit(RateLimit::new().requests_per_second(20.0));
+    assert!(limited.config().rate_limit.is_some());
+    assert!(client.config().rate_limit.is_none());
+
+    let start = Instant::now();
+    for _ in 0..25 {
+        client.chat_completion(request()).await.unwrap();
+    }
+    assert!(
+        start.elapsed() < Duration::from_millis(100),
+        "{:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn reloading_the_config_changes_the_limits() {
+    let client = LlamaCppClient::default().unwrap().with_transport(
+        MockTransport::new().json("/v1/chat/completions", chat_response_with_usage("Hi", 1, 1)),
+    );
+    let config: LancorConfig =
+        serde_json::from_value(json!({ "rate_limit": { "requests_per_second": 20.0 } })).unwrap();
+    assert_eq!(
+        config.rate_limit,
+        Some(RateLimit::new().requests_per_second(20.0))
+    );
+
+    // A clone made before the reload is limited too
+    client.clone().reload_config(config);
+    let start = Instant::now();
+    for _ in 0..25 {
+        client.chat_completion(request()).await.unwrap();
+    }
+    assert!(
+        start.elapsed() >= Duration::from_millis(200),
+        "{:?}",
+        start.elapsed()
+    );
+
+    // The bucket is empty now, but a config without limits lets requests
+    // straight through
+    client.reload_config(LancorConfig::default());
+    let start = Instant::now();
+    for _ in 0..25 {
+        client.chat_completion(request()).await.unwrap();
+    }
+    assert!(
+        start.elapsed() < Duration::from_millis(100),
+        "{:?}",
+        start.elapsed()
+    );
+}
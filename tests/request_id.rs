@@ -0,0 +1,173 @@
+//! Request ids: generated per request, sent as `X-Request-Id` and reported
+//! on responses and errors along with the server's own id.
+
+mod common;
+
+use common::{chat_response, client, request};
+use lancor::transport::MockTransport;
+use lancor::{ApiError, LancorConfig, LlamaCppClient};
+
+fn is_uuid_v4(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+        && id.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+        && groups[2].starts_with('4')
+        && groups[3].starts_with(['8', '9', 'a', 'b'])
+}
+
+#[tokio::test]
+async fn every_request_gets_a_new_id() {
+    let mock = MockTransport::new().respond_with_headers(
+        "/v1/chat/completions",
+        200,
+        &[("x-request-id", "req_abc123")],
+        chat_response("Hi").to_string(),
+    );
+    let client = client(&mock);
+
+    let first = client.chat_completion(request()).await.unwrap();
+    let second = client.chat_completion(request()).await.unwrap();
+
+    let sent: Vec<String> = mock
+        .requests()
+        .iter()
+        .map(|r| r.header_value("x-request-id").unwrap().to_string())
+        .collect();
+    assert!(sent.iter().all(|id| is_uuid_v4(id)), "{:?}", sent);
+    assert_ne!(sent[0], sent[1]);
+    assert_eq!(first.request_id.as_deref(), Some(sent[0].as_str()));
+    assert_eq!(second.request_id.as_deref(), Some(sent[1].as_str()));
+    assert_eq!(first.server_request_id.as_deref(), Some("req_abc123"));
+}
+
+#[tokio::test]
+async fn errors_carry_both_ids() {
+    let mock = MockTransport::new().respond_with_headers(
+        "/v1/chat/completions",
+        500,
+        &[("apim-request-id", "6e0c0e8a")],
+        r#"{"error":{"message":"internal error"}}"#,
+    );
+
+    let err = client(&mock).chat_completion(request()).await.unwrap_err();
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    let sent = mock.requests()[0]
+        .header_value("x-request-id")
+        .unwrap()
+        .to_string();
+    assert_eq!(api_error.request_id.as_deref(), Some(sent.as_str()));
+    assert_eq!(api_error.server_request_id.as_deref(), Some("6e0c0e8a"));
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn retries_keep_the_id() {
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 503, "Service Unavailable")
+        .respond("/v1/chat/completions", 200, chat_response("Hi").to_string());
+    let client = client(&mock).with_retry(lancor::RetryPolicy::new(1).backoff(
+        std::time::Duration::from_millis(1),
+        std::time::Duration::from_millis(1),
+    ));
+
+    let response = client.chat_completion(request()).await.unwrap();
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].header_value("x-request-id"),
+        requests[1].header_value("x-request-id")
+    );
+    assert_eq!(
+        response.request_id.as_deref(),
+        requests[0].header_value("x-request-id")
+    );
+}
+
+#[tokio::test]
+async fn a_configured_id_is_kept() {
+    let mock =
+        MockTransport::new().respond("/v1/chat/completions", 200, chat_response("Hi").to_string());
+    let config = LancorConfig::default().header("X-Request-Id", "batch-42");
+    let client = LlamaCppClient::from_config(config)
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let response = client.chat_completion(request()).await.unwrap();
+    assert_eq!(response.request_id.as_deref(), Some("batch-42"));
+    let sent = &mock.requests()[0];
+    assert_eq!(
+        sent.headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("x-request-id"))
+            .count(),
+        1
+    );
+}
+
+#[cfg(feature = "tracing")]
+mod spans {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Collects the fields recorded on spans
+    #[derive(Default, Clone)]
+    struct Fields(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl Subscriber for Fields {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[tokio::test]
+    async fn requests_run_in_a_span_with_their_ids() {
+        let mock = MockTransport::new().respond_with_headers(
+            "/v1/chat/completions",
+            200,
+            &[("x-request-id", "req_abc123")],
+            chat_response("Hi").to_string(),
+        );
+        let fields = Fields::default();
+        let _guard = tracing::subscriber::set_default(fields.clone());
+
+        let response = client(&mock).chat_completion(request()).await.unwrap();
+
+        let fields = fields.0.lock().unwrap();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(field("request_id"), response.request_id);
+        assert_eq!(
+            field("server_request_id").as_deref(),
+            Some("\"req_abc123\"")
+        );
+        assert_eq!(field("status").as_deref(), Some("200"));
+        assert_eq!(field("action").as_deref(), Some("\"chat completion\""));
+    }
+}
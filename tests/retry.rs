@@ -0,0 +1,177 @@
+//! Retrying overloaded and unreachable servers, honouring `Retry-After`.
+
+#![cfg(feature = "runtime-tokio")]
+
+mod common;
+
+use common::{chat_response, request};
+use lancor::transport::MockTransport;
+use lancor::{ApiError, EmbeddingRequest, LlamaCppClient, RequestKind, RetryPolicy};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+fn client(mock: &MockTransport, policy: RetryPolicy) -> LlamaCppClient {
+    common::client(mock).with_retry(policy)
+}
+
+const RATE_LIMITED: &str =
+    r#"{"error":{"message":"Rate limit reached","type":"rate_limit_error"}}"#;
+
+#[tokio::test]
+async fn waits_as_long_as_retry_after_asks() {
+    let mock = MockTransport::new()
+        .respond_with_headers(
+            "/v1/chat/completions",
+            429,
+            &[("Retry-After", "1")],
+            RATE_LIMITED,
+        )
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let policy = RetryPolicy::new(2).backoff(Duration::from_millis(10), Duration::from_millis(10));
+
+    let start = Instant::now();
+    let response = client(&mock, policy).chat_completion(request()).await;
+    let elapsed = start.elapsed();
+
+    assert_eq!(response.unwrap().choices[0].message.content.text(), "Hi");
+    assert_eq!(mock.requests().len(), 2);
+    assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn retry_after_is_capped() {
+    let mock = MockTransport::new()
+        .respond_with_headers(
+            "/v1/chat/completions",
+            503,
+            &[("Retry-After", "3600")],
+            "Service Unavailable",
+        )
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let policy = RetryPolicy::new(1).max_retry_after(Duration::from_millis(50));
+
+    let start = Instant::now();
+    client(&mock, policy)
+        .chat_completion(request())
+        .await
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries_with_the_last_error() {
+    let mock = MockTransport::new().respond_with_headers(
+        "/v1/chat/completions",
+        429,
+        &[("Retry-After", "0")],
+        RATE_LIMITED,
+    );
+
+    let err = client(&mock, RetryPolicy::new(2))
+        .chat_completion(request())
+        .await
+        .unwrap_err();
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(api_error.status, 429);
+    assert_eq!(api_error.retry_after, Some(Duration::ZERO));
+    assert_eq!(mock.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 400, r#"{"error":"bad request"}"#)
+        .json("/v1/chat/completions", chat_response("Hi"));
+
+    let err = client(&mock, RetryPolicy::new(3))
+        .chat_completion(request())
+        .await
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<ApiError>().unwrap().status, 400);
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn http_dates_are_understood() {
+    let mock = MockTransport::new().respond_with_headers(
+        "/v1/chat/completions",
+        503,
+        &[("Retry-After", "Wed, 21 Oct 2015 07:28:00 GMT")],
+        "Service Unavailable",
+    );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    // A date in the past means "now"
+    let err = client.chat_completion(request()).await.unwrap_err();
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(api_error.retry_after, Some(Duration::ZERO));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn each_kind_of_request_can_have_its_own_policy() {
+    let fast = || RetryPolicy::new(1).backoff(Duration::from_millis(1), Duration::from_millis(1));
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 503, "Service Unavailable")
+        .respond("/v1/embeddings", 503, "Service Unavailable")
+        .respond("/v1/embeddings", 503, "Service Unavailable")
+        .json(
+            "/v1/embeddings",
+            json!({
+                "object": "list",
+                "model": "embed",
+                "data": [{ "object": "embedding", "index": 0, "embedding": [1.0] }],
+                "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+            }),
+        );
+    let client = client(&mock, fast())
+        .without_retry_for(RequestKind::Chat)
+        .with_retry_for(
+            RequestKind::Embedding,
+            RetryPolicy::new(5).backoff(Duration::from_millis(1), Duration::from_millis(1)),
+        );
+
+    assert!(client.chat_completion(request()).await.is_err());
+    assert_eq!(mock.requests().len(), 1);
+
+    client
+        .embedding(EmbeddingRequest::new("embed", "hello"))
+        .await
+        .unwrap();
+    assert_eq!(mock.requests().len(), 4);
+}
+
+#[tokio::test]
+async fn predicates_decide_which_errors_are_retried() {
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 500, "Internal Server Error")
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let policy = RetryPolicy::new(2)
+        .backoff(Duration::from_millis(1), Duration::from_millis(1))
+        .retry_if(|error| {
+            RetryPolicy::is_transient(error)
+                || error
+                    .downcast_ref::<ApiError>()
+                    .is_some_and(|e| e.status == 500)
+        });
+    client(&mock, policy)
+        .chat_completion(request())
+        .await
+        .unwrap();
+    assert_eq!(mock.requests().len(), 2);
+
+    let mock = MockTransport::new()
+        .respond("/v1/chat/completions", 503, "Service Unavailable")
+        .json("/v1/chat/completions", chat_response("Hi"));
+    let never = RetryPolicy::new(2).retry_if(|_| false);
+    assert!(
+        client(&mock, never)
+            .chat_completion(request())
+            .await
+            .is_err()
+    );
+    assert_eq!(mock.requests().len(), 1);
+}
@@ -0,0 +1,38 @@
+//! Driving the client from an executor other than Tokio.
+
+mod common;
+
+use common::{chat_response, request};
+use futures::executor::block_on;
+use futures::stream::StreamExt;
+use lancor::LlamaCppClient;
+use lancor::transport::MockTransport;
+use serde_json::json;
+
+#[test]
+fn requests_and_streams_run_without_a_tokio_runtime() {
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }]
+    });
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("Hello"))
+        .sse("/v1/chat/completions", vec![chunk.clone(), chunk]);
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock)
+        .max_concurrent_requests(1);
+
+    block_on(async {
+        let response = client.chat_completion(request()).await.unwrap();
+        assert_eq!(response.choices[0].message.content.text(), "Hello");
+
+        let stream = client.chat_completion_stream(request()).await.unwrap();
+        let chunks: Vec<_> = stream.collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(Result::is_ok));
+    });
+}
@@ -0,0 +1,161 @@
+//! Validating values with `lancor::schema`.
+
+#![cfg(feature = "schema")]
+
+use lancor::schema;
+#[cfg(not(feature = "jsonschema"))]
+use lancor::schema::SchemaViolation;
+use serde_json::json;
+
+#[cfg(not(feature = "jsonschema"))]
+fn problems(schema: &serde_json::Value, value: serde_json::Value) -> Vec<String> {
+    schema::validate(schema, &value)
+        .iter()
+        .map(SchemaViolation::to_string)
+        .collect()
+}
+
+#[test]
+#[cfg(not(feature = "jsonschema"))]
+fn checks_objects_and_their_properties() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "city": { "type": "string", "minLength": 2 },
+            "days": { "type": "integer", "minimum": 1, "maximum": 14 },
+            "unit": { "enum": ["c", "f"] }
+        },
+        "required": ["city"],
+        "additionalProperties": false
+    });
+
+    assert!(schema::is_valid(
+        &schema,
+        &json!({ "city": "Lisbon", "days": 3.0 })
+    ));
+    assert_eq!(
+        problems(&schema, json!({ "days": 0, "unit": "k", "extra": true })),
+        [
+            "missing required property \"city\"",
+            "/days: expected at least 1, got 0",
+            "unexpected property \"extra\"",
+            "/unit: expected one of \"c\", \"f\", got \"k\"",
+        ]
+    );
+    assert_eq!(
+        problems(&schema, json!({ "city": 7, "days": 2.5 })),
+        [
+            "/city: expected string, got 7",
+            "/days: expected integer, got 2.5"
+        ]
+    );
+    assert_eq!(problems(&schema, json!([])), ["expected object, got []"]);
+}
+
+#[test]
+#[cfg(not(feature = "jsonschema"))]
+fn checks_arrays_refs_and_combinators() {
+    let schema = json!({
+        "$defs": {
+            "tag": { "type": "string", "maxLength": 3 }
+        },
+        "type": "object",
+        "properties": {
+            "tags": {
+                "type": "array",
+                "items": { "$ref": "#/$defs/tag" },
+                "minItems": 1,
+                "uniqueItems": true
+            },
+            "id": { "anyOf": [{ "type": "integer" }, { "type": "null" }] },
+            "mode": { "oneOf": [{ "const": "a" }, { "type": "string", "maxLength": 0 }] }
+        }
+    });
+
+    assert!(schema::is_valid(
+        &schema,
+        &json!({ "tags": ["a", "b"], "id": null, "mode": "a" })
+    ));
+    assert_eq!(
+        problems(
+            &schema,
+            json!({ "tags": ["a", "long", "a"], "id": "x", "mode": "b" })
+        ),
+        [
+            "/id: matches none of the allowed schemas",
+            "/mode: expected to match exactly one schema, matches 0",
+            "/tags: item 2 repeats an earlier item",
+            "/tags/1: expected at most 3 characters, got 4",
+        ]
+    );
+    assert_eq!(
+        problems(&schema, json!({ "tags": [] })),
+        ["/tags: expected at least 1 items, got 0"]
+    );
+
+    // A cyclic reference ends
+    let cyclic = json!({ "$defs": { "a": { "$ref": "#/$defs/a" } }, "$ref": "#/$defs/a" });
+    assert!(problems(&cyclic, json!(1))[0].contains("nests too deeply"));
+    assert_eq!(
+        problems(&json!({ "$ref": "#/$defs/missing" }), json!(1)),
+        ["unknown schema reference #/$defs/missing"]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "jsonschema"))]
+fn rejects_schemas_with_keywords_it_cannot_check() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "code": { "type": "string", "pattern": "^[A-Z]{3}$" },
+            "when": { "type": "string", "format": "date-time" },
+            "size": { "type": "integer", "format": "uint8" }
+        }
+    });
+
+    assert_eq!(
+        schema::unchecked_keywords(&schema),
+        ["#/properties/code/pattern", "#/properties/when/format"]
+    );
+    // Even a value that would pass is not accepted on a partial check
+    assert_eq!(
+        problems(&schema, json!({ "code": "ABC" })),
+        [
+            "schema keyword #/properties/code/pattern can only be checked with the jsonschema feature",
+            "schema keyword #/properties/when/format can only be checked with the jsonschema feature",
+        ]
+    );
+    assert_eq!(
+        schema::CompiledSchema::compile(&schema)
+            .unwrap_err()
+            .problems
+            .len(),
+        2
+    );
+    // Formats outside the spec only annotate
+    assert!(schema::is_valid(
+        &json!({ "type": "integer", "format": "uint8" }),
+        &json!(7)
+    ));
+}
+
+#[test]
+#[cfg(feature = "jsonschema")]
+fn checks_patterns_and_formats_with_the_jsonschema_feature() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "code": { "type": "string", "pattern": "^[A-Z]{3}$" },
+            "when": { "type": "string", "format": "date-time" }
+        }
+    });
+
+    assert!(schema::unchecked_keywords(&schema).is_empty());
+    let compiled = schema::CompiledSchema::compile(&schema).unwrap();
+    assert!(compiled.is_valid(&json!({ "code": "ABC", "when": "2026-10-15T12:00:00Z" })));
+    let found = compiled.validate(&json!({ "code": "abc", "when": "today" }));
+    let paths: Vec<&str> = found.iter().map(|v| v.path.as_str()).collect();
+    assert_eq!(found.len(), 2);
+    assert!(paths.contains(&"/code") && paths.contains(&"/when"));
+}
@@ -0,0 +1,193 @@
+//! Tool and output schemas derived with the `schemars` feature.
+
+#![cfg(all(feature = "schemars", feature = "agent"))]
+
+mod common;
+
+use common::reply;
+use lancor::transport::MockTransport;
+use lancor::{ChatSession, LlamaCppClient, Message, OutputSchema, Tool, ToolRegistry, run_agent};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct City {
+    /// The city's English name
+    name: String,
+    population: u64,
+    mayor: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Unit {
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct Forecast {
+    city: String,
+    unit: Unit,
+}
+
+/// A type deriving `JsonSchema` can still implement `OutputSchema` by hand
+#[derive(Deserialize, JsonSchema)]
+struct Handwritten {
+    answer: bool,
+}
+
+impl OutputSchema for Handwritten {
+    fn json_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": { "answer": { "type": "boolean" } },
+            "required": ["answer"]
+        })
+    }
+}
+
+#[test]
+fn derived_schemas_describe_the_type() {
+    let schema = lancor::schema::of::<City>();
+    assert!(schema.get("$schema").is_none());
+    assert_eq!(schema["type"], "object");
+    assert_eq!(
+        schema["properties"]["name"]["description"],
+        "The city's English name"
+    );
+    assert_eq!(schema["required"], json!(["name", "population"]));
+    assert!(lancor::schema::is_valid(
+        &schema,
+        &json!({ "name": "Tokyo", "population": 14_000_000, "mayor": null })
+    ));
+    assert!(!lancor::schema::is_valid(
+        &schema,
+        &json!({ "name": "Tokyo" })
+    ));
+
+    // Nested types are referenced from $defs, which validation follows
+    let schema = lancor::schema::of::<Forecast>();
+    assert!(schema["$defs"]["Unit"].is_object());
+    assert!(lancor::schema::is_valid(
+        &schema,
+        &json!({ "city": "Lisbon", "unit": "celsius" })
+    ));
+    let violations =
+        lancor::schema::validate(&schema, &json!({ "city": "Lisbon", "unit": "kelvin" }));
+    assert_eq!(violations[0].path, "/unit");
+
+    assert_eq!(<Handwritten as OutputSchema>::schema_name(), "Handwritten");
+    assert_eq!(
+        <Handwritten as OutputSchema>::json_schema()["required"],
+        json!(["answer"])
+    );
+    let parsed: Handwritten = lancor::structured::parse_output(r#"{"answer": true}"#).unwrap();
+    assert!(parsed.answer);
+
+    let tool = Tool::function_for::<Forecast>("forecast", "Tomorrow's weather");
+    assert_eq!(tool.function.name, "forecast");
+    assert_eq!(tool.function.parameters, lancor::schema::of::<Forecast>());
+}
+
+#[tokio::test]
+async fn generate_typed_uses_the_derived_schema() {
+    let mock = MockTransport::new().json(
+        "/v1/chat/completions",
+        reply(json!({
+            "role": "assistant",
+            "content": r#"{"name": "Tokyo", "population": 14000000}"#
+        })),
+    );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let city: City = client
+        .generate_typed("test-model", "The largest city in Japan")
+        .await
+        .unwrap();
+    assert_eq!(city.name, "Tokyo");
+    assert_eq!(city.population, 14_000_000);
+    assert_eq!(city.mayor, None);
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    let format = &body["response_format"];
+    assert_eq!(format["type"], "json_schema");
+    assert_eq!(format["json_schema"]["name"], "City");
+    assert_eq!(
+        format["json_schema"]["schema"],
+        lancor::schema::of::<City>()
+    );
+}
+
+#[tokio::test]
+async fn typed_tools_get_deserialized_arguments() {
+    let call = |arguments: &str| {
+        reply(json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_0",
+                "type": "function",
+                "function": { "name": "forecast", "arguments": arguments }
+            }]
+        }))
+    };
+    let mock = MockTransport::new()
+        .json(
+            "/v1/chat/completions",
+            call(r#"{"city": "Lisbon", "unit": "kelvin"}"#),
+        )
+        .json(
+            "/v1/chat/completions",
+            call(r#"{"city": "Lisbon", "unit": "fahrenheit"}"#),
+        )
+        .json(
+            "/v1/chat/completions",
+            reply(json!({ "role": "assistant", "content": "Warm." })),
+        );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let mut tools = ToolRegistry::new();
+    tools
+        .register_typed(
+            "forecast",
+            "Tomorrow's weather",
+            |args: Forecast| async move {
+                let degrees = match args.unit {
+                    Unit::Celsius => "25C",
+                    Unit::Fahrenheit => "77F",
+                };
+                Ok(format!("{} in {}", degrees, args.city))
+            },
+        )
+        .unwrap();
+
+    let mut session = ChatSession::new(client, "test-model");
+    session.push(Message::user("Weather in Lisbon tomorrow?"));
+    let answer = run_agent(&mut session, &tools, 5).await.unwrap();
+    assert_eq!(answer, "Warm.");
+
+    let outputs: Vec<String> = session
+        .history()
+        .iter()
+        .filter(|message| message.role == "tool")
+        .map(|message| message.content.text())
+        .collect();
+    assert!(
+        outputs[0].starts_with("Error: Invalid arguments for forecast: /unit:"),
+        "{}",
+        outputs[0]
+    );
+    assert_eq!(outputs[1], "77F in Lisbon");
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(
+        body["tools"][0]["function"]["parameters"],
+        lancor::schema::of::<Forecast>()
+    );
+}
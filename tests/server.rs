@@ -0,0 +1,161 @@
+//! Supervising a llama-server process, using shell scripts in its place.
+
+#![cfg(all(feature = "server", unix))]
+
+use lancor::server::{LlamaServer, ServerConfig, ServerStatus};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// An executable script standing in for llama-server
+fn fake_server(name: &str, body: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lancor-server-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// Answer every HTTP request on a free port with 200 OK, like a ready
+/// server's /health
+async fn healthy_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]",
+                    )
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+async fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for(server: &LlamaServer, status: ServerStatus) {
+    for _ in 0..100 {
+        if server.status() == status {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!(
+        "server never reached {:?}, is {:?}",
+        status,
+        server.status()
+    );
+}
+
+#[test]
+fn command_line_includes_the_options() {
+    let config = ServerConfig::new("/models/m.gguf")
+        .port(9000)
+        .ctx_size(4096)
+        .gpu_layers(33)
+        .arg("--flash-attn");
+
+    assert_eq!(config.url(), "http://127.0.0.1:9000");
+    assert_eq!(
+        config.command_args(),
+        [
+            "-m",
+            "/models/m.gguf",
+            "--host",
+            "127.0.0.1",
+            "--port",
+            "9000",
+            "-c",
+            "4096",
+            "-ngl",
+            "33",
+            "--flash-attn"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn waits_for_health_and_shuts_down() {
+    let port = healthy_port().await;
+    let config = ServerConfig::new("m.gguf")
+        .binary(fake_server("sleepy", "exec sleep 30"))
+        .port(port);
+
+    let server = LlamaServer::spawn(config).await.unwrap();
+    assert_eq!(server.status(), ServerStatus::Ready);
+    assert_eq!(server.url(), format!("http://127.0.0.1:{}", port));
+    assert_eq!(server.restarts(), 0);
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn startup_failure_includes_the_output() {
+    let config = ServerConfig::new("missing.gguf")
+        .binary(fake_server(
+            "broken",
+            "echo 'error: failed to load model' >&2; exit 1",
+        ))
+        .port(free_port().await);
+
+    let err = LlamaServer::spawn(config).await.unwrap_err().to_string();
+    assert!(err.contains("exited during startup"), "{}", err);
+    assert!(err.contains("failed to load model"), "{}", err);
+}
+
+#[tokio::test]
+async fn startup_times_out_without_health() {
+    let config = ServerConfig::new("m.gguf")
+        .binary(fake_server("silent", "exec sleep 30"))
+        .port(free_port().await)
+        .startup_timeout(Duration::from_millis(300));
+
+    let err = LlamaServer::spawn(config).await.unwrap_err().to_string();
+    assert!(err.contains("not ready within"), "{}", err);
+}
+
+#[tokio::test]
+async fn restarts_a_crashed_server_until_the_limit() {
+    let port = healthy_port().await;
+    let config = ServerConfig::new("m.gguf")
+        .binary(fake_server("crashy", "sleep 0.2; exit 3"))
+        .port(port)
+        .max_restarts(Some(1));
+
+    let server = LlamaServer::spawn(config).await.unwrap();
+    wait_for(&server, ServerStatus::Stopped).await;
+    assert_eq!(server.restarts(), 1);
+    assert!(
+        server.last_exit().unwrap().contains('3'),
+        "{:?}",
+        server.last_exit()
+    );
+}
+
+#[tokio::test]
+async fn failed_restarts_are_reported() {
+    let port = healthy_port().await;
+    // The script deletes itself, so the restart cannot start it again
+    let config = ServerConfig::new("m.gguf")
+        .binary(fake_server("vanishing", "rm \"$0\"; sleep 0.2; exit 3"))
+        .port(port)
+        .max_restarts(Some(1));
+
+    let server = LlamaServer::spawn(config).await.unwrap();
+    assert_eq!(server.restart_error(), None);
+    wait_for(&server, ServerStatus::Stopped).await;
+    let error = server.restart_error().unwrap();
+    assert!(error.contains("Failed to start"), "{}", error);
+}
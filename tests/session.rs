@@ -0,0 +1,133 @@
+//! Saving and resuming chat sessions.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{chat_response, client};
+use lancor::session::ChatSession;
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, LlamaCppClient};
+use serde_json::{Value, json};
+
+#[tokio::test]
+async fn saved_sessions_resume_where_they_left_off() {
+    let path = std::env::temp_dir().join(format!("lancor-session-{}.json", std::process::id()));
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("Hello, Ada!"))
+        .json("/v1/chat/completions", chat_response("Your name is Ada."));
+
+    let mut session = ChatSession::new(client(&mock), "test-model")
+        .system_prompt("You are friendly.")
+        .defaults(
+            ChatCompletionRequest::new("test-model")
+                .temperature(0.2)
+                .max_tokens(64)
+                .extra("min_p", 0.05),
+        );
+    session.send("My name is Ada.").await.unwrap();
+    session.save(&path).unwrap();
+
+    let mut resumed = ChatSession::load(client(&mock), &path).unwrap();
+    assert_eq!(resumed.history().len(), 2);
+    assert_eq!(resumed.history()[1].content.text(), "Hello, Ada!");
+    assert_eq!(resumed.messages()[0].content.text(), "You are friendly.");
+    assert_eq!(resumed.metadata(), session.metadata());
+
+    let reply = resumed.send("What is my name?").await.unwrap();
+    assert_eq!(reply, "Your name is Ada.");
+    let body: Value = mock.requests()[1].json().unwrap();
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["temperature"], json!(0.2f32));
+    assert_eq!(body["max_tokens"], 64);
+    assert_eq!(body["min_p"], 0.05);
+    let contents: Vec<_> = body["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        contents,
+        [
+            "You are friendly.",
+            "My name is Ada.",
+            "Hello, Ada!",
+            "What is my name?"
+        ]
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn newer_session_files_are_rejected() {
+    let path = std::env::temp_dir().join(format!("lancor-session-v9-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        json!({ "version": 9, "settings": { "model": "m", "messages": [] } }).to_string(),
+    )
+    .unwrap();
+
+    let client = LlamaCppClient::default().unwrap();
+    let err = ChatSession::load(client, &path).unwrap_err();
+    assert!(err.to_string().contains("version 9"), "{}", err);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn messages_carry_metadata_that_is_not_sent() {
+    let mut response = chat_response("Hi there!");
+    response["usage"] = json!({ "prompt_tokens": 12, "completion_tokens": 4, "total_tokens": 16 });
+    let chunk = json!({
+        "id": "chatcmpl-2",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "served-model",
+        "choices": [{ "index": 0, "delta": { "content": "Bye" }, "finish_reason": null }]
+    });
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", response)
+        .sse("/v1/chat/completions", vec![chunk]);
+    let mut session = ChatSession::new(client(&mock), "test-model").temperature(0.5);
+
+    session.send("Hello").await.unwrap();
+    let mut stream = session.send_stream("Goodbye").await.unwrap();
+    while futures::StreamExt::next(&mut stream).await.is_some() {}
+    drop(stream);
+
+    let metadata = session.metadata();
+    assert_eq!(metadata.len(), 4);
+    assert!(metadata.iter().all(|m| m.id.starts_with("msg_")));
+    assert_ne!(metadata[0].id, metadata[1].id);
+    assert!(metadata[0].created_at > 0);
+    assert!(metadata[0].params.is_none());
+
+    let reply = &metadata[1];
+    assert_eq!(reply.tokens, 4);
+    assert_eq!(reply.model.as_deref(), Some("test-model"));
+    let params = reply.params.as_ref().unwrap();
+    assert_eq!(params["temperature"], json!(0.5));
+    assert!(!params.contains_key("messages"));
+
+    let streamed = &metadata[3];
+    assert_eq!(streamed.model.as_deref(), Some("served-model"));
+    assert!(streamed.tokens > 0);
+
+    let (message, _) = session.history_with_metadata().nth(3).unwrap();
+    assert_eq!(message.content.text(), "Bye");
+    let body: Value = mock.requests()[1].json().unwrap();
+    for message in body["messages"].as_array().unwrap() {
+        assert_eq!(message.as_object().unwrap().len(), 2, "{}", message);
+    }
+}
+
+#[tokio::test]
+async fn failed_turns_leave_no_metadata_behind() {
+    let mock = MockTransport::new().respond("/v1/chat/completions", 500, "boom");
+    let mut session = ChatSession::new(client(&mock), "test-model");
+
+    assert!(session.send("Hello").await.is_err());
+    assert!(session.history().is_empty());
+    assert!(session.metadata().is_empty());
+}
@@ -0,0 +1,88 @@
+//! Draining and aborting in-flight requests with `shutdown`.
+
+#![cfg(feature = "runtime-tokio")]
+
+mod common;
+
+use common::request;
+use futures::stream::StreamExt;
+use lancor::LlamaCppClient;
+use lancor::transport::{HttpRequest, HttpResponse, MockTransport, Transport};
+use serde_json::json;
+use std::time::Duration;
+
+/// Streams one chunk and then stalls forever
+#[derive(Debug, Clone)]
+struct StalledTransport;
+
+impl Transport for StalledTransport {
+    fn send(&self, _request: HttpRequest) -> lancor::BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        Box::pin(async move {
+            let first = format!("data: {}\n\n", chunk());
+            let body =
+                futures::stream::iter([Ok(first.into_bytes())]).chain(futures::stream::pending());
+            Ok(HttpResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: Box::pin(body),
+            })
+        })
+    }
+}
+
+fn chunk() -> serde_json::Value {
+    json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }]
+    })
+}
+
+#[tokio::test]
+async fn waits_for_streams_then_refuses_new_requests() {
+    let mock = MockTransport::new().sse("/v1/chat/completions", vec![chunk(), chunk()]);
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let stream = client.chat_completion_stream(request()).await.unwrap();
+    let read = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stream.collect::<Vec<_>>().await
+    };
+    let (report, chunks) = tokio::join!(client.shutdown(Duration::from_secs(5)), read);
+
+    assert_eq!(report.completed, 1);
+    assert!(report.aborted.is_empty());
+    assert!(chunks.iter().all(Result::is_ok));
+    assert!(client.is_shut_down());
+
+    let Err(err) = client.clone().chat_completion_stream(request()).await else {
+        panic!("a shut down client should refuse requests");
+    };
+    assert!(err.to_string().contains("shutting down"), "{}", err);
+}
+
+#[tokio::test]
+async fn aborts_what_outlasts_the_grace_period() {
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(StalledTransport);
+
+    let mut stream = client.chat_completion_stream(request()).await.unwrap();
+    stream.next().await.unwrap().unwrap();
+
+    let report = client.shutdown(Duration::from_millis(50)).await;
+    assert_eq!(report.completed, 0);
+    assert_eq!(report.aborted.len(), 1);
+    assert_eq!(report.aborted[0].action, "streaming chat completion");
+    assert!(!report.aborted[0].request_id.is_empty());
+    assert!(report.aborted[0].elapsed >= Duration::from_millis(50));
+
+    let err = stream.next().await.unwrap().unwrap_err();
+    assert!(format!("{:#}", err).contains("aborted"), "{:#}", err);
+    assert!(stream.next().await.is_none());
+
+    let again = client.shutdown(Duration::from_secs(5)).await;
+    assert!(again.aborted.is_empty());
+}
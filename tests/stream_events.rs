@@ -0,0 +1,782 @@
+//! Typed events over streamed chat completions.
+
+mod common;
+
+use common::client;
+use futures::StreamExt;
+use lancor::transport::MockTransport;
+use lancor::{
+    ChatCompletionRequest, ChatEvent, ChatStreamExt, FinishReason, FunctionCall, FunctionCallDelta,
+    LlamaCppClient, Message, ToolCall, ToolCallAccumulator, ToolCallDelta,
+};
+use serde_json::{Value, json};
+
+fn chunk(delta: Value, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "qwen",
+        "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }]
+    })
+}
+
+fn request() -> ChatCompletionRequest {
+    ChatCompletionRequest::new("qwen").message(Message::user("Hi"))
+}
+
+#[tokio::test]
+async fn chunks_become_events() {
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            chunk(json!({ "role": "assistant", "content": "" }), None),
+            chunk(json!({ "reasoning_content": "The user greets." }), None),
+            chunk(json!({ "content": "Hel" }), None),
+            chunk(json!({ "content": "lo" }), None),
+            chunk(json!({}), Some("stop")),
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "qwen",
+                "choices": [],
+                "usage": { "prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7 }
+            }),
+        ],
+    );
+
+    let stream = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap();
+    // The event stream can move to another task
+    let events = tokio::spawn(async move {
+        stream
+            .events()
+            .map(|event| event.unwrap())
+            .collect::<Vec<_>>()
+            .await
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(events.len(), 6, "{:?}", events);
+    assert_eq!(events[0], ChatEvent::Role("assistant".to_string()));
+    assert_eq!(
+        events[1],
+        ChatEvent::ReasoningDelta("The user greets.".to_string())
+    );
+    assert_eq!(events[2], ChatEvent::ContentDelta("Hel".to_string()));
+    assert_eq!(events[3], ChatEvent::ContentDelta("lo".to_string()));
+    let ChatEvent::Usage(usage) = &events[4] else {
+        panic!("{:?}", events[4]);
+    };
+    assert_eq!(usage.total_tokens, 7);
+    assert_eq!(events[5], ChatEvent::Done(Some(FinishReason::Stop)));
+}
+
+#[tokio::test]
+async fn tool_call_fragments_are_passed_on() {
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            chunk(
+                json!({ "tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "get_weather", "arguments": "" }
+                }] }),
+                None,
+            ),
+            chunk(
+                json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "{\"city\":" } }] }),
+                None,
+            ),
+            chunk(json!({}), Some("tool_calls")),
+        ],
+    );
+
+    let events: Vec<_> = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .events()
+        .map(|event| event.unwrap())
+        .collect()
+        .await;
+
+    assert_eq!(
+        events,
+        [
+            ChatEvent::ToolCallDelta(ToolCallDelta {
+                index: 0,
+                id: Some("call_1".to_string()),
+                kind: Some("function".to_string()),
+                function: Some(FunctionCallDelta {
+                    name: Some("get_weather".to_string()),
+                    arguments: Some(String::new()),
+                }),
+            }),
+            ChatEvent::ToolCallDelta(ToolCallDelta {
+                index: 0,
+                id: None,
+                kind: None,
+                function: Some(FunctionCallDelta {
+                    name: None,
+                    arguments: Some("{\"city\":".to_string()),
+                }),
+            }),
+            // Cut short, so only emitted whole when the stream ends
+            ChatEvent::ToolCall(ToolCall {
+                id: "call_1".to_string(),
+                kind: "function".to_string(),
+                function: FunctionCall {
+                    name: "get_weather".to_string(),
+                    arguments: "{\"city\":".to_string(),
+                },
+            }),
+            ChatEvent::Done(Some(FinishReason::ToolCalls)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn errors_end_without_done() {
+    let body = concat!(
+        "data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"error\":{\"code\":502,\"message\":\"Provider returned error\"}}\n\n",
+    );
+    let mock = MockTransport::new().respond("/v1/chat/completions", 200, body);
+
+    let events: Vec<_> = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .events()
+        .collect()
+        .await;
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        *events[0].as_ref().unwrap(),
+        ChatEvent::ContentDelta("Hi".to_string())
+    );
+    assert!(events[1].is_err());
+}
+
+fn fragment(index: u32, id: Option<&str>, name: Option<&str>, arguments: &str) -> ToolCallDelta {
+    ToolCallDelta {
+        index,
+        id: id.map(str::to_string),
+        kind: id.map(|_| "function".to_string()),
+        function: Some(FunctionCallDelta {
+            name: name.map(str::to_string),
+            arguments: Some(arguments.to_string()),
+        }),
+    }
+}
+
+#[tokio::test]
+async fn tool_calls_are_emitted_whole_once_their_arguments_parse() {
+    let tool_chunk = |delta: ToolCallDelta| chunk(json!({ "tool_calls": [delta] }), None);
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            tool_chunk(fragment(0, Some("call_a"), Some("get_weather"), "")),
+            tool_chunk(fragment(0, None, None, "{\"city\": ")),
+            tool_chunk(fragment(1, Some("call_b"), Some("get_time"), "{\"tz\"")),
+            tool_chunk(fragment(0, None, None, "\"Paris\"}")),
+            tool_chunk(fragment(1, None, None, ": \"CET\"}")),
+            chunk(json!({}), Some("tool_calls")),
+        ],
+    );
+
+    let calls: Vec<ToolCall> = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .events()
+        .filter_map(|event| async move {
+            match event.unwrap() {
+                ChatEvent::ToolCall(call) => Some(call),
+                _ => None,
+            }
+        })
+        .collect()
+        .await;
+
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].id, "call_a");
+    assert_eq!(calls[0].function.name, "get_weather");
+    assert_eq!(calls[0].function.arguments, r#"{"city": "Paris"}"#);
+    assert_eq!(calls[1].id, "call_b");
+    assert_eq!(calls[1].function.arguments, r#"{"tz": "CET"}"#);
+}
+
+#[test]
+fn accumulator_separates_calls_by_id_and_fills_in_gaps() {
+    let mut accumulator = ToolCallAccumulator::new();
+
+    // Some servers send every call at index 0, told apart by id
+    assert_eq!(
+        accumulator
+            .push(&fragment(0, Some("call_a"), Some("list_files"), ""))
+            .map(|call| call.id),
+        None
+    );
+    let second = accumulator
+        .push(&fragment(
+            0,
+            Some("call_b"),
+            Some("read_file"),
+            r#"{"path":"a.txt"}"#,
+        ))
+        .unwrap();
+    assert_eq!(second.function.name, "read_file");
+
+    // The argument-less call never parsed, so it comes out at the end
+    let rest = accumulator.finish();
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0].id, "call_a");
+    assert_eq!(rest[0].function.arguments, "{}");
+    assert!(accumulator.finish().is_empty());
+
+    assert_eq!(
+        accumulator
+            .calls()
+            .iter()
+            .map(|call| call.id.as_str())
+            .collect::<Vec<_>>(),
+        ["call_a", "call_b"]
+    );
+}
+
+async fn stopped_text(pieces: &[&str], stops: &[&str]) -> (String, Vec<Option<FinishReason>>) {
+    let mut chunks: Vec<Value> = pieces
+        .iter()
+        .map(|piece| chunk(json!({ "content": piece }), None))
+        .collect();
+    chunks.push(chunk(json!({}), Some("length")));
+    let mock = MockTransport::new().sse("/v1/chat/completions", chunks);
+
+    let chunks: Vec<_> = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .stop_at(stops.iter().copied())
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+    let text = chunks
+        .iter()
+        .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+        .collect();
+    let reasons = chunks
+        .iter()
+        .map(|chunk| chunk.choices[0].finish_reason.clone())
+        .collect();
+    (text, reasons)
+}
+
+#[tokio::test]
+async fn stop_sequences_split_across_chunks_end_the_stream() {
+    let (text, reasons) = stopped_text(
+        &["The answer", " is 42.<|im", "_end|>\nuser:", " more"],
+        &["<|im_end|>", "\nuser:"],
+    )
+    .await;
+
+    assert_eq!(text, "The answer is 42.");
+    // Cut at the third chunk, so the rest of the stream is never read
+    assert_eq!(reasons, [None, None, Some(FinishReason::Stop)]);
+}
+
+#[tokio::test]
+async fn held_back_text_is_released_when_it_is_not_a_stop() {
+    let (text, reasons) = stopped_text(&["a <", "|im", "ag", "e| b ", "é"], &["<|im_end|>"]).await;
+
+    assert_eq!(text, "a <|image| b é");
+    assert_eq!(reasons.last().unwrap(), &Some(FinishReason::Length));
+
+    let (text, _) = stopped_text(&["Hi</", "s"], &["</s>"]).await;
+    assert_eq!(text, "Hi</s");
+}
+
+#[tokio::test]
+async fn held_back_text_is_released_when_the_stream_just_ends() {
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![chunk(json!({ "content": "Done. ###" }), None)],
+    );
+
+    let pieces: Vec<String> = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .stop_at(["####"])
+        .map(|chunk| chunk.unwrap().choices[0].delta.content.clone().unwrap())
+        .collect()
+        .await;
+
+    assert_eq!(pieces, ["Done. ", "###"]);
+}
+
+#[tokio::test]
+async fn stats_time_the_stream() {
+    let mut usage = chunk(json!({}), Some("stop"));
+    usage["usage"] = json!({ "prompt_tokens": 5, "completion_tokens": 4, "total_tokens": 9 });
+    let paced = vec![
+        (
+            0,
+            chunk(json!({ "role": "assistant", "content": "" }), None),
+        ),
+        (40, chunk(json!({ "content": "One" }), None)),
+        (20, chunk(json!({ "content": " two" }), None)),
+        (20, chunk(json!({ "content": " three" }), None)),
+        (20, chunk(json!({ "content": " four" }), None)),
+        (0, usage),
+    ];
+    let chunks = futures::stream::iter(paced).then(|(delay, chunk)| async move {
+        tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+        Ok(serde_json::from_value(chunk)?)
+    });
+
+    let mut stream = chunks.with_stats();
+    assert!(stream.stats().is_none());
+    let mut text = String::new();
+    while let Some(chunk) = stream.next().await {
+        text.push_str(
+            chunk.unwrap().choices[0]
+                .delta
+                .content
+                .as_deref()
+                .unwrap_or(""),
+        );
+    }
+    assert_eq!(text, "One two three four");
+
+    let stats = stream.stats().unwrap();
+    let ms = |duration: Option<std::time::Duration>| duration.unwrap().as_millis();
+    assert_eq!(stats.tokens, 4);
+    assert_eq!(stats.completion_tokens, Some(4));
+    assert!(
+        (40..200).contains(&ms(stats.time_to_first_token)),
+        "{:?}",
+        stats
+    );
+    assert!(
+        (20..100).contains(&ms(stats.mean_inter_token_latency)),
+        "{:?}",
+        stats
+    );
+    assert!(stats.max_inter_token_latency >= stats.mean_inter_token_latency);
+    assert!(stats.total >= stats.time_to_first_token.unwrap());
+    // Three tokens over at least 60ms after the first
+    let speed = stats.tokens_per_second.unwrap();
+    assert!(speed > 10.0 && speed <= 50.0, "{:?}", stats);
+}
+
+#[tokio::test]
+async fn callbacks_receive_tokens_tool_calls_and_the_finish() {
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            chunk(json!({ "role": "assistant", "content": "" }), None),
+            chunk(json!({ "content": "Checking" }), None),
+            chunk(json!({ "content": "..." }), None),
+            chunk(
+                json!({ "tool_calls": [fragment(0, Some("call_1"), Some("get_weather"), r#"{"city":"Oslo"}"#)] }),
+                None,
+            ),
+            chunk(json!({}), Some("tool_calls")),
+        ],
+    );
+
+    let mut tokens = Vec::new();
+    let mut calls = Vec::new();
+    let mut done = None;
+    client(&mock)
+        .chat_completion_with_callbacks(
+            request(),
+            |token| tokens.push(token.to_string()),
+            |call| calls.push(call.function.name.clone()),
+            |reason| done = Some(reason),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(tokens, ["Checking", "..."]);
+    assert_eq!(calls, ["get_weather"]);
+    assert_eq!(done, Some(Some(FinishReason::ToolCalls)));
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["stream"], true);
+}
+
+#[tokio::test]
+async fn callbacks_stop_at_an_error_without_finishing() {
+    let mock = MockTransport::new().respond("/v1/chat/completions", 500, "boom");
+
+    let mut finished = false;
+    let result = client(&mock)
+        .chat_completion_with_callbacks(request(), |_| {}, |_| {}, |_| finished = true)
+        .await;
+
+    assert!(result.is_err());
+    assert!(!finished);
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn events_arrive_over_a_channel() {
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            chunk(json!({ "content": "Hel" }), None),
+            chunk(json!({ "content": "lo" }), None),
+            chunk(json!({}), Some("stop")),
+        ],
+    );
+
+    let (mut events, task) = client(&mock).chat_completion_channel(request(), 1);
+    let mut received = Vec::new();
+    while let Some(event) = events.recv().await {
+        received.push(event);
+    }
+    task.await.unwrap().unwrap();
+
+    assert_eq!(
+        received,
+        [
+            ChatEvent::ContentDelta("Hel".to_string()),
+            ChatEvent::ContentDelta("lo".to_string()),
+            ChatEvent::Done(Some(FinishReason::Stop)),
+        ]
+    );
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn channel_errors_come_from_the_task() {
+    let mock = MockTransport::new().respond(
+        "/v1/chat/completions",
+        503,
+        r#"{"error":{"message":"Loading model"}}"#,
+    );
+
+    let (mut events, task) = client(&mock).chat_completion_channel(request(), 8);
+    assert!(events.recv().await.is_none());
+    let err = task.await.unwrap().unwrap_err();
+    assert!(err.to_string().contains("Loading model"), "{}", err);
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn streams_write_their_text_and_return_the_rest() {
+    let mut last = chunk(json!({}), Some("stop"));
+    last["usage"] = json!({ "prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8 });
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            chunk(json!({ "role": "assistant", "content": "" }), None),
+            chunk(json!({ "reasoning_content": "Short answer." }), None),
+            chunk(json!({ "content": "Hello" }), None),
+            chunk(json!({ "content": ", world" }), None),
+            last,
+        ],
+    );
+
+    let mut written = Vec::new();
+    let summary = client(&mock)
+        .stream_to(request(), &mut written)
+        .await
+        .unwrap();
+
+    assert_eq!(String::from_utf8(written).unwrap(), "Hello, world");
+    assert_eq!(summary.id, "chatcmpl-1");
+    assert_eq!(summary.model, "qwen");
+    assert_eq!(summary.finish_reason, Some(FinishReason::Stop));
+    assert_eq!(summary.usage.unwrap().total_tokens, 8);
+    assert!(summary.tool_calls.is_empty());
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn flushing_writers_see_every_token() {
+    /// Counts flushes and records what each one made visible
+    #[derive(Default)]
+    struct Flushes {
+        buffer: Vec<u8>,
+        seen: Vec<String>,
+    }
+
+    impl tokio::io::AsyncWrite for Flushes {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            data: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.buffer.extend_from_slice(data);
+            std::task::Poll::Ready(Ok(data.len()))
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let text = String::from_utf8(self.buffer.clone()).unwrap();
+            self.seen.push(text);
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            chunk(json!({ "content": "a" }), None),
+            chunk(
+                json!({ "tool_calls": [fragment(0, Some("call_1"), Some("ping"), "{}")] }),
+                None,
+            ),
+            chunk(json!({ "content": "b" }), None),
+            chunk(json!({}), Some("tool_calls")),
+        ],
+    );
+    let client = client(&mock);
+
+    let mut writer = Flushes::default();
+    let summary = client
+        .stream_to_flushing(request(), &mut writer)
+        .await
+        .unwrap();
+    assert_eq!(writer.seen, ["a", "ab", "ab"]);
+    assert_eq!(summary.tool_calls[0].function.name, "ping");
+
+    let mut writer = Flushes::default();
+    client.stream_to(request(), &mut writer).await.unwrap();
+    assert_eq!(writer.seen, ["ab"]);
+}
+
+#[tokio::test]
+async fn content_streams_yield_only_text() {
+    let body = concat!(
+        "data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"reasoning_content\":\"hmm\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" there\"},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n",
+    );
+    let mock = MockTransport::new().respond("/v1/chat/completions", 200, body);
+
+    let text: Vec<String> = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap()
+        .content_stream()
+        .map(|text| text.unwrap())
+        .collect()
+        .await;
+
+    assert_eq!(text, ["Hi", " there"]);
+}
+
+/// Serves a stream that breaks after its first two chunks, then whole
+/// streams, recording the request bodies
+#[derive(Debug, Clone, Default)]
+struct Flaky {
+    bodies: std::sync::Arc<std::sync::Mutex<Vec<Value>>>,
+}
+
+fn sse(chunks: &[Value]) -> Vec<u8> {
+    chunks
+        .iter()
+        .map(|chunk| format!("data: {}\n\n", chunk))
+        .collect::<String>()
+        .into_bytes()
+}
+
+impl lancor::Transport for Flaky {
+    fn send(
+        &self,
+        request: lancor::transport::HttpRequest,
+    ) -> lancor::BoxFuture<'_, anyhow::Result<lancor::transport::HttpResponse>> {
+        Box::pin(async move {
+            let mut bodies = self.bodies.lock().unwrap();
+            bodies.push(request.json()?);
+            let pieces: Vec<anyhow::Result<Vec<u8>>> = if bodies.len() == 1 {
+                vec![
+                    Ok(sse(&[
+                        chunk(json!({ "role": "assistant", "content": "" }), None),
+                        chunk(json!({ "content": "The quick" }), None),
+                    ])),
+                    Ok(sse(&[chunk(json!({ "content": " brown" }), None)])),
+                    Err(anyhow::anyhow!("connection reset by peer")),
+                ]
+            } else {
+                vec![Ok(sse(&[
+                    chunk(json!({ "role": "assistant", "content": "" }), None),
+                    chunk(json!({ "content": " fox" }), None),
+                    chunk(json!({}), Some("stop")),
+                ]))]
+            };
+            Ok(lancor::transport::HttpResponse::streaming(
+                200,
+                Box::pin(futures::stream::iter(pieces)),
+            ))
+        })
+    }
+}
+
+#[tokio::test]
+async fn dropped_streams_resume_where_they_left_off() {
+    let transport = Flaky::default();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(transport.clone());
+
+    let chunks: Vec<_> = client
+        .chat_completion_stream_resuming(request().max_tokens(100), 2)
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+        .collect();
+    assert_eq!(text, "The quick brown fox");
+    let roles = chunks
+        .iter()
+        .filter(|chunk| chunk.choices[0].delta.role.is_some())
+        .count();
+    assert_eq!(roles, 1);
+
+    let bodies = transport.bodies.lock().unwrap();
+    assert_eq!(bodies.len(), 2);
+    let messages = bodies[1]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["content"], "The quick brown");
+    assert_eq!(bodies[1]["max_tokens"], 98);
+}
+
+#[tokio::test]
+async fn resuming_gives_up_after_max_resumes() {
+    let transport = Flaky::default();
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(transport.clone());
+
+    let results: Vec<_> = client
+        .chat_completion_stream_resuming(request(), 0)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+
+    let err = results.last().unwrap().as_ref().unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("connection reset"),
+        "{:#}",
+        err
+    );
+    assert_eq!(transport.bodies.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn streams_that_end_without_finishing_are_resumed() {
+    let mock = MockTransport::new()
+        .sse(
+            "/v1/chat/completions",
+            vec![chunk(json!({ "content": "Once upon" }), None)],
+        )
+        .sse(
+            "/v1/chat/completions",
+            vec![
+                chunk(json!({ "content": " a time" }), None),
+                chunk(json!({}), Some("length")),
+            ],
+        );
+
+    let text: Vec<String> = client(&mock)
+        .chat_completion_stream_resuming(request(), 1)
+        .await
+        .unwrap()
+        .content_stream()
+        .map(|text| text.unwrap())
+        .collect()
+        .await;
+
+    assert_eq!(text, ["Once upon", " a time"]);
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[cfg(feature = "runtime-tokio")]
+fn progress() -> Value {
+    let mut progress = chunk(json!({}), None);
+    progress["timings"] = json!({ "prompt_n": 10, "predicted_n": 0 });
+    progress
+}
+
+#[cfg(feature = "runtime-tokio")]
+async fn bounded_texts(overflow: lancor::Overflow) -> (Vec<String>, usize, u64) {
+    let mock = MockTransport::new().sse(
+        "/v1/chat/completions",
+        vec![
+            progress(),
+            chunk(json!({ "content": "a" }), None),
+            progress(),
+            chunk(json!({ "content": "b" }), None),
+            progress(),
+            chunk(json!({ "content": "c" }), Some("stop")),
+        ],
+    );
+    let stream = client(&mock)
+        .chat_completion_stream(request())
+        .await
+        .unwrap();
+    let mut bounded = stream.bounded(2, overflow);
+
+    // A slow consumer: the reader fills the buffer in the meantime
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let queued = bounded.queued();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = bounded.next().await {
+        chunks.push(chunk.unwrap());
+    }
+    let texts = chunks
+        .iter()
+        .map(|chunk| chunk.choices[0].delta.content.clone().unwrap_or_default())
+        .collect();
+    (texts, queued, bounded.dropped())
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn bounded_streams_hold_back_the_reader() {
+    let (texts, queued, dropped) = bounded_texts(lancor::Overflow::Block).await;
+    assert_eq!(queued, 2);
+    assert_eq!(texts, ["", "a", "", "b", "", "c"]);
+    assert_eq!(dropped, 0);
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[tokio::test]
+async fn bounded_streams_can_drop_progress_but_never_output() {
+    let (texts, queued, dropped) = bounded_texts(lancor::Overflow::DropProgress).await;
+    assert_eq!(queued, 2);
+    let output: Vec<_> = texts.iter().filter(|text| !text.is_empty()).collect();
+    assert_eq!(output, ["a", "b", "c"]);
+    assert!(dropped >= 2, "{}", dropped);
+    assert_eq!(texts.len() as u64 + dropped, 6);
+}
@@ -0,0 +1,59 @@
+//! JSON replies with `json_mode` and `chat_completion_json`.
+
+mod common;
+
+use common::chat_response;
+use lancor::structured::JSON_MODE_HINT;
+use lancor::transport::MockTransport;
+use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+use serde_json::{Value, json};
+
+#[test]
+fn json_mode_hints_go_in_the_system_prompt() {
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("List three colors"))
+        .json_mode_with_hint();
+    assert_eq!(request.messages.len(), 2);
+    assert_eq!(request.messages[0].role, "system");
+    assert_eq!(request.messages[0].content.text(), JSON_MODE_HINT);
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::system("You are terse."))
+        .message(Message::user("List three colors"))
+        .json_mode_with_hint();
+    assert_eq!(request.messages.len(), 2);
+    assert_eq!(
+        request.messages[0].content.text(),
+        format!("You are terse.\n\n{}", JSON_MODE_HINT)
+    );
+}
+
+#[tokio::test]
+async fn json_replies_are_parsed() {
+    let mock = MockTransport::new().json(
+        "/v1/chat/completions",
+        chat_response("```json\n{\"colors\": [\"red\", \"green\", \"blue\"]}\n```"),
+    );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Colors?"));
+    let value = client.chat_completion_json(request).await.unwrap();
+    assert_eq!(value["colors"][2], "blue");
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["response_format"], json!({ "type": "json_object" }));
+}
+
+#[tokio::test]
+async fn replies_that_are_not_json_fail() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Red, green"));
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("Colors?"))
+        .json_mode();
+    let err = client.chat_completion_json(request).await.unwrap_err();
+    assert!(err.to_string().contains("not valid JSON"), "{}", err);
+}
@@ -0,0 +1,115 @@
+//! Prompt templates with conditionals and includes.
+
+#![cfg(feature = "templates")]
+
+use lancor::{ChatTemplate, PromptTemplate};
+use std::collections::HashMap;
+
+#[test]
+fn conditionals_pick_a_branch() {
+    let template = PromptTemplate::parse(
+        "You are an assistant.\n{{#if persona}}\nAct as {{persona}}.\n{{else}}\nBe neutral.\n{{/if}}\nAnswer briefly.",
+    )
+    .unwrap();
+    assert_eq!(
+        template.variables().into_iter().collect::<Vec<_>>(),
+        ["persona"]
+    );
+
+    let with = template
+        .render(&HashMap::from([("persona", "a pirate")]))
+        .unwrap();
+    assert_eq!(
+        with,
+        "You are an assistant.\nAct as a pirate.\nAnswer briefly."
+    );
+
+    let without = template.render(&HashMap::<&str, &str>::new()).unwrap();
+    assert_eq!(
+        without,
+        "You are an assistant.\nBe neutral.\nAnswer briefly."
+    );
+
+    let empty = template.render(&HashMap::from([("persona", "")])).unwrap();
+    assert_eq!(empty, without);
+}
+
+#[test]
+fn only_variables_in_taken_branches_are_required() {
+    let template = PromptTemplate::parse("{{#if docs}}Use: {{docs}} {{source}}{{/if}}Hi").unwrap();
+    assert_eq!(
+        template.render(&HashMap::<&str, &str>::new()).unwrap(),
+        "Hi"
+    );
+
+    let err = template
+        .render(&HashMap::from([("docs", "notes")]))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Missing template variables: source");
+}
+
+#[test]
+fn malformed_conditionals_are_rejected() {
+    for source in [
+        "{{#if a}}unclosed",
+        "{{/if}}",
+        "{{else}}",
+        "{{#if a}}x{{else}}y{{else}}z{{/if}}",
+        "{{#if not-a-name}}x{{/if}}",
+    ] {
+        assert!(PromptTemplate::parse(source).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn partials_are_included() {
+    let partials = HashMap::from([
+        ("rules", "Be concise.\n{{#if strict}}Never guess.\n{{/if}}"),
+        ("loop", "{{> loop}}"),
+    ]);
+    let template =
+        PromptTemplate::parse_with_partials("{{> rules}}\nTopic: {{topic}}", &partials).unwrap();
+    assert_eq!(template.variables().len(), 2);
+    assert_eq!(
+        template
+            .render(&HashMap::from([("topic", "Rust"), ("strict", "yes")]))
+            .unwrap(),
+        "Be concise.\nNever guess.\nTopic: Rust"
+    );
+
+    let err = PromptTemplate::parse_with_partials("{{> loop}}", &partials).unwrap_err();
+    assert!(format!("{:#}", err).contains("cycle"), "{:#}", err);
+    let err = PromptTemplate::parse_with_partials("{{> missing}}", &partials).unwrap_err();
+    assert!(err.to_string().contains("Unknown partial"), "{}", err);
+    assert!(PromptTemplate::parse("{{> rules}}").is_err());
+}
+
+#[test]
+fn template_files_include_files_next_to_them() {
+    let dir = std::env::temp_dir().join(format!("lancor-templates-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("shared")).unwrap();
+    std::fs::write(
+        dir.join("shared/persona.md"),
+        "You are {{name}}.\n{{> tone.md}}\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("shared/tone.md"), "Stay friendly.\n").unwrap();
+    std::fs::write(
+        dir.join("chat.md"),
+        "### system\n{{> shared/persona.md}}\n### user\n{{question}}\n",
+    )
+    .unwrap();
+
+    let template = ChatTemplate::from_file(dir.join("chat.md")).unwrap();
+    let messages = template
+        .render(&HashMap::from([("name", "Ferris"), ("question", "Hi?")]))
+        .unwrap();
+    assert_eq!(messages[0].role, "system");
+    assert_eq!(
+        messages[0].content.text(),
+        "You are Ferris.\nStay friendly."
+    );
+    assert_eq!(messages[1].content.text(), "Hi?");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,148 @@
+//! Per-category request timeouts with `with_timeouts`.
+
+#![cfg(feature = "runtime-tokio")]
+
+mod common;
+
+use common::{chat_response, request};
+use futures::stream::StreamExt;
+use lancor::transport::{HttpRequest, HttpResponse, Transport};
+use lancor::{EmbeddingRequest, LlamaCppClient, Timeouts};
+use serde_json::json;
+use std::time::Duration;
+
+/// Answers after `delay`, then sends `body` in `chunks` pieces `gap` apart
+#[derive(Debug, Clone)]
+struct SlowTransport {
+    delay: Duration,
+    gap: Duration,
+    chunks: Vec<String>,
+}
+
+impl SlowTransport {
+    fn new(delay_ms: u64, gap_ms: u64, chunks: Vec<String>) -> Self {
+        Self {
+            delay: Duration::from_millis(delay_ms),
+            gap: Duration::from_millis(gap_ms),
+            chunks,
+        }
+    }
+}
+
+impl Transport for SlowTransport {
+    fn send(&self, _request: HttpRequest) -> lancor::BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.delay).await;
+            let gap = self.gap;
+            let body = futures::stream::iter(self.chunks.clone()).then(move |chunk| async move {
+                tokio::time::sleep(gap).await;
+                Ok(chunk.into_bytes())
+            });
+            Ok(HttpResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: Box::pin(body),
+            })
+        })
+    }
+}
+
+fn sse_chunks(count: usize) -> Vec<String> {
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }]
+    });
+    let mut chunks = vec![format!("data: {}\n\n", chunk); count];
+    chunks.push("data: [DONE]\n\n".to_string());
+    chunks
+}
+
+fn client(transport: SlowTransport, timeouts: Timeouts) -> LlamaCppClient {
+    LlamaCppClient::default()
+        .unwrap()
+        .with_transport(transport)
+        .with_timeouts(timeouts)
+}
+
+#[tokio::test]
+async fn generation_and_embedding_have_their_own_limits() {
+    let timeouts = Timeouts::none()
+        .generation(Duration::from_millis(500))
+        .embedding(Duration::from_millis(50));
+
+    // A slow body counts against the whole-request limit
+    let slow = SlowTransport::new(20, 100, vec![chat_response("Hi").to_string()]);
+    let response = client(slow.clone(), timeouts)
+        .chat_completion(request())
+        .await
+        .unwrap();
+    assert_eq!(response.choices[0].message.content, "Hi".into());
+
+    let err = client(slow, timeouts)
+        .embedding(EmbeddingRequest::new("test-model", "Hi"))
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Timed out after 50ms"),
+        "{:#}",
+        err
+    );
+
+    let slower = SlowTransport::new(400, 200, vec![chat_response("Hi").to_string()]);
+    let err = client(slower, timeouts)
+        .chat_completion(request())
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Timed out after 500ms"),
+        "{:#}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn streams_are_limited_between_chunks_not_overall() {
+    let timeouts = Timeouts::none()
+        .generation(Duration::from_millis(50))
+        .first_byte(Duration::from_millis(200))
+        .idle(Duration::from_millis(100));
+
+    // Forty chunks 20ms apart outlast every limit but never stall
+    let steady = SlowTransport::new(100, 20, sse_chunks(40));
+    let stream = client(steady, timeouts)
+        .chat_completion_stream(request())
+        .await
+        .unwrap();
+    let chunks: Vec<_> = stream.collect().await;
+    assert_eq!(chunks.len(), 40);
+    assert!(chunks.iter().all(Result::is_ok));
+
+    let stalled = SlowTransport::new(10, 150, sse_chunks(3));
+    let mut stream = client(stalled, timeouts)
+        .chat_completion_stream(request())
+        .await
+        .unwrap();
+    assert!(stream.next().await.unwrap().is_ok());
+    let err = stream.next().await.unwrap().unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Timed out after 100ms"),
+        "{:#}",
+        err
+    );
+    assert!(stream.next().await.is_none());
+
+    let Err(err) = client(SlowTransport::new(300, 0, sse_chunks(1)), timeouts)
+        .chat_completion_stream(request())
+        .await
+    else {
+        panic!("the stream should not start");
+    };
+    assert!(
+        format!("{:#}", err).contains("Timed out after 200ms"),
+        "{:#}",
+        err
+    );
+}
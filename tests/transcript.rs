@@ -0,0 +1,132 @@
+//! JSONL transcripts of requests, streamed deltas and responses.
+
+#![cfg(feature = "transcript")]
+
+mod common;
+
+use common::request;
+use futures::stream::StreamExt;
+use lancor::LlamaCppClient;
+use lancor::transcript::TranscriptWriter;
+use lancor::transport::MockTransport;
+use serde_json::{Value, json};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// A writer whose output the test can read back
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn lines(&self) -> Vec<Value> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+fn chunk(content: &str) -> Value {
+    json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+    })
+}
+
+#[tokio::test]
+async fn requests_deltas_and_responses_are_recorded() {
+    let mut last = chunk("!");
+    last["usage"] = json!({ "prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5 });
+    let mock = MockTransport::new()
+        .sse("/v1/chat/completions", vec![chunk("Hello"), last])
+        .json(
+            "/v1/chat/completions",
+            json!({
+                "id": "chatcmpl-2",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "test-model",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Hi there" },
+                    "finish_reason": "stop"
+                }]
+            }),
+        );
+    let buffer = Buffer::default();
+    let client = LlamaCppClient::with_api_key("http://server:8080", "sk-secret")
+        .unwrap()
+        .with_transport(mock)
+        .with_transcript(TranscriptWriter::new(buffer.clone()));
+
+    let stream = client.chat_completion_stream(request()).await.unwrap();
+    let chunks: Vec<_> = stream.collect().await;
+    assert_eq!(chunks.len(), 2);
+    client.chat_completion(request()).await.unwrap();
+
+    let lines = buffer.lines();
+    let events: Vec<_> = lines.iter().map(|l| l["event"].as_str().unwrap()).collect();
+    assert_eq!(
+        events,
+        [
+            "request", "delta", "delta", "response", "request", "response"
+        ]
+    );
+    assert!(lines.iter().all(|l| l["ts"].as_f64().unwrap() > 1.0e9));
+
+    let streamed_id = &lines[0]["request_id"];
+    assert!(!streamed_id.as_str().unwrap().is_empty());
+    assert!(lines[..4].iter().all(|l| &l["request_id"] == streamed_id));
+    assert_ne!(&lines[4]["request_id"], streamed_id);
+
+    assert_eq!(lines[0]["url"], "http://server:8080/v1/chat/completions");
+    assert_eq!(lines[0]["body"]["messages"][0]["content"], "Hi");
+    assert_eq!(lines[1]["data"]["choices"][0]["delta"]["content"], "Hello");
+
+    let streamed = &lines[3];
+    assert_eq!(streamed["status"], 200);
+    assert_eq!(streamed["streamed"], true);
+    assert_eq!(streamed["content"], "Hello!");
+    assert_eq!(streamed["usage"]["completion_tokens"], 2);
+
+    let response = &lines[5];
+    assert_eq!(
+        response["body"]["choices"][0]["message"]["content"],
+        "Hi there"
+    );
+    assert!(
+        !buffer
+            .lines()
+            .iter()
+            .any(|l| l.to_string().contains("sk-secret"))
+    );
+}
+
+#[tokio::test]
+async fn failed_requests_end_with_an_error() {
+    let buffer = Buffer::default();
+    let client = LlamaCppClient::new("http://127.0.0.1:9")
+        .unwrap()
+        .with_transcript(TranscriptWriter::new(buffer.clone()));
+
+    assert!(client.chat_completion(request()).await.is_err());
+    let lines = buffer.lines();
+    assert_eq!(lines[0]["event"], "request");
+    let last = lines.last().unwrap();
+    assert_eq!(last["event"], "error");
+    assert!(last["error"].as_str().is_some());
+}
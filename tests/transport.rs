@@ -0,0 +1,372 @@
+//! Client tests against [`MockTransport`], without a server.
+
+mod common;
+
+use common::chat_response;
+use futures::stream::StreamExt;
+use lancor::transport::MockTransport;
+use lancor::{
+    ChatCompletionRequest, LancorConfig, LlamaCppClient, Message, Preset, Presets, TokenizeRequest,
+};
+use serde_json::{Value, json};
+
+fn chunk(content: &str) -> Value {
+    json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+    })
+}
+
+#[tokio::test]
+async fn chat_completion_uses_transport() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("Hi!"));
+    let client = LlamaCppClient::with_api_key("http://server:8080", "secret")
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hello"));
+    let response = client.chat_completion(request).await.unwrap();
+    assert_eq!(response.choices[0].message.content.text(), "Hi!");
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].url, "http://server:8080/v1/chat/completions");
+    assert_eq!(
+        requests[0].header_value("authorization"),
+        Some("Bearer secret")
+    );
+    let body: Value = requests[0].json().unwrap();
+    assert_eq!(body["messages"][0]["content"], "Hello");
+}
+
+#[tokio::test]
+async fn chat_completion_stream_parses_events() {
+    let mock = MockTransport::new().sse("/v1/chat/completions", vec![chunk("Hel"), chunk("lo")]);
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let request = ChatCompletionRequest::new("test-model")
+        .message(Message::user("Hello"))
+        .stream(true);
+    let chunks: Vec<_> = client
+        .chat_completion_stream(request)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+
+    let text: String = chunks
+        .into_iter()
+        .map(|chunk| chunk.unwrap().choices[0].delta.content.clone().unwrap())
+        .collect();
+    assert_eq!(text, "Hello");
+}
+
+#[tokio::test]
+async fn stream_comments_fields_and_heartbeats_are_skipped() {
+    // What nginx and other proxies put around a llama.cpp stream
+    let body = format!(
+        ": keep-alive\n\nevent: message\nid: 1\r\ndata: {}\r\n\r\ndata:\n\n:ping\nretry: 3000\n\n\ndata: {}\n\ndata: [DONE]\n\n",
+        chunk("Hel"),
+        chunk("lo"),
+    );
+    let mock = MockTransport::new().respond_with_headers(
+        "/v1/chat/completions",
+        200,
+        &[("Content-Type", "text/event-stream")],
+        body,
+    );
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hello"));
+    let text: String = client
+        .chat_completion_stream(request)
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap().choices[0].delta.content.clone().unwrap())
+        .collect()
+        .await;
+    assert_eq!(text, "Hello");
+}
+
+#[tokio::test]
+async fn multi_line_stream_data_is_joined() {
+    let body = "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\ndata: \"created\":0,\"model\":\"m\",\ndata: \"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n";
+    let mock = MockTransport::new().respond("/v1/chat/completions", 200, body);
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let request = ChatCompletionRequest::new("m").message(Message::user("Hello"));
+    let chunks: Vec<_> = client
+        .chat_completion_stream(request)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(
+        chunks[0].as_ref().unwrap().choices[0]
+            .delta
+            .content
+            .as_deref(),
+        Some("Hi")
+    );
+}
+
+#[tokio::test]
+async fn unknown_response_fields_are_kept() {
+    let mut response = chat_response("Hi!");
+    response["__verbose"] = json!({ "prompt_n": 5, "stopping_word": "" });
+    response["choices"][0]["logprobs"] = json!(null);
+    response["usage"]["prompt_tokens_details"] = json!({ "cached_tokens": 3 });
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", response)
+        .sse(
+            "/v1/chat/completions",
+            vec![json!({
+                "choices": [{ "delta": { "content": "Hi" }, "finish_reason": null }],
+                "__verbose": { "predicted_n": 1 }
+            })],
+        );
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hello"));
+
+    let response = client.chat_completion(request.clone()).await.unwrap();
+    assert_eq!(response.extra["__verbose"]["prompt_n"], json!(5));
+    assert!(response.choices[0].extra.contains_key("logprobs"));
+    assert_eq!(
+        response.usage.extra["prompt_tokens_details"]["cached_tokens"],
+        json!(3)
+    );
+
+    // Chunks with only the fields that matter still parse
+    let chunks: Vec<_> = client
+        .chat_completion_stream(request)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    let chunk = chunks[0].as_ref().unwrap();
+    assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("Hi"));
+    assert_eq!(chunk.extra["__verbose"]["predicted_n"], json!(1));
+}
+
+#[tokio::test]
+async fn responses_without_usage_parse() {
+    let mut response = chat_response("Hi!");
+    response.as_object_mut().unwrap().remove("usage");
+    response.as_object_mut().unwrap().remove("created");
+    let mock = MockTransport::new().json("/v1/chat/completions", response);
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hello"));
+    let response = client.chat_completion(request).await.unwrap();
+    assert_eq!(response.choices[0].message.content.text(), "Hi!");
+    assert_eq!(response.usage.total_tokens, 0);
+}
+
+#[tokio::test]
+async fn base_urls_are_normalized() {
+    for base_url in [
+        "http://server:8080",
+        "http://server:8080/",
+        "http://server:8080/v1",
+        "http://server:8080/v1/",
+    ] {
+        let mock = MockTransport::new()
+            .json("/v1/chat/completions", chat_response("Hi!"))
+            .json("/tokenize", json!({ "tokens": [1] }));
+        let client = LlamaCppClient::new(base_url)
+            .unwrap()
+            .with_transport(mock.clone());
+
+        let request = ChatCompletionRequest::new("test-model").message(Message::user("Hello"));
+        client.chat_completion(request).await.unwrap();
+        client.tokenize(TokenizeRequest::new("Hi")).await.unwrap();
+
+        let urls: Vec<_> = mock.requests().into_iter().map(|r| r.url).collect();
+        assert_eq!(
+            urls,
+            [
+                "http://server:8080/v1/chat/completions",
+                "http://server:8080/tokenize"
+            ],
+            "{}",
+            base_url
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_api_prefix_can_be_moved() {
+    for (prefix, url) in [
+        ("/api/v1", "http://server/api/v1/chat/completions"),
+        ("api/v1/", "http://server/api/v1/chat/completions"),
+        ("", "http://server/chat/completions"),
+    ] {
+        let mock = MockTransport::new()
+            .json("/api/v1/chat/completions", chat_response("Hi!"))
+            .json("/chat/completions", chat_response("Hi!"));
+        let config = LancorConfig::new("http://server/").api_prefix(prefix);
+        let client = LlamaCppClient::from_config(config)
+            .unwrap()
+            .with_transport(mock.clone());
+
+        let request = ChatCompletionRequest::new("test-model").message(Message::user("Hello"));
+        client.chat_completion(request).await.unwrap();
+        assert_eq!(mock.requests()[0].url, url);
+    }
+}
+
+#[tokio::test]
+async fn error_status_is_an_error() {
+    let mock = MockTransport::new().respond("/tokenize", 503, "loading model");
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let err = client
+        .tokenize(TokenizeRequest::new("Hello"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("503"), "{}", err);
+    assert!(err.to_string().contains("loading model"), "{}", err);
+}
+
+#[tokio::test]
+async fn unmatched_path_is_not_found() {
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(MockTransport::new());
+
+    let err = client
+        .tokenize(TokenizeRequest::new("Hello"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("404"), "{}", err);
+}
+
+#[tokio::test]
+async fn responses_are_returned_in_order() {
+    let mock = MockTransport::new()
+        .json("/v1/chat/completions", chat_response("first"))
+        .json("/v1/chat/completions", chat_response("second"));
+    let client = LlamaCppClient::default().unwrap().with_transport(mock);
+
+    let mut replies = Vec::new();
+    for _ in 0..3 {
+        let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+        let response = client.chat_completion(request).await.unwrap();
+        replies.push(response.choices[0].message.content.text());
+    }
+    assert_eq!(replies, ["first", "second", "second"]);
+}
+
+#[tokio::test]
+async fn presets_are_applied_before_sending() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("ok"));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_presets(Presets::new().defaults(Preset::new().temperature(0.25)))
+        .with_transport(mock.clone());
+
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+    client.chat_completion(request).await.unwrap();
+
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["temperature"], 0.25);
+}
+
+#[tokio::test]
+async fn builders_on_a_clone_leave_the_original_alone() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("ok"));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let warm = client
+        .clone()
+        .with_presets(Presets::new().defaults(Preset::new().temperature(0.5)));
+    assert_eq!(warm.config().presets.defaults.temperature, Some(0.5));
+    assert_eq!(client.config().presets.defaults.temperature, None);
+
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+    client.chat_completion(request.clone()).await.unwrap();
+    warm.chat_completion(request).await.unwrap();
+    let bodies: Vec<Value> = mock
+        .requests()
+        .iter()
+        .map(|request| request.json().unwrap())
+        .collect();
+    assert!(bodies[0].get("temperature").is_none());
+    assert_eq!(bodies[1]["temperature"], 0.5);
+}
+
+#[tokio::test]
+async fn deterministic_requests_fix_the_seed_and_disable_penalties() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("ok"));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+
+    client
+        .chat_completion(request.clone().deterministic(42))
+        .await
+        .unwrap();
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert_eq!(body["temperature"], 0.0);
+    assert_eq!(body["top_p"], 1.0);
+    assert_eq!(body["seed"], 42);
+    assert_eq!(body["presence_penalty"], 0.0);
+    assert_eq!(body["frequency_penalty"], 0.0);
+    assert_eq!(body["repeat_penalty"], 1.0);
+
+    // A client-level default fills in what the request leaves unset
+    let client = client.deterministic(7);
+    client
+        .chat_completion(request.temperature(0.5))
+        .await
+        .unwrap();
+    let body: Value = mock.requests()[1].json().unwrap();
+    assert_eq!(body["temperature"], 0.5);
+    assert_eq!(body["seed"], 7);
+    assert_eq!(body["repeat_penalty"], 1.0);
+}
+
+#[tokio::test]
+async fn deterministic_clients_keep_their_seed_to_themselves() {
+    let mock = MockTransport::new().json("/v1/chat/completions", chat_response("ok"));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+    let seeded = client.clone().deterministic(7);
+    let request = ChatCompletionRequest::new("test-model").message(Message::user("Hi"));
+
+    client.chat_completion(request.clone()).await.unwrap();
+    let body: Value = mock.requests()[0].json().unwrap();
+    assert!(body.get("seed").is_none());
+    assert!(body.get("temperature").is_none());
+
+    // A reload replaces the config but not the client's seed
+    seeded.reload_config(LancorConfig::default());
+    seeded.chat_completion(request).await.unwrap();
+    let body: Value = mock.requests()[1].json().unwrap();
+    assert_eq!(body["seed"], 7);
+    assert_eq!(body["temperature"], 0.0);
+}
+
+#[test]
+fn characters_split_across_network_chunks_arrive_whole() {
+    let body = format!("data: {}\n\ndata: {}\r\n\r\n", chunk("héllo "), chunk("👋"));
+    let mut decoder = lancor::stream::SseDecoder::new();
+    let mut text = String::new();
+    // One byte at a time splits every multi-byte character
+    for byte in body.as_bytes() {
+        decoder.push(std::slice::from_ref(byte));
+        while let Some(data) = decoder.next_event() {
+            let chunk: lancor::ChatCompletionChunk = serde_json::from_slice(data).unwrap();
+            text.push_str(chunk.choices[0].delta.content.as_deref().unwrap());
+        }
+    }
+    assert_eq!(text, "héllo 👋");
+}
@@ -0,0 +1,76 @@
+//! Token usage accounting with [`UsageTracker`].
+
+mod common;
+
+use common::chat_response_with_usage;
+use lancor::transport::MockTransport;
+use lancor::usage::{Pricing, UsageTracker};
+use lancor::{ChatCompletionRequest, LlamaCppClient, Message};
+
+fn request(model: &str) -> ChatCompletionRequest {
+    ChatCompletionRequest::new(model).message(Message::user("Hello"))
+}
+
+#[tokio::test]
+async fn tracks_tokens_and_cost_per_model() {
+    let tracker = UsageTracker::new()
+        .price("big", Pricing::new(1.0, 2.0))
+        .default_price(Pricing::new(0.1, 0.1));
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(MockTransport::new().json(
+            "/v1/chat/completions",
+            chat_response_with_usage("Hi", 1000, 500),
+        ))
+        .with_usage_tracker(tracker.clone());
+
+    client.chat_completion(request("big")).await.unwrap();
+    client.chat_completion(request("big")).await.unwrap();
+    client.chat_completion(request("small")).await.unwrap();
+
+    let snapshot = tracker.snapshot();
+    let big = snapshot.model("big");
+    assert_eq!(big.requests, 2);
+    assert_eq!(big.prompt_tokens, 2000);
+    assert_eq!(big.completion_tokens, 1000);
+    assert!((big.cost - 4.0).abs() < 1e-9);
+    assert!((snapshot.model("small").cost - 0.15).abs() < 1e-9);
+    assert_eq!(snapshot.total().total_tokens(), 4500);
+    assert!((tracker.total_cost() - 4.15).abs() < 1e-9);
+
+    let taken = tracker.reset();
+    assert_eq!(taken, snapshot);
+    assert_eq!(tracker.snapshot().total().requests, 0);
+}
+
+#[tokio::test]
+async fn refuses_requests_over_budget() {
+    let mock = MockTransport::new().json(
+        "/v1/chat/completions",
+        chat_response_with_usage("Hi", 600, 100),
+    );
+    let tracker = UsageTracker::new().token_budget(1000);
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone())
+        .with_usage_tracker(tracker.clone());
+
+    client.chat_completion(request("m")).await.unwrap();
+    client.chat_completion(request("m")).await.unwrap();
+    let err = client.chat_completion(request("m")).await.unwrap_err();
+    assert!(err.to_string().contains("Token budget"), "{}", err);
+    assert_eq!(mock.requests().len(), 2);
+
+    tracker.reset();
+    client.chat_completion(request("m")).await.unwrap();
+}
+
+#[test]
+fn cost_budget_is_checked() {
+    let tracker = UsageTracker::new()
+        .default_price(Pricing::new(10.0, 10.0))
+        .cost_budget(1.0);
+    assert!(tracker.check_budget().is_ok());
+    tracker.record("m", 50, 50);
+    assert!(tracker.check_budget().is_err());
+}
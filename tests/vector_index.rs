@@ -0,0 +1,186 @@
+//! Storing and searching vectors in a `VectorIndex`.
+
+use lancor::embeddings::VectorEntry;
+use lancor::transport::MockTransport;
+use lancor::{LlamaCppClient, VectorIndex, VectorStorage};
+use serde_json::json;
+
+fn index() -> VectorIndex {
+    let mut index = VectorIndex::new();
+    index
+        .insert("x", vec![1.0, 0.0], json!({"axis": "x"}))
+        .unwrap();
+    index
+        .insert("y", vec![0.0, 1.0], json!({"axis": "y"}))
+        .unwrap();
+    index.insert("xy", vec![1.0, 1.0], json!(null)).unwrap();
+    index
+}
+
+#[test]
+fn queries_best_first() {
+    let hits = index().query(&[1.0, 0.2], 2);
+    assert_eq!(hits[0].id, "x");
+    assert_eq!(hits[0].metadata, json!({"axis": "x"}));
+    assert_eq!(hits[1].id, "xy");
+}
+
+#[test]
+fn threshold_drops_weak_matches() {
+    let hits = index().query_above(&[1.0, 0.0], 10, 0.5);
+    let ids: Vec<_> = hits.iter().map(|hit| hit.id.as_str()).collect();
+    assert_eq!(ids, ["x", "xy"]);
+}
+
+#[test]
+fn insert_replaces_and_remove_keeps_ids_consistent() {
+    let mut index = index();
+    index.insert("x", vec![-1.0, 0.0], json!(1)).unwrap();
+    assert_eq!(index.len(), 3);
+    assert_eq!(index.get("x").unwrap().embedding, [-1.0, 0.0]);
+
+    assert!(index.remove("x").is_some());
+    assert!(index.remove("x").is_none());
+    assert_eq!(index.len(), 2);
+    assert_eq!(index.get("xy").unwrap().embedding, [1.0, 1.0]);
+}
+
+#[test]
+fn rejects_mismatched_dimensions() {
+    let err = index()
+        .insert("z", vec![1.0, 2.0, 3.0], json!(null))
+        .unwrap_err();
+    assert!(err.to_string().contains("3 dimensions"), "{}", err);
+}
+
+#[test]
+fn saves_and_loads() {
+    let path = std::env::temp_dir().join(format!("lancor-index-{}.json", std::process::id()));
+    index().save(&path).unwrap();
+
+    let loaded = VectorIndex::load(&path).unwrap();
+    assert!(loaded.entries().eq(index().entries()));
+    assert_eq!(loaded.query(&[0.0, 1.0], 1)[0].id, "y");
+    let _ = std::fs::remove_file(&path);
+
+    let rebuilt = VectorIndex::from_entries(vec![VectorEntry {
+        id: "a".to_string(),
+        embedding: vec![1.0],
+        metadata: json!(null),
+    }])
+    .unwrap();
+    assert_eq!(rebuilt.dimension(), Some(1));
+}
+
+/// Deterministic pseudo-random vectors
+fn vectors(count: usize, dims: usize, mut state: u32) -> Vec<Vec<f32>> {
+    (0..count)
+        .map(|_| {
+            (0..dims)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (state % 2000) as f32 / 1000.0 - 1.0
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn ids(hits: &[lancor::embeddings::VectorMatch]) -> Vec<&str> {
+    hits.iter().map(|hit| hit.id.as_str()).collect()
+}
+
+#[test]
+fn compact_storage_saves_memory_and_keeps_recall() {
+    let mut full = VectorIndex::new();
+    for (i, vector) in vectors(2000, 384, 0x2545_f491).into_iter().enumerate() {
+        full.insert(i.to_string(), vector, json!(i)).unwrap();
+    }
+    let half = full.clone().with_storage(VectorStorage::F16);
+    let quarter = full.clone().with_storage(VectorStorage::I8);
+    assert_eq!(full.vector_bytes(), 2000 * 384 * 4);
+    assert_eq!(half.vector_bytes(), full.vector_bytes() / 2);
+    assert_eq!(quarter.vector_bytes(), 2000 * (384 + 4));
+
+    let (mut half_recall, mut quarter_recall) = (0, 0);
+    for query in vectors(20, 384, 0x9e37_79b9) {
+        let expected = full.query(&query, 10);
+        let expected = ids(&expected);
+        let recall = |index: &VectorIndex| {
+            let hits = index.query(&query, 10);
+            assert!((hits[0].score - full.query(&query, 1)[0].score).abs() < 0.01);
+            ids(&hits).iter().filter(|id| expected.contains(id)).count()
+        };
+        half_recall += recall(&half);
+        quarter_recall += recall(&quarter);
+    }
+    assert!(half_recall >= 196, "f16 recall {}/200", half_recall);
+    assert!(quarter_recall >= 180, "i8 recall {}/200", quarter_recall);
+}
+
+#[test]
+fn compact_storage_round_trips_entries() {
+    let mut index = VectorIndex::new().with_storage(VectorStorage::F16);
+    index.insert("a", vec![0.5, -2.0, 1e-6], json!(1)).unwrap();
+    index
+        .insert("b", vec![1.0, 0.1, 65504.0], json!(2))
+        .unwrap();
+    index.insert("a", vec![0.25, 3.0, -1.0], json!(3)).unwrap();
+    assert_eq!(index.get("a").unwrap().embedding, [0.25, 3.0, -1.0]);
+    assert_eq!(index.metadata("a"), Some(&json!(3)));
+    let b = index.get("b").unwrap().embedding;
+    assert!((b[1] - 0.1).abs() < 1e-4 && b[2] == 65504.0);
+
+    index.remove("a").unwrap();
+    assert_eq!(index.ids().collect::<Vec<_>>(), ["b"]);
+    assert_eq!(index.query(&[1.0, 0.1, 65504.0], 1)[0].id, "b");
+
+    let mut index = index.with_storage(VectorStorage::I8);
+    index
+        .insert("c", vec![-4.0, 2.0, 0.0], json!(null))
+        .unwrap();
+    // Scaled so the largest component is exact
+    assert_eq!(index.get("c").unwrap().embedding[0], -4.0);
+    assert!((index.get("c").unwrap().embedding[1] - 2.0).abs() < 0.02);
+    index.insert("zero", vec![0.0; 3], json!(null)).unwrap();
+    assert_eq!(index.get("zero").unwrap().embedding, [0.0; 3]);
+
+    let path = std::env::temp_dir().join(format!("lancor-index-i8-{}.json", std::process::id()));
+    index.save(&path).unwrap();
+    let loaded = VectorIndex::load(&path).unwrap();
+    assert_eq!(loaded.storage(), VectorStorage::I8);
+    assert!(loaded.entries().eq(index.entries()));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn inserts_and_queries_by_text() {
+    let mock = MockTransport::new().json(
+        "/v1/embeddings",
+        json!({
+            "object": "list", "model": "embed",
+            "data": [{ "object": "embedding", "index": 0, "embedding": [0.0, 1.0] }],
+            "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+        }),
+    );
+    let client = LlamaCppClient::default()
+        .unwrap()
+        .with_transport(mock.clone());
+
+    let mut index = index();
+    index
+        .insert_text(&client, "embed", "doc", "some text", json!({"page": 1}))
+        .await
+        .unwrap();
+    let hits = index
+        .query_text(&client, "embed", "query", 2)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 2);
+    assert!(hits.iter().any(|hit| hit.id == "doc"));
+
+    let sent: serde_json::Value = mock.requests()[0].json().unwrap();
+    assert_eq!(sent["input"], "some text");
+}